@@ -10,10 +10,12 @@ CLIENT_PORT=0
 SECURE=0
 KEY=""
 KEY_FILE=""
+ISSUER=""
+NAME=""
 
 usage() {
   cat <<USAGE
-Usage: $0 [-s HOST:PORT] [-l LOG] [--low-gfx] [--no-vsync] [--client-port N] [--secure] [--key HEX] [--key-file PATH]
+Usage: $0 [-s HOST:PORT] [-l LOG] [--low-gfx] [--no-vsync] [--client-port N] [--secure] [--key HEX] [--key-file PATH] [--issuer HOST:PORT --name NAME]
 
 Options:
   -s, --server      Server address (default: 127.0.0.1:5000)
@@ -24,6 +26,8 @@ Options:
       --secure      Enable Secure auth (requires --key or --key-file)
       --key         64-hex shared key (with or without 0x)
       --key-file    Path to key file (32B binary or HEX string)
+      --issuer      Token issuer address; get a connect token instead of holding the key
+      --name        Account name for --issuer (password: PLAYER_PASSWORD or prompt)
 USAGE
 }
 
@@ -37,6 +41,8 @@ while [[ $# -gt 0 ]]; do
     --secure) SECURE=1; shift;;
     --key) KEY="$2"; shift 2;;
     --key-file) KEY_FILE="$2"; shift 2;;
+    --issuer) ISSUER="$2"; shift 2;;
+    --name) NAME="$2"; shift 2;;
     -h|--help) usage; exit 0;;
     *) echo "Unknown option: $1"; usage; exit 1;;
   esac
@@ -49,7 +55,17 @@ if [[ $LOW_GFX -eq 1 ]]; then export LOW_GFX=1; else unset LOW_GFX || true; fi
 if [[ $NO_VSYNC -eq 1 ]]; then export NO_VSYNC=1; else unset NO_VSYNC || true; fi
 if [[ "$CLIENT_PORT" != "0" ]]; then export CLIENT_PORT="$CLIENT_PORT"; else unset CLIENT_PORT || true; fi
 
-if [[ $SECURE -eq 1 ]]; then
+if [[ -n "$ISSUER" ]]; then
+  if [[ -z "$NAME" ]]; then
+    echo "--issuer needs --name" >&2
+    exit 2
+  fi
+  export TOKEN_ISSUER="$ISSUER" PLAYER_NAME="$NAME"
+  if [[ -z "${PLAYER_PASSWORD:-}" ]]; then
+    read -r -s -p "password for $NAME: " PLAYER_PASSWORD; echo
+  fi
+  export PLAYER_PASSWORD
+elif [[ $SECURE -eq 1 ]]; then
   export SECURE=1
   if [[ -n "$KEY" ]]; then
     export NETCODE_KEY="$KEY"
@@ -1,15 +1,22 @@
 param(
   [string]$Address = "0.0.0.0",
   [int]$Port = 5000,
-  [string]$LogLevel = "warn"
+  [string]$LogLevel = "warn",
+  [string]$Config = "",
+  # それ以外のサーバ引数はそのまま渡す（例: --secure --key-file key.hex --max-clients 16）
+  [Parameter(ValueFromRemainingArguments = $true)]
+  [string[]]$Rest = @()
 )
 
 $ErrorActionPreference = "Stop"
 
-$env:SERVER_ADDR = "$Address:$Port"
 $env:RUST_LOG = $LogLevel
 
-Write-Host "SERVER_ADDR=$($env:SERVER_ADDR)  RUST_LOG=$($env:RUST_LOG)" -ForegroundColor Cyan
+$serverArgs = @("--public-addr", "${Address}:${Port}")
+if ($Config) { $serverArgs += @("--config", $Config) }
+$serverArgs += $Rest
+
+Write-Host "RUST_LOG=$($env:RUST_LOG)  server $($serverArgs -join ' ')" -ForegroundColor Cyan
 
 $exePaths = @(
   Join-Path $PSScriptRoot "server.exe",
@@ -20,9 +27,9 @@ $exe = $exePaths | Where-Object { Test-Path $_ } | Select-Object -First 1
 
 if ($exe) {
   Write-Host "Running executable: $exe" -ForegroundColor Green
-  & $exe
+  & $exe @serverArgs
 } else {
   Write-Host "Executable not found. Falling back to cargo run --release --bin server" -ForegroundColor Yellow
-  cargo run --release --bin server
+  cargo run --release --bin server -- @serverArgs
 }
 
@@ -0,0 +1,498 @@
+// ===== 接続トークンの発行（Secure モード） =====
+// クライアントに NETCODE_KEY を持たせないため、鍵はトークン発行サービス（src/bin/token-issuer.rs）
+// だけが持つ。クライアントは名前とパスワードを送り、署名済みの ConnectToken を受け取って接続する。
+//
+// HTTP: POST /token に JSON（TokenRequest）。成功は 200 でトークンのバイト列（ConnectToken::write の形）、
+// 失敗は 4xx/5xx で理由の本文。平文なのでループバックか TLS 終端の内側で公開する。
+// 要求は固定数のワーカーで捌き、ログイン失敗が続く IP/名前はしばらく 429 で断る（Argon2 を回さない）。
+//
+// ユーザーは TOML（users.toml）に保存する。パスワードは Argon2 のハッシュ（PHC 文字列）だけを持つ。
+// [[user]]
+// name = "alice"
+// account_id = 1
+// password = "$argon2id$v=19$..."
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use bevy::log::{info, warn};
+use renet::transport::ConnectToken;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, TrySendError};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::net::{ConnectUserData, PLAYER_NAME_MAX_CHARS, PROTOCOL_ID, SERVER_PASSWORD_MAX_BYTES};
+
+pub const DEFAULT_ISSUER_PORT: u16 = 7000;
+// 接続のハンドシェイクが終わるまでの猶予（接続後は関係ない）
+const TOKEN_TIMEOUT_SECS: i32 = 15;
+const HTTP_TIMEOUT: Duration = Duration::from_secs(5);
+const HTTP_BODY_MAX: usize = 4096;
+// ヘッダ込みの 1 要求の上限
+const HTTP_REQUEST_MAX: u64 = 16 * 1024;
+// 同時に Argon2 を回すのはこの数まで。あふれた接続は待たせずに閉じる
+const ISSUER_WORKERS: usize = 4;
+const ISSUER_QUEUE: usize = 32;
+// この期間にこの回数失敗した IP/名前は、期間が明けるまで断る
+const LOGIN_FAILURES_MAX: u32 = 5;
+const LOGIN_LOCKOUT: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UserEntry {
+    pub name: String,
+    pub account_id: u64,
+    pub password: String, // Argon2 の PHC 文字列
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UserStore {
+    #[serde(default, rename = "user")]
+    pub users: Vec<UserEntry>,
+}
+
+impl UserStore {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+        toml::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    // 無ければ空（add-user で最初の 1 人を作るとき）
+    pub fn load_or_default(path: impl AsRef<Path>) -> Result<Self, String> {
+        if path.as_ref().exists() {
+            Self::load(path)
+        } else {
+            Ok(Self::default())
+        }
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), String> {
+        let path = path.as_ref();
+        let text = toml::to_string(self).map_err(|e| e.to_string())?;
+        std::fs::write(path, text).map_err(|e| format!("cannot write {}: {}", path.display(), e))
+    }
+
+    // 追加またはパスワード変更。account_id は一度振ったら変えない
+    pub fn set_password(&mut self, name: &str, password: &str) -> Result<u64, String> {
+        validate_name(name)?;
+        if password.is_empty() {
+            return Err("password must not be empty".into());
+        }
+        let hash = hash_password(password)?;
+        if let Some(user) = self.users.iter_mut().find(|u| u.name == name) {
+            user.password = hash;
+            return Ok(user.account_id);
+        }
+        let account_id = self.users.iter().map(|u| u.account_id).max().unwrap_or(0) + 1;
+        self.users.push(UserEntry {
+            name: name.to_string(),
+            account_id,
+            password: hash,
+        });
+        Ok(account_id)
+    }
+
+    pub fn authenticate(&self, name: &str, password: &str) -> Option<&UserEntry> {
+        let user = self.users.iter().find(|u| u.name == name);
+        // 知らない名前でも同じだけ Argon2 を回す（応答時間でアカウントの有無が分からないように）
+        let phc = user.map_or(dummy_hash(), |u| &u.password);
+        let hash = PasswordHash::new(phc).ok()?;
+        Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .ok()?;
+        user
+    }
+}
+
+// 誰のパスワードでもないハッシュ。パラメータは本物と同じ
+fn dummy_hash() -> &'static str {
+    static DUMMY: OnceLock<String> = OnceLock::new();
+    DUMMY.get_or_init(|| {
+        hash_password(&format!("{:032x}", rand::random::<u128>())).expect("argon2 dummy hash")
+    })
+}
+
+fn validate_name(name: &str) -> Result<(), String> {
+    let n = name.chars().count();
+    if n == 0 || n > PLAYER_NAME_MAX_CHARS {
+        return Err(format!(
+            "name must be 1-{} characters",
+            PLAYER_NAME_MAX_CHARS
+        ));
+    }
+    if name.chars().any(|c| c.is_control() || c.is_whitespace()) {
+        return Err("name must not contain spaces or control characters".into());
+    }
+    Ok(())
+}
+
+pub fn hash_password(password: &str) -> Result<String, String> {
+    let salt = SaltString::encode_b64(&rand::random::<[u8; 16]>()).map_err(|e| e.to_string())?;
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|h| h.to_string())
+        .map_err(|e| e.to_string())
+}
+
+// クライアント → 発行サービス。version/caps/server_password はそのままトークンの user data に入る
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TokenRequest {
+    pub name: String,
+    pub password: String,
+    pub version: u32,
+    pub caps: u32,
+    #[serde(default)]
+    pub server_password: String,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum IssueError {
+    BadCredentials,
+    BadRequest(String),
+    Unavailable(String),
+}
+
+pub struct TokenIssuer {
+    pub key: [u8; 32],
+    // トークンに載せるゲームサーバの公開アドレス
+    pub server_addrs: Vec<SocketAddr>,
+    // リクエストごとに読み直すので、add-user は再起動なしで効く
+    pub users_path: PathBuf,
+    pub expire_secs: u64,
+}
+
+impl TokenIssuer {
+    pub fn issue(&self, req: &TokenRequest) -> Result<ConnectToken, IssueError> {
+        if req.server_password.len() > SERVER_PASSWORD_MAX_BYTES {
+            return Err(IssueError::BadRequest(format!(
+                "server password must be at most {} bytes",
+                SERVER_PASSWORD_MAX_BYTES
+            )));
+        }
+        let users = UserStore::load(&self.users_path).map_err(IssueError::Unavailable)?;
+        let Some(user) = users.authenticate(&req.name, &req.password) else {
+            return Err(IssueError::BadCredentials);
+        };
+        // 名前とアカウントはストアのもの（サーバはこれを信頼する）
+        let user_data = ConnectUserData {
+            version: req.version,
+            caps: req.caps,
+            account_id: user.account_id,
+            name: user.name.clone(),
+            password: req.server_password.clone(),
+        }
+        .to_bytes();
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap();
+        ConnectToken::generate(
+            now,
+            PROTOCOL_ID,
+            self.expire_secs,
+            rand::random::<u64>(),
+            TOKEN_TIMEOUT_SECS,
+            self.server_addrs.clone(),
+            Some(&user_data),
+            &self.key,
+        )
+        .map_err(|e| IssueError::Unavailable(e.to_string()))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum ThrottleKey {
+    Addr(IpAddr),
+    Name(String),
+}
+
+// ログイン失敗の記録（キー → 回数と最初の失敗の時刻）
+#[derive(Default)]
+struct LoginThrottle {
+    failures: Mutex<HashMap<ThrottleKey, (u32, Instant)>>,
+}
+
+impl LoginThrottle {
+    fn locked(&self, keys: &[ThrottleKey], now: Instant) -> bool {
+        let failures = self.failures.lock().unwrap();
+        keys.iter().any(|k| {
+            failures.get(k).is_some_and(|&(count, since)| {
+                count >= LOGIN_FAILURES_MAX && now.duration_since(since) < LOGIN_LOCKOUT
+            })
+        })
+    }
+
+    fn fail(&self, keys: &[ThrottleKey], now: Instant) {
+        let mut failures = self.failures.lock().unwrap();
+        failures.retain(|_, (_, since)| now.duration_since(*since) < LOGIN_LOCKOUT);
+        for k in keys {
+            failures.entry(k.clone()).or_insert((0, now)).0 += 1;
+        }
+    }
+
+    // 成功で消すのは名前だけ（IP は自分のアカウントで数を戻せないように）
+    fn succeed(&self, name: &str) {
+        let mut failures = self.failures.lock().unwrap();
+        failures.remove(&ThrottleKey::Name(name.to_string()));
+    }
+}
+
+// 読み込み全体の期限。1 バイトずつ送ってワーカーを握り続ける接続を切る
+struct Deadline {
+    stream: TcpStream,
+    until: Instant,
+}
+
+impl Read for Deadline {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let left = self.until.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Err(std::io::ErrorKind::TimedOut.into());
+        }
+        self.stream.set_read_timeout(Some(left))?;
+        self.stream.read(buf)
+    }
+}
+
+// 固定数のワーカーで捌く（Argon2 の検証が重いので、同時に回す数を抑える）
+pub fn serve_tokens(listener: TcpListener, issuer: Arc<TokenIssuer>) {
+    let throttle = Arc::new(LoginThrottle::default());
+    let (tx, rx) = mpsc::sync_channel::<TcpStream>(ISSUER_QUEUE);
+    let rx = Arc::new(Mutex::new(rx));
+    for _ in 0..ISSUER_WORKERS {
+        let (rx, issuer, throttle) = (rx.clone(), issuer.clone(), throttle.clone());
+        std::thread::spawn(move || loop {
+            let Ok(stream) = rx.lock().unwrap().recv() else {
+                return;
+            };
+            let peer = stream.peer_addr().ok();
+            if let Err(e) = serve_token_request(stream, &issuer, &throttle, peer) {
+                warn!("token issuer {:?}: {}", peer, e);
+            }
+        });
+    }
+    for stream in listener.incoming().flatten() {
+        if let Err(TrySendError::Full(stream)) = tx.try_send(stream) {
+            warn!("token issuer: busy, dropped {:?}", stream.peer_addr().ok());
+        }
+    }
+}
+
+fn serve_token_request(
+    stream: TcpStream,
+    issuer: &TokenIssuer,
+    throttle: &LoginThrottle,
+    peer: Option<SocketAddr>,
+) -> std::io::Result<()> {
+    let mut out = stream.try_clone()?;
+    let mut reader = BufReader::new(
+        Deadline {
+            stream,
+            until: Instant::now() + HTTP_TIMEOUT,
+        }
+        .take(HTTP_REQUEST_MAX),
+    );
+    let mut request = String::new();
+    reader.read_line(&mut request)?;
+    let mut content_length = 0usize;
+    let mut line = String::new();
+    while reader.read_line(&mut line)? > 0 && !line.trim().is_empty() {
+        if let Some((k, v)) = line.split_once(':') {
+            if k.trim().eq_ignore_ascii_case("content-length") {
+                content_length = v.trim().parse().unwrap_or(usize::MAX);
+            }
+        }
+        line.clear();
+    }
+    let mut parts = request.split_whitespace();
+    let (status, body): (&str, Vec<u8>) = match (parts.next(), parts.next()) {
+        (Some("POST"), Some("/token")) if content_length > HTTP_BODY_MAX => {
+            ("413 Payload Too Large", b"request too large\n".to_vec())
+        }
+        (Some("POST"), Some("/token")) => {
+            let mut buf = vec![0u8; content_length];
+            reader.read_exact(&mut buf)?;
+            let req = serde_json::from_slice::<TokenRequest>(&buf);
+            let keys: Vec<ThrottleKey> = match &req {
+                Ok(req) => peer
+                    .map(|p| ThrottleKey::Addr(p.ip()))
+                    .into_iter()
+                    .chain([ThrottleKey::Name(req.name.clone())])
+                    .collect(),
+                Err(_) => Vec::new(),
+            };
+            match req {
+                Err(e) => ("400 Bad Request", format!("{}\n", e).into_bytes()),
+                Ok(req) if throttle.locked(&keys, Instant::now()) => {
+                    warn!("throttled login for {:?} from {:?}", req.name, peer);
+                    (
+                        "429 Too Many Requests",
+                        b"too many attempts, try later\n".to_vec(),
+                    )
+                }
+                Ok(req) => match issuer.issue(&req) {
+                    Ok(token) => {
+                        throttle.succeed(&req.name);
+                        info!("issued token for {:?} to {:?}", req.name, peer);
+                        let mut bytes = Vec::new();
+                        token.write(&mut bytes)?;
+                        ("200 OK", bytes)
+                    }
+                    Err(IssueError::BadCredentials) => {
+                        throttle.fail(&keys, Instant::now());
+                        info!("rejected login for {:?} from {:?}", req.name, peer);
+                        ("401 Unauthorized", b"wrong name or password\n".to_vec())
+                    }
+                    Err(IssueError::BadRequest(e)) => {
+                        ("400 Bad Request", format!("{}\n", e).into_bytes())
+                    }
+                    Err(IssueError::Unavailable(e)) => {
+                        warn!("token issuer: {}", e);
+                        ("503 Service Unavailable", b"try again later\n".to_vec())
+                    }
+                },
+            }
+        }
+        (Some(_), Some("/token")) => ("405 Method Not Allowed", b"use POST\n".to_vec()),
+        _ => ("404 Not Found", b"not found\n".to_vec()),
+    };
+    let content_type = if status.starts_with("200") {
+        "application/octet-stream"
+    } else {
+        "text/plain; charset=utf-8"
+    };
+    write!(
+        out,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        content_type,
+        body.len()
+    )?;
+    out.write_all(&body)?;
+    out.flush()
+}
+
+// クライアント側。発行サービスから自分用のトークンを取る
+pub fn fetch_connect_token(
+    issuer: SocketAddr,
+    name: &str,
+    password: &str,
+    server_password: &str,
+) -> Result<ConnectToken, String> {
+    let hello = ConnectUserData::local();
+    let body = serde_json::to_vec(&TokenRequest {
+        name: name.to_string(),
+        password: password.to_string(),
+        version: hello.version,
+        caps: hello.caps,
+        server_password: server_password.to_string(),
+    })
+    .map_err(|e| e.to_string())?;
+    let mut stream = TcpStream::connect_timeout(&issuer, HTTP_TIMEOUT)
+        .map_err(|e| format!("cannot reach token issuer {}: {}", issuer, e))?;
+    stream
+        .set_read_timeout(Some(HTTP_TIMEOUT))
+        .map_err(|e| e.to_string())?;
+    write!(
+        stream,
+        "POST /token HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        issuer,
+        body.len()
+    )
+    .and_then(|_| stream.write_all(&body))
+    .map_err(|e| format!("token issuer {}: {}", issuer, e))?;
+    let mut response = Vec::new();
+    stream
+        .read_to_end(&mut response)
+        .map_err(|e| format!("token issuer {}: {}", issuer, e))?;
+    let split = response
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .ok_or_else(|| format!("token issuer {}: malformed response", issuer))?;
+    let head = String::from_utf8_lossy(&response[..split]);
+    let body = &response[split + 4..];
+    let status = head.split_whitespace().nth(1).unwrap_or("");
+    if status != "200" {
+        return Err(format!(
+            "token issuer {}: {}",
+            issuer,
+            String::from_utf8_lossy(body).trim()
+        ));
+    }
+    ConnectToken::read(&mut &body[..]).map_err(|e| format!("token issuer {}: {}", issuer, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    fn issuer_with(dir: &Path) -> TokenIssuer {
+        let users_path = dir.join("users.toml");
+        let mut users = UserStore::default();
+        assert_eq!(users.set_password("alice", "pw1").unwrap(), 1);
+        assert_eq!(users.set_password("bob", "pw2").unwrap(), 2);
+        assert_eq!(users.set_password("alice", "pw3").unwrap(), 1);
+        users.save(&users_path).unwrap();
+        TokenIssuer {
+            key: [7; 32],
+            server_addrs: vec!["127.0.0.1:5000".parse().unwrap()],
+            users_path,
+            expire_secs: 60,
+        }
+    }
+
+    #[test]
+    fn only_the_current_password_authenticates() {
+        let dir = TempDir::new("auth-store");
+        let issuer = issuer_with(dir.path());
+        let users = UserStore::load(&issuer.users_path).unwrap();
+        assert_eq!(users.authenticate("alice", "pw3").unwrap().account_id, 1);
+        assert!(users.authenticate("alice", "pw1").is_none());
+        assert!(users.authenticate("carol", "pw2").is_none());
+        assert!(UserStore::default().set_password("a b", "x").is_err());
+        assert!(!std::fs::read_to_string(&issuer.users_path)
+            .unwrap()
+            .contains("pw3"));
+    }
+
+    #[test]
+    fn repeated_failures_lock_out_until_the_window_passes() {
+        let throttle = LoginThrottle::default();
+        let t0 = Instant::now();
+        let addr = ThrottleKey::Addr([10, 0, 0, 1].into());
+        let keys = [addr.clone(), ThrottleKey::Name("alice".into())];
+        for _ in 0..LOGIN_FAILURES_MAX {
+            assert!(!throttle.locked(&keys, t0));
+            throttle.fail(&keys, t0);
+        }
+        assert!(throttle.locked(&keys, t0));
+        // 別の IP からでも名前で止まる。成功しても IP の記録は残る
+        assert!(throttle.locked(&[ThrottleKey::Name("alice".into())], t0));
+        throttle.succeed("alice");
+        assert!(throttle.locked(&[addr], t0));
+        assert!(!throttle.locked(&keys, t0 + LOGIN_LOCKOUT));
+    }
+
+    #[test]
+    fn tokens_are_issued_over_http() {
+        let dir = TempDir::new("auth-http");
+        let issuer = Arc::new(issuer_with(dir.path()));
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || serve_tokens(listener, issuer));
+
+        let token = fetch_connect_token(addr, "bob", "pw2", "").unwrap();
+        assert_eq!(token.protocol_id, PROTOCOL_ID);
+        assert_eq!(
+            token.server_addresses[0],
+            Some("127.0.0.1:5000".parse().unwrap())
+        );
+        let err = fetch_connect_token(addr, "bob", "nope", "").unwrap_err();
+        assert!(err.contains("wrong name or password"), "{}", err);
+    }
+}
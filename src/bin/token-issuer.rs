@@ -0,0 +1,131 @@
+use bevy_online_campus::auth::{serve_tokens, TokenIssuer, UserStore, DEFAULT_ISSUER_PORT};
+use bevy_online_campus::net::{read_key_file, read_netcode_key};
+use clap::{CommandFactory, Parser, Subcommand};
+use std::io::BufRead;
+use std::net::{Ipv4Addr, SocketAddr, TcpListener};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// Connect-token issuer for secure servers.
+///
+/// Holds the netcode private key so clients don't have to: a client sends an
+/// account name and password and gets back a signed connect token. Serve it
+/// on a loopback address (or behind a TLS proxy); passwords travel in plain text.
+#[derive(Parser, Debug)]
+#[command(name = "token-issuer", version, about)]
+struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Account store (TOML, created by add-user)
+    #[arg(
+        long,
+        env = "ISSUER_USERS",
+        value_name = "PATH",
+        default_value = "users.toml",
+        global = true
+    )]
+    users: PathBuf,
+
+    /// HTTP address to listen on [default: 127.0.0.1:7000]
+    #[arg(long, env = "ISSUER_BIND", value_name = "ADDR:PORT")]
+    bind: Option<SocketAddr>,
+
+    /// Game server address written into tokens (the server's --public-addr)
+    #[arg(long, env = "SERVER_ADDR", value_name = "ADDR:PORT")]
+    server_addr: Option<SocketAddr>,
+
+    /// Netcode private key: 32 raw bytes or 64 hex digits [fallback: NETCODE_KEY as hex]
+    #[arg(long, env = "NETCODE_KEY_FILE", value_name = "PATH")]
+    key_file: Option<PathBuf>,
+
+    /// Seconds a token stays valid before the client must connect
+    #[arg(long, env = "TOKEN_EXPIRE", default_value_t = 120)]
+    token_expire: u64,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Add an account or change its password (the password is read from stdin)
+    AddUser { name: String },
+}
+
+fn add_user(args: &Args, name: &str) -> Result<(), String> {
+    let mut users = UserStore::load_or_default(&args.users)?;
+    eprintln!("password for {}:", name);
+    let mut password = String::new();
+    std::io::stdin()
+        .lock()
+        .read_line(&mut password)
+        .map_err(|e| e.to_string())?;
+    let account_id = users.set_password(name, password.trim_end_matches(['\r', '\n']))?;
+    users.save(&args.users)?;
+    println!(
+        "{} (account {}) saved to {}",
+        name,
+        account_id,
+        args.users.display()
+    );
+    Ok(())
+}
+
+fn resolve(args: &Args) -> Result<(TcpListener, TokenIssuer), String> {
+    let server_addr = args
+        .server_addr
+        .ok_or("--server-addr (or SERVER_ADDR) is required")?;
+    let key = match &args.key_file {
+        Some(path) => read_key_file(path).map_err(|e| format!("--key-file: {}", e))?,
+        None => read_netcode_key()?,
+    };
+    // 起動時に一度読んで、壊れたファイルならここで止める
+    let users = UserStore::load(&args.users).map_err(|e| format!("--users: {}", e))?;
+    if users.users.is_empty() {
+        eprintln!(
+            "warning: {} has no accounts (use add-user)",
+            args.users.display()
+        );
+    }
+    let bind = args
+        .bind
+        .unwrap_or_else(|| SocketAddr::from((Ipv4Addr::LOCALHOST, DEFAULT_ISSUER_PORT)));
+    let listener =
+        TcpListener::bind(bind).map_err(|e| format!("--bind: cannot bind {}: {}", bind, e))?;
+    Ok((
+        listener,
+        TokenIssuer {
+            key,
+            server_addrs: vec![server_addr],
+            users_path: args.users.clone(),
+            expire_secs: args.token_expire,
+        },
+    ))
+}
+
+fn main() {
+    let args = Args::parse();
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            tracing_subscriber::EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info")),
+        )
+        .init();
+    if let Some(Command::AddUser { name }) = &args.command {
+        if let Err(e) = add_user(&args, name) {
+            eprintln!("add-user: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    let (listener, issuer) = resolve(&args).unwrap_or_else(|e| {
+        Args::command()
+            .error(clap::error::ErrorKind::ValueValidation, e)
+            .exit()
+    });
+    if let Ok(addr) = listener.local_addr() {
+        println!(
+            "token issuer listening on http://{} (tokens for {})",
+            addr, issuer.server_addrs[0]
+        );
+    }
+    serve_tokens(listener, Arc::new(issuer));
+}
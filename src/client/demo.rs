@@ -0,0 +1,436 @@
+// ===== デモ再生 =====
+// サーバの --record-demo で録ったファイルを、プロセス内の RenetServer からループバックで流し込む。
+// 受信側は通常の net_recv_snapshot / net_recv_events がそのまま処理する（サーバ接続・入力送信はしない）。
+// 操作: Space 一時停止 / [ ] 5 秒戻る・進む / Home 先頭へ / - = 速度半分・倍 / WASD・E・Q 自由カメラ（Ctrl で速く）
+use super::*;
+use crate::demo::{DemoFrame, DemoHeader};
+use bevy_renet::renet::{ClientId, RenetServer};
+
+// 録画には載らない ID（スナップショットに自分として現れない）
+const DEMO_VIEWER_ID: u64 = 0;
+const DEMO_SEEK_STEP_SEC: f64 = 5.0;
+const DEMO_SPEED_MIN: f64 = 0.125;
+const DEMO_SPEED_MAX: f64 = 8.0;
+const FREE_CAM_SPEED: f32 = 10.0;
+const FREE_CAM_FAST_MUL: f32 = 4.0;
+
+#[derive(Resource)]
+pub struct DemoPlayback {
+    header: DemoHeader,
+    frames: Vec<DemoFrame>,
+    next: usize, // 次に流すフレーム
+    clock: f64,  // 再生位置（秒、先頭フレームが 0）
+    speed: f64,
+    paused: bool,
+    catch_up_from: Option<usize>, // シーク後、ここから next の手前までを早送りで流す
+    server: RenetServer,
+}
+
+impl DemoPlayback {
+    pub fn new(header: DemoHeader, frames: Vec<DemoFrame>) -> Self {
+        Self {
+            header,
+            frames,
+            next: 0,
+            clock: 0.0,
+            speed: 1.0,
+            paused: false,
+            catch_up_from: None,
+            server: loopback_server(),
+        }
+    }
+
+    fn frame_time(&self, i: usize) -> f64 {
+        let (Some(first), Some(f)) = (self.frames.first(), self.frames.get(i)) else {
+            return 0.0;
+        };
+        f.tick.wrapping_sub(first.tick) as f64 / self.header.tick_hz
+    }
+
+    fn duration(&self) -> f64 {
+        self.frame_time(self.frames.len().saturating_sub(1))
+    }
+
+    // secs までに流し終えているべきフレーム数
+    fn index_at(&self, secs: f64) -> usize {
+        self.frames.partition_point(|f| {
+            f.tick.wrapping_sub(self.frames[0].tick) as f64 / self.header.tick_hz <= secs
+        })
+    }
+
+    // 再生位置を移す。巻き戻しなら true（受信済みの状態を捨ててから先頭から流し直す）
+    fn seek(&mut self, secs: f64) -> bool {
+        let secs = secs.clamp(0.0, self.duration());
+        let target = self.index_at(secs);
+        let rewind = target < self.next;
+        self.catch_up_from = Some(if rewind { 0 } else { self.next });
+        self.next = target;
+        self.clock = secs;
+        rewind
+    }
+}
+
+fn loopback_server() -> RenetServer {
+    let mut server = RenetServer::new(connection_config());
+    server.add_connection(ClientId::from_raw(DEMO_VIEWER_ID));
+    server
+}
+
+fn loopback_client() -> RenetClient {
+    let mut client = RenetClient::new(connection_config());
+    client.set_connected();
+    client
+}
+
+// シークで飛ばすフレーム [from, to) のうち、流し直す必要のあるメッセージ
+// 状態はスナップショットが持つので、to の手前の最後のキーフレーム以降だけ流す。
+// イベントは後に残るもの（足場・マップ・ラウンド・名前）だけ。スコアとルールは最新の 1 つで足りる
+fn catch_up_messages(frames: &[DemoFrame], from: usize, to: usize) -> Vec<ServerMessage> {
+    let keyframe = (from..to)
+        .rev()
+        .find(|i| frames[*i].is_keyframe())
+        .unwrap_or(from);
+    let mut out = Vec::new();
+    let mut score = None;
+    let mut rules = None;
+    for (i, frame) in frames.iter().enumerate().take(to).skip(from) {
+        for msg in &frame.messages {
+            match msg {
+                ServerMessage::Snapshot(_) | ServerMessage::DeltaSnapshot(_) => {
+                    if i >= keyframe {
+                        out.push(msg.clone());
+                    }
+                }
+                ServerMessage::Score(_) => score = Some(msg.clone()),
+                ServerMessage::Rules(_) => rules = Some(msg.clone()),
+                ServerMessage::Event(
+                    EventMsg::ScaffoldSpawn { .. }
+                    | EventMsg::ScaffoldDespawn { .. }
+                    | EventMsg::MapChange { .. }
+                    | EventMsg::RoundStart { .. }
+                    | EventMsg::RoundEnd { .. }
+                    | EventMsg::PlayerInfo { .. },
+                ) => out.push(msg.clone()),
+                _ => {}
+            }
+        }
+    }
+    out.extend(rules);
+    out.extend(score);
+    out
+}
+
+#[derive(Component)]
+struct UiDemoStatus;
+
+// ClientPlugins に含まれる。DemoPlayback が insert されていなければ何もしない
+pub struct ClientDemoPlugin;
+
+impl Plugin for ClientDemoPlugin {
+    fn build(&self, app: &mut App) {
+        let playing = resource_exists::<DemoPlayback>;
+        app.add_systems(PreStartup, setup_demo_client.run_if(playing))
+            .add_systems(
+                Startup,
+                setup_demo_viewer.after(setup_player).run_if(playing),
+            )
+            .add_systems(PreUpdate, demo_pump.run_if(playing))
+            .add_systems(
+                Update,
+                (
+                    demo_controls
+                        .before(net_recv_snapshot)
+                        .before(net_recv_events),
+                    demo_free_camera
+                        .after(mouse_look_system)
+                        .after(keyboard_look_system),
+                    demo_status_text,
+                )
+                    .run_if(playing),
+            );
+    }
+}
+
+// setup_net_client は RenetClient があれば netcode で接続しない
+fn setup_demo_client(mut commands: Commands, demo: Res<DemoPlayback>) {
+    commands.insert_resource(loopback_client());
+    commands.insert_resource(LocalNetInfo { id: DEMO_VIEWER_ID });
+    info!(
+        "demo: {} frames ({:.0}s @ {} Hz), map {:?}",
+        demo.frames.len(),
+        demo.duration(),
+        demo.header.tick_hz,
+        demo.header.map
+    );
+}
+
+// 自機は当たり判定を外して自由カメラにする（KCC が無ければ予測移動も止まる）
+fn setup_demo_viewer(mut commands: Commands, player_q: Query<Entity, With<Player>>) {
+    if let Ok(e) = player_q.get_single() {
+        commands
+            .entity(e)
+            .remove::<(KinematicCharacterController, Collider)>();
+    }
+    commands.spawn((
+        TextBundle::from_section(
+            "",
+            TextStyle {
+                font_size: 22.0,
+                color: Color::BLACK,
+                ..default()
+            },
+        )
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            left: Val::Percent(35.0),
+            bottom: Val::Px(10.0),
+            ..default()
+        }),
+        UiDemoStatus,
+    ));
+}
+
+// 再生位置まで録画を流し、ループバックの RenetServer と RenetClient の間でパケットを受け渡す
+fn demo_pump(time: Res<Time>, mut demo: ResMut<DemoPlayback>, mut client: ResMut<RenetClient>) {
+    let demo = &mut *demo;
+    let cid = ClientId::from_raw(DEMO_VIEWER_ID);
+    let mut outgoing = Vec::new();
+    if let Some(from) = demo.catch_up_from.take() {
+        outgoing = catch_up_messages(&demo.frames, from, demo.next);
+    }
+    if !demo.paused {
+        demo.clock = (demo.clock + time.delta_seconds_f64() * demo.speed).min(demo.duration());
+    }
+    while demo.next < demo.frames.len() && demo.frame_time(demo.next) <= demo.clock {
+        outgoing.extend(demo.frames[demo.next].messages.iter().cloned());
+        demo.next += 1;
+    }
+    for msg in &outgoing {
+        let channel = match msg {
+            ServerMessage::Snapshot(_) | ServerMessage::DeltaSnapshot(_) => CH_SNAPSHOT,
+            _ => CH_RELIABLE,
+        };
+        if let Ok(bytes) = bincode::serialize(msg) {
+            demo.server.send_message(cid, channel, bytes);
+        }
+    }
+    demo.server.update(time.delta());
+    if let Ok(packets) = demo.server.get_packets_to_send(cid) {
+        for p in packets {
+            client.process_packet(&p);
+        }
+    }
+    for p in client.get_packets_to_send() {
+        let _ = demo.server.process_packet_from(&p, cid);
+    }
+    // 受信側が返す ACK などは使わない
+    for channel in [CH_INPUT, CH_SNAPSHOT, CH_RELIABLE] {
+        while demo.server.receive_message(cid, channel).is_some() {}
+    }
+}
+
+// 巻き戻し時に捨てる受信済みの状態（送受信途中のメッセージも接続ごと捨てる）
+#[derive(SystemParam)]
+struct DemoReceived<'w, 's> {
+    client: ResMut<'w, RenetClient>,
+    remap: ResMut<'w, RemoteMap>,
+    rhist: ResMut<'w, RemoteHistory>,
+    last_tick: ResMut<'w, LastSnapshotTick>,
+    baselines: ResMut<'w, SnapshotBaselines>,
+    scaffolds: ResMut<'w, NetScaffoldMap>,
+    killlog: Query<'w, 's, Entity, With<UiKillEntry>>,
+}
+
+impl DemoReceived<'_, '_> {
+    fn clear(&mut self, commands: &mut Commands, demo: &mut DemoPlayback) {
+        *self.client = loopback_client();
+        demo.server = loopback_server();
+        for (_, e) in self.remap.0.drain() {
+            commands.entity(e).despawn_recursive();
+        }
+        for (_, e) in self.scaffolds.0.drain() {
+            commands.entity(e).despawn_recursive();
+        }
+        for e in self.killlog.iter() {
+            commands.entity(e).despawn_recursive();
+        }
+        self.rhist.0.clear();
+        self.last_tick.0 = None;
+        self.baselines.0.clear();
+    }
+}
+
+fn demo_controls(
+    mut commands: Commands,
+    keys: Res<ButtonInput<KeyCode>>,
+    mut demo: ResMut<DemoPlayback>,
+    mut received: DemoReceived,
+) {
+    if keys.just_pressed(KeyCode::Space) {
+        demo.paused = !demo.paused;
+    }
+    if keys.just_pressed(KeyCode::Minus) {
+        demo.speed = (demo.speed * 0.5).max(DEMO_SPEED_MIN);
+    }
+    if keys.just_pressed(KeyCode::Equal) {
+        demo.speed = (demo.speed * 2.0).min(DEMO_SPEED_MAX);
+    }
+    let target = if keys.just_pressed(KeyCode::Home) {
+        Some(0.0)
+    } else if keys.just_pressed(KeyCode::BracketLeft) {
+        Some(demo.clock - DEMO_SEEK_STEP_SEC)
+    } else if keys.just_pressed(KeyCode::BracketRight) {
+        Some(demo.clock + DEMO_SEEK_STEP_SEC)
+    } else {
+        None
+    };
+    if let Some(secs) = target {
+        if demo.seek(secs) {
+            received.clear(&mut commands, &mut demo);
+        }
+    }
+}
+
+// 視線方向へ飛ぶ（当たり判定なし）
+fn demo_free_camera(
+    time: Res<Time>,
+    keys: Res<ButtonInput<KeyCode>>,
+    mut player_q: Query<&mut Transform, With<Player>>,
+    cam_q: Query<&PlayerCamera>,
+) {
+    let (Ok(mut tf), Ok(cam)) = (player_q.get_single_mut(), cam_q.get_single()) else {
+        return;
+    };
+    let axis =
+        |pos: KeyCode, neg: KeyCode| (keys.pressed(pos) as i32 - keys.pressed(neg) as i32) as f32;
+    let local = Vec3::new(
+        axis(KeyCode::KeyD, KeyCode::KeyA),
+        axis(KeyCode::KeyE, KeyCode::KeyQ),
+        axis(KeyCode::KeyS, KeyCode::KeyW),
+    );
+    if local == Vec3::ZERO {
+        return;
+    }
+    let look = Quat::from_rotation_y(cam.yaw) * Quat::from_rotation_x(cam.pitch);
+    let mut speed = FREE_CAM_SPEED;
+    if keys.pressed(KeyCode::ControlLeft) || keys.pressed(KeyCode::ControlRight) {
+        speed *= FREE_CAM_FAST_MUL;
+    }
+    tf.translation += look * local.normalize() * speed * time.delta_seconds();
+}
+
+fn demo_status_text(demo: Res<DemoPlayback>, mut q: Query<&mut Text, With<UiDemoStatus>>) {
+    let Ok(mut t) = q.get_single_mut() else {
+        return;
+    };
+    let mmss = |secs: f64| format!("{:02}:{:02}", secs as u32 / 60, secs as u32 % 60);
+    t.sections[0].value = format!(
+        "DEMO {} / {}  x{}{}",
+        mmss(demo.clock),
+        mmss(demo.duration()),
+        demo.speed,
+        if demo.paused { "  (paused)" } else { "" }
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot_frame(tick: u32, keyframe: bool, events: Vec<EventMsg>) -> DemoFrame {
+        let first = if keyframe {
+            ServerMessage::Snapshot(SnapshotMsg {
+                tick,
+                players: vec![],
+                acks: vec![],
+            })
+        } else {
+            ServerMessage::DeltaSnapshot(DeltaSnapshotMsg {
+                tick,
+                base_tick: tick - 1,
+                changed: vec![],
+                removed: vec![],
+                acks: vec![],
+            })
+        };
+        let mut messages = vec![first];
+        messages.extend(events.into_iter().map(ServerMessage::Event));
+        DemoFrame { tick, messages }
+    }
+
+    #[test]
+    fn catch_up_starts_at_the_last_keyframe_and_keeps_lasting_events() {
+        let frames = vec![
+            snapshot_frame(
+                1,
+                true,
+                vec![EventMsg::ScaffoldSpawn {
+                    sid: 1,
+                    owner: 2,
+                    pos: [0.0; 3],
+                }],
+            ),
+            snapshot_frame(
+                2,
+                false,
+                vec![EventMsg::Death {
+                    target_id: 3,
+                    by: 2,
+                }],
+            ),
+            snapshot_frame(3, true, vec![]),
+            snapshot_frame(4, false, vec![]),
+            snapshot_frame(5, false, vec![]),
+        ];
+        let msgs = catch_up_messages(&frames, 0, 4);
+        let ticks: Vec<u32> = msgs
+            .iter()
+            .filter_map(|m| match m {
+                ServerMessage::Snapshot(s) => Some(s.tick),
+                ServerMessage::DeltaSnapshot(d) => Some(d.tick),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(ticks, [3, 4]);
+        assert!(matches!(
+            msgs[0],
+            ServerMessage::Event(EventMsg::ScaffoldSpawn { sid: 1, .. })
+        ));
+        assert!(!msgs
+            .iter()
+            .any(|m| matches!(m, ServerMessage::Event(EventMsg::Death { .. }))));
+
+        // 前方へのシークで範囲にキーフレームが無ければ、続きの差分をそのまま流す
+        let msgs = catch_up_messages(&frames, 3, 5);
+        assert_eq!(msgs.len(), 2);
+        assert!(msgs
+            .iter()
+            .all(|m| matches!(m, ServerMessage::DeltaSnapshot(_))));
+    }
+
+    #[test]
+    fn seeking_back_rewinds_to_the_start() {
+        let header = DemoHeader {
+            protocol_version: PROTOCOL_VERSION,
+            map: None,
+            config: String::new(),
+            tick_hz: 10.0,
+            started_unix_ms: 0,
+        };
+        let frames = (1..=100)
+            .map(|t| snapshot_frame(t, t % 10 == 1, vec![]))
+            .collect();
+        let mut demo = DemoPlayback::new(header, frames);
+        assert!((demo.duration() - 9.9).abs() < 1e-9);
+        assert!(!demo.seek(5.0));
+        assert_eq!(demo.next, 51);
+        assert_eq!(demo.catch_up_from, Some(0));
+        demo.catch_up_from = None;
+        assert!(demo.seek(2.0));
+        assert_eq!(demo.next, 21);
+        assert_eq!(demo.catch_up_from, Some(0));
+        assert!(!demo.seek(100.0));
+        assert_eq!(demo.next, 100);
+        assert_eq!(demo.catch_up_from, Some(21));
+    }
+}
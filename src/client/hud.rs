@@ -0,0 +1,410 @@
+// ===== HUD / VFX =====
+// 表示専用。ゲーム状態（LocalHealth, LocalAmmo, ScoreData など）は net 側のシステムが更新する
+use super::*;
+
+pub struct ClientHudPlugin;
+
+impl Plugin for ClientHudPlugin {
+    fn build(&self, app: &mut App) {
+        if !app.is_plugin_added::<FrameTimeDiagnosticsPlugin>() {
+            app.add_plugins(FrameTimeDiagnosticsPlugin);
+        }
+        app.insert_resource(FpsTextTimer(Timer::from_seconds(0.5, TimerMode::Repeating)))
+            .add_systems(Startup, (setup_ui, setup_hud))
+            .add_systems(Update, hud_update_hp)
+            .add_systems(Update, hud_tick_hit_marker)
+            .add_systems(Update, hud_tick_killlog)
+            .add_systems(Update, hud_update_ammo)
+            .add_systems(Update, hud_update_net_status)
+            .add_systems(Update, fps_update_system)
+            .add_systems(Update, vfx_tick_and_cleanup);
+    }
+}
+
+#[derive(Component)]
+struct UiHp;
+
+#[derive(Component)]
+struct UiFps;
+
+#[derive(Resource)]
+struct FpsTextTimer(Timer);
+
+#[derive(Component)]
+pub(super) struct UiHitMarker {
+    pub(super) timer: Timer,
+}
+
+#[derive(Component)]
+pub(super) struct UiKillLog;
+
+#[derive(Component)]
+pub(super) struct UiKillEntry {
+    pub(super) timer: Timer,
+}
+
+#[derive(Component)]
+pub(super) struct UiScoreboard;
+
+#[derive(Component)]
+struct UiNetStatus;
+
+// VFX components
+#[derive(Component)]
+pub(super) struct MuzzleFx {
+    pub(super) timer: Timer,
+}
+
+#[derive(Component)]
+pub(super) struct TracerFx {
+    pub(super) timer: Timer,
+}
+
+#[derive(Component)]
+pub(super) struct ImpactFx {
+    pub(super) timer: Timer,
+}
+
+#[derive(Component)]
+pub(super) struct UiDamageVignette {
+    pub(super) timer: Timer,
+}
+
+#[derive(Component)]
+struct UiRoundText;
+
+#[derive(Component)]
+struct UiAmmo;
+
+fn setup_ui(mut commands: Commands) {
+    // 画面中央に簡易クロスヘア
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                width: Val::Percent(100.0),
+                height: Val::Percent(100.0),
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..default()
+            },
+            background_color: BackgroundColor(Color::NONE),
+            ..default()
+        })
+        .with_children(|parent| {
+            parent.spawn(TextBundle::from_section(
+                "+",
+                TextStyle {
+                    font_size: 28.0,
+                    color: Color::BLACK,
+                    ..default()
+                },
+            ));
+        });
+}
+
+fn setup_hud(mut commands: Commands) {
+    // HP表示（左下）
+    commands.spawn((
+        TextBundle::from_section(
+            "HP: 100",
+            TextStyle {
+                font_size: 36.0,
+                color: Color::BLACK,
+                ..default()
+            },
+        )
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            left: Val::Px(10.0),
+            bottom: Val::Px(10.0),
+            ..default()
+        }),
+        UiHp,
+    ));
+
+    // FPS表示（左上）
+    commands.spawn((
+        TextBundle::from_section(
+            "FPS: --",
+            TextStyle {
+                font_size: 18.0,
+                color: Color::BLACK,
+                ..default()
+            },
+        )
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            left: Val::Px(10.0),
+            top: Val::Px(10.0),
+            ..default()
+        }),
+        UiFps,
+    ));
+
+    // ヒットマーカー（中心に薄いX、初期は透過）
+    commands.spawn((
+        TextBundle {
+            text: Text::from_section(
+                "x",
+                TextStyle {
+                    font_size: 40.0,
+                    color: Color::srgba(0.0, 0.0, 0.0, 0.0),
+                    ..default()
+                },
+            ),
+            style: Style {
+                position_type: PositionType::Absolute,
+                left: Val::Percent(50.0),
+                top: Val::Percent(50.0),
+                ..default()
+            },
+            ..default()
+        },
+        UiHitMarker {
+            timer: Timer::from_seconds(0.0, TimerMode::Once),
+        },
+    ));
+
+    // キルログ（右上、縦積み）
+    commands.spawn((
+        NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                right: Val::Px(10.0),
+                top: Val::Px(10.0),
+                flex_direction: FlexDirection::Column,
+                row_gap: Val::Px(4.0),
+                ..default()
+            },
+            background_color: BackgroundColor(Color::NONE),
+            ..default()
+        },
+        UiKillLog,
+    ));
+
+    // スコアボード（中央上、非表示）
+    commands.spawn((
+        NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                left: Val::Percent(50.0),
+                top: Val::Px(40.0),
+                flex_direction: FlexDirection::Column,
+                row_gap: Val::Px(2.0),
+                ..default()
+            },
+            background_color: BackgroundColor(Color::NONE),
+            visibility: Visibility::Hidden,
+            ..default()
+        },
+        UiScoreboard,
+    ));
+
+    // ラウンド表示（中央上部、常時）
+    commands.spawn((
+        TextBundle::from_section(
+            "",
+            TextStyle {
+                font_size: 32.0,
+                color: Color::BLACK,
+                ..default()
+            },
+        )
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            left: Val::Percent(50.0),
+            top: Val::Px(12.0),
+            ..default()
+        }),
+        UiRoundText,
+    ));
+
+    // 弾数（右下）
+    commands.spawn((
+        TextBundle::from_section(
+            "Ammo: 0",
+            TextStyle {
+                font_size: 28.0,
+                color: Color::BLACK,
+                ..default()
+            },
+        )
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            right: Val::Px(10.0),
+            bottom: Val::Px(10.0),
+            ..default()
+        }),
+        UiAmmo,
+    ));
+
+    // 接続エラー表示（中央、通常は空）
+    commands.spawn((
+        TextBundle::from_section(
+            "",
+            TextStyle {
+                font_size: 28.0,
+                color: Color::srgb(0.8, 0.0, 0.0),
+                ..default()
+            },
+        )
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            left: Val::Percent(30.0),
+            top: Val::Percent(40.0),
+            ..default()
+        }),
+        UiNetStatus,
+    ));
+}
+
+fn round_ui_tick(
+    time: Res<Time>,
+    mut ui: ResMut<RoundUi>,
+    names: Res<PlayerNames>,
+    mut q: Query<&mut Text, With<UiRoundText>>,
+) {
+    if let Ok(mut t) = q.get_single_mut() {
+        if let Some(timer) = ui.phase_end.as_mut() {
+            timer.tick(time.delta());
+            let remain = (timer.duration().as_secs_f32() - timer.elapsed_secs()).max(0.0);
+            t.sections[0].value = format!(
+                "Round End{}  Next: {:.0}s",
+                ui.winner
+                    .map(|w| format!("  Winner {}", names.display(w)))
+                    .unwrap_or_default(),
+                remain
+            );
+        } else {
+            ui.time_left = (ui.time_left - time.delta_seconds()).max(0.0);
+            let m = (ui.time_left as i32 / 60).max(0);
+            let s = (ui.time_left as i32 % 60).max(0);
+            t.sections[0].value = format!("Time {:02}:{:02}", m, s);
+        }
+    }
+}
+
+// ===== HUD Systems =====
+fn hud_update_hp(mut q: Query<&mut Text, With<UiHp>>, hp: Res<LocalHealth>) {
+    if let Ok(mut t) = q.get_single_mut() {
+        t.sections[0].value = format!("HP: {}", hp.hp);
+        t.sections[0].style.color = Color::BLACK;
+    }
+}
+
+fn hud_tick_hit_marker(time: Res<Time>, mut q: Query<(&mut UiHitMarker, &mut Text)>) {
+    if let Ok((mut hm, mut text)) = q.get_single_mut() {
+        hm.timer.tick(time.delta());
+        let d = hm.timer.duration().as_secs_f32();
+        let alpha = if d <= 0.0 {
+            0.0
+        } else {
+            (d - hm.timer.elapsed_secs()).max(0.0) / d
+        };
+        text.sections[0].style.color = Color::srgba(0.0, 0.0, 0.0, alpha.clamp(0.0, 1.0));
+    }
+}
+
+fn hud_tick_killlog(
+    time: Res<Time>,
+    mut commands: Commands,
+    mut q: Query<(Entity, &mut UiKillEntry, &mut Text)>,
+) {
+    for (e, mut entry, mut text) in &mut q {
+        entry.timer.tick(time.delta());
+        let d = entry.timer.duration().as_secs_f32().max(0.0001);
+        let remain = (d - entry.timer.elapsed_secs()).max(0.0) / d;
+        text.sections[0].style.color = Color::srgba(1.0, 1.0, 1.0, remain.clamp(0.0, 1.0));
+        if entry.timer.finished() {
+            commands.entity(e).despawn_recursive();
+        }
+    }
+}
+
+fn hud_update_ammo(mut q: Query<&mut Text, With<UiAmmo>>, ammo: Res<LocalAmmo>) {
+    if let Ok(mut t) = q.get_single_mut() {
+        if ammo.reloading {
+            t.sections[0].value = format!("Reloading...");
+        } else {
+            t.sections[0].value = format!("Ammo: {}", ammo.ammo);
+        }
+    }
+}
+
+fn hud_update_net_status(
+    mut q: Query<&mut Text, With<UiNetStatus>>,
+    handshake: Res<NetHandshake>,
+) {
+    if !handshake.is_changed() {
+        return;
+    }
+    if let Ok(mut t) = q.get_single_mut() {
+        t.sections[0].value = match &handshake.rejected {
+            Some(reason) => format!("Connection rejected by server:\n{}", reason),
+            None => String::new(),
+        };
+    }
+}
+
+fn fps_update_system(
+    time: Res<Time>,
+    diagnostics: Res<DiagnosticsStore>,
+    mut timer: ResMut<FpsTextTimer>,
+    mut q: Query<&mut Text, With<UiFps>>,
+) {
+    timer.0.tick(time.delta());
+    if !timer.0.finished() {
+        return;
+    }
+
+    if let Ok(mut t) = q.get_single_mut() {
+        if let Some(fps) = diagnostics.get(&FrameTimeDiagnosticsPlugin::FPS) {
+            if let Some(avg) = fps.smoothed() {
+                t.sections[0].value = format!("FPS: {:.0}", avg);
+            } else if let Some(val) = fps.value() {
+                t.sections[0].value = format!("FPS: {:.0}", val);
+            }
+        }
+    }
+}
+
+// --- VFX tickers ---
+fn vfx_tick_and_cleanup(
+    time: Res<Time>,
+    mut commands: Commands,
+    mut q_muzzle: Query<(Entity, &mut MuzzleFx)>,
+    mut q_tracer: Query<(Entity, &mut TracerFx)>,
+    mut q_impact: Query<(Entity, &mut ImpactFx)>,
+    mut q_vign: Query<(Entity, &mut UiDamageVignette)>,
+    mut bg_colors: Query<&mut BackgroundColor>,
+) {
+    for (e, mut fx) in &mut q_muzzle {
+        fx.timer.tick(time.delta());
+        if fx.timer.finished() {
+            commands.entity(e).despawn_recursive();
+        }
+    }
+    for (e, mut fx) in &mut q_tracer {
+        fx.timer.tick(time.delta());
+        if fx.timer.finished() {
+            commands.entity(e).despawn_recursive();
+        }
+    }
+    for (e, mut fx) in &mut q_impact {
+        fx.timer.tick(time.delta());
+        if fx.timer.finished() {
+            commands.entity(e).despawn_recursive();
+        }
+    }
+    for (e, mut v) in &mut q_vign {
+        v.timer.tick(time.delta());
+        if let Ok(mut col) = bg_colors.get_mut(e) {
+            let t =
+                (1.0 - (v.timer.elapsed_secs() / v.timer.duration().as_secs_f32())).clamp(0.0, 1.0);
+            col.0 = Color::rgba(0.8, 0.0, 0.0, 0.35 * t);
+        }
+        if v.timer.finished() {
+            commands.entity(e).despawn_recursive();
+        }
+    }
+}
@@ -0,0 +1,1969 @@
+use bevy::app::PluginGroupBuilder;
+use bevy::diagnostic::{DiagnosticsStore, FrameTimeDiagnosticsPlugin};
+use bevy::ecs::system::SystemParam;
+use bevy::input::mouse::MouseMotion;
+use bevy::prelude::*;
+use bevy::render::camera::Projection;
+use bevy::window::CursorGrabMode;
+use bevy::window::WindowFocused;
+use bevy_rapier3d::prelude::*;
+use bevy_rapier3d::render::DebugRenderContext;
+use bevy_renet::renet::transport::ConnectToken;
+use bevy_renet::renet::RenetClient;
+use bevy_renet::transport::NetcodeClientPlugin;
+use bevy_renet::RenetClientPlugin;
+use std::f32::consts::PI;
+use std::time::Duration;
+
+use crate::movement::{self, MoveParams, MoveState};
+use crate::net::shared as shared_consts;
+use crate::net::*;
+
+mod demo;
+mod hud;
+
+pub use demo::{ClientDemoPlugin, DemoPlayback};
+use hud::*;
+
+pub use hud::ClientHudPlugin;
+
+// ===== Config =====
+const MAP_SCENE_PATH: &str = "maps/map.glb#Scene0"; // assets 配下に maps/map.glb を置いてください
+const PLAYER_START: Vec3 = Vec3::from_array(shared_consts::PLAYER_START);
+const MOUSE_SENSITIVITY: f32 = 0.0018; // rad/pixel
+const HIP_FOV: f32 = 90.0_f32.to_radians();
+const ADS_FOV: f32 = 65.0_f32.to_radians();
+const ADS_SENS_MUL: f32 = 0.6; // ADS中のマウス感度倍率
+const KEY_LOOK_SPEED: f32 = 2.2; // rad/s for arrow-key look
+                                 // Position reconciliation thresholds (light server convergence)
+const POS_DEADBAND: f32 = shared_consts::POS_DEADBAND; // meters: small jitterを無視して安定化
+const POS_SNAP: f32 = 0.8; // meters: 乖離が大きい時のみスナップ
+
+const PREDICTION_DT: f32 = 1.0 / 60.0;
+#[inline]
+fn wrap_pi(a: f32) -> f32 {
+    (a + PI).rem_euclid(2.0 * PI) - PI
+}
+
+#[derive(Resource, Default)]
+struct ScoreData(Vec<(u64, u32, u32)>); // (id, kills, deaths)
+
+// id -> 表示名（PlayerInfo で届く）。まだ届いていない ID は数字のまま出す
+#[derive(Resource, Default)]
+struct PlayerNames(std::collections::HashMap<u64, String>);
+
+impl PlayerNames {
+    fn display(&self, id: u64) -> String {
+        self.0.get(&id).cloned().unwrap_or_else(|| id.to_string())
+    }
+}
+
+#[derive(Resource, Default)]
+struct ScoreVisible(bool);
+
+#[derive(Resource, Default)]
+struct RoundUi {
+    phase_end: Option<Timer>,
+    time_left: f32,
+    winner: Option<u64>,
+}
+
+#[derive(Resource, Default)]
+struct LocalAmmo {
+    ammo: u16,
+    reloading: bool,
+}
+
+#[derive(Resource, Default)]
+struct LocalWeaponState {
+    fire_cd: f32,
+}
+
+// サーバから届いたルール値（Rules 受信まではプロトコル既定値）
+#[derive(Resource, Default)]
+struct ServerRules(RulesMsg);
+
+// サーバとのハンドシェイク結果（Welcome/Reject）
+#[derive(Resource, Default)]
+struct NetHandshake {
+    server_version: Option<u32>,
+    caps: u32,
+    rejected: Option<String>,
+}
+
+#[derive(Resource, Default)]
+struct ActorKindsMap(std::collections::HashMap<u64, ActorKind>);
+
+#[derive(Resource, Default)]
+struct ActorPositions(std::collections::HashMap<u64, Vec3>);
+
+#[derive(Component)]
+struct Player;
+
+#[derive(Component)]
+struct PlayerCamera {
+    yaw: f32,
+    pitch: f32,
+}
+
+// 見た目弾は廃止（サーバ権威のヒットスキャンに統一）
+
+#[derive(Resource, Default)]
+struct CursorLocked(pub bool);
+
+#[derive(Component, Default)]
+struct Controller {
+    // サーバと同じ movement::step で更新する縦速度・接地・ジャンプタイマ
+    motion: MoveState,
+}
+
+#[derive(Resource, Default)]
+struct MapReady(pub bool);
+
+// 読み込んだマップシーンのルートと、そのパス（サーバの MapChange で差し替える）
+#[derive(Component)]
+struct MapRoot;
+
+#[derive(Resource)]
+struct CurrentMap(String);
+
+#[derive(SystemParam)]
+struct ClientMap<'w, 's> {
+    current: ResMut<'w, CurrentMap>,
+    ready: ResMut<'w, MapReady>,
+    asset_server: Res<'w, AssetServer>,
+    roots: Query<'w, 's, Entity, With<MapRoot>>,
+}
+
+// ===== Scaffold (temporary platform) =====
+const SCAFFOLD_SIZE: Vec3 = Vec3::new(2.0, 0.5, 2.0); // WxHxD (meters)
+const SCAFFOLD_RANGE: f32 = 5.0; // meters
+const SCAFFOLD_HP: i32 = 150;
+
+#[derive(Component)]
+struct Scaffold {
+    hp: i32,
+    owner: u64,
+}
+
+#[derive(Resource, Default)]
+struct LocalScaffolds(Vec<Entity>); // FIFO 管理（ローカルプレイヤー用）
+
+// ネット同期された足場（サーバ権威）
+#[derive(Component)]
+struct NetScaffold {
+    sid: u64,
+}
+
+#[derive(Resource, Default)]
+struct NetScaffoldMap(std::collections::HashMap<u64, Entity>);
+
+// サーバ確定前のローカル可視用ゴースト足場
+#[derive(Component)]
+struct GhostScaffold;
+
+#[derive(Resource, Default)]
+struct LocalGhostScaffold(Option<Entity>);
+
+// net_recv_events が更新する HUD 系リソース
+#[derive(SystemParam)]
+struct HudRes<'w> {
+    score_data: ResMut<'w, ScoreData>,
+    round_ui: ResMut<'w, RoundUi>,
+    local_ammo: ResMut<'w, LocalAmmo>,
+    handshake: ResMut<'w, NetHandshake>,
+    rules: ResMut<'w, ServerRules>,
+    names: ResMut<'w, PlayerNames>,
+}
+
+// 発砲間隔の管理（クールダウンはサーバのルール値に合わせる）
+#[derive(SystemParam)]
+struct LocalFire<'w> {
+    weapon: ResMut<'w, LocalWeaponState>,
+    rules: Res<'w, ServerRules>,
+}
+
+#[derive(SystemParam)]
+struct NetScaffoldAssets<'w, 's> {
+    meshes: ResMut<'w, Assets<Mesh>>,
+    materials: ResMut<'w, Assets<StandardMaterial>>,
+    map: ResMut<'w, NetScaffoldMap>,
+    _marker: std::marker::PhantomData<&'s ()>,
+}
+
+// クライアント一式。ウィンドウ設定と物理（RapierPhysicsPlugin）は呼び出し側で追加する
+// デモ再生は DemoPlayback を insert したときだけ動く
+pub struct ClientPlugins;
+
+impl PluginGroup for ClientPlugins {
+    fn build(self) -> PluginGroupBuilder {
+        PluginGroupBuilder::start::<Self>()
+            .add(ClientWorldPlugin)
+            .add(ClientPlayerPlugin)
+            .add(ClientNetPlugin)
+            .add(ClientHudPlugin)
+            .add(ClientDemoPlugin)
+    }
+}
+
+// マップ・ライト・物理設定
+pub struct ClientWorldPlugin;
+
+impl Plugin for ClientWorldPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(ClearColor(Color::srgb(0.02, 0.02, 0.03)))
+            .insert_resource(AmbientLight {
+                color: Color::WHITE,
+                brightness: 300.0,
+            })
+            .insert_resource(MapReady(false))
+            .insert_resource(CurrentMap(MAP_SCENE_PATH.to_string()))
+            .insert_resource(DebugRenderContext {
+                enabled: matches!(
+                    std::env::var("DEBUG_COLLIDERS").ok().as_deref(),
+                    Some("1" | "true" | "TRUE")
+                ),
+                ..Default::default()
+            })
+            .add_systems(Startup, (setup_world, setup_physics))
+            .add_systems(Update, toggle_debug_colliders)
+            .add_systems(Update, add_mesh_colliders_for_map);
+    }
+}
+
+// ローカルプレイヤーの操作（視点・移動予測・足場設置）
+pub struct ClientPlayerPlugin;
+
+impl Plugin for ClientPlayerPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(CursorLocked(true))
+            .init_resource::<LocalScaffolds>()
+            .init_resource::<LocalGhostScaffold>()
+            .add_systems(Startup, setup_player)
+            .add_systems(Update, handle_focus_events)
+            .add_systems(Update, cursor_lock_controls)
+            .add_systems(Update, mouse_look_system)
+            .add_systems(Update, ads_zoom_system)
+            .add_systems(Update, keyboard_look_system)
+            .add_systems(Update, kcc_move_system_unified.after(net_send_input))
+            .add_systems(Update, kcc_post_step_system.after(kcc_move_system_unified))
+            .add_systems(
+                Update,
+                client_airborne_snap_control.after(kcc_post_step_system),
+            )
+            .add_systems(
+                Update,
+                scaffold_input_system.run_if(not(resource_exists::<DemoPlayback>)),
+            );
+    }
+}
+
+// renet クライアント、入力送信、スナップショット/イベント受信と自機の補正
+pub struct ClientNetPlugin;
+
+impl Plugin for ClientNetPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins((RenetClientPlugin, NetcodeClientPlugin))
+            .init_resource::<ConnStatePrev>()
+            .init_resource::<ActorKindsMap>()
+            .init_resource::<ActorPositions>()
+            .init_resource::<NetScaffoldMap>()
+            .add_systems(Startup, setup_net_client)
+            .add_systems(Update, net_log_connection)
+            .add_systems(Update, net_recv_snapshot)
+            .add_systems(Update, remote_interpolate_system.after(net_recv_snapshot))
+            .add_systems(
+                Update,
+                net_send_input
+                    .run_if(not(resource_exists::<DemoPlayback>))
+                    .after(mouse_look_system)
+                    .after(keyboard_look_system)
+                    .after(cursor_lock_controls)
+                    .after(handle_focus_events)
+                    .after(net_recv_snapshot),
+            )
+            .add_systems(Update, net_recv_events)
+            .add_systems(
+                Update,
+                reconcile_self
+                    .after(net_recv_snapshot)
+                    .after(net_recv_events)
+                    .before(PhysicsSet::StepSimulation),
+            );
+    }
+}
+
+fn setup_world(mut commands: Commands, asset_server: Res<AssetServer>) {
+    // マップのGLBシーンをロード
+    commands.spawn((
+        SceneBundle {
+            scene: asset_server.load(MAP_SCENE_PATH),
+            transform: Transform::from_xyz(0.0, 0.0, 0.0),
+            ..default()
+        },
+        MapRoot,
+    ));
+
+    // 環境光は Resource で設定済み。補助の方向ライトを追加
+    commands.spawn((DirectionalLightBundle {
+        directional_light: DirectionalLight {
+            shadows_enabled: !matches!(
+                std::env::var("LOW_GFX").ok().as_deref(),
+                Some("1" | "true" | "TRUE")
+            ),
+            illuminance: 30_000.0,
+            ..default()
+        },
+        transform: Transform::from_xyz(10.0, 12.0, 8.0).looking_at(Vec3::ZERO, Vec3::Y),
+        ..default()
+    },));
+}
+
+fn setup_physics(mut conf: ResMut<RapierConfiguration>) {
+    conf.gravity = Vec3::new(0.0, -shared_consts::GRAVITY, 0.0);
+}
+
+fn setup_player(mut commands: Commands, mut windows: Query<&mut Window>) {
+    // プレイヤー本体（位置と yaw を持つ）
+    let player = commands
+        .spawn((
+            Player,
+            SpatialBundle {
+                transform: Transform::from_translation(PLAYER_START),
+                ..default()
+            },
+        ))
+        // キャラクターコントローラと当たり判定（カプセル）
+        .insert({
+            let mut kcc = KinematicCharacterController::default();
+            // サーバと同一のオートステップ/スナップに合わせる
+            kcc.autostep = Some(CharacterAutostep {
+                max_height: CharacterLength::Absolute(0.5),
+                min_width: CharacterLength::Absolute(0.3),
+                include_dynamic_bodies: true,
+            });
+            kcc.snap_to_ground = Some(CharacterLength::Absolute(0.25));
+            (Collider::capsule_y(0.6, 0.3), kcc, Controller::default())
+        })
+        .id();
+
+    // カメラはプレイヤーの子: pitch はカメラにのみ反映
+    // 目線の高さ（プレイヤー中心から +0.7m）
+    let cam = Camera3dBundle {
+        transform: Transform::from_xyz(0.0, 0.7, 0.0),
+        camera: Camera {
+            hdr: !matches!(
+                std::env::var("LOW_GFX").ok().as_deref(),
+                Some("1" | "true" | "TRUE")
+            ),
+            ..default()
+        },
+        ..default()
+    };
+
+    commands.entity(player).with_children(|p| {
+        p.spawn((
+            cam,
+            PlayerCamera {
+                yaw: 0.0,
+                pitch: 0.0,
+            },
+        ));
+    });
+
+    // カーソルをロック
+    if let Ok(mut win) = windows.get_single_mut() {
+        win.cursor.visible = false;
+        win.cursor.grab_mode = CursorGrabMode::Locked;
+    }
+}
+
+fn cursor_lock_controls(
+    keys: Res<ButtonInput<KeyCode>>,
+    buttons: Res<ButtonInput<MouseButton>>,
+    mut win_q: Query<&mut Window>,
+    mut locked: ResMut<CursorLocked>,
+) {
+    let mut win = if let Ok(w) = win_q.get_single_mut() {
+        w
+    } else {
+        return;
+    };
+
+    // Esc で解除、左クリックで再ロック
+    if keys.just_pressed(KeyCode::Escape) {
+        locked.0 = false;
+    }
+    if buttons.just_pressed(MouseButton::Left) {
+        locked.0 = true;
+    }
+
+    match locked.0 {
+        true => {
+            win.cursor.visible = false;
+            win.cursor.grab_mode = CursorGrabMode::Locked;
+        }
+        false => {
+            win.cursor.visible = true;
+            win.cursor.grab_mode = CursorGrabMode::None;
+        }
+    }
+}
+
+fn mouse_look_system(
+    mut mouse_evr: EventReader<MouseMotion>,
+    locked: Res<CursorLocked>,
+    keys: Res<ButtonInput<KeyCode>>,
+    buttons: Res<ButtonInput<MouseButton>>,
+    mut q: ParamSet<(
+        Query<&mut Transform, (With<Player>, Without<Camera3d>)>,
+        Query<(&mut Transform, &mut PlayerCamera), With<Camera3d>>,
+    )>,
+) {
+    if !locked.0 {
+        mouse_evr.clear();
+        return;
+    }
+
+    let mut delta = Vec2::ZERO;
+    for ev in mouse_evr.read() {
+        delta += ev.delta;
+    }
+    // 微小ノイズ（トラックパッド等）を無視するデッドゾーン
+    if delta.length_squared() < 0.04 {
+        // ~0.2px 相当
+        return;
+    }
+
+    // まずカメラ側で yaw/pitch を更新し、必要値をローカルに保持
+    let mut new_yaw: f32 = 0.0;
+    let mut new_pitch: f32 = 0.0;
+    {
+        let mut cam_query = q.p1();
+        let Ok((mut cam_tf, mut pcam)) = cam_query.get_single_mut() else {
+            return;
+        };
+        let ads_key = keys.pressed(KeyCode::ShiftLeft) || keys.pressed(KeyCode::ShiftRight);
+        let sens_mul = if buttons.pressed(MouseButton::Right) || ads_key {
+            ADS_SENS_MUL
+        } else {
+            1.0
+        };
+        let sens = MOUSE_SENSITIVITY * sens_mul;
+        pcam.yaw = wrap_pi(pcam.yaw - delta.x * sens);
+        pcam.pitch = (pcam.pitch - delta.y * sens).clamp(-1.54, 1.54);
+        new_yaw = pcam.yaw;
+        new_pitch = pcam.pitch;
+        cam_tf.rotation = Quat::from_rotation_x(pcam.pitch);
+    }
+
+    // 次にプレイヤーの yaw 回転を反映（別スコープで別クエリを借用）
+    let mut player_query = q.p0();
+    if let Ok(mut player_tf) = player_query.get_single_mut() {
+        player_tf.rotation = Quat::from_rotation_y(new_yaw);
+    }
+}
+
+// フォーカス喪失時に入力をクリアして「押しっぱなし」状態を解消
+fn handle_focus_events(
+    mut focused_events: EventReader<WindowFocused>,
+    mut keys: ResMut<ButtonInput<KeyCode>>,
+    mut buttons: ResMut<ButtonInput<MouseButton>>,
+    mut mouse_evr: EventReader<MouseMotion>,
+    mut locked: ResMut<CursorLocked>,
+    mut win_q: Query<&mut Window>,
+) {
+    for ev in focused_events.read() {
+        if !ev.focused {
+            // 入力状態をリセット
+            keys.clear();
+            buttons.clear();
+            mouse_evr.clear();
+            // カーソルを解放
+            locked.0 = false;
+            if let Ok(mut w) = win_q.get_single_mut() {
+                w.cursor.visible = true;
+                w.cursor.grab_mode = CursorGrabMode::None;
+            }
+        }
+    }
+}
+
+fn keyboard_look_system(
+    time: Res<Time>,
+    keys: Res<ButtonInput<KeyCode>>,
+    mut q: ParamSet<(
+        Query<&mut Transform, (With<Player>, Without<Camera3d>)>,
+        Query<(&mut Transform, &mut PlayerCamera), With<Camera3d>>,
+    )>,
+) {
+    let dt = time.delta_seconds();
+    let horiz =
+        (keys.pressed(KeyCode::ArrowRight) as i32 - keys.pressed(KeyCode::ArrowLeft) as i32) as f32;
+    let vert =
+        (keys.pressed(KeyCode::ArrowDown) as i32 - keys.pressed(KeyCode::ArrowUp) as i32) as f32;
+    if horiz == 0.0 && vert == 0.0 {
+        return;
+    }
+
+    let mut new_yaw = 0.0f32;
+    let mut new_pitch = 0.0f32;
+    {
+        let mut cam_query = q.p1();
+        let Ok((mut cam_tf, mut pcam)) = cam_query.get_single_mut() else {
+            return;
+        };
+        // 矢印キー: 右=右回転（マウスの正方向と同じく yaw を減算）、上=上向き（pitch 減算）
+        pcam.yaw = wrap_pi(pcam.yaw - horiz * KEY_LOOK_SPEED * dt);
+        pcam.pitch = (pcam.pitch - vert * KEY_LOOK_SPEED * dt).clamp(-1.54, 1.54);
+        new_yaw = pcam.yaw;
+        new_pitch = pcam.pitch;
+        cam_tf.rotation = Quat::from_rotation_x(new_pitch);
+    }
+    let mut player_query = q.p0();
+    if let Ok(mut player_tf) = player_query.get_single_mut() {
+        player_tf.rotation = Quat::from_rotation_y(new_yaw);
+    }
+}
+
+fn toggle_debug_colliders(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut ctx: ResMut<DebugRenderContext>,
+) {
+    if keys.just_pressed(KeyCode::F3) {
+        ctx.enabled = !ctx.enabled;
+        info!(
+            target: "debug",
+            "rapier collider debug render {}",
+            if ctx.enabled { "enabled" } else { "disabled" }
+        );
+    }
+}
+
+fn kcc_post_step_system(
+    mut q: Query<(&mut Controller, Option<&KinematicCharacterControllerOutput>), With<Player>>,
+    mut qk: Query<&mut KinematicCharacterController, With<Player>>,
+) {
+    let (mut ctrl, output) = if let Ok(v) = q.get_single_mut() {
+        v
+    } else {
+        return;
+    };
+    let mut shallow_debug: Option<(f32, Vec<(Entity, Vec3)>)> = None;
+    if let Some(out) = output {
+        let mut grounded_by_floor = false;
+        if out.grounded && ctrl.motion.vy <= 0.0 {
+            let prev_vy = ctrl.motion.vy;
+            let mut shallow_contacts = Vec::new();
+            for col in &out.collisions {
+                // 壁など浅い法線で接地扱いになったケースを記録
+                if let Some(details) = col.hit.details {
+                    let normal: Vec3 = details.normal1.into();
+                    if normal.y >= shared_consts::GROUND_NORMAL_MIN_Y {
+                        grounded_by_floor = true;
+                    } else {
+                        shallow_contacts.push((col.entity, normal));
+                    }
+                }
+            }
+            if !grounded_by_floor && !shallow_contacts.is_empty() {
+                shallow_debug = Some((prev_vy, shallow_contacts));
+            }
+        }
+        ctrl.motion.land(grounded_by_floor);
+    }
+    // ジャンプフレームで無効化した snap_to_ground を復帰
+    if let Ok(mut kcc) = qk.get_single_mut() {
+        if let Some((prev_vy, shallow_contacts)) = shallow_debug.take() {
+            let normals: Vec<String> = shallow_contacts
+                .iter()
+                .map(|(entity, normal)| {
+                    format!(
+                        "{:?}:({:.2},{:.2},{:.2})",
+                        entity, normal.x, normal.y, normal.z
+                    )
+                })
+                .collect();
+            info!(
+                target: "kcc::grounding",
+                "grounded via shallow normal prev_vy={:.3} snap_to_ground={} normals={:?}",
+                prev_vy,
+                kcc.snap_to_ground.is_some(),
+                normals,
+            );
+            // 浅い法線で接地扱いになっているのでスナップを強制解除
+            kcc.snap_to_ground = None;
+        } else if ctrl.motion.grounded {
+            if kcc.snap_to_ground.is_none() {
+                kcc.snap_to_ground = Some(CharacterLength::Absolute(0.25));
+            }
+        } else {
+            kcc.snap_to_ground = None;
+        }
+    }
+}
+
+// サーバ実装と整合したローカルKCC移動（ジャンプ品質を統一）
+fn kcc_move_system_unified(
+    mut q: Query<(&mut KinematicCharacterController, &mut Controller), With<Player>>,
+    ready: Res<MapReady>,
+    mut pending: ResMut<PendingPredictionFrames>,
+    rules: Res<ServerRules>,
+) {
+    if !ready.0 {
+        pending.0.clear();
+        return;
+    }
+    let (mut kcc, mut ctrl) = if let Ok(pair) = q.get_single_mut() {
+        pair
+    } else {
+        pending.0.clear();
+        return;
+    };
+    if pending.0.is_empty() {
+        kcc.translation = Some(Vec3::ZERO);
+        return;
+    }
+
+    let params = MoveParams::from_rules(&rules.0);
+    let mut total_motion = Vec3::ZERO;
+    let mut disable_snap = false;
+    while let Some(frame) = pending.0.pop_front() {
+        let out = movement::step(&mut ctrl.motion, &frame, &params);
+        total_motion += out.motion;
+        disable_snap |= out.jumped;
+    }
+
+    if disable_snap {
+        kcc.snap_to_ground = None;
+    }
+    kcc.translation = Some(total_motion);
+}
+
+// 空中時は地面スナップを無効化して「地上にワープ」する補正を防ぐ。
+fn client_airborne_snap_control(
+    mut qk: Query<&mut KinematicCharacterController, With<Player>>,
+    q: Query<&Controller, With<Player>>,
+) {
+    let ctrl = if let Ok(c) = q.get_single() {
+        c
+    } else {
+        return;
+    };
+    if let Ok(mut kcc) = qk.get_single_mut() {
+        if ctrl.motion.grounded {
+            if kcc.snap_to_ground.is_none() {
+                kcc.snap_to_ground = Some(CharacterLength::Absolute(0.25));
+            }
+        } else {
+            kcc.snap_to_ground = None;
+        }
+    }
+}
+
+/*
+fn shoot_system(
+    buttons: Res<ButtonInput<MouseButton>>,
+    keys: Res<ButtonInput<KeyCode>>,
+    cam_global_q: Query<&GlobalTransform, With<Camera3d>>,
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    ammo: Res<LocalAmmo>,
+) {
+    if !(buttons.just_pressed(MouseButton::Left) || keys.just_pressed(KeyCode::KeyF)) {
+        return;
+    }
+    // リロード中や弾0のときは見た目の弾は出さない（サーバー権威の判定は継続）
+    if ammo.reloading || ammo.ammo == 0 {
+        return;
+    }
+    let cam_g = if let Ok(v) = cam_global_q.get_single() { v } else { return };
+
+    let forward: Vec3 = cam_g.forward().into();
+    let start = cam_g.translation();
+
+    // 小さな弾体（可視化用）
+    let mesh = meshes.add(Sphere::new(0.04).mesh().ico(4).unwrap());
+    let mat = materials.add(Color::srgb(1.0, 0.9, 0.2));
+
+    commands.spawn((
+        PbrBundle {
+            mesh,
+            material: mat,
+            transform: Transform::from_translation(start),
+            ..default()
+        },
+        Bullet {
+            dir: forward,
+            speed: BULLET_SPEED,
+            life: Timer::from_seconds(BULLET_LIFETIME, TimerMode::Once),
+        },
+    ));
+
+    // 砲口フラッシュっぽいライト（短命）
+    commands.spawn((
+        PointLightBundle {
+            point_light: PointLight { intensity: 500.0, range: 4.0, ..default() },
+            transform: Transform::from_translation(start + forward * 0.1),
+            ..default()
+        },
+        Bullet { dir: Vec3::ZERO, speed: 0.0, life: Timer::from_seconds(0.06, TimerMode::Once) },
+    ));
+}
+
+fn bullet_move_and_despawn(
+    time: Res<Time>,
+    mut commands: Commands,
+    mut bullets: Query<(Entity, &mut Transform, &mut Bullet)>,
+) {
+    for (e, mut tf, mut b) in &mut bullets {
+        b.life.tick(time.delta());
+        if b.life.finished() {
+            commands.entity(e).despawn_recursive();
+            continue;
+        }
+        if b.speed > 0.0 {
+            tf.translation += b.dir * b.speed * time.delta_seconds();
+        }
+    }
+}
+
+*/
+
+// GLBのメッシュに静的コライダーを自動付与
+fn add_mesh_colliders_for_map(
+    mut commands: Commands,
+    meshes: Res<Assets<Mesh>>,
+    mut ready: ResMut<MapReady>,
+    q: Query<(Entity, &Handle<Mesh>), (Added<Handle<Mesh>>, Without<Collider>, Without<Player>)>,
+) {
+    let mut any_inserted = false;
+    for (e, h) in &q {
+        if let Some(mesh) = meshes.get(h) {
+            if let Some(collider) = Collider::from_bevy_mesh(mesh, &ComputedColliderShape::TriMesh)
+            {
+                commands.entity(e).insert((collider, RigidBody::Fixed));
+                any_inserted = true;
+            }
+        }
+    }
+    if any_inserted && !ready.0 {
+        ready.0 = true;
+        info!("Map colliders ready (client)");
+    }
+}
+
+#[derive(Resource, Default)]
+struct ConnStatePrev {
+    connected: bool,
+}
+
+fn net_log_connection(mut prev: ResMut<ConnStatePrev>, mut client: ResMut<RenetClient>) {
+    let is_conn = client.is_connected();
+    if is_conn != prev.connected {
+        if is_conn {
+            info!("Client connected to server");
+        } else {
+            info!("Client not connected; attempting handshake to 127.0.0.1:5000");
+        }
+        prev.connected = is_conn;
+    }
+}
+
+// --- Networking (client) ---
+
+#[derive(Component)]
+struct RemoteAvatar {
+    id: u64,
+}
+
+#[derive(Resource, Default)]
+struct RemoteMap(std::collections::HashMap<u64, Entity>);
+
+#[derive(Resource)]
+struct LocalNetInfo {
+    id: u64,
+}
+
+// トークン発行サービスから取得した接続トークン（起動前に main が入れる）
+#[derive(Resource)]
+pub struct IssuedToken(pub ConnectToken);
+
+// 接続時に名乗る表示名（トークンで接続するときはトークン側の名前が使われる）
+#[derive(Resource)]
+pub struct LocalPlayerName(pub String);
+
+// パスワード付きサーバへの接続用（トークンで接続するときはトークン側に入る）
+#[derive(Resource)]
+pub struct ServerPassword(pub String);
+
+#[derive(Resource, Default)]
+struct InputSeq(u32);
+
+#[derive(Resource, Default)]
+struct AuthoritativeSelf {
+    pos: Option<Vec3>,
+    yaw: Option<f32>,
+    vy: Option<f32>,
+    grounded: Option<bool>,
+}
+
+#[derive(Resource)]
+struct LocalHealth {
+    hp: u16,
+}
+
+// 入力再適用のための最小バッファ/ACK（将来の拡張に備えた土台）
+#[derive(Resource, Default)]
+struct InputBuffer(std::collections::VecDeque<InputFrame>);
+#[derive(Resource, Default)]
+struct PredictionAccumulator {
+    remaining: f32,
+    pending_jump: bool,
+}
+
+#[derive(Resource, Default)]
+struct PendingPredictionFrames(std::collections::VecDeque<InputFrame>);
+
+#[derive(Resource, Default)]
+struct LastConfirmedSeq(Option<u32>);
+
+// 受信スナップショットの最新tickを保持（古いスナップ適用を防ぐ）
+#[derive(Resource, Default)]
+struct LastSnapshotTick(Option<u32>);
+
+// 差分スナップショット復元用の基準履歴（tick, 全アクター）
+#[derive(Resource, Default)]
+struct SnapshotBaselines(std::collections::VecDeque<(u32, Vec<PlayerStateMsg>)>);
+
+const SNAPSHOT_BASELINE_KEEP: usize = 64; // サーバ側の履歴(32)より長めに保持
+
+// リモートアバター補間用のサンプル
+#[derive(Clone, Copy)]
+struct RemoteSample {
+    tick: u32,
+    pos: Vec3,
+    yaw: f32,
+}
+
+// エンティティIDごとのサンプル履歴
+#[derive(Resource, Default)]
+struct RemoteHistory(std::collections::HashMap<u64, std::collections::VecDeque<RemoteSample>>);
+
+// レンダ遅延（tick数）。到着ジッタ平滑化のため既定3tick（~100ms@30Hz）
+#[derive(Resource)]
+struct InterpDelayTicks(u32);
+
+// 直近のローカル発砲記録（VFX重複抑止用）
+#[derive(Resource, Default)]
+struct RecentLocalFires(std::collections::VecDeque<(f32, Vec3, Vec3)>); // (time, origin, dir)
+
+// RenetClient が既にあれば（デモ再生）netcode で接続しない
+fn setup_net_client(
+    mut commands: Commands,
+    existing: Option<Res<RenetClient>>,
+    token: Option<Res<IssuedToken>>,
+    name: Option<Res<LocalPlayerName>>,
+    password: Option<Res<ServerPassword>>,
+) {
+    if existing.is_none() {
+        let name = name.map(|n| n.0.clone()).unwrap_or_default();
+        let password = password.map(|p| p.0.clone()).unwrap_or_default();
+        let (client, transport, client_id) =
+            new_client(None, token.map(|t| t.0.clone()), &name, &password);
+        commands.insert_resource(client);
+        commands.insert_resource(transport);
+        commands.insert_resource(LocalNetInfo {
+            id: client_id.raw(),
+        });
+    }
+    commands.insert_resource(RemoteMap::default());
+    commands.insert_resource(InputSeq::default());
+    commands.insert_resource(PredictionAccumulator::default());
+    commands.insert_resource(PendingPredictionFrames::default());
+    commands.insert_resource(AuthoritativeSelf::default());
+    commands.insert_resource(LocalHealth { hp: 100 });
+    commands.insert_resource(ScoreData::default());
+    commands.insert_resource(PlayerNames::default());
+    commands.insert_resource(ScoreVisible::default());
+    commands.insert_resource(RoundUi::default());
+    commands.insert_resource(LocalAmmo {
+        ammo: 0,
+        reloading: false,
+    });
+    commands.insert_resource(LocalWeaponState::default());
+    commands.insert_resource(NetHandshake::default());
+    commands.insert_resource(ServerRules::default());
+    commands.insert_resource(InputBuffer::default());
+    commands.insert_resource(LastConfirmedSeq::default());
+    commands.insert_resource(LastSnapshotTick::default());
+    commands.insert_resource(SnapshotBaselines::default());
+    commands.insert_resource(RemoteHistory::default());
+    commands.insert_resource(InterpDelayTicks(3));
+    commands.insert_resource(RecentLocalFires::default());
+}
+
+// ===== Scaffold Systems =====
+fn scaffold_input_system(
+    keys: Res<ButtonInput<KeyCode>>,
+    cam_q: Query<&GlobalTransform, With<Camera3d>>,
+    player_q: Query<Entity, With<Player>>,
+    rapier: Res<RapierContext>,
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    local_id: Res<LocalNetInfo>,
+    mut owned: ResMut<LocalScaffolds>,
+    mut client: ResMut<RenetClient>,
+    mut ghost: ResMut<LocalGhostScaffold>,
+    rules: Res<ServerRules>,
+) {
+    if !keys.just_pressed(KeyCode::KeyQ) {
+        return;
+    }
+
+    // 接続中はローカル生成せず、サーバへ生成要求のみ送る
+    if client.is_connected() {
+        // クライアント単体時と同じロジックでヒット点を出し、最終配置座標を送る
+        let cam_g = if let Ok(v) = cam_q.get_single() {
+            v
+        } else {
+            return;
+        };
+        let player_ent = if let Ok(e) = player_q.get_single() {
+            e
+        } else {
+            return;
+        };
+        let origin = cam_g.translation();
+        let dir: Vec3 = cam_g.forward().into();
+        if dir.length_squared() < 1e-6 {
+            return;
+        }
+        let mut hit_pos = origin + dir * SCAFFOLD_RANGE;
+        if let Some((_entity, toi)) = rapier.cast_ray(
+            origin,
+            dir,
+            SCAFFOLD_RANGE,
+            true,
+            QueryFilter::default()
+                .exclude_collider(player_ent)
+                .exclude_sensors(),
+        ) {
+            hit_pos = origin + dir * toi;
+        }
+        let place_pos = hit_pos + Vec3::Y * (SCAFFOLD_SIZE.y * 0.5 + 0.01);
+        if let Ok(bytes) = bincode::serialize(&ClientMessage::PlaceScaffold {
+            pos: [place_pos.x, place_pos.y, place_pos.z],
+        }) {
+            let _ = client.send_message(CH_RELIABLE, bytes);
+        }
+        // 視覚フィードバック用のゴースト足場（コライダー無し）を即時表示
+        // 既存のゴーストがあれば消す
+        if let Some(e) = ghost.0.take() {
+            commands.entity(e).despawn_recursive();
+        }
+        let mesh = meshes.add(Cuboid::new(
+            SCAFFOLD_SIZE.x,
+            SCAFFOLD_SIZE.y,
+            SCAFFOLD_SIZE.z,
+        ));
+        let col = Color::srgba(0.2, 0.9, 1.0, 0.25);
+        let mat = materials.add(StandardMaterial {
+            base_color: col,
+            emissive: Color::srgb(0.2, 0.6, 0.9).into(),
+            alpha_mode: AlphaMode::Blend,
+            unlit: true,
+            ..default()
+        });
+        let ent = commands
+            .spawn((
+                PbrBundle {
+                    mesh,
+                    material: mat,
+                    transform: Transform::from_translation(place_pos),
+                    ..default()
+                },
+                GhostScaffold,
+            ))
+            .id();
+        ghost.0 = Some(ent);
+        return;
+    }
+    let cam_g = if let Ok(v) = cam_q.get_single() {
+        v
+    } else {
+        return;
+    };
+    let player_ent = if let Ok(e) = player_q.get_single() {
+        e
+    } else {
+        return;
+    };
+
+    let origin = cam_g.translation();
+    let dir: Vec3 = cam_g.forward().into();
+
+    let mut hit_pos = origin + dir * SCAFFOLD_RANGE;
+    if let Some((entity, toi)) = rapier.cast_ray(
+        origin,
+        dir,
+        SCAFFOLD_RANGE,
+        true,
+        QueryFilter::default()
+            .exclude_collider(player_ent)
+            .exclude_sensors(),
+    ) {
+        let _ = entity; // 現状は未使用
+        hit_pos = origin + dir * toi;
+    }
+
+    // 常に水平（Y+ up）で配置。床の場合は僅かに浮かせてZファイティング回避
+    let place_pos = hit_pos + Vec3::Y * (SCAFFOLD_SIZE.y * 0.5 + 0.01);
+
+    // 上限（サーバのルール）を超えたら一番古いものを消す
+    if owned.0.len() >= rules.0.scaffold_limit as usize {
+        if let Some(old) = owned.0.first().copied() {
+            commands.entity(old).despawn_recursive();
+        }
+        owned.0.remove(0);
+    }
+
+    let mesh = meshes.add(Cuboid::new(
+        SCAFFOLD_SIZE.x,
+        SCAFFOLD_SIZE.y,
+        SCAFFOLD_SIZE.z,
+    ));
+    let col = Color::srgba(0.2, 0.9, 1.0, 0.45);
+    let mat = materials.add(StandardMaterial {
+        base_color: col,
+        emissive: Color::srgb(0.3, 0.8, 1.0).into(),
+        alpha_mode: AlphaMode::Blend,
+        unlit: true,
+        ..default()
+    });
+
+    let ent = commands
+        .spawn((
+            PbrBundle {
+                mesh,
+                material: mat,
+                transform: Transform::from_translation(place_pos),
+                ..default()
+            },
+            Scaffold {
+                hp: SCAFFOLD_HP,
+                owner: local_id.id,
+            },
+            Collider::cuboid(
+                SCAFFOLD_SIZE.x * 0.5,
+                SCAFFOLD_SIZE.y * 0.5,
+                SCAFFOLD_SIZE.z * 0.5,
+            ),
+            RigidBody::Fixed,
+        ))
+        .id();
+
+    owned.0.push(ent);
+}
+
+fn net_send_input(
+    time: Res<Time>,
+    keys: Res<ButtonInput<KeyCode>>,
+    buttons: Res<ButtonInput<MouseButton>>,
+    cam_q: Query<(&Transform, &PlayerCamera), With<Camera3d>>,
+    player_tf_q: Query<&Transform, With<Player>>,
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut client: ResMut<RenetClient>,
+    mut seq: ResMut<InputSeq>,
+    mut buf: ResMut<InputBuffer>,
+    mut accumulator: ResMut<PredictionAccumulator>,
+    mut pending_frames: ResMut<PendingPredictionFrames>,
+    last_conf: Res<LastConfirmedSeq>,
+    mut fire: LocalFire,
+    mut recent: ResMut<RecentLocalFires>,
+) {
+    let fire_cooldown = fire.rules.0.fire_cooldown;
+    let weapon = &mut fire.weapon;
+    let (cam_tf_local, cam) = if let Ok(v) = cam_q.get_single() {
+        v
+    } else {
+        return;
+    };
+    let player_tf = if let Ok(tf) = player_tf_q.get_single() {
+        tf
+    } else {
+        return;
+    };
+
+    weapon.fire_cd = (weapon.fire_cd - time.delta_seconds()).max(0.0);
+
+    accumulator.remaining += time.delta_seconds();
+    if accumulator.remaining > PREDICTION_DT * 5.0 {
+        accumulator.remaining = PREDICTION_DT * 5.0;
+    }
+
+    let mut mv = [0.0f32, 0.0f32];
+    if keys.pressed(KeyCode::KeyW) {
+        mv[1] -= 1.0;
+    }
+    if keys.pressed(KeyCode::KeyS) {
+        mv[1] += 1.0;
+    }
+    if keys.pressed(KeyCode::KeyA) {
+        mv[0] -= 1.0;
+    }
+    if keys.pressed(KeyCode::KeyD) {
+        mv[0] += 1.0;
+    }
+
+    let ads_key = keys.pressed(KeyCode::ShiftLeft) || keys.pressed(KeyCode::ShiftRight);
+    let ads = buttons.pressed(MouseButton::Right) || ads_key;
+
+    let fire_hold = buttons.pressed(MouseButton::Left) || keys.pressed(KeyCode::KeyF);
+    let fire_trigger = buttons.just_pressed(MouseButton::Left) || keys.just_pressed(KeyCode::KeyF);
+    if keys.just_pressed(KeyCode::Space) {
+        accumulator.pending_jump = true;
+    }
+
+    let yaw_rot = player_tf.rotation;
+    let pitch_rot = Quat::from_rotation_x(cam.pitch);
+    let forward = yaw_rot * (pitch_rot * Vec3::NEG_Z);
+    let origin = player_tf.translation + yaw_rot * cam_tf_local.translation;
+    let shot_dir = forward.normalize_or_zero();
+
+    let can_fire_now = (fire_trigger || fire_hold)
+        && weapon.fire_cd <= 0.0
+        && shot_dir.length_squared() > 1e-6;
+
+    let mut fire_flag_sent = false;
+    while accumulator.remaining >= PREDICTION_DT {
+        accumulator.remaining -= PREDICTION_DT;
+        seq.0 = seq.0.wrapping_add(1);
+        let jump = if accumulator.pending_jump {
+            accumulator.pending_jump = false;
+            true
+        } else {
+            false
+        };
+        let fire_event = can_fire_now && !fire_flag_sent;
+        if fire_event {
+            fire_flag_sent = true;
+        }
+        // 送信と同じ量子化を通した値で予測する（サーバの受信値と一致させる）
+        let frame = InputFrame {
+            seq: seq.0,
+            mv,
+            jump,
+            fire: fire_event,
+            ads,
+            yaw: cam.yaw,
+            pitch: cam.pitch,
+            dt: PREDICTION_DT,
+        }
+        .quantized();
+        buf.0.push_back(frame.clone());
+        pending_frames.0.push_back(frame.clone());
+        if let Ok(bytes) = bincode::serialize(&ClientMessage::Input(frame)) {
+            let _ = client.send_message(CH_INPUT, bytes);
+        }
+    }
+
+    if let Some(ack) = last_conf.0 {
+        while let Some(front) = buf.0.front() {
+            if front.seq <= ack {
+                buf.0.pop_front();
+            } else {
+                break;
+            }
+        }
+    }
+
+    if can_fire_now {
+        if let Ok(bytes) = bincode::serialize(&ClientMessage::Fire {
+            origin: [origin.x, origin.y, origin.z],
+            dir: [shot_dir.x, shot_dir.y, shot_dir.z],
+        }) {
+            let _ = client.send_message(CH_RELIABLE, bytes);
+        }
+        weapon.fire_cd = fire_cooldown;
+        let col = Color::srgb(0.95, 0.9, 0.2);
+        let mmesh = meshes.add(Cuboid::new(0.06, 0.06, 0.06));
+        let mmat = materials.add(StandardMaterial {
+            base_color: col,
+            emissive: col.into(),
+            unlit: true,
+            ..default()
+        });
+        commands.spawn((
+            PbrBundle {
+                mesh: mmesh,
+                material: mmat,
+                transform: Transform::from_translation(origin),
+                ..default()
+            },
+            MuzzleFx {
+                timer: Timer::from_seconds(0.06, TimerMode::Once),
+            },
+        ));
+        let seg = shot_dir * 50.0;
+        let len = seg.length();
+        if len > 0.001 {
+            let tmesh = meshes.add(Cuboid::new(0.02, 0.02, len.max(0.05)));
+            let tmat = materials.add(StandardMaterial {
+                base_color: col,
+                emissive: col.into(),
+                unlit: true,
+                ..default()
+            });
+            let rot = Quat::from_rotation_arc(Vec3::Z, seg.normalize());
+            let pos = origin + seg * 0.5;
+            commands.spawn((
+                PbrBundle {
+                    mesh: tmesh,
+                    material: tmat,
+                    transform: Transform {
+                        translation: pos,
+                        rotation: rot,
+                        scale: Vec3::ONE,
+                    },
+                    ..default()
+                },
+                TracerFx {
+                    timer: Timer::from_seconds(0.06, TimerMode::Once),
+                },
+            ));
+        }
+        recent
+            .0
+            .push_back((time.elapsed_seconds(), origin, shot_dir));
+        while let Some(&(t, _, _)) = recent.0.front() {
+            if time.elapsed_seconds() - t > 0.4 {
+                recent.0.pop_front();
+            } else {
+                break;
+            }
+        }
+    }
+}
+
+fn net_recv_snapshot(
+    mut commands: Commands,
+    mut client: ResMut<RenetClient>,
+    mut remap: ResMut<RemoteMap>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    local: Res<LocalNetInfo>,
+    mut self_auth: ResMut<AuthoritativeSelf>,
+    mut kinds: ResMut<ActorKindsMap>,
+    mut positions: ResMut<ActorPositions>,
+    mut last_conf: ResMut<LastConfirmedSeq>,
+    mut last_tick: ResMut<LastSnapshotTick>,
+    mut rhist: ResMut<RemoteHistory>,
+    mut baselines: ResMut<SnapshotBaselines>,
+    mut my_hp: ResMut<LocalHealth>,
+) {
+    let mut ack_tick: Option<u32> = None;
+    while let Some(raw) = client.receive_message(CH_SNAPSHOT) {
+        let decoded = match bincode::deserialize::<ServerMessage>(&raw) {
+            Ok(ServerMessage::Snapshot(snap)) => Some(snap),
+            // 基準を保持していなければ復元できないので捨てる（ACK が進めば全量/新基準で届く）
+            Ok(ServerMessage::DeltaSnapshot(delta)) => baselines
+                .0
+                .iter()
+                .find(|(t, _)| *t == delta.base_tick)
+                .map(|(_, base)| apply_delta(base, &delta)),
+            _ => None,
+        };
+        if let Some(snap) = decoded {
+            if matches!(
+                std::env::var("NET_SNAPSHOT_LOG").ok().as_deref(),
+                Some("1" | "true" | "TRUE")
+            ) && snap.players.len() > 0
+            {
+                info!("client: snapshot players={}", snap.players.len());
+            }
+            // 入力ACK: このクライアントの直近確定seqを拾い、未確定バッファの整理に使う
+            if let Some((_id, seq)) = snap.acks.iter().find(|(id, _)| *id == local.id) {
+                last_conf.0 = Some(*seq);
+            }
+            // 古いスナップは破棄（tick単調増加を前提）
+            if let Some(prev) = last_tick.0 {
+                if snap.tick <= prev {
+                    continue;
+                }
+            }
+            last_tick.0 = Some(snap.tick);
+            baselines.0.push_back((snap.tick, snap.players.clone()));
+            while baselines.0.len() > SNAPSHOT_BASELINE_KEEP {
+                baselines.0.pop_front();
+            }
+            ack_tick = Some(snap.tick);
+            // 関心範囲外に出たアクターはスナップショットから消える（ActorLeave の取りこぼし対策）
+            let present: std::collections::HashSet<u64> =
+                snap.players.iter().map(|p| p.id).collect();
+            let stale: Vec<u64> = remap
+                .0
+                .keys()
+                .filter(|id| !present.contains(id))
+                .copied()
+                .collect();
+            for id in stale {
+                if let Some(ent) = remap.0.remove(&id) {
+                    commands.entity(ent).despawn_recursive();
+                }
+                rhist.0.remove(&id);
+            }
+            for p in snap.players {
+                kinds.0.insert(p.id, p.kind);
+                positions
+                    .0
+                    .insert(p.id, Vec3::new(p.pos[0], p.pos[1], p.pos[2]));
+                if p.id == local.id {
+                    self_auth.pos = Some(Vec3::new(p.pos[0], p.pos[1], p.pos[2]));
+                    self_auth.yaw = Some(p.yaw);
+                    self_auth.vy = Some(p.vy);
+                    self_auth.grounded = Some(p.grounded);
+                    // 再接続で引き継いだ体力など、イベントだけでは分からない値もここで揃う
+                    my_hp.hp = p.hp;
+                    continue;
+                }
+                let pos = Vec3::new(p.pos[0], p.pos[1], p.pos[2]);
+                if p.alive {
+                    // 補間用履歴に push（後段の補間システムが使用）
+                    let entry = rhist
+                        .0
+                        .entry(p.id)
+                        .or_insert_with(|| std::collections::VecDeque::with_capacity(16));
+                    entry.push_back(RemoteSample {
+                        tick: last_tick.0.unwrap_or(0),
+                        pos,
+                        yaw: p.yaw,
+                    });
+                    while entry.len() > 16 {
+                        entry.pop_front();
+                    }
+                    if let Some(&ent) = remap.0.get(&p.id) {
+                        if let Some(mut ec) = commands.get_entity(ent) {
+                            ec.insert(
+                                Transform::from_translation(pos)
+                                    .with_rotation(Quat::from_rotation_y(p.yaw)),
+                            );
+                        }
+                    } else {
+                        let mesh = meshes.add(Cuboid::new(0.4, 1.8, 0.4));
+                        let mat = materials.add(match p.kind {
+                            ActorKind::Human => Color::srgb(0.2, 0.9, 0.3),
+                            ActorKind::Bot => Color::srgb(0.9, 0.2, 0.2),
+                        });
+                        let ent = commands
+                            .spawn((
+                                PbrBundle {
+                                    mesh,
+                                    material: mat,
+                                    transform: Transform::from_translation(pos),
+                                    ..default()
+                                },
+                                RemoteAvatar { id: p.id },
+                            ))
+                            .id();
+                        remap.0.insert(p.id, ent);
+                    }
+                } else {
+                    if let Some(ent) = remap.0.remove(&p.id) {
+                        commands.entity(ent).despawn_recursive();
+                    }
+                }
+            }
+        }
+    }
+    // 最新の復元済み tick を ACK（サーバはこれを次の差分の基準にする）
+    if let Some(tick) = ack_tick {
+        if let Ok(bytes) = bincode::serialize(&ClientMessage::SnapshotAck { tick }) {
+            let _ = client.send_message(CH_INPUT, bytes);
+        }
+    }
+}
+
+// リモートアバターのTransformを補間して更新
+fn remote_interpolate_system(
+    remap: Res<RemoteMap>,
+    rhist: Res<RemoteHistory>,
+    delay: Res<InterpDelayTicks>,
+    last_tick: Res<LastSnapshotTick>,
+    mut q_tf: Query<&mut Transform>,
+) {
+    let Some(latest) = last_tick.0 else { return };
+    let target_tick = latest.saturating_sub(delay.0);
+    for (id, ent) in remap.0.iter() {
+        let Some(hist) = rhist.0.get(id) else {
+            continue;
+        };
+        if hist.is_empty() {
+            continue;
+        }
+        let mut a = hist.front().copied().unwrap();
+        let mut b = hist.back().copied().unwrap();
+        if target_tick <= a.tick {
+            b = a;
+        } else if target_tick >= b.tick {
+            a = b;
+        } else {
+            for w in hist.as_slices().0.windows(2) {
+                let x = w[0];
+                let y = w[1];
+                if x.tick <= target_tick && target_tick <= y.tick {
+                    a = x;
+                    b = y;
+                    break;
+                }
+            }
+        }
+        let t = if b.tick > a.tick {
+            (target_tick - a.tick) as f32 / (b.tick - a.tick) as f32
+        } else {
+            0.0
+        };
+        let pos = a.pos.lerp(b.pos, t.clamp(0.0, 1.0));
+        let dy = wrap_pi(b.yaw - a.yaw);
+        let yaw = wrap_pi(a.yaw + dy * t.clamp(0.0, 1.0));
+        if let Ok(mut tf) = q_tf.get_mut(*ent) {
+            tf.translation = pos;
+            tf.rotation = Quat::from_rotation_y(yaw);
+        }
+    }
+}
+
+fn net_recv_events(
+    mut commands: Commands,
+    mut client: ResMut<RenetClient>,
+    mut remap: ResMut<RemoteMap>,
+    mut sc_assets: NetScaffoldAssets,
+    local: Res<LocalNetInfo>,
+    mut self_auth: ResMut<AuthoritativeSelf>,
+    mut my_hp: ResMut<LocalHealth>,
+    mut hit_q: Query<&mut UiHitMarker>,
+    log_root_q: Query<Entity, With<UiKillLog>>,
+    board_root_q: Query<Entity, With<UiScoreboard>>,
+    mut hud: HudRes,
+    mut kinds: ResMut<ActorKindsMap>,
+    mut player_q: Query<(&mut Transform, &mut Controller), With<Player>>,
+    mut ghost: ResMut<LocalGhostScaffold>,
+    mut rhist: ResMut<RemoteHistory>,
+    mut map: ClientMap,
+) {
+    // ローカルエイリアス（既存コードの参照名を維持）
+    let score_data = &mut hud.score_data;
+    let round_ui = &mut hud.round_ui;
+    let local_ammo = &mut hud.local_ammo;
+    let handshake = &mut hud.handshake;
+    let rules = &mut hud.rules;
+    let names = &mut hud.names;
+    while let Some(raw) = client.receive_message(CH_RELIABLE) {
+        if let Ok(msg) = bincode::deserialize::<ServerMessage>(&raw) {
+            match msg {
+                ServerMessage::Event(ev) => match ev {
+                    // ActorEnter は自分以外にしか届かないので Spawn と同じ扱いでよい
+                    EventMsg::Spawn { id, pos, kind } | EventMsg::ActorEnter { id, pos, kind } => {
+                        let p = Vec3::new(pos[0], pos[1], pos[2]);
+                        kinds.0.insert(id, kind);
+                        if id == local.id {
+                            self_auth.pos = Some(p);
+                            my_hp.hp = 100;
+                            // Teleport local player to server spawn to avoid later corrections.
+                            if let Ok((mut tf, mut ctrl)) = player_q.get_single_mut() {
+                                tf.translation = p;
+                                ctrl.motion = MoveState::standing();
+                            }
+                        } else {
+                            if let Some(&ent) = remap.0.get(&id) {
+                                if let Some(mut ec) = commands.get_entity(ent) {
+                                    ec.insert(Transform::from_translation(p));
+                                }
+                            } else {
+                                let mesh = sc_assets.meshes.add(Cuboid::new(0.4, 1.8, 0.4));
+                                let mat = sc_assets.materials.add(match kind {
+                                    ActorKind::Human => Color::srgb(0.2, 0.9, 0.3),
+                                    ActorKind::Bot => Color::srgb(0.9, 0.2, 0.2),
+                                });
+                                let ent = commands
+                                    .spawn((
+                                        PbrBundle {
+                                            mesh,
+                                            material: mat,
+                                            transform: Transform::from_translation(p),
+                                            ..default()
+                                        },
+                                        RemoteAvatar { id },
+                                    ))
+                                    .id();
+                                remap.0.insert(id, ent);
+                            }
+                        }
+                    }
+                    EventMsg::Despawn { id } | EventMsg::ActorLeave { id } => {
+                        if let Some(ent) = remap.0.remove(&id) {
+                            commands.entity(ent).despawn_recursive();
+                        }
+                        // 再入場時に古いサンプルと補間しないよう履歴も捨てる
+                        rhist.0.remove(&id);
+                    }
+                    EventMsg::Hit {
+                        target_id,
+                        new_hp,
+                        by,
+                    } => {
+                        if target_id == local.id {
+                            my_hp.hp = new_hp;
+                        }
+                        if by == local.id {
+                            if let Ok(mut hm) = hit_q.get_single_mut() {
+                                hm.timer.set_duration(Duration::from_secs_f32(0.15));
+                                hm.timer.reset();
+                            }
+                        }
+                        if target_id == local.id {
+                            // Add damage vignette overlay
+                            commands.spawn((
+                                NodeBundle {
+                                    style: Style {
+                                        position_type: PositionType::Absolute,
+                                        width: Val::Percent(100.0),
+                                        height: Val::Percent(100.0),
+                                        ..default()
+                                    },
+                                    background_color: BackgroundColor(Color::rgba(
+                                        0.8, 0.0, 0.0, 0.35,
+                                    )),
+                                    ..default()
+                                },
+                                UiDamageVignette {
+                                    timer: Timer::from_seconds(0.4, TimerMode::Once),
+                                },
+                            ));
+                        }
+                    }
+                    EventMsg::Death { target_id, by } => {
+                        if target_id == local.id {
+                            my_hp.hp = 0;
+                        }
+                        if let Some(ent) = remap.0.remove(&target_id) {
+                            commands.entity(ent).despawn_recursive();
+                        }
+                        // キルログ追加
+                        let killer = if by == local.id {
+                            "You".to_string()
+                        } else {
+                            names.display(by)
+                        };
+                        let victim = if target_id == local.id {
+                            "You".to_string()
+                        } else {
+                            names.display(target_id)
+                        };
+                        let line = format!("{} -> {}", killer, victim);
+                        if let Ok(root) = log_root_q.get_single() {
+                            push_log_line(&mut commands, root, line, 3.0);
+                        }
+                    }
+                    EventMsg::Announce { text } => {
+                        info!("server announcement: {}", text);
+                        if let Ok(root) = log_root_q.get_single() {
+                            push_log_line(&mut commands, root, format!("[server] {}", text), 8.0);
+                        }
+                    }
+                    EventMsg::MapChange { scene } => {
+                        if scene == map.current.0 {
+                            continue;
+                        }
+                        info!("map change: {} -> {}", map.current.0, scene);
+                        for e in map.roots.iter() {
+                            commands.entity(e).despawn_recursive();
+                        }
+                        // 新しいマップのコライダが入るまで予測移動を止める
+                        map.ready.0 = false;
+                        commands.spawn((
+                            SceneBundle {
+                                scene: map.asset_server.load(scene.clone()),
+                                ..default()
+                            },
+                            MapRoot,
+                        ));
+                        map.current.0 = scene;
+                    }
+                    EventMsg::Fire {
+                        id,
+                        origin,
+                        dir,
+                        hit,
+                    } => {
+                        // 自分の発砲はローカル即時VFXを出しているため、サーバVFXは重複回避
+                        if id == local.id {
+                            continue;
+                        }
+                        // VFX: muzzle + tracer (+ impact)
+                        let o = Vec3::new(origin[0], origin[1], origin[2]);
+                        let d = Vec3::new(dir[0], dir[1], dir[2]).normalize_or_zero();
+                        let end = match hit {
+                            Some(h) => Vec3::new(h[0], h[1], h[2]),
+                            None => o + d * 50.0,
+                        };
+                        let col = match kinds.0.get(&id).copied() {
+                            Some(ActorKind::Bot) => Color::srgb(0.95, 0.25, 0.2),
+                            _ => Color::srgb(0.95, 0.9, 0.2),
+                        };
+                        // muzzle
+                        let mmesh = sc_assets.meshes.add(Cuboid::new(0.06, 0.06, 0.06));
+                        let mmat = sc_assets.materials.add(StandardMaterial {
+                            base_color: col,
+                            emissive: col.into(),
+                            unlit: true,
+                            ..default()
+                        });
+                        commands.spawn((
+                            PbrBundle {
+                                mesh: mmesh,
+                                material: mmat,
+                                transform: Transform::from_translation(o),
+                                ..default()
+                            },
+                            MuzzleFx {
+                                timer: Timer::from_seconds(0.06, TimerMode::Once),
+                            },
+                        ));
+                        // tracer
+                        let seg = end - o;
+                        let len = seg.length();
+                        if len > 0.001 {
+                            let tmesh =
+                                sc_assets.meshes.add(Cuboid::new(0.02, 0.02, len.max(0.05)));
+                            let tmat = sc_assets.materials.add(StandardMaterial {
+                                base_color: col,
+                                emissive: col.into(),
+                                unlit: true,
+                                ..default()
+                            });
+                            let rot = Quat::from_rotation_arc(Vec3::Z, seg.normalize());
+                            let pos = o + seg * 0.5;
+                            commands.spawn((
+                                PbrBundle {
+                                    mesh: tmesh,
+                                    material: tmat,
+                                    transform: Transform {
+                                        translation: pos,
+                                        rotation: rot,
+                                        scale: Vec3::ONE,
+                                    },
+                                    ..default()
+                                },
+                                TracerFx {
+                                    timer: Timer::from_seconds(0.06, TimerMode::Once),
+                                },
+                            ));
+                        }
+                        // impact
+                        if let Some(h) = hit {
+                            let hp = Vec3::new(h[0], h[1], h[2]);
+                            let imesh = sc_assets.meshes.add(Cuboid::new(0.05, 0.05, 0.02));
+                            let imat = sc_assets.materials.add(StandardMaterial {
+                                base_color: Color::srgb(1.0, 0.6, 0.3),
+                                emissive: Color::srgb(1.0, 0.6, 0.3).into(),
+                                unlit: true,
+                                ..default()
+                            });
+                            commands.spawn((
+                                PbrBundle {
+                                    mesh: imesh,
+                                    material: imat,
+                                    transform: Transform::from_translation(hp),
+                                    ..default()
+                                },
+                                ImpactFx {
+                                    timer: Timer::from_seconds(0.2, TimerMode::Once),
+                                },
+                            ));
+                        }
+                    }
+                    EventMsg::RoundStart { time_left_sec } => {
+                        round_ui.phase_end = None;
+                        round_ui.time_left = time_left_sec as f32;
+                        round_ui.winner = None;
+                    }
+                    EventMsg::RoundEnd {
+                        winner_id,
+                        next_in_sec,
+                    } => {
+                        round_ui.winner = winner_id;
+                        round_ui.phase_end =
+                            Some(Timer::from_seconds(next_in_sec as f32, TimerMode::Once));
+                    }
+                    EventMsg::Ammo {
+                        id,
+                        ammo,
+                        reloading,
+                    } => {
+                        if id == local.id {
+                            local_ammo.ammo = ammo;
+                            local_ammo.reloading = reloading;
+                        }
+                    }
+                    EventMsg::ScaffoldSpawn {
+                        sid,
+                        owner: owner_id,
+                        pos,
+                    } => {
+                        // 関心範囲への再入場で同じ sid が再送されうる
+                        if sc_assets.map.0.contains_key(&sid) {
+                            continue;
+                        }
+                        let p = Vec3::new(pos[0], pos[1], pos[2]);
+                        let mesh = sc_assets.meshes.add(Cuboid::new(
+                            SCAFFOLD_SIZE.x,
+                            SCAFFOLD_SIZE.y,
+                            SCAFFOLD_SIZE.z,
+                        ));
+                        let col = Color::srgba(0.2, 0.9, 1.0, 0.45);
+                        let mat = sc_assets.materials.add(StandardMaterial {
+                            base_color: col,
+                            emissive: Color::srgb(0.3, 0.8, 1.0).into(),
+                            alpha_mode: AlphaMode::Blend,
+                            unlit: true,
+                            ..default()
+                        });
+                        let ent = commands
+                            .spawn((
+                                PbrBundle {
+                                    mesh,
+                                    material: mat,
+                                    transform: Transform::from_translation(p),
+                                    ..default()
+                                },
+                                NetScaffold { sid },
+                                Collider::cuboid(
+                                    SCAFFOLD_SIZE.x * 0.5,
+                                    SCAFFOLD_SIZE.y * 0.5,
+                                    SCAFFOLD_SIZE.z * 0.5,
+                                ),
+                                RigidBody::Fixed,
+                            ))
+                            .id();
+                        sc_assets.map.0.insert(sid, ent);
+                        // 自分が要求した場合はゴーストを除去
+                        if owner_id == local.id {
+                            if let Some(e) = ghost.0.take() {
+                                commands.entity(e).despawn_recursive();
+                            }
+                        }
+                    }
+                    EventMsg::ScaffoldDespawn { sid } => {
+                        if let Some(ent) = sc_assets.map.0.remove(&sid) {
+                            commands.entity(ent).despawn_recursive();
+                        }
+                    }
+                    EventMsg::PlayerInfo { id, name, .. } => {
+                        names.0.insert(id, name);
+                        if let Ok(root) = board_root_q.get_single() {
+                            rebuild_scoreboard(&mut commands, root, &score_data.0, names);
+                        }
+                    }
+                },
+                ServerMessage::Score(entries) => {
+                    // 更新して、スコアボードUIを再構築
+                    score_data.0 = entries
+                        .into_iter()
+                        .map(|e| (e.id, e.kills, e.deaths))
+                        .collect();
+                    if let Ok(root) = board_root_q.get_single() {
+                        rebuild_scoreboard(&mut commands, root, &score_data.0, names);
+                    }
+                }
+                ServerMessage::Welcome { version, caps } => {
+                    info!("handshake ok: server protocol v{} caps={:#x}", version, caps);
+                    handshake.server_version = Some(version);
+                    handshake.caps = caps;
+                    handshake.rejected = None;
+                }
+                ServerMessage::Reject { reason } => {
+                    error!("connection rejected by server: {}", reason);
+                    handshake.rejected = Some(reason);
+                }
+                ServerMessage::Rules(r) => {
+                    info!(
+                        "server rules: move_speed={} fire_cooldown={:.3}s mag={}",
+                        r.move_speed, r.fire_cooldown, r.mag_size
+                    );
+                    rules.0 = r;
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+// スコアボード UI を作り直す（キル降順）
+fn rebuild_scoreboard(
+    commands: &mut Commands,
+    root: Entity,
+    score: &[(u64, u32, u32)],
+    names: &PlayerNames,
+) {
+    if let Some(mut ec) = commands.get_entity(root) {
+        ec.despawn_descendants();
+    }
+    commands.entity(root).with_children(|p| {
+        p.spawn(TextBundle::from_section(
+            format!("{:<24}  {:>5} {:>6}", "Name", "K", "D"),
+            TextStyle {
+                font_size: 28.0,
+                color: Color::BLACK,
+                ..default()
+            },
+        ));
+        let mut rows = score.to_vec();
+        rows.sort_by_key(|e| (-(e.1 as i32), e.2 as i32));
+        for (id, k, d) in rows {
+            p.spawn(TextBundle::from_section(
+                format!("{:<24}  {:>5} {:>6}", names.display(id), k, d),
+                TextStyle {
+                    font_size: 24.0,
+                    color: Color::BLACK,
+                    ..default()
+                },
+            ));
+        }
+    });
+}
+
+// キルログ欄に 1 行追加（secs 秒で消える）
+fn push_log_line(commands: &mut Commands, root: Entity, line: String, secs: f32) {
+    commands.entity(root).with_children(|p| {
+        p.spawn((
+            TextBundle::from_section(
+                line,
+                TextStyle {
+                    font_size: 24.0,
+                    color: Color::BLACK,
+                    ..default()
+                },
+            ),
+            UiKillEntry {
+                timer: Timer::from_seconds(secs, TimerMode::Once),
+            },
+        ));
+    });
+}
+
+// 自分プレイヤーの補正（簡易リコンシリエーション）
+fn reconcile_self(
+    time: Res<Time>,
+    mut q: Query<&mut Transform, With<Player>>,
+    mut qk: Query<&mut KinematicCharacterController, With<Player>>,
+    self_auth: Res<AuthoritativeSelf>,
+    buf: Res<InputBuffer>,
+    rules: Res<ServerRules>,
+) {
+    // Default: enable light position reconciliation. Set RECONCILE_POS=0 to disable.
+    if matches!(
+        std::env::var("RECONCILE_POS").ok().as_deref(),
+        Some("0" | "false" | "FALSE")
+    ) {
+        return;
+    }
+    let mut tf = if let Ok(t) = q.get_single_mut() {
+        t
+    } else {
+        return;
+    };
+    if let Some(base) = self_auth.pos {
+        // 未確定入力を再適用した予測ターゲットを作る（簡易）
+        let mut target = base;
+        if !buf.0.is_empty() {
+            let params = MoveParams::from_rules(&rules.0);
+            // スナップショットには縦速度と接地しか載らないので、空中なら空中ジャンプは使用済みとみなす
+            let grounded = self_auth.grounded.unwrap_or(true);
+            let state = MoveState {
+                vy: self_auth.vy.unwrap_or(0.0),
+                grounded,
+                air_jumps: if grounded { 0 } else { params.max_air_jumps },
+                ..Default::default()
+            };
+            target = movement::predict(base, state, &buf.0, &params);
+        }
+        let diff = target - tf.translation;
+        let d = diff.length();
+        // Deadband: ignore tiny differences to avoid visible jitter.
+        if d <= POS_DEADBAND {
+            return;
+        }
+        // Snap when far out-of-bounds to recover quickly.
+        // ただし Transform を直接書き換えず、KCC に追加移動として与え、衝突解決に任せる。
+        if let Ok(mut kcc) = qk.get_single_mut() {
+            let grounded = self_auth.grounded.unwrap_or(false);
+            if d >= POS_SNAP {
+                // 空中ではY補正を入れない（落下中のがくつきを防止）
+                let mut corr = diff;
+                if !grounded {
+                    corr.y = 0.0;
+                }
+                kcc.translation = Some(kcc.translation.unwrap_or(Vec3::ZERO) + corr);
+                return;
+            }
+            // Smooth correction within thresholds (collidable via KCC).
+            let rate = 6.0; // per second (gentle correction to avoid warps)
+            let step = (rate * time.delta_seconds()).min(1.0);
+            let mut corr = diff * step;
+            if !grounded {
+                corr.y = 0.0;
+            }
+            kcc.translation = Some(kcc.translation.unwrap_or(Vec3::ZERO) + corr);
+        }
+    }
+    if matches!(
+        std::env::var("RECONCILE_YAW").ok().as_deref(),
+        Some("1" | "true" | "TRUE")
+    ) {
+        if let Some(yaw) = self_auth.yaw {
+            // 軽い追従のみ（強いワープは避ける）
+            let current_yaw = tf.rotation.to_euler(EulerRot::YXZ).0;
+            let delta = wrap_pi(yaw - current_yaw);
+            let step = (6.0 * time.delta_seconds()).min(1.0);
+            tf.rotation = Quat::from_rotation_y(wrap_pi(current_yaw + delta * step));
+        }
+    }
+
+    fn scoreboard_toggle(
+        keys: Res<ButtonInput<KeyCode>>,
+        mut q: Query<&mut Visibility, With<UiScoreboard>>,
+    ) {
+        if keys.just_pressed(KeyCode::Tab) {
+            if let Ok(mut v) = q.get_single_mut() {
+                *v = match *v {
+                    Visibility::Hidden => Visibility::Visible,
+                    _ => Visibility::Hidden,
+                };
+            }
+        }
+    }
+}
+
+// 右クリックADS時にFOVを切り替える（クライアント視覚のみ）
+fn ads_zoom_system(
+    buttons: Res<ButtonInput<MouseButton>>,
+    keys: Res<ButtonInput<KeyCode>>,
+    mut q: Query<&mut Projection, With<Camera3d>>,
+) {
+    let Ok(mut proj) = q.get_single_mut() else {
+        return;
+    };
+    if let Projection::Perspective(ref mut p) = *proj {
+        let ads_key = keys.pressed(KeyCode::ShiftLeft) || keys.pressed(KeyCode::ShiftRight);
+        p.fov = if buttons.pressed(MouseButton::Right) || ads_key {
+            ADS_FOV
+        } else {
+            HIP_FOV
+        };
+    }
+}
@@ -0,0 +1,211 @@
+// ===== デモファイル（試合の記録） =====
+// サーバが tick ごとの全アクター状態とイベントを ServerMessage の列として書き出し、
+// クライアントの再生モードが同じ受信処理に流し込む。
+//
+// 形式: "BOCD" + 形式版数(u16 LE) + [長さ(u32 LE) + bincode(DemoHeader)] + [長さ(u32 LE) + bincode(DemoFrame)]*
+// 各フレームの先頭は Snapshot（キーフレーム）か、直前フレームからの DeltaSnapshot。
+// キーフレームは DEMO_KEYFRAME_TICKS ごとに入るので、シークはそこから再生し直せばよい。
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufReader, ErrorKind, Read, Write};
+use std::path::Path;
+
+use crate::net::ServerMessage;
+
+pub const DEMO_MAGIC: [u8; 4] = *b"BOCD";
+// ヘッダ・フレームの形式を変えたら上げる（メッセージ自体の版数は protocol_version）
+pub const DEMO_FORMAT_VERSION: u16 = 1;
+pub const DEMO_KEYFRAME_TICKS: u32 = 60;
+// 壊れたファイルで巨大な確保をしないための上限
+const DEMO_RECORD_MAX: u32 = 16 << 20;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DemoHeader {
+    pub protocol_version: u32,
+    // 録画開始時のマップ（assets/ からのシーンパス）。途中の変更は MapChange イベントで入る
+    pub map: Option<String>,
+    // 録画開始時のサーバ設定（server.toml と同じ TOML）
+    pub config: String,
+    pub tick_hz: f64,
+    pub started_unix_ms: u64,
+}
+
+// サーバの 1 tick 分。messages はクライアントに届けるのと同じ形
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DemoFrame {
+    pub tick: u32,
+    pub messages: Vec<ServerMessage>,
+}
+
+impl DemoFrame {
+    // 全量スナップショットで始まるフレーム（シークの起点にできる）
+    pub fn is_keyframe(&self) -> bool {
+        matches!(self.messages.first(), Some(ServerMessage::Snapshot(_)))
+    }
+}
+
+pub struct DemoWriter<W: Write> {
+    out: W,
+}
+
+impl<W: Write> DemoWriter<W> {
+    pub fn new(mut out: W, header: &DemoHeader) -> std::io::Result<Self> {
+        out.write_all(&DEMO_MAGIC)?;
+        out.write_all(&DEMO_FORMAT_VERSION.to_le_bytes())?;
+        write_record(&mut out, header)?;
+        Ok(Self { out })
+    }
+
+    pub fn write_frame(&mut self, frame: &DemoFrame) -> std::io::Result<()> {
+        write_record(&mut self.out, frame)
+    }
+
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.out.flush()
+    }
+}
+
+// 長さ(u32 LE) + bincode の 1 レコード（サーバの入力記録も同じ枠で書く）
+pub(crate) fn write_record<T: Serialize>(out: &mut impl Write, value: &T) -> std::io::Result<()> {
+    let bytes =
+        bincode::serialize(value).map_err(|e| std::io::Error::new(ErrorKind::InvalidData, e))?;
+    out.write_all(&(bytes.len() as u32).to_le_bytes())?;
+    out.write_all(&bytes)
+}
+
+pub struct DemoReader<R: Read> {
+    pub header: DemoHeader,
+    input: R,
+}
+
+impl<R: Read> DemoReader<R> {
+    pub fn new(mut input: R) -> Result<Self, String> {
+        let mut magic = [0u8; 6];
+        input
+            .read_exact(&mut magic)
+            .map_err(|e| format!("not a demo file: {}", e))?;
+        if magic[..4] != DEMO_MAGIC {
+            return Err("not a demo file (bad magic)".into());
+        }
+        let format = u16::from_le_bytes([magic[4], magic[5]]);
+        if format != DEMO_FORMAT_VERSION {
+            return Err(format!(
+                "unsupported demo format {} (this build reads {})",
+                format, DEMO_FORMAT_VERSION
+            ));
+        }
+        let header: DemoHeader =
+            read_record(&mut input)?.ok_or_else(|| "demo header is missing".to_string())?;
+        // メッセージは bincode なので版が違うと読めない
+        if header.protocol_version != crate::net::PROTOCOL_VERSION {
+            return Err(format!(
+                "demo was recorded with protocol {} (this build speaks {})",
+                header.protocol_version,
+                crate::net::PROTOCOL_VERSION
+            ));
+        }
+        Ok(Self { header, input })
+    }
+
+    // 末尾なら None。録画中に落ちて最後のフレームが途中で切れていても、そこまでは読める
+    pub fn next_frame(&mut self) -> Result<Option<DemoFrame>, String> {
+        read_record(&mut self.input)
+    }
+}
+
+pub(crate) fn read_record<T: for<'de> Deserialize<'de>>(
+    input: &mut impl Read,
+) -> Result<Option<T>, String> {
+    let mut len = [0u8; 4];
+    match input.read_exact(&mut len) {
+        Ok(()) => {}
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.to_string()),
+    }
+    let len = u32::from_le_bytes(len);
+    if len > DEMO_RECORD_MAX {
+        return Err(format!("record too large ({} bytes)", len));
+    }
+    let mut buf = vec![0u8; len as usize];
+    match input.read_exact(&mut buf) {
+        Ok(()) => {}
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.to_string()),
+    }
+    bincode::deserialize(&buf)
+        .map(Some)
+        .map_err(|e| format!("corrupt record: {}", e))
+}
+
+// ファイル全体を読み込む（ヘッダと全フレーム）
+pub fn read_demo(path: impl AsRef<Path>) -> Result<(DemoHeader, Vec<DemoFrame>), String> {
+    let path = path.as_ref();
+    let file = File::open(path).map_err(|e| format!("cannot open {}: {}", path.display(), e))?;
+    let mut reader = DemoReader::new(BufReader::new(file))?;
+    let mut frames = Vec::new();
+    while let Some(frame) = reader.next_frame()? {
+        frames.push(frame);
+    }
+    Ok((reader.header, frames))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::{EventMsg, SnapshotMsg};
+
+    fn header() -> DemoHeader {
+        DemoHeader {
+            protocol_version: crate::net::PROTOCOL_VERSION,
+            map: Some("maps/map.glb#Scene0".into()),
+            config: String::new(),
+            tick_hz: 60.0,
+            started_unix_ms: 1,
+        }
+    }
+
+    #[test]
+    fn frames_round_trip_and_truncation_is_tolerated() {
+        let mut w = DemoWriter::new(Vec::new(), &header()).unwrap();
+        for tick in 1..=3 {
+            w.write_frame(&DemoFrame {
+                tick,
+                messages: vec![
+                    ServerMessage::Snapshot(SnapshotMsg {
+                        tick,
+                        players: vec![],
+                        acks: vec![],
+                    }),
+                    ServerMessage::Event(EventMsg::Despawn { id: 7 }),
+                ],
+            })
+            .unwrap();
+        }
+        let mut bytes = w.out;
+        let mut r = DemoReader::new(bytes.as_slice()).unwrap();
+        assert_eq!(r.header, header());
+        let mut ticks = Vec::new();
+        while let Some(f) = r.next_frame().unwrap() {
+            assert!(f.is_keyframe());
+            ticks.push(f.tick);
+        }
+        assert_eq!(ticks, [1, 2, 3]);
+
+        bytes.truncate(bytes.len() - 3);
+        let mut r = DemoReader::new(bytes.as_slice()).unwrap();
+        let mut n = 0;
+        while r.next_frame().unwrap().is_some() {
+            n += 1;
+        }
+        assert_eq!(n, 2);
+    }
+
+    #[test]
+    fn other_files_are_rejected() {
+        assert!(DemoReader::new(&b"GLTF\x01\x00"[..]).is_err());
+        let mut bytes = DEMO_MAGIC.to_vec();
+        bytes.extend_from_slice(&(DEMO_FORMAT_VERSION + 1).to_le_bytes());
+        let err = DemoReader::new(bytes.as_slice()).err().unwrap();
+        assert!(err.contains("unsupported demo format"), "{}", err);
+    }
+}
@@ -0,0 +1,10 @@
+// クライアント/サーバ共通のライブラリ。バイナリ（src/main.rs, src/bin/*.rs）は
+// ここのプラグインを組み合わせるだけにして、テストやリッスンサーバからも同じ構成を使えるようにする
+pub mod auth;
+pub mod client;
+pub mod demo;
+pub mod movement;
+pub mod net;
+pub mod server;
+#[cfg(test)]
+mod test_util;
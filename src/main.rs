@@ -1,2338 +1,109 @@
 ﻿// #![windows_subsystem = "windows"]
 
-use bevy::diagnostic::{DiagnosticsStore, FrameTimeDiagnosticsPlugin};
-use bevy::ecs::system::SystemParam;
-use bevy::input::mouse::MouseMotion;
 use bevy::prelude::*;
-use bevy::render::camera::Projection;
-use bevy::window::CursorGrabMode;
-use bevy::window::WindowFocused;
+use bevy_online_campus::auth::fetch_connect_token;
+use bevy_online_campus::client::{
+    ClientPlugins, DemoPlayback, IssuedToken, LocalPlayerName, ServerPassword,
+};
+use bevy_online_campus::demo::read_demo;
+use bevy_online_campus::net::parse_server_password;
 use bevy_rapier3d::prelude::*;
-use bevy_rapier3d::render::{DebugRenderContext, RapierDebugRenderPlugin};
-use bevy_renet::renet::RenetClient;
-use bevy_renet::transport::NetcodeClientPlugin;
-use bevy_renet::RenetClientPlugin;
-use std::f32::consts::PI;
-use std::time::Duration;
-
-#[path = "net.rs"]
-mod net;
-use crate::net::shared as shared_consts;
-use net::*;
-
-// ===== Config =====
-const MAP_SCENE_PATH: &str = "maps/map.glb#Scene0"; // assets 配下に maps/map.glb を置いてください
-const PLAYER_START: Vec3 = Vec3::from_array(shared_consts::PLAYER_START);
-const MOVE_SPEED: f32 = shared_consts::MOVE_SPEED; // m/s
-const MOUSE_SENSITIVITY: f32 = 0.0018; // rad/pixel
-const HIP_FOV: f32 = 90.0_f32.to_radians();
-const ADS_FOV: f32 = 65.0_f32.to_radians();
-const ADS_SENS_MUL: f32 = 0.6; // ADS中のマウス感度倍率
-const ADS_SPEED_MUL: f32 = 0.6; // ADS中の移動速度倍率
-const GRAVITY: f32 = 9.81; // m/s^2
-const JUMP_SPEED: f32 = 5.2; // m/s (必要なら調整)
-const KEY_LOOK_SPEED: f32 = 2.2; // rad/s for arrow-key look
-                                 // Position reconciliation thresholds (light server convergence)
-const POS_DEADBAND: f32 = 0.05; // meters: small jitterを無視して安定化
-const POS_SNAP: f32 = 0.8; // meters: 乖離が大きい時のみスナップ
-
-const PREDICTION_DT: f32 = 1.0 / 60.0;
-const FIRE_COOLDOWN: f32 = 1.0 / 7.5;
-#[inline]
-fn wrap_pi(a: f32) -> f32 {
-    (a + PI).rem_euclid(2.0 * PI) - PI
-}
-
-// ===== HUD Components =====
-#[derive(Component)]
-struct UiHp;
-
-#[derive(Component)]
-struct UiFps;
-
-#[derive(Resource)]
-struct FpsTextTimer(Timer);
-
-#[derive(Component)]
-struct UiHitMarker {
-    timer: Timer,
-}
-
-#[derive(Component)]
-struct UiKillLog;
-
-#[derive(Component)]
-struct UiKillEntry {
-    timer: Timer,
-}
-
-#[derive(Component)]
-struct UiScoreboard;
-
-#[derive(Resource, Default)]
-struct ScoreData(Vec<(u64, u32, u32)>); // (id, kills, deaths)
-
-#[derive(Resource, Default)]
-struct ScoreVisible(bool);
-
-#[derive(Resource, Default)]
-struct RoundUi {
-    phase_end: Option<Timer>,
-    time_left: f32,
-    winner: Option<u64>,
-}
-
-#[derive(Resource, Default)]
-struct LocalAmmo {
-    ammo: u16,
-    reloading: bool,
-}
-
-#[derive(Resource, Default)]
-struct LocalWeaponState {
-    fire_cd: f32,
-}
-
-// VFX components
-#[derive(Component)]
-struct MuzzleFx {
-    timer: Timer,
-}
-
-#[derive(Component)]
-struct TracerFx {
-    timer: Timer,
-}
-
-#[derive(Component)]
-struct ImpactFx {
-    timer: Timer,
-}
-
-#[derive(Resource, Default)]
-struct ActorKindsMap(std::collections::HashMap<u64, ActorKind>);
-
-#[derive(Resource, Default)]
-struct ActorPositions(std::collections::HashMap<u64, Vec3>);
-
-#[derive(Component)]
-struct UiDamageVignette {
-    timer: Timer,
-}
-
-#[derive(Component)]
-struct Player;
-
-#[derive(Component)]
-struct PlayerCamera {
-    yaw: f32,
-    pitch: f32,
-}
-
-// 見た目弾は廃止（サーバ権威のヒットスキャンに統一）
-
-#[derive(Resource, Default)]
-struct CursorLocked(pub bool);
-
-#[derive(Component, Default)]
-struct Controller {
-    vy: f32,
-    on_ground: bool,
-    jumps: u8, // 空中で使ったジャンプ回数（2段ジャンプ用）
-    // サーバと整合するための簡易バッファ/コヨーテ/クールダウン
-    jump_buf: f32,
-    coyote: f32,
-    jump_cd: f32,
-}
-
-#[derive(Resource, Default)]
-struct MapReady(pub bool);
-
-// ===== Scaffold (temporary platform) =====
-const SCAFFOLD_SIZE: Vec3 = Vec3::new(2.0, 0.5, 2.0); // WxHxD (meters)
-const SCAFFOLD_RANGE: f32 = 5.0; // meters
-const SCAFFOLD_HP: i32 = 150;
-const SCAFFOLD_PER_PLAYER_LIMIT: usize = 3;
-
-#[derive(Component)]
-struct Scaffold {
-    hp: i32,
-    owner: u64,
-}
-
-#[derive(Resource, Default)]
-struct LocalScaffolds(Vec<Entity>); // FIFO 管理（ローカルプレイヤー用）
-
-// ネット同期された足場（サーバ権威）
-#[derive(Component)]
-struct NetScaffold {
-    sid: u64,
-}
-
-#[derive(Resource, Default)]
-struct NetScaffoldMap(std::collections::HashMap<u64, Entity>);
-
-// サーバ確定前のローカル可視用ゴースト足場
-#[derive(Component)]
-struct GhostScaffold;
-
-#[derive(Resource, Default)]
-struct LocalGhostScaffold(Option<Entity>);
-
-#[derive(SystemParam)]
-struct NetScaffoldAssets<'w, 's> {
-    meshes: ResMut<'w, Assets<Mesh>>,
-    materials: ResMut<'w, Assets<StandardMaterial>>,
-    map: ResMut<'w, NetScaffoldMap>,
-    _marker: std::marker::PhantomData<&'s ()>,
+use bevy_rapier3d::render::RapierDebugRenderPlugin;
+use clap::{CommandFactory, Parser};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+/// Bevy Online Campus client.
+#[derive(Parser, Debug)]
+#[command(name = "client", version, about)]
+struct Args {
+    /// Play back a demo recorded with the server's --record-demo instead of connecting
+    #[arg(long, value_name = "FILE")]
+    demo: Option<PathBuf>,
+
+    /// Get a connect token from this token issuer instead of holding NETCODE_KEY (secure servers)
+    #[arg(
+        long,
+        env = "TOKEN_ISSUER",
+        value_name = "ADDR:PORT",
+        requires_all = ["name", "password"],
+        conflicts_with = "demo"
+    )]
+    issuer: Option<SocketAddr>,
+
+    /// Name shown to other players (the account name when using --issuer)
+    #[arg(long, env = "PLAYER_NAME")]
+    name: Option<String>,
+
+    /// Account password for --issuer
+    #[arg(long, env = "PLAYER_PASSWORD", hide_env_values = true)]
+    password: Option<String>,
+
+    /// Password of a password-protected server
+    #[arg(long, env = "SERVER_PASSWORD", hide_env_values = true, value_parser = parse_server_password)]
+    server_password: Option<String>,
 }
 
 fn main() {
-    App::new()
-        .insert_resource(ClearColor(Color::srgb(0.02, 0.02, 0.03)))
-        .insert_resource(AmbientLight {
-            color: Color::WHITE,
-            brightness: 300.0,
-        })
-        .insert_resource(CursorLocked(true))
-        .insert_resource(MapReady(false))
-        .insert_resource(ConnStatePrev::default())
-        .insert_resource(FpsTextTimer(Timer::from_seconds(0.5, TimerMode::Repeating)))
-        .insert_resource(ActorKindsMap::default())
-        .insert_resource(ActorPositions::default())
-        .insert_resource(LocalScaffolds::default())
-        .insert_resource(NetScaffoldMap::default())
-        .insert_resource(LocalGhostScaffold::default())
-        .add_plugins(DefaultPlugins.set(WindowPlugin {
-            primary_window: Some(Window {
-                title: "Bevy FPS".into(),
-                present_mode: if matches!(
-                    std::env::var("NO_VSYNC").ok().as_deref(),
-                    Some("1" | "true" | "TRUE")
-                ) {
-                    bevy::window::PresentMode::AutoNoVsync
-                } else {
-                    bevy::window::PresentMode::AutoVsync
-                },
-                ..default()
-            }),
-            ..default()
-        }))
-        .add_plugins((RenetClientPlugin, NetcodeClientPlugin))
-        .add_plugins(FrameTimeDiagnosticsPlugin)
-        .add_plugins((
-            RapierPhysicsPlugin::<NoUserData>::default(),
-            RapierDebugRenderPlugin::default(),
-        ))
-        .insert_resource(DebugRenderContext {
-            enabled: matches!(
-                std::env::var("DEBUG_COLLIDERS").ok().as_deref(),
-                Some("1" | "true" | "TRUE")
-            ),
-            ..Default::default()
-        })
-        .add_systems(
-            Startup,
-            (
-                setup_world,
-                setup_ui,
-                setup_physics,
-                setup_net_client,
-                setup_player,
-                setup_hud,
-            ),
-        )
-        .add_systems(Update, handle_focus_events)
-        .add_systems(Update, cursor_lock_controls)
-        .add_systems(Update, mouse_look_system)
-        .add_systems(Update, ads_zoom_system)
-        .add_systems(Update, keyboard_look_system)
-        .add_systems(Update, toggle_debug_colliders)
-        // 見た目弾の生成/更新は削除
-        .add_systems(Update, add_mesh_colliders_for_map)
-        .add_systems(Update, net_log_connection)
-        .add_systems(Update, net_recv_snapshot)
-        .add_systems(Update, remote_interpolate_system.after(net_recv_snapshot))
-        .add_systems(
-            Update,
-            net_send_input
-                .after(mouse_look_system)
-                .after(keyboard_look_system)
-                .after(cursor_lock_controls)
-                .after(handle_focus_events)
-                .after(net_recv_snapshot),
-        )
-        .add_systems(Update, kcc_move_system_unified.after(net_send_input))
-        .add_systems(Update, kcc_post_step_system.after(kcc_move_system_unified))
-        .add_systems(
-            Update,
-            client_airborne_snap_control.after(kcc_post_step_system),
-        )
-        // replay_unconfirmed_inputs は reconcile_self に統合したため不要
-        .add_systems(Update, net_recv_events)
-        .add_systems(
-            Update,
-            reconcile_self
-                .after(net_recv_snapshot)
-                .after(net_recv_events)
-                .before(PhysicsSet::StepSimulation),
-        )
-        .add_systems(Update, hud_update_hp)
-        .add_systems(Update, hud_tick_hit_marker)
-        .add_systems(Update, hud_tick_killlog)
-        .add_systems(Update, hud_update_ammo)
-        .add_systems(Update, fps_update_system)
-        .add_systems(Update, scaffold_input_system)
-        .add_systems(Update, vfx_tick_and_cleanup)
-        .run();
-}
-
-fn setup_world(mut commands: Commands, asset_server: Res<AssetServer>) {
-    // マップのGLBシーンをロード
-    commands.spawn(SceneBundle {
-        scene: asset_server.load(MAP_SCENE_PATH),
-        transform: Transform::from_xyz(0.0, 0.0, 0.0),
-        ..default()
+    let args = Args::parse();
+    let demo = args.demo.as_ref().map(|path| {
+        let (header, frames) = read_demo(path).unwrap_or_else(|e| {
+            Args::command()
+                .error(
+                    clap::error::ErrorKind::ValueValidation,
+                    format!("--demo: {e}"),
+                )
+                .exit()
+        });
+        DemoPlayback::new(header, frames)
     });
-
-    // 環境光は Resource で設定済み。補助の方向ライトを追加
-    commands.spawn((DirectionalLightBundle {
-        directional_light: DirectionalLight {
-            shadows_enabled: !matches!(
-                std::env::var("LOW_GFX").ok().as_deref(),
-                Some("1" | "true" | "TRUE")
-            ),
-            illuminance: 30_000.0,
-            ..default()
-        },
-        transform: Transform::from_xyz(10.0, 12.0, 8.0).looking_at(Vec3::ZERO, Vec3::Y),
-        ..default()
-    },));
-}
-
-fn setup_physics(mut conf: ResMut<RapierConfiguration>) {
-    conf.gravity = Vec3::new(0.0, -shared_consts::GRAVITY, 0.0);
-}
-
-fn setup_player(mut commands: Commands, mut windows: Query<&mut Window>) {
-    // プレイヤー本体（位置と yaw を持つ）
-    let player = commands
-        .spawn((
-            Player,
-            SpatialBundle {
-                transform: Transform::from_translation(PLAYER_START),
-                ..default()
-            },
-        ))
-        // キャラクターコントローラと当たり判定（カプセル）
-        .insert({
-            let mut kcc = KinematicCharacterController::default();
-            // サーバと同一のオートステップ/スナップに合わせる
-            kcc.autostep = Some(CharacterAutostep {
-                max_height: CharacterLength::Absolute(0.5),
-                min_width: CharacterLength::Absolute(0.3),
-                include_dynamic_bodies: true,
-            });
-            kcc.snap_to_ground = Some(CharacterLength::Absolute(0.25));
-            (Collider::capsule_y(0.6, 0.3), kcc, Controller::default())
+    // ウィンドウを開く前に取る（パスワード違いなどはここで終了）
+    let token = args.issuer.map(|issuer| {
+        let name = args.name.as_deref().unwrap_or_default();
+        let password = args.password.as_deref().unwrap_or_default();
+        let server_password = args.server_password.as_deref().unwrap_or_default();
+        fetch_connect_token(issuer, name, password, server_password).unwrap_or_else(|e| {
+            Args::command()
+                .error(
+                    clap::error::ErrorKind::ValueValidation,
+                    format!("--issuer: {e}"),
+                )
+                .exit()
         })
-        .id();
-
-    // カメラはプレイヤーの子: pitch はカメラにのみ反映
-    // 目線の高さ（プレイヤー中心から +0.7m）
-    let cam = Camera3dBundle {
-        transform: Transform::from_xyz(0.0, 0.7, 0.0),
-        camera: Camera {
-            hdr: !matches!(
-                std::env::var("LOW_GFX").ok().as_deref(),
-                Some("1" | "true" | "TRUE")
-            ),
-            ..default()
-        },
-        ..default()
-    };
-
-    commands.entity(player).with_children(|p| {
-        p.spawn((
-            cam,
-            PlayerCamera {
-                yaw: 0.0,
-                pitch: 0.0,
-            },
-        ));
     });
 
-    // カーソルをロック
-    if let Ok(mut win) = windows.get_single_mut() {
-        win.cursor.visible = false;
-        win.cursor.grab_mode = CursorGrabMode::Locked;
-    }
-}
-
-fn setup_ui(mut commands: Commands) {
-    // 画面中央に簡易クロスヘア
-    commands
-        .spawn(NodeBundle {
-            style: Style {
-                width: Val::Percent(100.0),
-                height: Val::Percent(100.0),
-                justify_content: JustifyContent::Center,
-                align_items: AlignItems::Center,
-                ..default()
-            },
-            background_color: BackgroundColor(Color::NONE),
-            ..default()
-        })
-        .with_children(|parent| {
-            parent.spawn(TextBundle::from_section(
-                "+",
-                TextStyle {
-                    font_size: 28.0,
-                    color: Color::BLACK,
-                    ..default()
-                },
-            ));
-        });
-}
-
-fn cursor_lock_controls(
-    keys: Res<ButtonInput<KeyCode>>,
-    buttons: Res<ButtonInput<MouseButton>>,
-    mut win_q: Query<&mut Window>,
-    mut locked: ResMut<CursorLocked>,
-) {
-    let mut win = if let Ok(w) = win_q.get_single_mut() {
-        w
-    } else {
-        return;
-    };
-
-    // Esc で解除、左クリックで再ロック
-    if keys.just_pressed(KeyCode::Escape) {
-        locked.0 = false;
-    }
-    if buttons.just_pressed(MouseButton::Left) {
-        locked.0 = true;
-    }
-
-    match locked.0 {
-        true => {
-            win.cursor.visible = false;
-            win.cursor.grab_mode = CursorGrabMode::Locked;
-        }
-        false => {
-            win.cursor.visible = true;
-            win.cursor.grab_mode = CursorGrabMode::None;
-        }
-    }
-}
-
-fn mouse_look_system(
-    mut mouse_evr: EventReader<MouseMotion>,
-    locked: Res<CursorLocked>,
-    keys: Res<ButtonInput<KeyCode>>,
-    buttons: Res<ButtonInput<MouseButton>>,
-    mut q: ParamSet<(
-        Query<&mut Transform, (With<Player>, Without<Camera3d>)>,
-        Query<(&mut Transform, &mut PlayerCamera), With<Camera3d>>,
-    )>,
-) {
-    if !locked.0 {
-        mouse_evr.clear();
-        return;
-    }
-
-    let mut delta = Vec2::ZERO;
-    for ev in mouse_evr.read() {
-        delta += ev.delta;
-    }
-    // 微小ノイズ（トラックパッド等）を無視するデッドゾーン
-    if delta.length_squared() < 0.04 {
-        // ~0.2px 相当
-        return;
-    }
-
-    // まずカメラ側で yaw/pitch を更新し、必要値をローカルに保持
-    let mut new_yaw: f32 = 0.0;
-    let mut new_pitch: f32 = 0.0;
-    {
-        let mut cam_query = q.p1();
-        let Ok((mut cam_tf, mut pcam)) = cam_query.get_single_mut() else {
-            return;
-        };
-        let ads_key = keys.pressed(KeyCode::ShiftLeft) || keys.pressed(KeyCode::ShiftRight);
-        let sens_mul = if buttons.pressed(MouseButton::Right) || ads_key {
-            ADS_SENS_MUL
-        } else {
-            1.0
-        };
-        let sens = MOUSE_SENSITIVITY * sens_mul;
-        pcam.yaw = wrap_pi(pcam.yaw - delta.x * sens);
-        pcam.pitch = (pcam.pitch - delta.y * sens).clamp(-1.54, 1.54);
-        new_yaw = pcam.yaw;
-        new_pitch = pcam.pitch;
-        cam_tf.rotation = Quat::from_rotation_x(pcam.pitch);
-    }
-
-    // 次にプレイヤーの yaw 回転を反映（別スコープで別クエリを借用）
-    let mut player_query = q.p0();
-    if let Ok(mut player_tf) = player_query.get_single_mut() {
-        player_tf.rotation = Quat::from_rotation_y(new_yaw);
-    }
-}
-
-fn setup_hud(mut commands: Commands) {
-    // HP表示（左下）
-    commands.spawn((
-        TextBundle::from_section(
-            "HP: 100",
-            TextStyle {
-                font_size: 36.0,
-                color: Color::BLACK,
-                ..default()
-            },
-        )
-        .with_style(Style {
-            position_type: PositionType::Absolute,
-            left: Val::Px(10.0),
-            bottom: Val::Px(10.0),
-            ..default()
-        }),
-        UiHp,
-    ));
-
-    // FPS表示（左上）
-    commands.spawn((
-        TextBundle::from_section(
-            "FPS: --",
-            TextStyle {
-                font_size: 18.0,
-                color: Color::BLACK,
-                ..default()
-            },
-        )
-        .with_style(Style {
-            position_type: PositionType::Absolute,
-            left: Val::Px(10.0),
-            top: Val::Px(10.0),
-            ..default()
-        }),
-        UiFps,
-    ));
-
-    // ヒットマーカー（中心に薄いX、初期は透過）
-    commands.spawn((
-        TextBundle {
-            text: Text::from_section(
-                "x",
-                TextStyle {
-                    font_size: 40.0,
-                    color: Color::srgba(0.0, 0.0, 0.0, 0.0),
-                    ..default()
-                },
-            ),
-            style: Style {
-                position_type: PositionType::Absolute,
-                left: Val::Percent(50.0),
-                top: Val::Percent(50.0),
-                ..default()
-            },
-            ..default()
-        },
-        UiHitMarker {
-            timer: Timer::from_seconds(0.0, TimerMode::Once),
-        },
-    ));
-
-    // キルログ（右上、縦積み）
-    commands.spawn((
-        NodeBundle {
-            style: Style {
-                position_type: PositionType::Absolute,
-                right: Val::Px(10.0),
-                top: Val::Px(10.0),
-                flex_direction: FlexDirection::Column,
-                row_gap: Val::Px(4.0),
-                ..default()
-            },
-            background_color: BackgroundColor(Color::NONE),
-            ..default()
-        },
-        UiKillLog,
-    ));
-
-    // スコアボード（中央上、非表示）
-    commands.spawn((
-        NodeBundle {
-            style: Style {
-                position_type: PositionType::Absolute,
-                left: Val::Percent(50.0),
-                top: Val::Px(40.0),
-                flex_direction: FlexDirection::Column,
-                row_gap: Val::Px(2.0),
-                ..default()
-            },
-            background_color: BackgroundColor(Color::NONE),
-            visibility: Visibility::Hidden,
-            ..default()
-        },
-        UiScoreboard,
-    ));
-
-    // ラウンド表示（中央上部、常時）
-    commands.spawn((
-        TextBundle::from_section(
-            "",
-            TextStyle {
-                font_size: 32.0,
-                color: Color::BLACK,
-                ..default()
-            },
-        )
-        .with_style(Style {
-            position_type: PositionType::Absolute,
-            left: Val::Percent(50.0),
-            top: Val::Px(12.0),
-            ..default()
-        }),
-        UiRoundText,
-    ));
-
-    // 弾数（右下）
-    commands.spawn((
-        TextBundle::from_section(
-            "Ammo: 0",
-            TextStyle {
-                font_size: 28.0,
-                color: Color::BLACK,
-                ..default()
+    let mut app = App::new();
+    app.add_plugins(DefaultPlugins.set(WindowPlugin {
+        primary_window: Some(Window {
+            title: "Bevy FPS".into(),
+            present_mode: if matches!(
+                std::env::var("NO_VSYNC").ok().as_deref(),
+                Some("1" | "true" | "TRUE")
+            ) {
+                bevy::window::PresentMode::AutoNoVsync
+            } else {
+                bevy::window::PresentMode::AutoVsync
             },
-        )
-        .with_style(Style {
-            position_type: PositionType::Absolute,
-            right: Val::Px(10.0),
-            bottom: Val::Px(10.0),
             ..default()
         }),
-        UiAmmo,
-    ));
-}
-
-#[derive(Component)]
-struct UiRoundText;
-
-#[derive(Component)]
-struct UiAmmo;
-
-fn round_ui_tick(
-    time: Res<Time>,
-    mut ui: ResMut<RoundUi>,
-    mut q: Query<&mut Text, With<UiRoundText>>,
-) {
-    if let Ok(mut t) = q.get_single_mut() {
-        if let Some(timer) = ui.phase_end.as_mut() {
-            timer.tick(time.delta());
-            let remain = (timer.duration().as_secs_f32() - timer.elapsed_secs()).max(0.0);
-            t.sections[0].value = format!(
-                "Round End{}  Next: {:.0}s",
-                ui.winner
-                    .map(|w| format!("  Winner {}", w))
-                    .unwrap_or_default(),
-                remain
-            );
-        } else {
-            ui.time_left = (ui.time_left - time.delta_seconds()).max(0.0);
-            let m = (ui.time_left as i32 / 60).max(0);
-            let s = (ui.time_left as i32 % 60).max(0);
-            t.sections[0].value = format!("Time {:02}:{:02}", m, s);
-        }
-    }
-}
-
-// フォーカス喪失時に入力をクリアして「押しっぱなし」状態を解消
-fn handle_focus_events(
-    mut focused_events: EventReader<WindowFocused>,
-    mut keys: ResMut<ButtonInput<KeyCode>>,
-    mut buttons: ResMut<ButtonInput<MouseButton>>,
-    mut mouse_evr: EventReader<MouseMotion>,
-    mut locked: ResMut<CursorLocked>,
-    mut win_q: Query<&mut Window>,
-) {
-    for ev in focused_events.read() {
-        if !ev.focused {
-            // 入力状態をリセット
-            keys.clear();
-            buttons.clear();
-            mouse_evr.clear();
-            // カーソルを解放
-            locked.0 = false;
-            if let Ok(mut w) = win_q.get_single_mut() {
-                w.cursor.visible = true;
-                w.cursor.grab_mode = CursorGrabMode::None;
-            }
-        }
-    }
-}
-
-fn keyboard_look_system(
-    time: Res<Time>,
-    keys: Res<ButtonInput<KeyCode>>,
-    mut q: ParamSet<(
-        Query<&mut Transform, (With<Player>, Without<Camera3d>)>,
-        Query<(&mut Transform, &mut PlayerCamera), With<Camera3d>>,
-    )>,
-) {
-    let dt = time.delta_seconds();
-    let horiz =
-        (keys.pressed(KeyCode::ArrowRight) as i32 - keys.pressed(KeyCode::ArrowLeft) as i32) as f32;
-    let vert =
-        (keys.pressed(KeyCode::ArrowDown) as i32 - keys.pressed(KeyCode::ArrowUp) as i32) as f32;
-    if horiz == 0.0 && vert == 0.0 {
-        return;
-    }
-
-    let mut new_yaw = 0.0f32;
-    let mut new_pitch = 0.0f32;
-    {
-        let mut cam_query = q.p1();
-        let Ok((mut cam_tf, mut pcam)) = cam_query.get_single_mut() else {
-            return;
-        };
-        // 矢印キー: 右=右回転（マウスの正方向と同じく yaw を減算）、上=上向き（pitch 減算）
-        pcam.yaw = wrap_pi(pcam.yaw - horiz * KEY_LOOK_SPEED * dt);
-        pcam.pitch = (pcam.pitch - vert * KEY_LOOK_SPEED * dt).clamp(-1.54, 1.54);
-        new_yaw = pcam.yaw;
-        new_pitch = pcam.pitch;
-        cam_tf.rotation = Quat::from_rotation_x(new_pitch);
-    }
-    let mut player_query = q.p0();
-    if let Ok(mut player_tf) = player_query.get_single_mut() {
-        player_tf.rotation = Quat::from_rotation_y(new_yaw);
-    }
-}
-
-fn toggle_debug_colliders(
-    keys: Res<ButtonInput<KeyCode>>,
-    mut ctx: ResMut<DebugRenderContext>,
-) {
-    if keys.just_pressed(KeyCode::F3) {
-        ctx.enabled = !ctx.enabled;
-        info!(
-            target: "debug",
-            "rapier collider debug render {}",
-            if ctx.enabled { "enabled" } else { "disabled" }
-        );
-    }
-}
-
-fn kcc_move_system(
-    time: Res<Time>,
-    keys: Res<ButtonInput<KeyCode>>,
-    buttons: Res<ButtonInput<MouseButton>>,
-    cam_q: Query<&PlayerCamera, With<Camera3d>>,
-    mut q: Query<(&mut KinematicCharacterController, &mut Controller), With<Player>>,
-    ready: Res<MapReady>,
-) {
-    if !ready.0 {
-        return;
-    }
-    let (mut kcc, mut ctrl) = if let Ok(v) = q.get_single_mut() {
-        v
-    } else {
-        return;
-    };
-    let cam = if let Ok(v) = cam_q.get_single() {
-        v
-    } else {
-        return;
-    };
-
-    // 入力（水平面）
-    let mut input = Vec3::ZERO;
-    if keys.pressed(KeyCode::KeyW) {
-        input += Vec3::NEG_Z;
-    }
-    if keys.pressed(KeyCode::KeyS) {
-        input += Vec3::Z;
-    }
-    if keys.pressed(KeyCode::KeyA) {
-        input += Vec3::NEG_X;
-    }
-    if keys.pressed(KeyCode::KeyD) {
-        input += Vec3::X;
-    }
-
-    let mut horiz = Vec3::ZERO;
-    if input.length_squared() > 1e-6 {
-        let yaw_rot = Quat::from_rotation_y(cam.yaw);
-        horiz = (yaw_rot * input).normalize();
-    }
-
-    // スピード調整（スプリント削除）
-    let mut speed = MOVE_SPEED;
-    let ads_key = keys.pressed(KeyCode::ShiftLeft) || keys.pressed(KeyCode::ShiftRight);
-    if buttons.pressed(MouseButton::Right) || ads_key {
-        speed *= shared_consts::ADS_SPEED_MUL;
-    }
-
-    // 重力・ジャンプ
-    let dt = time.delta_seconds();
-    ctrl.vy -= GRAVITY * dt;
-    if keys.just_pressed(KeyCode::Space) {
-        // 地上なら通常ジャンプ、空中なら1回だけ追加ジャンプを許可
-        if ctrl.on_ground || ctrl.jumps < 1 {
-            ctrl.vy = JUMP_SPEED;
-            if !ctrl.on_ground {
-                ctrl.jumps = ctrl.jumps.saturating_add(1);
-            }
-            ctrl.on_ground = false;
-            // サーバと一致: このフレームは地面スナップを無効化して初速を確実に反映
-            kcc.snap_to_ground = None;
-        }
-    }
-
-    let motion = horiz * speed * dt + Vec3::Y * ctrl.vy * dt;
-    kcc.translation = Some(motion);
-}
-
-fn kcc_post_step_system(
-    mut q: Query<(&mut Controller, Option<&KinematicCharacterControllerOutput>), With<Player>>,
-    mut qk: Query<&mut KinematicCharacterController, With<Player>>,
-) {
-    let (mut ctrl, output) = if let Ok(v) = q.get_single_mut() {
-        v
-    } else {
-        return;
-    };
-    let mut shallow_debug: Option<(f32, Vec<(Entity, Vec3)>)> = None;
-    if let Some(out) = output {
-        ctrl.on_ground = out.grounded;
-        let mut grounded_by_floor = false;
-        if out.grounded && ctrl.vy <= 0.0 {
-            let prev_vy = ctrl.vy;
-            let mut shallow_contacts = Vec::new();
-            for col in &out.collisions {
-                // 壁など浅い法線で接地扱いになったケースを記録
-                if let Some(details) = col.hit.details {
-                    let normal: Vec3 = details.normal1.into();
-                    if normal.y >= shared_consts::GROUND_NORMAL_MIN_Y {
-                        grounded_by_floor = true;
-                    } else {
-                        shallow_contacts.push((col.entity, normal));
-                    }
-                }
-            }
-            if grounded_by_floor {
-                ctrl.vy = 0.0;
-                ctrl.jumps = 0; // 地上に戻ったので空中ジャンプ回数をリセット
-            } else if !shallow_contacts.is_empty() {
-                shallow_debug = Some((prev_vy, shallow_contacts));
-            }
-        }
-        if !grounded_by_floor {
-            ctrl.on_ground = false;
-        }
-    }
-    // ジャンプフレームで無効化した snap_to_ground を復帰
-    if let Ok(mut kcc) = qk.get_single_mut() {
-        if let Some((prev_vy, shallow_contacts)) = shallow_debug.take() {
-            let normals: Vec<String> = shallow_contacts
-                .iter()
-                .map(|(entity, normal)| {
-                    format!(
-                        "{:?}:({:.2},{:.2},{:.2})",
-                        entity, normal.x, normal.y, normal.z
-                    )
-                })
-                .collect();
-            info!(
-                target: "kcc::grounding",
-                "grounded via shallow normal prev_vy={:.3} snap_to_ground={} normals={:?}",
-                prev_vy,
-                kcc.snap_to_ground.is_some(),
-                normals,
-            );
-            // 浅い法線で接地扱いになっているのでスナップを強制解除
-            kcc.snap_to_ground = None;
-        } else if ctrl.on_ground {
-            if kcc.snap_to_ground.is_none() {
-                kcc.snap_to_ground = Some(CharacterLength::Absolute(0.25));
-            }
-        } else {
-            kcc.snap_to_ground = None;
-        }
-    }
-}
-
-// サーバ実装と整合したローカルKCC移動（ジャンプ品質を統一）
-fn kcc_move_system_unified(
-    mut q: Query<(&mut KinematicCharacterController, &mut Controller), With<Player>>,
-    ready: Res<MapReady>,
-    mut pending: ResMut<PendingPredictionFrames>,
-) {
-    if !ready.0 {
-        pending.0.clear();
-        return;
-    }
-    let (mut kcc, mut ctrl) = if let Ok(pair) = q.get_single_mut() {
-        pair
-    } else {
-        pending.0.clear();
-        return;
-    };
-    if pending.0.is_empty() {
-        kcc.translation = Some(Vec3::ZERO);
-        return;
-    }
-
-    let mut total_motion = Vec3::ZERO;
-    let mut disable_snap = false;
-
-    while let Some(frame) = pending.0.pop_front() {
-        if frame.jump {
-            ctrl.jump_buf = shared_consts::JUMP_BUFFER_SEC;
-        }
-        if ctrl.jump_buf > 0.0 {
-            ctrl.jump_buf = (ctrl.jump_buf - frame.dt).max(0.0);
-        }
-        if ctrl.jump_cd > 0.0 {
-            ctrl.jump_cd = (ctrl.jump_cd - frame.dt).max(0.0);
-        }
-        if ctrl.on_ground {
-            ctrl.coyote = shared_consts::COYOTE_SEC;
-            ctrl.jumps = 0;
-        } else if ctrl.coyote > 0.0 {
-            ctrl.coyote = (ctrl.coyote - frame.dt).max(0.0);
-        }
-
-        ctrl.vy -= shared_consts::GRAVITY * frame.dt;
-        let mut jumped_now = false;
-        if ctrl.jump_buf > 0.0 && ctrl.jump_cd <= 0.0 {
-            if ctrl.on_ground || ctrl.coyote > 0.0 {
-                ctrl.vy = shared_consts::JUMP_SPEED;
-                ctrl.jump_buf = 0.0;
-                jumped_now = true;
-            } else if ctrl.jumps < 1 {
-                ctrl.vy = shared_consts::JUMP_SPEED;
-                ctrl.jumps = ctrl.jumps.saturating_add(1);
-                ctrl.jump_buf = 0.0;
-                jumped_now = true;
-            }
-        }
-
-        let mut horiz = Vec3::ZERO;
-        let input = Vec3::new(frame.mv[0], 0.0, frame.mv[1]);
-        if input.length_squared() > 1e-6 {
-            let yaw_rot = Quat::from_rotation_y(frame.yaw);
-            horiz = (yaw_rot * input).normalize();
-        }
-        let mut speed = MOVE_SPEED;
-        if frame.ads {
-            speed *= shared_consts::ADS_SPEED_MUL;
-        }
-        let motion = horiz * speed * frame.dt + Vec3::Y * ctrl.vy * frame.dt;
-        total_motion += motion;
-
-        if jumped_now {
-            ctrl.on_ground = false;
-            ctrl.jump_cd = shared_consts::JUMP_COOLDOWN_SEC;
-            disable_snap = true;
-        }
-    }
-
-    if disable_snap {
-        kcc.snap_to_ground = None;
-    }
-    kcc.translation = Some(total_motion);
-}
-
-// 空中時は地面スナップを無効化して「地上にワープ」する補正を防ぐ。
-fn client_airborne_snap_control(
-    mut qk: Query<&mut KinematicCharacterController, With<Player>>,
-    q: Query<&Controller, With<Player>>,
-) {
-    let ctrl = if let Ok(c) = q.get_single() {
-        c
-    } else {
-        return;
-    };
-    if let Ok(mut kcc) = qk.get_single_mut() {
-        if ctrl.on_ground {
-            if kcc.snap_to_ground.is_none() {
-                kcc.snap_to_ground = Some(CharacterLength::Absolute(0.25));
-            }
-        } else {
-            kcc.snap_to_ground = None;
-        }
-    }
-}
-
-/*
-fn shoot_system(
-    buttons: Res<ButtonInput<MouseButton>>,
-    keys: Res<ButtonInput<KeyCode>>,
-    cam_global_q: Query<&GlobalTransform, With<Camera3d>>,
-    mut commands: Commands,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
-    ammo: Res<LocalAmmo>,
-) {
-    if !(buttons.just_pressed(MouseButton::Left) || keys.just_pressed(KeyCode::KeyF)) {
-        return;
-    }
-    // リロード中や弾0のときは見た目の弾は出さない（サーバー権威の判定は継続）
-    if ammo.reloading || ammo.ammo == 0 {
-        return;
-    }
-    let cam_g = if let Ok(v) = cam_global_q.get_single() { v } else { return };
-
-    let forward: Vec3 = cam_g.forward().into();
-    let start = cam_g.translation();
-
-    // 小さな弾体（可視化用）
-    let mesh = meshes.add(Sphere::new(0.04).mesh().ico(4).unwrap());
-    let mat = materials.add(Color::srgb(1.0, 0.9, 0.2));
-
-    commands.spawn((
-        PbrBundle {
-            mesh,
-            material: mat,
-            transform: Transform::from_translation(start),
-            ..default()
-        },
-        Bullet {
-            dir: forward,
-            speed: BULLET_SPEED,
-            life: Timer::from_seconds(BULLET_LIFETIME, TimerMode::Once),
-        },
-    ));
-
-    // 砲口フラッシュっぽいライト（短命）
-    commands.spawn((
-        PointLightBundle {
-            point_light: PointLight { intensity: 500.0, range: 4.0, ..default() },
-            transform: Transform::from_translation(start + forward * 0.1),
-            ..default()
-        },
-        Bullet { dir: Vec3::ZERO, speed: 0.0, life: Timer::from_seconds(0.06, TimerMode::Once) },
-    ));
-}
-
-fn bullet_move_and_despawn(
-    time: Res<Time>,
-    mut commands: Commands,
-    mut bullets: Query<(Entity, &mut Transform, &mut Bullet)>,
-) {
-    for (e, mut tf, mut b) in &mut bullets {
-        b.life.tick(time.delta());
-        if b.life.finished() {
-            commands.entity(e).despawn_recursive();
-            continue;
-        }
-        if b.speed > 0.0 {
-            tf.translation += b.dir * b.speed * time.delta_seconds();
-        }
-    }
-}
-
-*/
-// ===== HUD Systems =====
-fn hud_update_hp(mut q: Query<&mut Text, With<UiHp>>, hp: Res<LocalHealth>) {
-    if let Ok(mut t) = q.get_single_mut() {
-        t.sections[0].value = format!("HP: {}", hp.hp);
-        t.sections[0].style.color = Color::BLACK;
-    }
-}
-
-fn hud_tick_hit_marker(time: Res<Time>, mut q: Query<(&mut UiHitMarker, &mut Text)>) {
-    if let Ok((mut hm, mut text)) = q.get_single_mut() {
-        hm.timer.tick(time.delta());
-        let d = hm.timer.duration().as_secs_f32();
-        let alpha = if d <= 0.0 {
-            0.0
-        } else {
-            (d - hm.timer.elapsed_secs()).max(0.0) / d
-        };
-        text.sections[0].style.color = Color::srgba(0.0, 0.0, 0.0, alpha.clamp(0.0, 1.0));
-    }
-}
-
-fn hud_tick_killlog(
-    time: Res<Time>,
-    mut commands: Commands,
-    mut q: Query<(Entity, &mut UiKillEntry, &mut Text)>,
-) {
-    for (e, mut entry, mut text) in &mut q {
-        entry.timer.tick(time.delta());
-        let d = entry.timer.duration().as_secs_f32().max(0.0001);
-        let remain = (d - entry.timer.elapsed_secs()).max(0.0) / d;
-        text.sections[0].style.color = Color::srgba(1.0, 1.0, 1.0, remain.clamp(0.0, 1.0));
-        if entry.timer.finished() {
-            commands.entity(e).despawn_recursive();
-        }
-    }
-}
-
-// GLBのメッシュに静的コライダーを自動付与
-fn add_mesh_colliders_for_map(
-    mut commands: Commands,
-    meshes: Res<Assets<Mesh>>,
-    mut ready: ResMut<MapReady>,
-    q: Query<(Entity, &Handle<Mesh>), (Added<Handle<Mesh>>, Without<Collider>, Without<Player>)>,
-) {
-    let mut any_inserted = false;
-    for (e, h) in &q {
-        if let Some(mesh) = meshes.get(h) {
-            if let Some(collider) = Collider::from_bevy_mesh(mesh, &ComputedColliderShape::TriMesh)
-            {
-                commands.entity(e).insert((collider, RigidBody::Fixed));
-                any_inserted = true;
-            }
-        }
-    }
-    if any_inserted && !ready.0 {
-        ready.0 = true;
-        info!("Map colliders ready (client)");
-    }
-}
-
-#[derive(Resource, Default)]
-struct ConnStatePrev {
-    connected: bool,
-}
-
-fn net_log_connection(mut prev: ResMut<ConnStatePrev>, mut client: ResMut<RenetClient>) {
-    let is_conn = client.is_connected();
-    if is_conn != prev.connected {
-        if is_conn {
-            info!("Client connected to server");
-        } else {
-            info!("Client not connected; attempting handshake to 127.0.0.1:5000");
-        }
-        prev.connected = is_conn;
-    }
-}
-
-// --- Networking (client) ---
-
-#[derive(Component)]
-struct RemoteAvatar {
-    id: u64,
-}
-
-#[derive(Resource, Default)]
-struct RemoteMap(std::collections::HashMap<u64, Entity>);
-
-#[derive(Resource)]
-struct LocalNetInfo {
-    id: u64,
-}
-
-#[derive(Resource, Default)]
-struct InputSeq(u32);
-
-#[derive(Resource, Default)]
-struct AuthoritativeSelf {
-    pos: Option<Vec3>,
-    yaw: Option<f32>,
-    vy: Option<f32>,
-    grounded: Option<bool>,
-}
-
-#[derive(Resource)]
-struct LocalHealth {
-    hp: u16,
-}
-
-// 入力再適用のための最小バッファ/ACK（将来の拡張に備えた土台）
-#[derive(Resource, Default)]
-struct InputBuffer(std::collections::VecDeque<InputFrame>);
-#[derive(Resource, Default)]
-struct PredictionAccumulator {
-    remaining: f32,
-    pending_jump: bool,
-}
-
-#[derive(Resource, Default)]
-struct PendingPredictionFrames(std::collections::VecDeque<InputFrame>);
-
-#[derive(Resource, Default)]
-struct LastConfirmedSeq(Option<u32>);
-
-// 受信スナップショットの最新tickを保持（古いスナップ適用を防ぐ）
-#[derive(Resource, Default)]
-struct LastSnapshotTick(Option<u32>);
-
-// リモートアバター補間用のサンプル
-#[derive(Clone, Copy)]
-struct RemoteSample {
-    tick: u32,
-    pos: Vec3,
-    yaw: f32,
-}
-
-// エンティティIDごとのサンプル履歴
-#[derive(Resource, Default)]
-struct RemoteHistory(std::collections::HashMap<u64, std::collections::VecDeque<RemoteSample>>);
-
-// レンダ遅延（tick数）。到着ジッタ平滑化のため既定3tick（~100ms@30Hz）
-#[derive(Resource)]
-struct InterpDelayTicks(u32);
-
-// 直近のローカル発砲記録（VFX重複抑止用）
-#[derive(Resource, Default)]
-struct RecentLocalFires(std::collections::VecDeque<(f32, Vec3, Vec3)>); // (time, origin, dir)
-
-fn setup_net_client(mut commands: Commands) {
-    let (client, transport, client_id) = new_client(None);
-    commands.insert_resource(client);
-    commands.insert_resource(transport);
-    commands.insert_resource(LocalNetInfo {
-        id: client_id.raw(),
-    });
-    commands.insert_resource(RemoteMap::default());
-    commands.insert_resource(InputSeq::default());
-    commands.insert_resource(PredictionAccumulator::default());
-    commands.insert_resource(PendingPredictionFrames::default());
-    commands.insert_resource(AuthoritativeSelf::default());
-    commands.insert_resource(LocalHealth { hp: 100 });
-    commands.insert_resource(ScoreData::default());
-    commands.insert_resource(ScoreVisible::default());
-    commands.insert_resource(RoundUi::default());
-    commands.insert_resource(LocalAmmo {
-        ammo: 0,
-        reloading: false,
-    });
-    commands.insert_resource(LocalWeaponState::default());
-    commands.insert_resource(InputBuffer::default());
-    commands.insert_resource(LastConfirmedSeq::default());
-    commands.insert_resource(LastSnapshotTick::default());
-    commands.insert_resource(RemoteHistory::default());
-    commands.insert_resource(InterpDelayTicks(3));
-    commands.insert_resource(RecentLocalFires::default());
-}
-
-// ===== Scaffold Systems =====
-fn scaffold_input_system(
-    keys: Res<ButtonInput<KeyCode>>,
-    cam_q: Query<&GlobalTransform, With<Camera3d>>,
-    player_q: Query<Entity, With<Player>>,
-    rapier: Res<RapierContext>,
-    mut commands: Commands,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
-    local_id: Res<LocalNetInfo>,
-    mut owned: ResMut<LocalScaffolds>,
-    mut client: ResMut<RenetClient>,
-    mut ghost: ResMut<LocalGhostScaffold>,
-) {
-    if !keys.just_pressed(KeyCode::KeyQ) {
-        return;
-    }
-
-    // 接続中はローカル生成せず、サーバへ生成要求のみ送る
-    if client.is_connected() {
-        // クライアント単体時と同じロジックでヒット点を出し、最終配置座標を送る
-        let cam_g = if let Ok(v) = cam_q.get_single() {
-            v
-        } else {
-            return;
-        };
-        let player_ent = if let Ok(e) = player_q.get_single() {
-            e
-        } else {
-            return;
-        };
-        let origin = cam_g.translation();
-        let dir: Vec3 = cam_g.forward().into();
-        if dir.length_squared() < 1e-6 {
-            return;
-        }
-        let mut hit_pos = origin + dir * SCAFFOLD_RANGE;
-        if let Some((_entity, toi)) = rapier.cast_ray(
-            origin,
-            dir,
-            SCAFFOLD_RANGE,
-            true,
-            QueryFilter::default()
-                .exclude_collider(player_ent)
-                .exclude_sensors(),
-        ) {
-            hit_pos = origin + dir * toi;
-        }
-        let place_pos = hit_pos + Vec3::Y * (SCAFFOLD_SIZE.y * 0.5 + 0.01);
-        if let Ok(bytes) = bincode::serialize(&ClientMessage::PlaceScaffold {
-            pos: [place_pos.x, place_pos.y, place_pos.z],
-        }) {
-            let _ = client.send_message(CH_RELIABLE, bytes);
-        }
-        // 視覚フィードバック用のゴースト足場（コライダー無し）を即時表示
-        // 既存のゴーストがあれば消す
-        if let Some(e) = ghost.0.take() {
-            commands.entity(e).despawn_recursive();
-        }
-        let mesh = meshes.add(Cuboid::new(
-            SCAFFOLD_SIZE.x,
-            SCAFFOLD_SIZE.y,
-            SCAFFOLD_SIZE.z,
-        ));
-        let col = Color::srgba(0.2, 0.9, 1.0, 0.25);
-        let mat = materials.add(StandardMaterial {
-            base_color: col,
-            emissive: Color::srgb(0.2, 0.6, 0.9).into(),
-            alpha_mode: AlphaMode::Blend,
-            unlit: true,
-            ..default()
-        });
-        let ent = commands
-            .spawn((
-                PbrBundle {
-                    mesh,
-                    material: mat,
-                    transform: Transform::from_translation(place_pos),
-                    ..default()
-                },
-                GhostScaffold,
-            ))
-            .id();
-        ghost.0 = Some(ent);
-        return;
-    }
-    let cam_g = if let Ok(v) = cam_q.get_single() {
-        v
-    } else {
-        return;
-    };
-    let player_ent = if let Ok(e) = player_q.get_single() {
-        e
-    } else {
-        return;
-    };
-
-    let origin = cam_g.translation();
-    let dir: Vec3 = cam_g.forward().into();
-
-    let mut hit_pos = origin + dir * SCAFFOLD_RANGE;
-    if let Some((entity, toi)) = rapier.cast_ray(
-        origin,
-        dir,
-        SCAFFOLD_RANGE,
-        true,
-        QueryFilter::default()
-            .exclude_collider(player_ent)
-            .exclude_sensors(),
-    ) {
-        let _ = entity; // 現状は未使用
-        hit_pos = origin + dir * toi;
-    }
-
-    // 常に水平（Y+ up）で配置。床の場合は僅かに浮かせてZファイティング回避
-    let place_pos = hit_pos + Vec3::Y * (SCAFFOLD_SIZE.y * 0.5 + 0.01);
-
-    // 3つ上限：超えたら一番古いものを消す
-    if owned.0.len() >= SCAFFOLD_PER_PLAYER_LIMIT {
-        if let Some(old) = owned.0.first().copied() {
-            commands.entity(old).despawn_recursive();
-        }
-        owned.0.remove(0);
-    }
-
-    let mesh = meshes.add(Cuboid::new(
-        SCAFFOLD_SIZE.x,
-        SCAFFOLD_SIZE.y,
-        SCAFFOLD_SIZE.z,
-    ));
-    let col = Color::srgba(0.2, 0.9, 1.0, 0.45);
-    let mat = materials.add(StandardMaterial {
-        base_color: col,
-        emissive: Color::srgb(0.3, 0.8, 1.0).into(),
-        alpha_mode: AlphaMode::Blend,
-        unlit: true,
         ..default()
-    });
-
-    let ent = commands
-        .spawn((
-            PbrBundle {
-                mesh,
-                material: mat,
-                transform: Transform::from_translation(place_pos),
-                ..default()
-            },
-            Scaffold {
-                hp: SCAFFOLD_HP,
-                owner: local_id.id,
-            },
-            Collider::cuboid(
-                SCAFFOLD_SIZE.x * 0.5,
-                SCAFFOLD_SIZE.y * 0.5,
-                SCAFFOLD_SIZE.z * 0.5,
-            ),
-            RigidBody::Fixed,
-        ))
-        .id();
-
-    owned.0.push(ent);
-}
-
-fn hud_update_ammo(mut q: Query<&mut Text, With<UiAmmo>>, ammo: Res<LocalAmmo>) {
-    if let Ok(mut t) = q.get_single_mut() {
-        if ammo.reloading {
-            t.sections[0].value = format!("Reloading...");
-        } else {
-            t.sections[0].value = format!("Ammo: {}", ammo.ammo);
-        }
-    }
-}
-
-fn fps_update_system(
-    time: Res<Time>,
-    diagnostics: Res<DiagnosticsStore>,
-    mut timer: ResMut<FpsTextTimer>,
-    mut q: Query<&mut Text, With<UiFps>>,
-) {
-    timer.0.tick(time.delta());
-    if !timer.0.finished() {
-        return;
-    }
-
-    if let Ok(mut t) = q.get_single_mut() {
-        if let Some(fps) = diagnostics.get(&FrameTimeDiagnosticsPlugin::FPS) {
-            if let Some(avg) = fps.smoothed() {
-                t.sections[0].value = format!("FPS: {:.0}", avg);
-            } else if let Some(val) = fps.value() {
-                t.sections[0].value = format!("FPS: {:.0}", val);
-            }
-        }
-    }
-}
-
-fn net_send_input(
-    time: Res<Time>,
-    keys: Res<ButtonInput<KeyCode>>,
-    buttons: Res<ButtonInput<MouseButton>>,
-    cam_q: Query<(&Transform, &PlayerCamera), With<Camera3d>>,
-    player_tf_q: Query<&Transform, With<Player>>,
-    mut commands: Commands,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
-    mut client: ResMut<RenetClient>,
-    mut seq: ResMut<InputSeq>,
-    mut buf: ResMut<InputBuffer>,
-    mut accumulator: ResMut<PredictionAccumulator>,
-    mut pending_frames: ResMut<PendingPredictionFrames>,
-    last_conf: Res<LastConfirmedSeq>,
-    mut weapon: ResMut<LocalWeaponState>,
-    mut recent: ResMut<RecentLocalFires>,
-) {
-    let (cam_tf_local, cam) = if let Ok(v) = cam_q.get_single() {
-        v
-    } else {
-        return;
-    };
-    let player_tf = if let Ok(tf) = player_tf_q.get_single() {
-        tf
-    } else {
-        return;
-    };
-
-    weapon.fire_cd = (weapon.fire_cd - time.delta_seconds()).max(0.0);
-
-    accumulator.remaining += time.delta_seconds();
-    if accumulator.remaining > PREDICTION_DT * 5.0 {
-        accumulator.remaining = PREDICTION_DT * 5.0;
-    }
-
-    let mut mv = [0.0f32, 0.0f32];
-    if keys.pressed(KeyCode::KeyW) {
-        mv[1] -= 1.0;
-    }
-    if keys.pressed(KeyCode::KeyS) {
-        mv[1] += 1.0;
+    }))
+    .add_plugins((
+        RapierPhysicsPlugin::<NoUserData>::default(),
+        RapierDebugRenderPlugin::default(),
+    ))
+    .add_plugins(ClientPlugins);
+    if let Some(demo) = demo {
+        app.insert_resource(demo);
     }
-    if keys.pressed(KeyCode::KeyA) {
-        mv[0] -= 1.0;
+    if let Some(token) = token {
+        app.insert_resource(IssuedToken(token));
     }
-    if keys.pressed(KeyCode::KeyD) {
-        mv[0] += 1.0;
-    }
-
-    let ads_key = keys.pressed(KeyCode::ShiftLeft) || keys.pressed(KeyCode::ShiftRight);
-    let ads = buttons.pressed(MouseButton::Right) || ads_key;
-
-    let fire_hold = buttons.pressed(MouseButton::Left) || keys.pressed(KeyCode::KeyF);
-    let fire_trigger = buttons.just_pressed(MouseButton::Left) || keys.just_pressed(KeyCode::KeyF);
-    if keys.just_pressed(KeyCode::Space) {
-        accumulator.pending_jump = true;
-    }
-
-    let yaw_rot = player_tf.rotation;
-    let pitch_rot = Quat::from_rotation_x(cam.pitch);
-    let forward = yaw_rot * (pitch_rot * Vec3::NEG_Z);
-    let origin = player_tf.translation + yaw_rot * cam_tf_local.translation;
-    let shot_dir = forward.normalize_or_zero();
-
-    let can_fire_now = (fire_trigger || fire_hold)
-        && weapon.fire_cd <= 0.0
-        && shot_dir.length_squared() > 1e-6;
-
-    let mut fire_flag_sent = false;
-    while accumulator.remaining >= PREDICTION_DT {
-        accumulator.remaining -= PREDICTION_DT;
-        seq.0 = seq.0.wrapping_add(1);
-        let jump = if accumulator.pending_jump {
-            accumulator.pending_jump = false;
-            true
-        } else {
-            false
-        };
-        let fire_event = can_fire_now && !fire_flag_sent;
-        if fire_event {
-            fire_flag_sent = true;
-        }
-        let frame = InputFrame {
-            seq: seq.0,
-            mv,
-            jump,
-            fire: fire_event,
-            ads,
-            yaw: cam.yaw,
-            pitch: cam.pitch,
-            dt: PREDICTION_DT,
-        };
-        buf.0.push_back(frame.clone());
-        pending_frames.0.push_back(frame.clone());
-        if let Ok(bytes) = bincode::serialize(&ClientMessage::Input(frame)) {
-            let _ = client.send_message(CH_INPUT, bytes);
-        }
+    if let Some(name) = args.name {
+        app.insert_resource(LocalPlayerName(name));
     }
-
-    if let Some(ack) = last_conf.0 {
-        while let Some(front) = buf.0.front() {
-            if front.seq <= ack {
-                buf.0.pop_front();
-            } else {
-                break;
-            }
-        }
+    if let Some(password) = args.server_password {
+        app.insert_resource(ServerPassword(password));
     }
-
-    if can_fire_now {
-        if let Ok(bytes) = bincode::serialize(&ClientMessage::Fire {
-            origin: [origin.x, origin.y, origin.z],
-            dir: [shot_dir.x, shot_dir.y, shot_dir.z],
-        }) {
-            let _ = client.send_message(CH_RELIABLE, bytes);
-        }
-        weapon.fire_cd = FIRE_COOLDOWN;
-        let col = Color::srgb(0.95, 0.9, 0.2);
-        let mmesh = meshes.add(Cuboid::new(0.06, 0.06, 0.06));
-        let mmat = materials.add(StandardMaterial {
-            base_color: col,
-            emissive: col.into(),
-            unlit: true,
-            ..default()
-        });
-        commands.spawn((
-            PbrBundle {
-                mesh: mmesh,
-                material: mmat,
-                transform: Transform::from_translation(origin),
-                ..default()
-            },
-            MuzzleFx {
-                timer: Timer::from_seconds(0.06, TimerMode::Once),
-            },
-        ));
-        let seg = shot_dir * 50.0;
-        let len = seg.length();
-        if len > 0.001 {
-            let tmesh = meshes.add(Cuboid::new(0.02, 0.02, len.max(0.05)));
-            let tmat = materials.add(StandardMaterial {
-                base_color: col,
-                emissive: col.into(),
-                unlit: true,
-                ..default()
-            });
-            let rot = Quat::from_rotation_arc(Vec3::Z, seg.normalize());
-            let pos = origin + seg * 0.5;
-            commands.spawn((
-                PbrBundle {
-                    mesh: tmesh,
-                    material: tmat,
-                    transform: Transform {
-                        translation: pos,
-                        rotation: rot,
-                        scale: Vec3::ONE,
-                    },
-                    ..default()
-                },
-                TracerFx {
-                    timer: Timer::from_seconds(0.06, TimerMode::Once),
-                },
-            ));
-        }
-        recent
-            .0
-            .push_back((time.elapsed_seconds(), origin, shot_dir));
-        while let Some(&(t, _, _)) = recent.0.front() {
-            if time.elapsed_seconds() - t > 0.4 {
-                recent.0.pop_front();
-            } else {
-                break;
-            }
-        }
-    }
-}
-
-fn net_recv_snapshot(
-    mut commands: Commands,
-    mut client: ResMut<RenetClient>,
-    mut remap: ResMut<RemoteMap>,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
-    local: Res<LocalNetInfo>,
-    mut self_auth: ResMut<AuthoritativeSelf>,
-    mut kinds: ResMut<ActorKindsMap>,
-    mut positions: ResMut<ActorPositions>,
-    mut last_conf: ResMut<LastConfirmedSeq>,
-    mut last_tick: ResMut<LastSnapshotTick>,
-    mut rhist: ResMut<RemoteHistory>,
-) {
-    while let Some(raw) = client.receive_message(CH_SNAPSHOT) {
-        if let Ok(ServerMessage::Snapshot(snap)) = bincode::deserialize::<ServerMessage>(&raw) {
-            if matches!(
-                std::env::var("NET_SNAPSHOT_LOG").ok().as_deref(),
-                Some("1" | "true" | "TRUE")
-            ) && snap.players.len() > 0
-            {
-                info!("client: snapshot players={}", snap.players.len());
-            }
-            // 入力ACK: このクライアントの直近確定seqを拾い、未確定バッファの整理に使う
-            if let Some((_id, seq)) = snap.acks.iter().find(|(id, _)| *id == local.id) {
-                last_conf.0 = Some(*seq);
-            }
-            // 古いスナップは破棄（tick単調増加を前提）
-            if let Some(prev) = last_tick.0 {
-                if snap.tick <= prev {
-                    continue;
-                }
-            }
-            last_tick.0 = Some(snap.tick);
-            for p in snap.players {
-                kinds.0.insert(p.id, p.kind);
-                positions
-                    .0
-                    .insert(p.id, Vec3::new(p.pos[0], p.pos[1], p.pos[2]));
-                if p.id == local.id {
-                    self_auth.pos = Some(Vec3::new(p.pos[0], p.pos[1], p.pos[2]));
-                    self_auth.yaw = Some(p.yaw);
-                    self_auth.vy = Some(p.vy);
-                    self_auth.grounded = Some(p.grounded);
-                    continue;
-                }
-                let pos = Vec3::new(p.pos[0], p.pos[1], p.pos[2]);
-                if p.alive {
-                    // 補間用履歴に push（後段の補間システムが使用）
-                    let entry = rhist
-                        .0
-                        .entry(p.id)
-                        .or_insert_with(|| std::collections::VecDeque::with_capacity(16));
-                    entry.push_back(RemoteSample {
-                        tick: last_tick.0.unwrap_or(0),
-                        pos,
-                        yaw: p.yaw,
-                    });
-                    while entry.len() > 16 {
-                        entry.pop_front();
-                    }
-                    if let Some(&ent) = remap.0.get(&p.id) {
-                        if let Some(mut ec) = commands.get_entity(ent) {
-                            ec.insert(
-                                Transform::from_translation(pos)
-                                    .with_rotation(Quat::from_rotation_y(p.yaw)),
-                            );
-                        }
-                    } else {
-                        let mesh = meshes.add(Cuboid::new(0.4, 1.8, 0.4));
-                        let mat = materials.add(match p.kind {
-                            ActorKind::Human => Color::srgb(0.2, 0.9, 0.3),
-                            ActorKind::Bot => Color::srgb(0.9, 0.2, 0.2),
-                        });
-                        let ent = commands
-                            .spawn((
-                                PbrBundle {
-                                    mesh,
-                                    material: mat,
-                                    transform: Transform::from_translation(pos),
-                                    ..default()
-                                },
-                                RemoteAvatar { id: p.id },
-                            ))
-                            .id();
-                        remap.0.insert(p.id, ent);
-                    }
-                } else {
-                    if let Some(ent) = remap.0.remove(&p.id) {
-                        commands.entity(ent).despawn_recursive();
-                    }
-                }
-            }
-        }
-    }
-}
-
-// リモートアバターのTransformを補間して更新
-fn remote_interpolate_system(
-    remap: Res<RemoteMap>,
-    rhist: Res<RemoteHistory>,
-    delay: Res<InterpDelayTicks>,
-    last_tick: Res<LastSnapshotTick>,
-    mut q_tf: Query<&mut Transform>,
-) {
-    let Some(latest) = last_tick.0 else { return };
-    let target_tick = latest.saturating_sub(delay.0);
-    for (id, ent) in remap.0.iter() {
-        let Some(hist) = rhist.0.get(id) else {
-            continue;
-        };
-        if hist.is_empty() {
-            continue;
-        }
-        let mut a = hist.front().copied().unwrap();
-        let mut b = hist.back().copied().unwrap();
-        if target_tick <= a.tick {
-            b = a;
-        } else if target_tick >= b.tick {
-            a = b;
-        } else {
-            for w in hist.as_slices().0.windows(2) {
-                let x = w[0];
-                let y = w[1];
-                if x.tick <= target_tick && target_tick <= y.tick {
-                    a = x;
-                    b = y;
-                    break;
-                }
-            }
-        }
-        let t = if b.tick > a.tick {
-            (target_tick - a.tick) as f32 / (b.tick - a.tick) as f32
-        } else {
-            0.0
-        };
-        let pos = a.pos.lerp(b.pos, t.clamp(0.0, 1.0));
-        let dy = wrap_pi(b.yaw - a.yaw);
-        let yaw = wrap_pi(a.yaw + dy * t.clamp(0.0, 1.0));
-        if let Ok(mut tf) = q_tf.get_mut(*ent) {
-            tf.translation = pos;
-            tf.rotation = Quat::from_rotation_y(yaw);
-        }
-    }
-}
-
-fn net_recv_events(
-    mut commands: Commands,
-    mut client: ResMut<RenetClient>,
-    mut remap: ResMut<RemoteMap>,
-    mut sc_assets: NetScaffoldAssets,
-    local: Res<LocalNetInfo>,
-    mut self_auth: ResMut<AuthoritativeSelf>,
-    mut my_hp: ResMut<LocalHealth>,
-    mut hit_q: Query<&mut UiHitMarker>,
-    log_root_q: Query<Entity, With<UiKillLog>>,
-    mut score_data: ResMut<ScoreData>,
-    board_root_q: Query<Entity, With<UiScoreboard>>,
-    mut round_ui: ResMut<RoundUi>,
-    mut local_ammo: ResMut<LocalAmmo>,
-    mut kinds: ResMut<ActorKindsMap>,
-    mut player_q: Query<(&mut Transform, &mut Controller), With<Player>>,
-    mut ghost: ResMut<LocalGhostScaffold>,
-) {
-    while let Some(raw) = client.receive_message(CH_RELIABLE) {
-        if let Ok(msg) = bincode::deserialize::<ServerMessage>(&raw) {
-            match msg {
-                ServerMessage::Event(ev) => match ev {
-                    EventMsg::Spawn { id, pos, kind } => {
-                        let p = Vec3::new(pos[0], pos[1], pos[2]);
-                        kinds.0.insert(id, kind);
-                        if id == local.id {
-                            self_auth.pos = Some(p);
-                            my_hp.hp = 100;
-                            // Teleport local player to server spawn to avoid later corrections.
-                            if let Ok((mut tf, mut ctrl)) = player_q.get_single_mut() {
-                                tf.translation = p;
-                                ctrl.vy = 0.0;
-                                ctrl.on_ground = true;
-                                ctrl.jumps = 0;
-                            }
-                        } else {
-                            if let Some(&ent) = remap.0.get(&id) {
-                                if let Some(mut ec) = commands.get_entity(ent) {
-                                    ec.insert(Transform::from_translation(p));
-                                }
-                            } else {
-                                let mesh = sc_assets.meshes.add(Cuboid::new(0.4, 1.8, 0.4));
-                                let mat = sc_assets.materials.add(match kind {
-                                    ActorKind::Human => Color::srgb(0.2, 0.9, 0.3),
-                                    ActorKind::Bot => Color::srgb(0.9, 0.2, 0.2),
-                                });
-                                let ent = commands
-                                    .spawn((
-                                        PbrBundle {
-                                            mesh,
-                                            material: mat,
-                                            transform: Transform::from_translation(p),
-                                            ..default()
-                                        },
-                                        RemoteAvatar { id },
-                                    ))
-                                    .id();
-                                remap.0.insert(id, ent);
-                            }
-                        }
-                    }
-                    EventMsg::Despawn { id } => {
-                        if let Some(ent) = remap.0.remove(&id) {
-                            commands.entity(ent).despawn_recursive();
-                        }
-                    }
-                    EventMsg::Hit {
-                        target_id,
-                        new_hp,
-                        by,
-                    } => {
-                        if target_id == local.id {
-                            my_hp.hp = new_hp;
-                        }
-                        if by == local.id {
-                            if let Ok(mut hm) = hit_q.get_single_mut() {
-                                hm.timer.set_duration(Duration::from_secs_f32(0.15));
-                                hm.timer.reset();
-                            }
-                        }
-                        if target_id == local.id {
-                            // Add damage vignette overlay
-                            commands.spawn((
-                                NodeBundle {
-                                    style: Style {
-                                        position_type: PositionType::Absolute,
-                                        width: Val::Percent(100.0),
-                                        height: Val::Percent(100.0),
-                                        ..default()
-                                    },
-                                    background_color: BackgroundColor(Color::rgba(
-                                        0.8, 0.0, 0.0, 0.35,
-                                    )),
-                                    ..default()
-                                },
-                                UiDamageVignette {
-                                    timer: Timer::from_seconds(0.4, TimerMode::Once),
-                                },
-                            ));
-                        }
-                    }
-                    EventMsg::Death { target_id, by } => {
-                        if target_id == local.id {
-                            my_hp.hp = 0;
-                        }
-                        if let Some(ent) = remap.0.remove(&target_id) {
-                            commands.entity(ent).despawn_recursive();
-                        }
-                        // キルログ追加
-                        let killer = if by == local.id {
-                            "You".to_string()
-                        } else {
-                            format!("{}", by)
-                        };
-                        let victim = if target_id == local.id {
-                            "You".to_string()
-                        } else {
-                            format!("{}", target_id)
-                        };
-                        let line = format!("{} -> {}", killer, victim);
-                        if let Ok(root) = log_root_q.get_single() {
-                            commands.entity(root).with_children(|p| {
-                                p.spawn((
-                                    TextBundle::from_section(
-                                        line,
-                                        TextStyle {
-                                            font_size: 24.0,
-                                            color: Color::BLACK,
-                                            ..default()
-                                        },
-                                    ),
-                                    UiKillEntry {
-                                        timer: Timer::from_seconds(3.0, TimerMode::Once),
-                                    },
-                                ));
-                            });
-                        }
-                    }
-                    EventMsg::Fire {
-                        id,
-                        origin,
-                        dir,
-                        hit,
-                    } => {
-                        // 自分の発砲はローカル即時VFXを出しているため、サーバVFXは重複回避
-                        if id == local.id {
-                            continue;
-                        }
-                        // VFX: muzzle + tracer (+ impact)
-                        let o = Vec3::new(origin[0], origin[1], origin[2]);
-                        let d = Vec3::new(dir[0], dir[1], dir[2]).normalize_or_zero();
-                        let end = match hit {
-                            Some(h) => Vec3::new(h[0], h[1], h[2]),
-                            None => o + d * 50.0,
-                        };
-                        let col = match kinds.0.get(&id).copied() {
-                            Some(ActorKind::Bot) => Color::srgb(0.95, 0.25, 0.2),
-                            _ => Color::srgb(0.95, 0.9, 0.2),
-                        };
-                        // muzzle
-                        let mmesh = sc_assets.meshes.add(Cuboid::new(0.06, 0.06, 0.06));
-                        let mmat = sc_assets.materials.add(StandardMaterial {
-                            base_color: col,
-                            emissive: col.into(),
-                            unlit: true,
-                            ..default()
-                        });
-                        commands.spawn((
-                            PbrBundle {
-                                mesh: mmesh,
-                                material: mmat,
-                                transform: Transform::from_translation(o),
-                                ..default()
-                            },
-                            MuzzleFx {
-                                timer: Timer::from_seconds(0.06, TimerMode::Once),
-                            },
-                        ));
-                        // tracer
-                        let seg = end - o;
-                        let len = seg.length();
-                        if len > 0.001 {
-                            let tmesh =
-                                sc_assets.meshes.add(Cuboid::new(0.02, 0.02, len.max(0.05)));
-                            let tmat = sc_assets.materials.add(StandardMaterial {
-                                base_color: col,
-                                emissive: col.into(),
-                                unlit: true,
-                                ..default()
-                            });
-                            let rot = Quat::from_rotation_arc(Vec3::Z, seg.normalize());
-                            let pos = o + seg * 0.5;
-                            commands.spawn((
-                                PbrBundle {
-                                    mesh: tmesh,
-                                    material: tmat,
-                                    transform: Transform {
-                                        translation: pos,
-                                        rotation: rot,
-                                        scale: Vec3::ONE,
-                                    },
-                                    ..default()
-                                },
-                                TracerFx {
-                                    timer: Timer::from_seconds(0.06, TimerMode::Once),
-                                },
-                            ));
-                        }
-                        // impact
-                        if let Some(h) = hit {
-                            let hp = Vec3::new(h[0], h[1], h[2]);
-                            let imesh = sc_assets.meshes.add(Cuboid::new(0.05, 0.05, 0.02));
-                            let imat = sc_assets.materials.add(StandardMaterial {
-                                base_color: Color::srgb(1.0, 0.6, 0.3),
-                                emissive: Color::srgb(1.0, 0.6, 0.3).into(),
-                                unlit: true,
-                                ..default()
-                            });
-                            commands.spawn((
-                                PbrBundle {
-                                    mesh: imesh,
-                                    material: imat,
-                                    transform: Transform::from_translation(hp),
-                                    ..default()
-                                },
-                                ImpactFx {
-                                    timer: Timer::from_seconds(0.2, TimerMode::Once),
-                                },
-                            ));
-                        }
-                    }
-                    EventMsg::RoundStart { time_left_sec } => {
-                        round_ui.phase_end = None;
-                        round_ui.time_left = time_left_sec as f32;
-                        round_ui.winner = None;
-                    }
-                    EventMsg::RoundEnd {
-                        winner_id,
-                        next_in_sec,
-                    } => {
-                        round_ui.winner = winner_id;
-                        round_ui.phase_end =
-                            Some(Timer::from_seconds(next_in_sec as f32, TimerMode::Once));
-                    }
-                    EventMsg::Ammo {
-                        id,
-                        ammo,
-                        reloading,
-                    } => {
-                        if id == local.id {
-                            local_ammo.ammo = ammo;
-                            local_ammo.reloading = reloading;
-                        }
-                    }
-                    EventMsg::ScaffoldSpawn {
-                        sid,
-                        owner: owner_id,
-                        pos,
-                    } => {
-                        let p = Vec3::new(pos[0], pos[1], pos[2]);
-                        let mesh = sc_assets.meshes.add(Cuboid::new(
-                            SCAFFOLD_SIZE.x,
-                            SCAFFOLD_SIZE.y,
-                            SCAFFOLD_SIZE.z,
-                        ));
-                        let col = Color::srgba(0.2, 0.9, 1.0, 0.45);
-                        let mat = sc_assets.materials.add(StandardMaterial {
-                            base_color: col,
-                            emissive: Color::srgb(0.3, 0.8, 1.0).into(),
-                            alpha_mode: AlphaMode::Blend,
-                            unlit: true,
-                            ..default()
-                        });
-                        let ent = commands
-                            .spawn((
-                                PbrBundle {
-                                    mesh,
-                                    material: mat,
-                                    transform: Transform::from_translation(p),
-                                    ..default()
-                                },
-                                NetScaffold { sid },
-                                Collider::cuboid(
-                                    SCAFFOLD_SIZE.x * 0.5,
-                                    SCAFFOLD_SIZE.y * 0.5,
-                                    SCAFFOLD_SIZE.z * 0.5,
-                                ),
-                                RigidBody::Fixed,
-                            ))
-                            .id();
-                        sc_assets.map.0.insert(sid, ent);
-                        // 自分が要求した場合はゴーストを除去
-                        if owner_id == local.id {
-                            if let Some(e) = ghost.0.take() {
-                                commands.entity(e).despawn_recursive();
-                            }
-                        }
-                    }
-                    EventMsg::ScaffoldDespawn { sid } => {
-                        if let Some(ent) = sc_assets.map.0.remove(&sid) {
-                            commands.entity(ent).despawn_recursive();
-                        }
-                    }
-                },
-                ServerMessage::Score(entries) => {
-                    // 更新して、スコアボードUIを再構築
-                    score_data.0 = entries
-                        .into_iter()
-                        .map(|e| (e.id, e.kills, e.deaths))
-                        .collect();
-                    if let Ok(root) = board_root_q.get_single() {
-                        if let Some(mut ec) = commands.get_entity(root) {
-                            ec.despawn_descendants();
-                        }
-                        commands.entity(root).with_children(|p| {
-                            p.spawn(TextBundle::from_section(
-                                format!("{:>6}  {:>5} {:>6}", "ID", "K", "D"),
-                                TextStyle {
-                                    font_size: 28.0,
-                                    color: Color::BLACK,
-                                    ..default()
-                                },
-                            ));
-                            let mut rows = score_data.0.clone();
-                            rows.sort_by_key(|e| (-(e.1 as i32), e.2 as i32));
-                            for (id, k, d) in rows {
-                                p.spawn(TextBundle::from_section(
-                                    format!("{:>6}  {:>5} {:>6}", id, k, d),
-                                    TextStyle {
-                                        font_size: 24.0,
-                                        color: Color::BLACK,
-                                        ..default()
-                                    },
-                                ));
-                            }
-                        });
-                    }
-                }
-                _ => {}
-            }
-        }
-    }
-}
-
-// 自分プレイヤーの補正（簡易リコンシリエーション）
-fn predict_position_from_inputs(
-    base: Vec3,
-    mut vy: f32,
-    mut grounded: bool,
-    inputs: &std::collections::VecDeque<InputFrame>,
-) -> Vec3 {
-    let mut pos = base;
-    let mut jump_buf = 0.0f32;
-    let mut jump_cd = 0.0f32;
-    let mut coyote = if grounded {
-        shared_consts::COYOTE_SEC
-    } else {
-        0.0
-    };
-    let mut used_jumps: u8 = if grounded { 0 } else { 1 };
-
-    for frame in inputs {
-        if frame.jump {
-            jump_buf = shared_consts::JUMP_BUFFER_SEC;
-        }
-        if jump_buf > 0.0 {
-            jump_buf = (jump_buf - frame.dt).max(0.0);
-        }
-        if jump_cd > 0.0 {
-            jump_cd = (jump_cd - frame.dt).max(0.0);
-        }
-        if grounded {
-            coyote = shared_consts::COYOTE_SEC;
-            used_jumps = 0;
-        } else if coyote > 0.0 {
-            coyote = (coyote - frame.dt).max(0.0);
-        }
-
-        vy -= shared_consts::GRAVITY * frame.dt;
-        let mut jumped = false;
-        if jump_buf > 0.0 && jump_cd <= 0.0 {
-            if grounded || coyote > 0.0 {
-                vy = shared_consts::JUMP_SPEED;
-                jump_buf = 0.0;
-                jump_cd = shared_consts::JUMP_COOLDOWN_SEC;
-                grounded = false;
-                jumped = true;
-            } else if used_jumps < 1 {
-                vy = shared_consts::JUMP_SPEED;
-                used_jumps = used_jumps.saturating_add(1);
-                jump_buf = 0.0;
-                jump_cd = shared_consts::JUMP_COOLDOWN_SEC;
-                jumped = true;
-            }
-        }
-
-        let mut horiz = Vec3::ZERO;
-        let input = Vec3::new(frame.mv[0], 0.0, frame.mv[1]);
-        if input.length_squared() > 1e-6 {
-            let yaw_rot = Quat::from_rotation_y(frame.yaw);
-            horiz = (yaw_rot * input).normalize();
-        }
-        let mut speed = MOVE_SPEED;
-        if frame.ads {
-            speed *= shared_consts::ADS_SPEED_MUL;
-        }
-        pos += horiz * speed * frame.dt + Vec3::Y * vy * frame.dt;
-
-        if jumped {
-            grounded = false;
-        }
-        if vy <= 0.0 {
-            grounded = false;
-        }
-    }
-
-    pos
+    app.run();
 }
-
-fn reconcile_self(
-    time: Res<Time>,
-    mut q: Query<&mut Transform, With<Player>>,
-    mut qk: Query<&mut KinematicCharacterController, With<Player>>,
-    self_auth: Res<AuthoritativeSelf>,
-    buf: Res<InputBuffer>,
-) {
-    // Default: enable light position reconciliation. Set RECONCILE_POS=0 to disable.
-    if matches!(
-        std::env::var("RECONCILE_POS").ok().as_deref(),
-        Some("0" | "false" | "FALSE")
-    ) {
-        return;
-    }
-    let mut tf = if let Ok(t) = q.get_single_mut() {
-        t
-    } else {
-        return;
-    };
-    if let Some(base) = self_auth.pos {
-        // 未確定入力を再適用した予測ターゲットを作る（簡易）
-        let mut target = base;
-        if !buf.0.is_empty() {
-            target = predict_position_from_inputs(
-                base,
-                self_auth.vy.unwrap_or(0.0),
-                self_auth.grounded.unwrap_or(true),
-                &buf.0,
-            );
-        }
-        let diff = target - tf.translation;
-        let d = diff.length();
-        // Deadband: ignore tiny differences to avoid visible jitter.
-        if d <= POS_DEADBAND {
-            return;
-        }
-        // Snap when far out-of-bounds to recover quickly.
-        // ただし Transform を直接書き換えず、KCC に追加移動として与え、衝突解決に任せる。
-        if let Ok(mut kcc) = qk.get_single_mut() {
-            let grounded = self_auth.grounded.unwrap_or(false);
-            if d >= POS_SNAP {
-                // 空中ではY補正を入れない（落下中のがくつきを防止）
-                let mut corr = diff;
-                if !grounded {
-                    corr.y = 0.0;
-                }
-                kcc.translation = Some(kcc.translation.unwrap_or(Vec3::ZERO) + corr);
-                return;
-            }
-            // Smooth correction within thresholds (collidable via KCC).
-            let rate = 6.0; // per second (gentle correction to avoid warps)
-            let step = (rate * time.delta_seconds()).min(1.0);
-            let mut corr = diff * step;
-            if !grounded {
-                corr.y = 0.0;
-            }
-            kcc.translation = Some(kcc.translation.unwrap_or(Vec3::ZERO) + corr);
-        }
-    }
-    if matches!(
-        std::env::var("RECONCILE_YAW").ok().as_deref(),
-        Some("1" | "true" | "TRUE")
-    ) {
-        if let Some(yaw) = self_auth.yaw {
-            // 軽い追従のみ（強いワープは避ける）
-            let current_yaw = tf.rotation.to_euler(EulerRot::YXZ).0;
-            let delta = wrap_pi(yaw - current_yaw);
-            let step = (6.0 * time.delta_seconds()).min(1.0);
-            tf.rotation = Quat::from_rotation_y(wrap_pi(current_yaw + delta * step));
-        }
-    }
-
-    // 入力ACKを基点に、未確定入力を簡易再適用して視覚的な差し戻しを低減
-    fn replay_unconfirmed_inputs(
-        self_auth: Res<AuthoritativeSelf>,
-        buf: Res<InputBuffer>,
-        mut q: Query<(&mut Transform, &mut Controller), With<Player>>,
-    ) {
-        if buf.0.is_empty() {
-            return;
-        }
-        let Some(base) = self_auth.pos else { return };
-        let Ok((mut tf, mut ctrl)) = q.get_single_mut() else {
-            return;
-        };
-        // ベースをサーバ確定位置に置き、簡易再シム（衝突は考慮しない）
-        let mut pos = base;
-        let mut vy = 0.0f32;
-        let mut used_jumps: u8 = 0;
-        for f in buf.0.iter() {
-            let mut horiz = Vec3::ZERO;
-            let input = Vec3::new(f.mv[0], 0.0, f.mv[1]);
-            if input.length_squared() > 1e-6 {
-                let yaw_rot = Quat::from_rotation_y(f.yaw);
-                horiz = (yaw_rot * input).normalize();
-            }
-            let mut speed = MOVE_SPEED;
-            // jump
-            if f.jump && (used_jumps == 0) {
-                vy = shared_consts::JUMP_SPEED;
-                used_jumps = used_jumps.saturating_add(1);
-            }
-            // integrate
-            vy -= shared_consts::GRAVITY * f.dt;
-            pos += horiz * speed * f.dt + Vec3::Y * vy * f.dt;
-        }
-        tf.translation = pos;
-        ctrl.vy = vy; // 目安として反映
-    }
-
-    fn scoreboard_toggle(
-        keys: Res<ButtonInput<KeyCode>>,
-        mut q: Query<&mut Visibility, With<UiScoreboard>>,
-    ) {
-        if keys.just_pressed(KeyCode::Tab) {
-            if let Ok(mut v) = q.get_single_mut() {
-                *v = match *v {
-                    Visibility::Hidden => Visibility::Visible,
-                    _ => Visibility::Hidden,
-                };
-            }
-        }
-    }
-}
-
-// --- VFX tickers ---
-fn vfx_tick_and_cleanup(
-    time: Res<Time>,
-    mut commands: Commands,
-    mut q_muzzle: Query<(Entity, &mut MuzzleFx)>,
-    mut q_tracer: Query<(Entity, &mut TracerFx)>,
-    mut q_impact: Query<(Entity, &mut ImpactFx)>,
-    mut q_vign: Query<(Entity, &mut UiDamageVignette)>,
-    mut bg_colors: Query<&mut BackgroundColor>,
-) {
-    for (e, mut fx) in &mut q_muzzle {
-        fx.timer.tick(time.delta());
-        if fx.timer.finished() {
-            commands.entity(e).despawn_recursive();
-        }
-    }
-    for (e, mut fx) in &mut q_tracer {
-        fx.timer.tick(time.delta());
-        if fx.timer.finished() {
-            commands.entity(e).despawn_recursive();
-        }
-    }
-    for (e, mut fx) in &mut q_impact {
-        fx.timer.tick(time.delta());
-        if fx.timer.finished() {
-            commands.entity(e).despawn_recursive();
-        }
-    }
-    for (e, mut v) in &mut q_vign {
-        v.timer.tick(time.delta());
-        if let Ok(mut col) = bg_colors.get_mut(e) {
-            let t =
-                (1.0 - (v.timer.elapsed_secs() / v.timer.duration().as_secs_f32())).clamp(0.0, 1.0);
-            col.0 = Color::rgba(0.8, 0.0, 0.0, 0.35 * t);
-        }
-        if v.timer.finished() {
-            commands.entity(e).despawn_recursive();
-        }
-    }
-}
-
-// 右クリックADS時にFOVを切り替える（クライアント視覚のみ）
-fn ads_zoom_system(
-    buttons: Res<ButtonInput<MouseButton>>,
-    keys: Res<ButtonInput<KeyCode>>,
-    mut q: Query<&mut Projection, With<Camera3d>>,
-) {
-    let Ok(mut proj) = q.get_single_mut() else {
-        return;
-    };
-    if let Projection::Perspective(ref mut p) = *proj {
-        let ads_key = keys.pressed(KeyCode::ShiftLeft) || keys.pressed(KeyCode::ShiftRight);
-        p.fov = if buttons.pressed(MouseButton::Right) || ads_key {
-            ADS_FOV
-        } else {
-            HIP_FOV
-        };
-    }
-}
-
-
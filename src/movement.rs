@@ -0,0 +1,276 @@
+// ===== 共有移動ステップ =====
+// クライアント予測とサーバ権威シミュレーションの両方がこの step() を使う。
+// 衝突は扱わない（結果の motion を呼び出し側が KCC に渡し、接地は land() で戻す）。
+use bevy::prelude::{Quat, Vec3};
+
+use crate::net::{shared, InputFrame, RulesMsg};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MoveParams {
+    pub move_speed: f32,
+    pub ads_speed_mul: f32,
+    pub gravity: f32,
+    pub jump_speed: f32,
+    pub jump_buffer: f32,
+    pub coyote_time: f32,
+    pub jump_cooldown: f32,
+    pub max_air_jumps: u8,
+}
+
+impl Default for MoveParams {
+    fn default() -> Self {
+        Self {
+            move_speed: shared::MOVE_SPEED,
+            ads_speed_mul: shared::ADS_SPEED_MUL,
+            gravity: shared::GRAVITY,
+            jump_speed: shared::JUMP_SPEED,
+            jump_buffer: shared::JUMP_BUFFER_SEC,
+            coyote_time: shared::COYOTE_SEC,
+            jump_cooldown: shared::JUMP_COOLDOWN_SEC,
+            max_air_jumps: shared::MAX_AIR_JUMPS,
+        }
+    }
+}
+
+impl MoveParams {
+    // サーバから届いたルール値で上書き（ジャンプ品質パラメータは共有定数のまま）
+    pub fn from_rules(rules: &RulesMsg) -> Self {
+        Self {
+            move_speed: rules.move_speed,
+            gravity: rules.gravity,
+            jump_speed: rules.jump_speed,
+            ..Default::default()
+        }
+    }
+}
+
+// 1キャラクタ分の縦速度・接地・ジャンプ関連タイマ
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct MoveState {
+    pub vy: f32,
+    pub grounded: bool,
+    pub air_jumps: u8, // 空中で使ったジャンプ回数
+    pub jump_buf: f32,
+    pub coyote: f32,
+    pub jump_cd: f32,
+}
+
+impl MoveState {
+    // スポーン直後（接地・静止）
+    pub fn standing() -> Self {
+        Self {
+            grounded: true,
+            ..Default::default()
+        }
+    }
+
+    // KCC 解決後に呼ぶ。床法線で接地していて下降中なら着地させる
+    pub fn land(&mut self, on_floor: bool) {
+        if on_floor && self.vy <= 0.0 {
+            self.vy = 0.0;
+            self.air_jumps = 0;
+            self.grounded = true;
+        } else {
+            self.grounded = false;
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StepOutput {
+    pub motion: Vec3,
+    pub jumped: bool, // このフレームで跳んだ（呼び出し側で地面スナップを切る）
+}
+
+// 入力1フレーム分を進める。同じ state と同じ入力列なら必ず同じ結果になる
+pub fn step(state: &mut MoveState, input: &InputFrame, params: &MoveParams) -> StepOutput {
+    let dt = input.dt;
+    if input.jump {
+        state.jump_buf = params.jump_buffer;
+    } else {
+        state.jump_buf = (state.jump_buf - dt).max(0.0);
+    }
+    state.jump_cd = (state.jump_cd - dt).max(0.0);
+    if state.grounded {
+        state.coyote = params.coyote_time;
+        state.air_jumps = 0;
+    } else {
+        state.coyote = (state.coyote - dt).max(0.0);
+    }
+
+    state.vy -= params.gravity * dt;
+    let mut jumped = false;
+    if state.jump_buf > 0.0 && state.jump_cd <= 0.0 {
+        if state.grounded || state.coyote > 0.0 {
+            jumped = true;
+        } else if state.air_jumps < params.max_air_jumps {
+            state.air_jumps += 1;
+            jumped = true;
+        }
+    }
+    if jumped {
+        state.vy = params.jump_speed;
+        state.jump_buf = 0.0;
+        state.jump_cd = params.jump_cooldown;
+        // 同じ tick の後続フレームでは接地扱いにしない
+        state.grounded = false;
+    }
+
+    let mut horiz = Vec3::ZERO;
+    let dir = Vec3::new(input.mv[0], 0.0, input.mv[1]);
+    if dir.length_squared() > 1e-6 {
+        horiz = (Quat::from_rotation_y(input.yaw) * dir).normalize();
+    }
+    let mut speed = params.move_speed;
+    if input.ads {
+        speed *= params.ads_speed_mul;
+    }
+    StepOutput {
+        motion: horiz * speed * dt + Vec3::Y * state.vy * dt,
+        jumped,
+    }
+}
+
+// 衝突なしの再シミュレーション（リコンシル用）。接地から始めた場合は開始高さを床とみなす
+pub fn predict<'a>(
+    base: Vec3,
+    mut state: MoveState,
+    inputs: impl IntoIterator<Item = &'a InputFrame>,
+    params: &MoveParams,
+) -> Vec3 {
+    let floor = state.grounded.then_some(base.y);
+    let mut pos = base;
+    for input in inputs {
+        pos += step(&mut state, input, params).motion;
+        match floor {
+            Some(y) if pos.y <= y => {
+                pos.y = y;
+                state.land(true);
+            }
+            _ => state.land(false),
+        }
+    }
+    pos
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DT: f32 = 1.0 / 60.0;
+
+    fn frame(seq: u32, mv: [f32; 2], jump: bool, yaw: f32) -> InputFrame {
+        InputFrame {
+            seq,
+            mv,
+            jump,
+            fire: false,
+            ads: seq.is_multiple_of(7),
+            yaw,
+            pitch: 0.0,
+            dt: DT,
+        }
+    }
+
+    // 歩き・旋回・連打ジャンプを混ぜた入力列
+    fn scripted_inputs() -> Vec<InputFrame> {
+        (0..240)
+            .map(|i| {
+                let mv = match (i / 30) % 4 {
+                    0 => [0.0, -1.0],
+                    1 => [1.0, -1.0],
+                    2 => [-1.0, 0.0],
+                    _ => [0.0, 0.0],
+                };
+                frame(i, mv, i % 45 < 2 || i % 45 == 20, i as f32 * 0.05)
+            })
+            .collect()
+    }
+
+    // KCC の代わりに y=0 の床で着地させる
+    fn run(inputs: &[InputFrame]) -> Vec<(Vec3, MoveState)> {
+        let params = MoveParams::default();
+        let mut state = MoveState::standing();
+        let mut pos = Vec3::ZERO;
+        inputs
+            .iter()
+            .map(|f| {
+                pos += step(&mut state, f, &params).motion;
+                let on_floor = pos.y <= 0.0;
+                if on_floor {
+                    pos.y = 0.0;
+                }
+                state.land(on_floor);
+                (pos, state)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn predict_matches_step_on_flat_ground() {
+        let inputs = scripted_inputs();
+        let trajectory = run(&inputs);
+        assert!(
+            trajectory.iter().any(|(p, _)| p.y > 0.5),
+            "script should leave the ground"
+        );
+        let expected = trajectory.last().unwrap().0;
+        let got = predict(
+            Vec3::ZERO,
+            MoveState::standing(),
+            &inputs,
+            &MoveParams::default(),
+        );
+        assert_eq!(got, expected);
+    }
+
+    #[test]
+    fn buffered_jump_fires_on_landing() {
+        let params = MoveParams::default();
+        let mut state = MoveState {
+            vy: -1.0,
+            ..Default::default()
+        };
+        // 着地直前に押したジャンプがバッファに残る（空中ジャンプは使い切り済み）
+        state.air_jumps = params.max_air_jumps;
+        assert!(!step(&mut state, &frame(1, [0.0; 2], true, 0.0), &params).jumped);
+        state.land(true);
+        assert!(step(&mut state, &frame(2, [0.0; 2], false, 0.0), &params).jumped);
+        assert_eq!(state.vy, params.jump_speed);
+    }
+
+    #[test]
+    fn coyote_time_allows_late_ground_jump() {
+        let params = MoveParams::default();
+        let mut state = MoveState::standing();
+        // 足場から落ちた直後
+        step(&mut state, &frame(1, [0.0; 2], false, 0.0), &params);
+        state.land(false);
+        let out = step(&mut state, &frame(2, [0.0; 2], true, 0.0), &params);
+        assert!(out.jumped);
+        assert_eq!(
+            state.air_jumps, 0,
+            "coyote jump must not consume the air jump"
+        );
+    }
+
+    #[test]
+    fn cooldown_blocks_immediate_rejump_then_air_jump_once() {
+        let params = MoveParams::default();
+        let mut state = MoveState::standing();
+        assert!(step(&mut state, &frame(1, [0.0; 2], true, 0.0), &params).jumped);
+        state.land(false);
+        assert!(!step(&mut state, &frame(2, [0.0; 2], true, 0.0), &params).jumped);
+        state.land(false);
+        let mut seq = 3;
+        let mut air = 0;
+        while (seq as f32) * DT < 1.0 {
+            if step(&mut state, &frame(seq, [0.0; 2], true, 0.0), &params).jumped {
+                air += 1;
+            }
+            state.land(false);
+            seq += 1;
+        }
+        assert_eq!(air, params.max_air_jumps as usize);
+    }
+}
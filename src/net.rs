@@ -1,21 +1,26 @@
 use bevy_renet::renet::{ClientId, ConnectionConfig, RenetClient, RenetServer};
 use renet::transport::{
     ClientAuthentication, ConnectToken, NetcodeClientTransport, NetcodeServerTransport,
-    ServerAuthentication, ServerConfig,
+    ServerAuthentication, ServerConfig, NETCODE_USER_DATA_BYTES,
 };
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::env;
 use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4, UdpSocket};
 use std::time::SystemTime;
 
 pub const PROTOCOL_ID: u64 = 7_294_871_223_100_001;
 pub const SERVER_PORT: u16 = 5000;
+// メッセージ形式を変更したら必ず上げる（不一致のクライアントは接続時に Reject される）
+pub const PROTOCOL_VERSION: u32 = 10;
 
 pub const CH_INPUT: u8 = 0; // unreliable, ordered
 pub const CH_SNAPSHOT: u8 = 1; // unreliable, ordered
 pub const CH_RELIABLE: u8 = 2; // reliable, ordered (events)
 
+// 送受信は quant::PackedInputFrame（10バイト）を経由する
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(from = "quant::PackedInputFrame", into = "quant::PackedInputFrame")]
 pub struct InputFrame {
     pub seq: u32,
     pub mv: [f32; 2], // x,z on local plane
@@ -27,7 +32,9 @@ pub struct InputFrame {
     pub dt: f32,
 }
 
+// 送受信は quant::PackedPlayerState（21バイト）を経由する
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(from = "quant::PackedPlayerState", into = "quant::PackedPlayerState")]
 pub struct PlayerStateMsg {
     pub id: u64,
     pub pos: [f32; 3],
@@ -47,6 +54,34 @@ pub struct SnapshotMsg {
     pub acks: Vec<(u64, u32)>,
 }
 
+// アクター1体分の差分（基準から変化したフィールドのみ Some）
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PlayerDelta {
+    pub id: u64,
+    #[serde(with = "quant::opt_pos")]
+    pub pos: Option<[f32; 3]>,
+    #[serde(with = "quant::opt_angle")]
+    pub yaw: Option<f32>,
+    pub alive: Option<bool>,
+    pub hp: Option<u16>,
+    #[serde(with = "quant::opt_vel")]
+    pub vy: Option<f32>,
+    pub grounded: Option<bool>,
+    pub kind: Option<ActorKind>,
+}
+
+// クライアントが ACK 済みの tick を基準にした差分スナップショット
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeltaSnapshotMsg {
+    pub tick: u32,
+    pub base_tick: u32,
+    // 基準から変化した/新たに現れたアクター（新規は全フィールド Some）
+    pub changed: Vec<PlayerDelta>,
+    // 基準に存在し、今回いなくなったアクター
+    pub removed: Vec<u64>,
+    pub acks: Vec<(u64, u32)>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum ClientMessage {
     Input(InputFrame),
@@ -54,6 +89,10 @@ pub enum ClientMessage {
     PlaceScaffold { pos: [f32; 3] },
     // 射撃要求（クライアントのカメラ原点・方向を送る）
     Fire { origin: [f32; 3], dir: [f32; 3] },
+    // 受信（復元）できた最新スナップショット tick。差分の基準になる
+    SnapshotAck { tick: u32 },
+    // 通算成績の問い合わせ（None ならキル順の上位）。ServerMessage::Stats で返る
+    StatsQuery { account_id: Option<u64> },
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -61,6 +100,45 @@ pub enum ServerMessage {
     Snapshot(SnapshotMsg),
     Event(EventMsg),
     Score(Vec<ScoreEntry>),
+    // ハンドシェイク成功: サーバの版数と、双方が対応する能力ビット
+    Welcome { version: u32, caps: u32 },
+    // ハンドシェイク失敗（理由はクライアント画面に表示される）
+    Reject { reason: String },
+    DeltaSnapshot(DeltaSnapshotMsg),
+    // ルール値（接続時と、サーバ設定が変わったときに送る）
+    Rules(RulesMsg),
+    // StatsQuery への応答（該当なしなら空）
+    Stats(Vec<PlayerStats>),
+}
+
+// クライアントの予測・発砲・HUD が使うサーバ側のルール値
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct RulesMsg {
+    pub move_speed: f32,
+    pub jump_speed: f32,
+    pub gravity: f32,
+    pub fire_cooldown: f32,
+    pub mag_size: u16,
+    pub reload_sec: f32,
+    pub win_kills: u32,
+    pub round_time_sec: f32,
+    pub scaffold_limit: u32, // 1人が同時に置ける足場の数（超えたら古いものから消える）
+}
+
+impl Default for RulesMsg {
+    fn default() -> Self {
+        Self {
+            move_speed: shared::MOVE_SPEED,
+            jump_speed: shared::JUMP_SPEED,
+            gravity: shared::GRAVITY,
+            fire_cooldown: 1.0 / 7.5,
+            mag_size: 30,
+            reload_sec: 1.6,
+            win_kills: 10,
+            round_time_sec: 300.0,
+            scaffold_limit: 3,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -109,6 +187,29 @@ pub enum EventMsg {
     ScaffoldDespawn {
         sid: u64,
     },
+    // 関心範囲への出入り（Spawn/Despawn と違いリスポーン・切断を意味しない）
+    ActorEnter {
+        id: u64,
+        pos: [f32; 3],
+        kind: ActorKind,
+    },
+    ActorLeave {
+        id: u64,
+    },
+    // 管理者からのお知らせ（キルログ欄に表示）
+    Announce {
+        text: String,
+    },
+    // 使用中のマップ（接続時と管理者のマップ変更時）。assets/ からのシーンパス
+    MapChange {
+        scene: String,
+    },
+    // 表示名とアカウント（接続時に全員へ、参加者には既存分も）。account_id 0 はゲスト/ボット
+    PlayerInfo {
+        id: u64,
+        name: String,
+        account_id: u64,
+    },
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -118,12 +219,256 @@ pub struct ScoreEntry {
     pub deaths: u32,
 }
 
+// アカウントごとの通算成績（サーバの成績ファイルも同じ形で保存する。項目を足しても古いファイルを読めるよう default）
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PlayerStats {
+    pub account_id: u64,
+    pub name: String, // 最後に使われた表示名
+    pub kills: u32,
+    pub deaths: u32,
+    pub shots: u32,
+    pub hits: u32,
+    pub rounds_won: u32,
+    pub scaffolds: u32,
+    pub play_secs: f64,
+}
+
+impl PlayerStats {
+    // 命中率（0..=1、撃っていなければ 0）
+    pub fn accuracy(&self) -> f32 {
+        if self.shots == 0 {
+            0.0
+        } else {
+            self.hits as f32 / self.shots as f32
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Copy)]
 pub enum ActorKind {
     Human,
     Bot,
 }
 
+// 能力ビット（接続時にクライアントが申告し、サーバは自身の対応分との AND を有効化する）
+pub mod caps {
+    // スナップショットを ACK 済み tick との差分で受け取れる
+    pub const DELTA_SNAPSHOTS: u32 = 1 << 0;
+
+    // このビルドのクライアントが申告する能力
+    pub const CLIENT: u32 = DELTA_SNAPSHOTS;
+    // このビルドのサーバが対応する能力
+    pub const SERVER: u32 = DELTA_SNAPSHOTS;
+}
+
+// netcode の connect user data に載せるハンドシェイク情報
+// 先頭4バイトは常に version（u32 LE）。以降のレイアウトは version ごとに変わってよい
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConnectUserData {
+    pub version: u32,
+    pub caps: u32,
+    // Secure ではトークン発行サービスが入れる。0 はゲスト
+    // Unsecure の netcode 接続ではクライアントが書けてしまうので、サーバは 0 として扱う
+    pub account_id: u64,
+    pub name: String,
+    // サーバパスワード（設定しているサーバだけが見る）
+    pub password: String,
+}
+
+pub const PLAYER_NAME_MAX_CHARS: usize = 24;
+// user data（256 バイト）に名前と一緒に収まる長さ
+pub const SERVER_PASSWORD_MAX_BYTES: usize = 64;
+
+// 制御文字を除いて前後の空白を落とし、長さを切り詰める（空なら空のまま）
+pub fn clean_player_name(name: &str) -> String {
+    let cleaned: String = name.chars().filter(|c| !c.is_control()).collect();
+    let cut: String = cleaned.trim().chars().take(PLAYER_NAME_MAX_CHARS).collect();
+    cut.trim_end().to_string()
+}
+
+// clap の value_parser 用（長すぎると user data に収まらない）
+pub fn parse_server_password(s: &str) -> Result<String, String> {
+    if s.is_empty() || s.len() > SERVER_PASSWORD_MAX_BYTES {
+        return Err(format!(
+            "server password must be 1-{} bytes",
+            SERVER_PASSWORD_MAX_BYTES
+        ));
+    }
+    Ok(s.to_string())
+}
+
+impl ConnectUserData {
+    pub fn local() -> Self {
+        Self {
+            version: PROTOCOL_VERSION,
+            caps: caps::CLIENT,
+            account_id: 0,
+            name: String::new(),
+            password: String::new(),
+        }
+    }
+
+    pub fn to_bytes(&self) -> [u8; NETCODE_USER_DATA_BYTES] {
+        let mut out = [0u8; NETCODE_USER_DATA_BYTES];
+        let bytes = bincode::serialize(self).expect("serialize connect user data");
+        assert!(
+            bytes.len() <= NETCODE_USER_DATA_BYTES,
+            "connect user data too large"
+        );
+        out[..bytes.len()].copy_from_slice(&bytes);
+        out
+    }
+}
+
+// サーバ側のハンドシェイク検証。Err はそのまま Reject の理由として送る
+pub fn check_handshake(
+    user_data: Option<&[u8; NETCODE_USER_DATA_BYTES]>,
+) -> Result<ConnectUserData, String> {
+    let Some(data) = user_data else {
+        return Err("missing handshake data; please update your client".into());
+    };
+    let version = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+    if version != PROTOCOL_VERSION {
+        // version 0 は user data を送らない旧クライアント
+        return Err(format!(
+            "protocol version mismatch (client {}, server {}); please update your client",
+            version, PROTOCOL_VERSION
+        ));
+    }
+    let mut hello: ConnectUserData = bincode::deserialize(&data[..])
+        .map_err(|e| format!("malformed handshake data: {}", e))?;
+    hello.caps &= caps::SERVER;
+    hello.name = clean_player_name(&hello.name);
+    Ok(hello)
+}
+
+impl PlayerDelta {
+    fn full(p: &PlayerStateMsg) -> Self {
+        Self {
+            id: p.id,
+            pos: Some(p.pos),
+            yaw: Some(p.yaw),
+            alive: Some(p.alive),
+            hp: Some(p.hp),
+            vy: Some(p.vy),
+            grounded: Some(p.grounded),
+            kind: Some(p.kind),
+        }
+    }
+
+    // 変化がなければ None（量子化後に同じ値になる変化は送らない）
+    fn between(base: &PlayerStateMsg, cur: &PlayerStateMsg) -> Option<Self> {
+        let pos_changed = quant::pos_to_bits(base.pos) != quant::pos_to_bits(cur.pos);
+        let yaw_changed = quant::angle_to_u16(base.yaw) != quant::angle_to_u16(cur.yaw);
+        let vy_changed = quant::vel_to_i16(base.vy) != quant::vel_to_i16(cur.vy);
+        let d = Self {
+            id: cur.id,
+            pos: pos_changed.then_some(cur.pos),
+            yaw: yaw_changed.then_some(cur.yaw),
+            alive: (base.alive != cur.alive).then_some(cur.alive),
+            hp: (base.hp != cur.hp).then_some(cur.hp),
+            vy: vy_changed.then_some(cur.vy),
+            grounded: (base.grounded != cur.grounded).then_some(cur.grounded),
+            kind: (base.kind != cur.kind).then_some(cur.kind),
+        };
+        let changed = d.pos.is_some()
+            || d.yaw.is_some()
+            || d.alive.is_some()
+            || d.hp.is_some()
+            || d.vy.is_some()
+            || d.grounded.is_some()
+            || d.kind.is_some();
+        changed.then_some(d)
+    }
+
+    fn apply(&self, p: &mut PlayerStateMsg) {
+        if let Some(v) = self.pos {
+            p.pos = v;
+        }
+        if let Some(v) = self.yaw {
+            p.yaw = v;
+        }
+        if let Some(v) = self.alive {
+            p.alive = v;
+        }
+        if let Some(v) = self.hp {
+            p.hp = v;
+        }
+        if let Some(v) = self.vy {
+            p.vy = v;
+        }
+        if let Some(v) = self.grounded {
+            p.grounded = v;
+        }
+        if let Some(v) = self.kind {
+            p.kind = v;
+        }
+    }
+}
+
+// base（base_tick 時点の全アクター）から cur への差分を作る
+pub fn diff_snapshot(
+    base_tick: u32,
+    base: &[PlayerStateMsg],
+    cur: &SnapshotMsg,
+) -> DeltaSnapshotMsg {
+    let base_map: HashMap<u64, &PlayerStateMsg> = base.iter().map(|p| (p.id, p)).collect();
+    let mut changed = Vec::new();
+    for p in &cur.players {
+        match base_map.get(&p.id) {
+            Some(b) => changed.extend(PlayerDelta::between(b, p)),
+            None => changed.push(PlayerDelta::full(p)),
+        }
+    }
+    let removed = base
+        .iter()
+        .filter(|b| !cur.players.iter().any(|p| p.id == b.id))
+        .map(|b| b.id)
+        .collect();
+    DeltaSnapshotMsg {
+        tick: cur.tick,
+        base_tick,
+        changed,
+        removed,
+        acks: cur.acks.clone(),
+    }
+}
+
+// base に差分を適用して完全なスナップショットを復元する
+pub fn apply_delta(base: &[PlayerStateMsg], delta: &DeltaSnapshotMsg) -> SnapshotMsg {
+    let mut players: Vec<PlayerStateMsg> = base
+        .iter()
+        .filter(|p| !delta.removed.contains(&p.id))
+        .cloned()
+        .collect();
+    for d in &delta.changed {
+        match players.iter_mut().find(|p| p.id == d.id) {
+            Some(p) => d.apply(p),
+            None => {
+                // 新規アクター: 全フィールドが揃っている前提（欠けていれば既定値）
+                let mut p = PlayerStateMsg {
+                    id: d.id,
+                    pos: [0.0; 3],
+                    yaw: 0.0,
+                    alive: true,
+                    hp: 100,
+                    vy: 0.0,
+                    grounded: true,
+                    kind: ActorKind::Human,
+                };
+                d.apply(&mut p);
+                players.push(p);
+            }
+        }
+    }
+    SnapshotMsg {
+        tick: delta.tick,
+        players,
+        acks: delta.acks.clone(),
+    }
+}
+
 pub fn connection_config() -> ConnectionConfig {
     ConnectionConfig::default()
 }
@@ -139,67 +484,346 @@ pub mod shared {
     pub const JUMP_BUFFER_SEC: f32 = 0.12;
     pub const COYOTE_SEC: f32 = 0.10;
     pub const JUMP_COOLDOWN_SEC: f32 = 0.15;
+    pub const MAX_AIR_JUMPS: u8 = 1; // 2段ジャンプ
     pub const PLAYER_START: [f32; 3] = [-30.0, 30.0, 5.0];
+    // クライアントの位置補正デッドバンド（量子化誤差はこれより十分小さく保つ）
+    pub const POS_DEADBAND: f32 = 0.05;
+}
+
+// ===== 量子化（ビットパック）エンコード =====
+// 位置はマップ範囲に対する固定小数点、角度は16bit、移動入力は軸ごとの方向ビット
+pub mod quant {
+    use super::{ActorKind, InputFrame, PlayerStateMsg};
+    use serde::{Deserialize, Serialize};
+    use std::f32::consts::{FRAC_PI_2, PI, TAU};
+
+    // 位置: [MAP_MIN, MAP_MIN + MAP_EXTENT] を各軸 POS_BITS で量子化（範囲外はクランプ）
+    pub const MAP_MIN: [f32; 3] = [-512.0, -128.0, -512.0];
+    pub const MAP_EXTENT: f32 = 1024.0;
+    pub const POS_BITS: u32 = 20;
+    const POS_MAX: u64 = (1 << POS_BITS) - 1;
+    pub const POS_STEP: f32 = MAP_EXTENT / POS_MAX as f32; // ~1mm
+    // 縦速度: 1/256 m/s 刻み（±128 m/s）
+    const VEL_SCALE: f32 = 256.0;
+    // dt: 1/7680 s 刻み（1/60 s が 128 ちょうど、最大 ~33ms）
+    pub const DT_SCALE: f32 = 7680.0;
+
+    // pos ワードの上位4bit（60..63）に載せるフラグ
+    const FLAG_ALIVE: u64 = 1 << 60;
+    const FLAG_GROUNDED: u64 = 1 << 61;
+    const FLAG_BOT: u64 = 1 << 62;
+
+    // 入力ビット: mv x(2bit) | mv z(2bit) | jump | fire | ads
+    const IN_JUMP: u8 = 1 << 4;
+    const IN_FIRE: u8 = 1 << 5;
+    const IN_ADS: u8 = 1 << 6;
+
+    pub fn pos_to_bits(p: [f32; 3]) -> u64 {
+        let mut out = 0u64;
+        for (axis, v) in p.iter().enumerate() {
+            let t = ((v - MAP_MIN[axis]) / POS_STEP)
+                .round()
+                .clamp(0.0, POS_MAX as f32) as u64;
+            out |= t << (axis as u32 * POS_BITS);
+        }
+        out
+    }
+
+    pub fn bits_to_pos(bits: u64) -> [f32; 3] {
+        let mut out = [0.0f32; 3];
+        for (axis, o) in out.iter_mut().enumerate() {
+            let t = (bits >> (axis as u32 * POS_BITS)) & POS_MAX;
+            *o = MAP_MIN[axis] + t as f32 * POS_STEP;
+        }
+        out
+    }
+
+    // yaw 等の周期角: [-PI, PI) を 16bit 一周で表現
+    pub fn angle_to_u16(a: f32) -> u16 {
+        let t = (a + PI).rem_euclid(TAU) / TAU;
+        ((t * 65536.0).round() as u32 & 0xFFFF) as u16
+    }
+
+    pub fn u16_to_angle(v: u16) -> f32 {
+        v as f32 / 65536.0 * TAU - PI
+    }
+
+    // pitch: [-PI/2, PI/2] を 16bit で表現（範囲外はクランプ）
+    pub fn pitch_to_u16(p: f32) -> u16 {
+        ((p.clamp(-FRAC_PI_2, FRAC_PI_2) + FRAC_PI_2) / PI * 65535.0).round() as u16
+    }
+
+    pub fn u16_to_pitch(v: u16) -> f32 {
+        v as f32 / 65535.0 * PI - FRAC_PI_2
+    }
+
+    pub fn vel_to_i16(v: f32) -> i16 {
+        (v * VEL_SCALE)
+            .round()
+            .clamp(i16::MIN as f32, i16::MAX as f32) as i16
+    }
+
+    pub fn i16_to_vel(v: i16) -> f32 {
+        v as f32 / VEL_SCALE
+    }
+
+    fn axis_to_bits(v: f32) -> u8 {
+        if v > 0.5 {
+            1
+        } else if v < -0.5 {
+            2
+        } else {
+            0
+        }
+    }
+
+    fn bits_to_axis(b: u8) -> f32 {
+        match b & 0b11 {
+            1 => 1.0,
+            2 => -1.0,
+            _ => 0.0,
+        }
+    }
+
+    #[derive(Clone, Copy, Serialize, Deserialize)]
+    pub struct PackedPlayerState {
+        id: u64,
+        pos_flags: u64,
+        yaw: u16,
+        vy: i16,
+        hp: u8,
+    }
+
+    impl From<PlayerStateMsg> for PackedPlayerState {
+        fn from(p: PlayerStateMsg) -> Self {
+            let mut pos_flags = pos_to_bits(p.pos);
+            if p.alive {
+                pos_flags |= FLAG_ALIVE;
+            }
+            if p.grounded {
+                pos_flags |= FLAG_GROUNDED;
+            }
+            if p.kind == ActorKind::Bot {
+                pos_flags |= FLAG_BOT;
+            }
+            Self {
+                id: p.id,
+                pos_flags,
+                yaw: angle_to_u16(p.yaw),
+                vy: vel_to_i16(p.vy),
+                hp: p.hp.min(u8::MAX as u16) as u8,
+            }
+        }
+    }
+
+    impl From<PackedPlayerState> for PlayerStateMsg {
+        fn from(p: PackedPlayerState) -> Self {
+            Self {
+                id: p.id,
+                pos: bits_to_pos(p.pos_flags),
+                yaw: u16_to_angle(p.yaw),
+                alive: p.pos_flags & FLAG_ALIVE != 0,
+                hp: p.hp as u16,
+                vy: i16_to_vel(p.vy),
+                grounded: p.pos_flags & FLAG_GROUNDED != 0,
+                kind: if p.pos_flags & FLAG_BOT != 0 {
+                    ActorKind::Bot
+                } else {
+                    ActorKind::Human
+                },
+            }
+        }
+    }
+
+    #[derive(Clone, Copy, Serialize, Deserialize)]
+    pub struct PackedInputFrame {
+        seq: u32,
+        yaw: u16,
+        pitch: u16,
+        dt: u8,
+        bits: u8,
+    }
+
+    impl From<InputFrame> for PackedInputFrame {
+        fn from(f: InputFrame) -> Self {
+            let mut bits = axis_to_bits(f.mv[0]) | (axis_to_bits(f.mv[1]) << 2);
+            if f.jump {
+                bits |= IN_JUMP;
+            }
+            if f.fire {
+                bits |= IN_FIRE;
+            }
+            if f.ads {
+                bits |= IN_ADS;
+            }
+            Self {
+                seq: f.seq,
+                yaw: angle_to_u16(f.yaw),
+                pitch: pitch_to_u16(f.pitch),
+                dt: (f.dt * DT_SCALE).round().clamp(0.0, u8::MAX as f32) as u8,
+                bits,
+            }
+        }
+    }
+
+    impl From<PackedInputFrame> for InputFrame {
+        fn from(f: PackedInputFrame) -> Self {
+            Self {
+                seq: f.seq,
+                mv: [bits_to_axis(f.bits), bits_to_axis(f.bits >> 2)],
+                jump: f.bits & IN_JUMP != 0,
+                fire: f.bits & IN_FIRE != 0,
+                ads: f.bits & IN_ADS != 0,
+                yaw: u16_to_angle(f.yaw),
+                pitch: u16_to_pitch(f.pitch),
+                dt: f.dt as f32 / DT_SCALE,
+            }
+        }
+    }
+
+    impl InputFrame {
+        // 送信時と同じ量子化を通した値（予測をサーバの受信値と一致させるため）
+        pub fn quantized(&self) -> InputFrame {
+            PackedInputFrame::from(self.clone()).into()
+        }
+    }
+
+    // PlayerDelta の Option フィールド用 serde アダプタ
+    pub mod opt_pos {
+        use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+        pub fn serialize<S: Serializer>(v: &Option<[f32; 3]>, s: S) -> Result<S::Ok, S::Error> {
+            v.map(super::pos_to_bits).serialize(s)
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Option<[f32; 3]>, D::Error> {
+            Ok(Option::<u64>::deserialize(d)?.map(super::bits_to_pos))
+        }
+    }
+
+    pub mod opt_angle {
+        use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+        pub fn serialize<S: Serializer>(v: &Option<f32>, s: S) -> Result<S::Ok, S::Error> {
+            v.map(super::angle_to_u16).serialize(s)
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Option<f32>, D::Error> {
+            Ok(Option::<u16>::deserialize(d)?.map(super::u16_to_angle))
+        }
+    }
+
+    pub mod opt_vel {
+        use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+        pub fn serialize<S: Serializer>(v: &Option<f32>, s: S) -> Result<S::Ok, S::Error> {
+            v.map(super::vel_to_i16).serialize(s)
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Option<f32>, D::Error> {
+            Ok(Option::<i16>::deserialize(d)?.map(super::i16_to_vel))
+        }
+    }
+}
+
+// サーバのソケット・認証設定（CLI 引数、無ければ環境変数から組み立てる）
+#[derive(Debug, Clone)]
+pub struct ServerNetSettings {
+    pub bind_addr: SocketAddr,   // 実際にバインドするアドレス
+    pub public_addr: SocketAddr, // 接続トークンでクライアントに広告するアドレス
+    pub max_clients: usize,
+    pub private_key: Option<[u8; 32]>, // Some なら Secure 認証
+}
+
+impl ServerNetSettings {
+    // bind/public の片方だけ指定されたら、もう片方はポートを合わせて補う
+    // （既定は 0.0.0.0 でバインドし、127.0.0.1 を広告）
+    pub fn new(bind: Option<SocketAddr>, public: Option<SocketAddr>) -> Self {
+        let port = bind.or(public).map(|a| a.port()).unwrap_or(SERVER_PORT);
+        let bind_addr =
+            bind.unwrap_or_else(|| SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, port)));
+        let public_addr =
+            public.unwrap_or_else(|| SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::LOCALHOST, port)));
+        Self {
+            bind_addr,
+            public_addr,
+            max_clients: 32,
+            private_key: None,
+        }
+    }
+
+    // 環境変数のみで組み立てる（SERVER_ADDR / SECURE / NETCODE_KEY / NETCODE_KEY_FILE）
+    pub fn from_env() -> Result<Self, String> {
+        let public = match env::var("SERVER_ADDR") {
+            Ok(s) => Some(
+                s.parse::<SocketAddr>()
+                    .map_err(|e| format!("SERVER_ADDR={}: {}", s, e))?,
+            ),
+            Err(_) => None,
+        };
+        let mut settings = Self::new(None, public);
+        // Secure/Unsecure 切替（WAN時は SECURE=1 と NETCODE_KEY を設定）
+        let secure = matches!(
+            env::var("SECURE").ok().as_deref(),
+            Some("1" | "true" | "TRUE")
+        );
+        if secure {
+            settings.private_key = Some(read_netcode_key()?);
+        }
+        Ok(settings)
+    }
 }
 
-pub fn new_server() -> (RenetServer, NetcodeServerTransport) {
+pub fn new_server(
+    settings: &ServerNetSettings,
+) -> Result<(RenetServer, NetcodeServerTransport), String> {
     let server = RenetServer::new(connection_config());
-    // SERVER_ADDR=host:port があればそれを広告先にし、ポートも合わせてバインド
-    let env_server_addr = env::var("SERVER_ADDR")
-        .ok()
-        .and_then(|s| s.parse::<SocketAddr>().ok());
-    let (public_addr, bind_port) = match env_server_addr {
-        Some(sock) => (sock, sock.port()),
-        None => (
-            SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::LOCALHOST, SERVER_PORT)),
-            SERVER_PORT,
-        ),
-    };
-    // Bind to all interfaces for reachability on LAN
-    let bind_addr = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, bind_port));
     let current_time = SystemTime::now()
         .duration_since(SystemTime::UNIX_EPOCH)
         .unwrap();
-    let max_clients = 32;
-    // Secure/Unsecure 切替（WAN時は SECURE=1 と NETCODE_KEY を設定）
-    let secure = matches!(
-        env::var("SECURE").ok().as_deref(),
-        Some("1" | "true" | "TRUE")
-    );
-    let authentication = if secure {
-        let key =
-            read_netcode_key().expect("SECURE=1 ですが NETCODE_KEY/NETCODE_KEY_FILE が不正です");
-        ServerAuthentication::Secure { private_key: key }
-    } else {
-        ServerAuthentication::Unsecure
+    let authentication = match settings.private_key {
+        Some(key) => ServerAuthentication::Secure { private_key: key },
+        None => ServerAuthentication::Unsecure,
     };
 
     let server_config = ServerConfig {
         current_time,
-        max_clients,
+        max_clients: settings.max_clients,
         protocol_id: PROTOCOL_ID,
-        public_addresses: vec![public_addr],
+        public_addresses: vec![settings.public_addr],
         authentication,
     };
-    let socket = UdpSocket::bind(bind_addr).expect("bind server socket");
+    let socket = UdpSocket::bind(settings.bind_addr)
+        .map_err(|e| format!("cannot bind {}: {}", settings.bind_addr, e))?;
     if let Ok(local) = socket.local_addr() {
         println!(
             "server socket bound at {} (public {})",
             local, server_config.public_addresses[0]
         );
     }
-    let transport = NetcodeServerTransport::new(server_config, socket).expect("transport");
-    (server, transport)
+    let transport = NetcodeServerTransport::new(server_config, socket)
+        .map_err(|e| format!("cannot start netcode transport: {}", e))?;
+    Ok((server, transport))
 }
 
-pub fn new_client(local_port: Option<u16>) -> (RenetClient, NetcodeClientTransport, ClientId) {
+// token があれば（トークン発行サービスから取得済み）それで接続する。接続先・クライアント ID・名前もトークンのもの
+pub fn new_client(
+    local_port: Option<u16>,
+    token: Option<ConnectToken>,
+    name: &str,
+    password: &str,
+) -> (RenetClient, NetcodeClientTransport, ClientId) {
     let client = RenetClient::new(connection_config());
     // SERVER_ADDR=host:port があれば優先（同一Wi-Fi/別PC接続向け）。無ければ 127.0.0.1:SERVER_PORT
     let server_addr = env::var("SERVER_ADDR")
         .ok()
         .and_then(|s| s.parse::<SocketAddr>().ok())
         .unwrap_or_else(|| SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::LOCALHOST, SERVER_PORT)));
-    let client_id = ClientId::from_raw(rand::random::<u64>());
+    let client_id = ClientId::from_raw(
+        token
+            .as_ref()
+            .map_or_else(rand::random::<u64>, |t| t.client_id),
+    );
     let current_time = SystemTime::now()
         .duration_since(SystemTime::UNIX_EPOCH)
         .unwrap();
@@ -208,9 +832,18 @@ pub fn new_client(local_port: Option<u16>) -> (RenetClient, NetcodeClientTranspo
         env::var("SECURE").ok().as_deref(),
         Some("1" | "true" | "TRUE")
     );
-    let authentication = if secure {
+    let hello = ConnectUserData {
+        name: clean_player_name(name),
+        password: password.to_string(),
+        ..ConnectUserData::local()
+    };
+    let authentication = if let Some(connect_token) = token {
+        ClientAuthentication::Secure { connect_token }
+    } else if secure {
+        // 開発用: 鍵を直接持って自分でトークンを作る（配布するクライアントでは TOKEN_ISSUER を使う）
         let key =
             read_netcode_key().expect("SECURE=1 ですが NETCODE_KEY/NETCODE_KEY_FILE が不正です");
+        let user_data = hello.to_bytes();
         let token = ConnectToken::generate(
             current_time,
             PROTOCOL_ID,
@@ -218,7 +851,7 @@ pub fn new_client(local_port: Option<u16>) -> (RenetClient, NetcodeClientTranspo
             client_id.raw(),   // client id
             15,                // handshake timeout seconds
             vec![server_addr], // server addresses
-            None,              // optional user data
+            Some(&user_data),  // handshake (version/caps/name)
             &key,
         )
         .expect("generate connect token");
@@ -231,7 +864,7 @@ pub fn new_client(local_port: Option<u16>) -> (RenetClient, NetcodeClientTranspo
             server_addr,
             client_id: client_id.raw(),
             protocol_id: PROTOCOL_ID,
-            user_data: None,
+            user_data: Some(hello.to_bytes()),
         }
     };
     // 環境変数 CLIENT_PORT があればそのポートでバインド（デバッグ用）
@@ -255,25 +888,31 @@ pub fn new_client(local_port: Option<u16>) -> (RenetClient, NetcodeClientTranspo
 
 // --- helpers ---
 
-fn read_netcode_key() -> Result<[u8; 32], String> {
+pub fn read_netcode_key() -> Result<[u8; 32], String> {
     // 優先: NETCODE_KEY（HEX 64文字 or 0x...付き）
     if let Ok(s) = env::var("NETCODE_KEY") {
         return parse_hex_key(&s);
     }
     // 次点: NETCODE_KEY_FILE（バイナリ32バイト or HEX文字列）
     if let Ok(path) = env::var("NETCODE_KEY_FILE") {
-        let data = std::fs::read(&path).map_err(|e| format!("read NETCODE_KEY_FILE: {}", e))?;
-        if data.len() == 32 {
-            let mut k = [0u8; 32];
-            k.copy_from_slice(&data);
-            return Ok(k);
-        }
-        let s = String::from_utf8_lossy(&data).trim().to_string();
-        return parse_hex_key(&s);
+        return read_key_file(path);
     }
     Err("NETCODE_KEY か NETCODE_KEY_FILE を指定してください".into())
 }
 
+// 鍵ファイル（バイナリ32バイト or HEX文字列）
+pub fn read_key_file(path: impl AsRef<std::path::Path>) -> Result<[u8; 32], String> {
+    let path = path.as_ref();
+    let data = std::fs::read(path).map_err(|e| format!("read {}: {}", path.display(), e))?;
+    if data.len() == 32 {
+        let mut k = [0u8; 32];
+        k.copy_from_slice(&data);
+        return Ok(k);
+    }
+    let s = String::from_utf8_lossy(&data).trim().to_string();
+    parse_hex_key(&s)
+}
+
 fn parse_hex_key(s: &str) -> Result<[u8; 32], String> {
     let s = s.trim();
     let s = s.strip_prefix("0x").unwrap_or(s);
@@ -288,3 +927,162 @@ fn parse_hex_key(s: &str) -> Result<[u8; 32], String> {
     }
     Ok(out)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::PI;
+
+    fn sample_state(pos: [f32; 3], yaw: f32, vy: f32) -> PlayerStateMsg {
+        PlayerStateMsg {
+            id: 0xDEAD_BEEF_0123_4567,
+            pos,
+            yaw,
+            alive: true,
+            hp: 65,
+            vy,
+            grounded: false,
+            kind: ActorKind::Bot,
+        }
+    }
+
+    fn roundtrip<T: Serialize + for<'de> Deserialize<'de>>(v: &T) -> T {
+        bincode::deserialize(&bincode::serialize(v).unwrap()).unwrap()
+    }
+
+    // 最大誤差は半ステップ。クライアントの補正デッドバンドより十分小さいこと
+    const _: () = assert!(quant::POS_STEP * 0.5 < shared::POS_DEADBAND * 0.1);
+
+    #[test]
+    fn position_error_stays_under_deadband() {
+        let mut x = quant::MAP_MIN[0];
+        while x < quant::MAP_MIN[0] + quant::MAP_EXTENT {
+            let p = [x, x * 0.25 + 3.3, -x * 0.7];
+            let q = quant::bits_to_pos(quant::pos_to_bits(p));
+            for axis in 0..3 {
+                assert!(
+                    (q[axis] - p[axis]).abs() <= quant::POS_STEP * 0.5 + 1e-4,
+                    "axis {} {} -> {}",
+                    axis,
+                    p[axis],
+                    q[axis]
+                );
+            }
+            x += 0.731;
+        }
+    }
+
+    #[test]
+    fn player_state_roundtrip() {
+        let s = sample_state([-30.123, 30.456, 5.789], 2.5, -7.77);
+        let r = roundtrip(&s);
+        assert_eq!(r.id, s.id);
+        for axis in 0..3 {
+            assert!((r.pos[axis] - s.pos[axis]).abs() < shared::POS_DEADBAND);
+        }
+        assert!((r.yaw - s.yaw).abs() <= PI / 65536.0 + 1e-6);
+        assert!((r.vy - s.vy).abs() <= 0.5 / 256.0 + 1e-6);
+        assert_eq!((r.alive, r.hp, r.grounded), (s.alive, s.hp, s.grounded));
+        assert_eq!(r.kind, s.kind);
+        assert_eq!(bincode::serialize(&s).unwrap().len(), 21);
+    }
+
+    #[test]
+    fn angles_wrap_and_clamp() {
+        for i in -720..=720 {
+            let a = i as f32 * 0.01;
+            let r = quant::u16_to_angle(quant::angle_to_u16(a));
+            let diff = (r - a + PI).rem_euclid(2.0 * PI) - PI;
+            assert!(diff.abs() <= PI / 65536.0 + 1e-5, "{} -> {}", a, r);
+        }
+        let p = quant::u16_to_pitch(quant::pitch_to_u16(10.0));
+        assert!((p - std::f32::consts::FRAC_PI_2).abs() < 1e-4);
+        let p = quant::u16_to_pitch(quant::pitch_to_u16(-1.54));
+        assert!((p + 1.54).abs() <= PI / 65535.0);
+    }
+
+    #[test]
+    fn input_frame_roundtrip_is_stable() {
+        let f = InputFrame {
+            seq: 123_456,
+            mv: [-1.0, 1.0],
+            jump: true,
+            fire: false,
+            ads: true,
+            yaw: -2.9,
+            pitch: 0.4,
+            dt: 1.0 / 60.0,
+        };
+        let r = roundtrip(&f);
+        assert_eq!(r.seq, f.seq);
+        assert_eq!(r.mv, f.mv);
+        assert_eq!((r.jump, r.fire, r.ads), (f.jump, f.fire, f.ads));
+        assert_eq!(r.dt, f.dt);
+        assert!((r.yaw - f.yaw).abs() <= PI / 65536.0 + 1e-6);
+        assert!((r.pitch - f.pitch).abs() <= PI / 65535.0);
+        // 量子化済みの値はもう一度通しても変わらない（予測とサーバで同じ値になる）
+        let q = f.quantized();
+        let rq = roundtrip(&q);
+        assert_eq!((rq.yaw, rq.pitch, rq.dt, rq.mv), (q.yaw, q.pitch, q.dt, q.mv));
+        assert_eq!(bincode::serialize(&f).unwrap().len(), 10);
+    }
+
+    #[test]
+    fn delta_roundtrip_matches_full_snapshot() {
+        let base = vec![
+            sample_state([1.0, 2.0, 3.0], 0.0, 0.0),
+            PlayerStateMsg {
+                id: 7,
+                ..sample_state([4.0, 5.0, 6.0], 1.0, 0.0)
+            },
+        ];
+        let cur = SnapshotMsg {
+            tick: 11,
+            players: vec![
+                // 量子化で消える程度の変化は送らない
+                sample_state([1.0001, 2.0, 3.0], 0.0, 0.0),
+                PlayerStateMsg {
+                    id: 9,
+                    ..sample_state([-8.0, 0.5, 12.0], -1.0, 3.0)
+                },
+            ],
+            acks: vec![(9, 42)],
+        };
+        let base_rt: Vec<PlayerStateMsg> = base.iter().map(roundtrip).collect();
+        let delta = roundtrip(&diff_snapshot(10, &base, &cur));
+        assert_eq!(delta.base_tick, 10);
+        assert_eq!(delta.removed, vec![7]);
+        assert_eq!(delta.changed.len(), 1);
+        let rebuilt = apply_delta(&base_rt, &delta);
+        assert_eq!(rebuilt.tick, 11);
+        assert_eq!(rebuilt.acks, vec![(9, 42)]);
+        assert_eq!(rebuilt.players.len(), 2);
+        for p in &cur.players {
+            let r = rebuilt.players.iter().find(|r| r.id == p.id).unwrap();
+            for axis in 0..3 {
+                assert!((r.pos[axis] - p.pos[axis]).abs() < shared::POS_DEADBAND);
+            }
+            assert_eq!(r.hp, p.hp);
+        }
+    }
+
+    #[test]
+    fn bind_and_public_addr_share_the_port() {
+        let public: SocketAddr = "203.0.113.5:6000".parse().unwrap();
+        let s = ServerNetSettings::new(None, Some(public));
+        assert_eq!(s.bind_addr, "0.0.0.0:6000".parse().unwrap());
+        assert_eq!(s.public_addr, public);
+        let s = ServerNetSettings::new(Some("127.0.0.1:7000".parse().unwrap()), None);
+        assert_eq!(s.public_addr, "127.0.0.1:7000".parse().unwrap());
+        let s = ServerNetSettings::new(None, None);
+        assert_eq!(s.bind_addr.port(), SERVER_PORT);
+    }
+
+    #[test]
+    fn hex_key_is_validated() {
+        let hex = "0x".to_string() + &"ab".repeat(32);
+        assert_eq!(parse_hex_key(&hex).unwrap(), [0xab; 32]);
+        assert!(parse_hex_key("abcd").is_err());
+        assert!(parse_hex_key(&"zz".repeat(32)).is_err());
+    }
+}
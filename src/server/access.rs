@@ -0,0 +1,429 @@
+// ===== アクセス制御 =====
+// 接続時（ServerEvent::ClientConnected）に BAN リスト・許可リスト・サーバパスワードを確かめる。
+// 対象はアカウント ID か IP アドレス（CIDR 可）。BAN には理由と期限（無ければ無期限）を付けられる。
+// 許可リストが空でなければ、載っているアカウント/アドレスだけが入れる（BAN が優先）。
+// AccessList::open したファイル（TOML）へ変更のたびに保存し、open しなければメモリ上だけで持つ。
+// 射撃検証による自動 BAN（anticheat.ban_after_kicks）もここに期限つきで載る。
+//
+// [[ban]]
+// addr = "203.0.113.0/24"
+// reason = "spam"
+// until = 1767225600   # UNIX 秒
+//
+// [[allow]]
+// account = 12
+use super::*;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+use subtle::ConstantTimeEq;
+
+// アドレスとプレフィックス長（ホスト部は 0 に揃える）
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub(super) struct Cidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    fn bits(addr: IpAddr) -> u8 {
+        if addr.is_ipv4() {
+            32
+        } else {
+            128
+        }
+    }
+
+    fn key(addr: IpAddr) -> u128 {
+        match addr {
+            IpAddr::V4(a) => u32::from(a) as u128,
+            IpAddr::V6(a) => u128::from(a),
+        }
+    }
+
+    // 先頭 prefix ビットが一致するか（IPv4 射影の IPv6 アドレスは IPv4 として比べる）
+    pub(super) fn contains(&self, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        if ip.is_ipv4() != self.addr.is_ipv4() {
+            return false;
+        }
+        let host = Self::bits(ip) - self.prefix;
+        host >= 128 || (Self::key(ip) ^ Self::key(self.addr)) >> host == 0
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr
+            .parse()
+            .map_err(|_| format!("{}: expected an IP address or CIDR", s))?;
+        let addr = addr.to_canonical();
+        let bits = Self::bits(addr);
+        let prefix = match prefix {
+            None => bits,
+            Some(p) => p
+                .parse::<u8>()
+                .ok()
+                .filter(|p| *p <= bits)
+                .ok_or_else(|| format!("{}: prefix must be 0-{}", s, bits))?,
+        };
+        let host = bits - prefix;
+        let net = if host >= 128 {
+            0
+        } else {
+            Self::key(addr) >> host << host
+        };
+        let addr = match addr {
+            IpAddr::V4(_) => IpAddr::V4((net as u32).into()),
+            IpAddr::V6(_) => IpAddr::V6(net.into()),
+        };
+        Ok(Self { addr, prefix })
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.prefix == Self::bits(self.addr) {
+            write!(f, "{}", self.addr)
+        } else {
+            write!(f, "{}/{}", self.addr, self.prefix)
+        }
+    }
+}
+
+// 単一アドレス
+impl From<IpAddr> for Cidr {
+    fn from(addr: IpAddr) -> Self {
+        let addr = addr.to_canonical();
+        Self {
+            addr,
+            prefix: Self::bits(addr),
+        }
+    }
+}
+
+impl TryFrom<String> for Cidr {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, String> {
+        s.parse()
+    }
+}
+
+impl From<Cidr> for String {
+    fn from(c: Cidr) -> String {
+        c.to_string()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(super) enum Target {
+    Account(u64),
+    Addr(Cidr),
+}
+
+impl Target {
+    // 数字だけならアカウント、それ以外はアドレス
+    pub(super) fn parse(s: &str) -> Result<Self, String> {
+        match s.parse::<u64>() {
+            Ok(0) => Err("account 0 is a guest and cannot be listed".into()),
+            Ok(account_id) => Ok(Self::Account(account_id)),
+            Err(_) => s.parse().map(Self::Addr),
+        }
+    }
+
+    pub(super) fn matches(&self, addr: Option<IpAddr>, account_id: u64) -> bool {
+        match self {
+            Self::Account(a) => account_id != 0 && *a == account_id,
+            Self::Addr(net) => addr.is_some_and(|ip| net.contains(ip)),
+        }
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Account(a) => write!(f, "account {}", a),
+            Self::Addr(net) => write!(f, "{}", net),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(super) struct Rule {
+    #[serde(flatten)]
+    pub(super) target: Target,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub(super) reason: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(super) until: Option<u64>, // UNIX 秒。None は無期限
+}
+
+impl Rule {
+    fn active(&self, now: u64) -> bool {
+        self.until.is_none_or(|t| t > now)
+    }
+}
+
+#[derive(Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AccessFile {
+    #[serde(default, rename = "ban")]
+    bans: Vec<Rule>,
+    #[serde(default)]
+    allow: Vec<Rule>,
+}
+
+#[derive(Resource, Default)]
+pub struct AccessList {
+    path: Option<PathBuf>,
+    bans: Vec<Rule>,
+    allow: Vec<Rule>,
+}
+
+pub(super) fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+// 残り時間の目安（一番大きい単位で切り上げ）
+pub(super) fn format_remaining(secs: u64) -> String {
+    match secs {
+        0..=59 => format!("{}s", secs.max(1)),
+        60..=3599 => format!("{}m", secs.div_ceil(60)),
+        3600..=86_399 => format!("{}h", secs.div_ceil(3600)),
+        _ => format!("{}d", secs.div_ceil(86_400)),
+    }
+}
+
+// "90s" / "30m" / "12h" / "7d"
+pub(super) fn parse_duration(s: &str) -> Option<u64> {
+    let unit = match s.chars().last()? {
+        's' => 1,
+        'm' => 60,
+        'h' => 3600,
+        'd' => 86_400,
+        _ => return None,
+    };
+    let n: u64 = s[..s.len() - 1].parse().ok().filter(|n| *n > 0)?;
+    n.checked_mul(unit)
+}
+
+impl AccessList {
+    // 無ければ空で始め、最初の変更で作る
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref();
+        let file: AccessFile = if path.exists() {
+            let text = fs::read_to_string(path)
+                .map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+            toml::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))?
+        } else {
+            AccessFile::default()
+        };
+        Ok(Self {
+            path: Some(path.to_path_buf()),
+            bans: file.bans,
+            allow: file.allow,
+        })
+    }
+
+    // 入れてよければ Ok、だめなら Reject の理由
+    pub(super) fn check(
+        &self,
+        addr: Option<IpAddr>,
+        account_id: u64,
+        now: u64,
+    ) -> Result<(), String> {
+        if let Some(ban) = self
+            .bans
+            .iter()
+            .find(|r| r.active(now) && r.target.matches(addr, account_id))
+        {
+            let mut reason = "You are banned from this server".to_string();
+            if !ban.reason.is_empty() {
+                reason.push_str(&format!(": {}", ban.reason));
+            }
+            if let Some(until) = ban.until {
+                reason.push_str(&format!(" ({} left)", format_remaining(until - now)));
+            }
+            return Err(reason);
+        }
+        let mut allow = self.allow.iter().filter(|r| r.active(now)).peekable();
+        if allow.peek().is_some() && !allow.any(|r| r.target.matches(addr, account_id)) {
+            return Err("This server only admits listed players".into());
+        }
+        Ok(())
+    }
+
+    pub(super) fn bans(&self, now: u64) -> impl Iterator<Item = &Rule> {
+        self.bans.iter().filter(move |r| r.active(now))
+    }
+
+    pub(super) fn allowed(&self, now: u64) -> impl Iterator<Item = &Rule> {
+        self.allow.iter().filter(move |r| r.active(now))
+    }
+
+    // 同じ対象の BAN は置き換える
+    pub(super) fn ban(&mut self, rule: Rule) -> Result<(), String> {
+        self.bans.retain(|r| r.target != rule.target);
+        self.bans.push(rule);
+        self.save()
+    }
+
+    // 外したら true
+    pub(super) fn unban(&mut self, target: Target) -> Result<bool, String> {
+        let before = self.bans.len();
+        self.bans.retain(|r| r.target != target);
+        let removed = self.bans.len() != before;
+        if removed {
+            self.save()?;
+        }
+        Ok(removed)
+    }
+
+    pub(super) fn allow(&mut self, target: Target) -> Result<(), String> {
+        self.allow.retain(|r| r.target != target);
+        self.allow.push(Rule {
+            target,
+            reason: String::new(),
+            until: None,
+        });
+        self.save()
+    }
+
+    pub(super) fn disallow(&mut self, target: Target) -> Result<bool, String> {
+        let before = self.allow.len();
+        self.allow.retain(|r| r.target != target);
+        let removed = self.allow.len() != before;
+        if removed {
+            self.save()?;
+        }
+        Ok(removed)
+    }
+
+    // 期限切れは保存のついでに捨てる。一時ファイルに書いてから置き換える
+    fn save(&mut self) -> Result<(), String> {
+        let now = unix_now();
+        self.bans.retain(|r| r.active(now));
+        self.allow.retain(|r| r.active(now));
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let file = AccessFile {
+            bans: self.bans.clone(),
+            allow: self.allow.clone(),
+        };
+        let mut tmp = path.clone().into_os_string();
+        tmp.push(".tmp");
+        toml::to_string(&file)
+            .map_err(|e| e.to_string())
+            .and_then(|text| fs::write(&tmp, text).map_err(|e| e.to_string()))
+            .and_then(|_| fs::rename(&tmp, path).map_err(|e| e.to_string()))
+            .map_err(|e| {
+                format!(
+                    "cannot save {} (change kept until restart): {}",
+                    path.display(),
+                    e
+                )
+            })
+    }
+}
+
+// 設定されていれば、接続時の user data の password と一致しないと入れない
+#[derive(Resource, Default)]
+pub struct ServerPassword(pub Option<String>);
+
+impl ServerPassword {
+    pub(super) fn check(&self, given: &str) -> Result<(), String> {
+        match &self.0 {
+            Some(_) if given.is_empty() => Err("This server requires a password".into()),
+            // 比較時間から一致した長さを推測されないように
+            Some(pw) if !bool::from(given.as_bytes().ct_eq(pw.as_bytes())) => {
+                Err("Wrong server password".into())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+// 接続を受け入れてよいか（ハンドシェイク後に accept_clients が確かめる）
+#[derive(SystemParam)]
+pub(super) struct Gate<'w> {
+    access: Res<'w, AccessList>,
+    password: Res<'w, ServerPassword>,
+}
+
+impl Gate<'_> {
+    pub(super) fn admit(
+        &self,
+        addr: Option<IpAddr>,
+        hello: &ConnectUserData,
+    ) -> Result<(), String> {
+        self.access.check(addr, hello.account_id, unix_now())?;
+        self.password.check(&hello.password)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn cidr_matches_its_network_only() {
+        let net: Cidr = "203.0.113.77/24".parse().unwrap();
+        assert_eq!(net.to_string(), "203.0.113.0/24");
+        assert!(net.contains("203.0.113.5".parse().unwrap()));
+        assert!(net.contains("::ffff:203.0.113.9".parse().unwrap()));
+        assert!(!net.contains("203.0.114.5".parse().unwrap()));
+        assert!(!net.contains("2001:db8::1".parse().unwrap()));
+        let one: Cidr = "2001:db8::1".parse().unwrap();
+        assert!(one.contains("2001:db8::1".parse().unwrap()));
+        assert!(!one.contains("2001:db8::2".parse().unwrap()));
+        let all: Cidr = "0.0.0.0/0".parse().unwrap();
+        assert!(all.contains("198.51.100.1".parse().unwrap()));
+        for bad in ["10.0.0.0/33", "10.0.0", "host/8", "10.0.0.0/"] {
+            assert!(bad.parse::<Cidr>().is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn bans_expire_and_survive_reopen() {
+        let dir = TempDir::new("access");
+        let path = dir.join("access.toml");
+        let mut list = AccessList::open(&path).unwrap();
+        let now = unix_now();
+        list.ban(Rule {
+            target: Target::parse("10.1.0.0/16").unwrap(),
+            reason: "spam".into(),
+            until: Some(now + 3600),
+        })
+        .unwrap();
+        list.ban(Rule {
+            target: Target::parse("7").unwrap(),
+            reason: String::new(),
+            until: None,
+        })
+        .unwrap();
+        let list = AccessList::open(&path).unwrap();
+        let ip = Some("10.1.2.3".parse().unwrap());
+        let err = list.check(ip, 0, now).unwrap_err();
+        assert!(err.contains("spam") && err.contains("1h left"), "{}", err);
+        assert!(list.check(ip, 0, now + 3600).is_ok());
+        assert!(list.check(None, 7, now).is_err());
+        // ゲスト（0）はアカウント BAN に当たらない
+        assert!(list.check(None, 0, now).is_ok());
+    }
+}
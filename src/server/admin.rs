@@ -0,0 +1,664 @@
+// ===== 管理コンソール / RCON =====
+// 標準入力と TCP（パスワード認証）から受けた 1 行コマンドを AdminQueue に積み、Update で実行して結果を返す。
+// 入力元のスレッドはサーババイナリが起動する（spawn_stdin_console / spawn_rcon）。
+use super::*;
+use bevy::asset::AssetPath;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use subtle::ConstantTimeEq;
+
+const RCON_REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+// auth 行を待つ時間と、認証失敗から切断までの待ち
+const RCON_AUTH_TIMEOUT: Duration = Duration::from_secs(10);
+const RCON_AUTH_FAIL_DELAY: Duration = Duration::from_secs(1);
+const ANNOUNCE_MAX_CHARS: usize = 200;
+
+const HELP: &str = "commands: status | kick <id> | ban <id> [dur] [reason] | banip <addr[/bits]> [dur] [reason] | banaccount <account> [dur] [reason] | unban <addr|account> | bans | allow <addr|account> | disallow <addr|account> | setbots <n> | endround | changemap <scene> | say <msg> | dump scores | stats [account] | help";
+const BAN_REASON_MAX_CHARS: usize = 120;
+
+struct AdminRequest {
+    line: String,
+    reply: Sender<String>,
+}
+
+// 入力元から実行システムへのキュー
+#[derive(Resource)]
+pub struct AdminQueue {
+    tx: Sender<AdminRequest>,
+    rx: Mutex<Receiver<AdminRequest>>,
+}
+
+impl Default for AdminQueue {
+    fn default() -> Self {
+        let (tx, rx) = channel();
+        Self {
+            tx,
+            rx: Mutex::new(rx),
+        }
+    }
+}
+
+impl AdminQueue {
+    // コマンドを積む。結果は次の Update 後に返ってくる
+    pub fn submit(&self, line: impl Into<String>) -> Receiver<String> {
+        let (reply, result) = channel();
+        let _ = self.tx.send(AdminRequest {
+            line: line.into(),
+            reply,
+        });
+        result
+    }
+
+    fn sender(&self) -> Sender<AdminRequest> {
+        self.tx.clone()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum AdminCommand {
+    Status,
+    Kick(u64),
+    // 接続中のプレイヤー（アカウントがあればアカウント、無ければアドレス）
+    Ban(u64, BanTerms),
+    BanTarget(Target, BanTerms),
+    Unban(Target),
+    Bans,
+    Allow(Target),
+    Disallow(Target),
+    SetBots(usize),
+    EndRound,
+    ChangeMap(String),
+    Say(String),
+    DumpScores,
+    Stats(Option<u64>),
+    Help,
+}
+
+// BAN の期限（秒、None は無期限）と理由
+#[derive(Debug, Clone, PartialEq)]
+struct BanTerms {
+    secs: Option<u64>,
+    reason: String,
+}
+
+impl BanTerms {
+    // 先頭が期間（30m / 12h / 7d）なら期限、残りは理由
+    fn parse(rest: &str) -> Self {
+        let (first, after) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+        let (secs, reason) = match parse_duration(first) {
+            Some(secs) => (Some(secs), after.trim()),
+            None => (None, rest),
+        };
+        Self {
+            secs,
+            reason: reason.chars().take(BAN_REASON_MAX_CHARS).collect(),
+        }
+    }
+
+    fn rule(self, target: Target) -> Rule {
+        Rule {
+            target,
+            reason: self.reason,
+            until: self.secs.map(|s| unix_now() + s),
+        }
+    }
+}
+
+impl AdminCommand {
+    fn parse(line: &str) -> Result<Self, String> {
+        let line = line.trim();
+        let (word, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let rest = rest.trim();
+        let id = || {
+            rest.parse::<u64>()
+                .map_err(|_| format!("{}: expected a player id", word))
+        };
+        // 最初の語が対象、残りが期限と理由
+        let (head, tail) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+        let target = || {
+            if head.is_empty() {
+                Err(format!("{}: expected an address or account id", word))
+            } else {
+                Target::parse(head).map_err(|e| format!("{}: {}", word, e))
+            }
+        };
+        match word.to_ascii_lowercase().as_str() {
+            "status" => Ok(Self::Status),
+            "kick" => Ok(Self::Kick(id()?)),
+            "ban" => head
+                .parse()
+                .map(|id| Self::Ban(id, BanTerms::parse(tail.trim())))
+                .map_err(|_| "ban: expected a player id".into()),
+            "banip" => match target()? {
+                t @ Target::Addr(_) => Ok(Self::BanTarget(t, BanTerms::parse(tail.trim()))),
+                Target::Account(_) => Err("banip: expected an address (use banaccount)".into()),
+            },
+            "banaccount" => match target()? {
+                t @ Target::Account(_) => Ok(Self::BanTarget(t, BanTerms::parse(tail.trim()))),
+                Target::Addr(_) => Err("banaccount: expected an account id (use banip)".into()),
+            },
+            "unban" => Ok(Self::Unban(target()?)),
+            "bans" => Ok(Self::Bans),
+            "allow" => Ok(Self::Allow(target()?)),
+            "disallow" => Ok(Self::Disallow(target()?)),
+            "setbots" => rest
+                .parse()
+                .map(Self::SetBots)
+                .map_err(|_| "setbots: expected a bot count".into()),
+            "endround" => Ok(Self::EndRound),
+            "changemap" if !rest.is_empty() => Ok(Self::ChangeMap(rest.to_string())),
+            "changemap" => {
+                Err("changemap: expected a scene path (e.g. maps/map.glb#Scene0)".into())
+            }
+            "say" if !rest.is_empty() => {
+                Ok(Self::Say(rest.chars().take(ANNOUNCE_MAX_CHARS).collect()))
+            }
+            "say" => Err("say: expected a message".into()),
+            "dump" if rest.eq_ignore_ascii_case("scores") => Ok(Self::DumpScores),
+            "stats" if rest.is_empty() => Ok(Self::Stats(None)),
+            "stats" => rest
+                .parse()
+                .map(|a| Self::Stats(Some(a)))
+                .map_err(|_| "stats: expected an account id".into()),
+            "help" | "?" => Ok(Self::Help),
+            _ => Err(format!("unknown command: {} ({})", word, HELP)),
+        }
+    }
+}
+
+pub struct AdminPlugin;
+
+impl Plugin for AdminPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<AdminQueue>()
+            // kick/ban の切断猶予を同じフレームから数える
+            .add_systems(
+                Update,
+                run_admin_commands.before(process_pending_disconnects),
+            );
+    }
+}
+
+// 標準入力の各行をコマンドとして実行し、結果を標準出力へ
+pub fn spawn_stdin_console(queue: &AdminQueue) {
+    let tx = queue.sender();
+    std::thread::spawn(move || {
+        for line in std::io::stdin().lock().lines() {
+            let Ok(line) = line else {
+                break;
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+            let (reply, result) = channel();
+            if tx.send(AdminRequest { line, reply }).is_err() {
+                break;
+            }
+            if let Ok(out) = result.recv() {
+                println!("{}", out);
+            }
+        }
+    });
+}
+
+// RCON: 1 接続 1 スレッドの行プロトコル。最初の行で `auth <password>`、以降は 1 行 1 コマンド。
+// 応答は本文の後に空行を付けて区切る。認証に失敗した接続は待たせてから閉じる
+pub fn spawn_rcon(listener: TcpListener, password: String, queue: &AdminQueue) {
+    let tx = queue.sender();
+    // 失敗の待ちは 1 件ずつ（並列に試しても 1 秒に 1 回まで）
+    let penalty = Arc::new(Mutex::new(()));
+    std::thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let tx = tx.clone();
+            let password = password.clone();
+            let penalty = penalty.clone();
+            std::thread::spawn(move || {
+                let peer = stream.peer_addr().ok();
+                if let Err(e) = serve_rcon(stream, &password, &tx, &penalty) {
+                    warn!("rcon {:?}: {}", peer, e);
+                }
+            });
+        }
+    });
+}
+
+fn serve_rcon(
+    stream: TcpStream,
+    password: &str,
+    tx: &Sender<AdminRequest>,
+    penalty: &Mutex<()>,
+) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(RCON_AUTH_TIMEOUT))?;
+    let mut out = stream.try_clone()?;
+    let mut lines = BufReader::new(stream).lines();
+    let authed = match lines.next() {
+        Some(line) => line?
+            .trim()
+            .strip_prefix("auth ")
+            .is_some_and(|given| bool::from(given.as_bytes().ct_eq(password.as_bytes()))),
+        None => return Ok(()),
+    };
+    if !authed {
+        if let Ok(_turn) = penalty.lock() {
+            std::thread::sleep(RCON_AUTH_FAIL_DELAY);
+        }
+        writeln!(out, "error: authentication failed\n")?;
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::PermissionDenied,
+            "authentication failed",
+        ));
+    }
+    out.set_read_timeout(None)?;
+    writeln!(out, "ok\n")?;
+    for line in lines {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let (reply, result) = channel();
+        if tx.send(AdminRequest { line, reply }).is_err() {
+            break;
+        }
+        let text = result
+            .recv_timeout(RCON_REPLY_TIMEOUT)
+            .unwrap_or_else(|_| "error: server did not respond".into());
+        writeln!(out, "{}\n", text)?;
+    }
+    Ok(())
+}
+
+// マップ変更で触るもの
+#[derive(SystemParam)]
+struct MapControl<'w, 's> {
+    asset_server: Res<'w, AssetServer>,
+    scene: ResMut<'w, MapScene>,
+    spawns: ResMut<'w, SpawnPoints>,
+    ready: ResMut<'w, MapReady>,
+    roots: Query<'w, 's, Entity, With<MapRoot>>,
+    scaffolds: ScaffoldReset<'w>,
+}
+
+#[derive(SystemParam)]
+struct AdminContext<'w, 's> {
+    commands: Commands<'w, 's>,
+    net: NetSend<'w>,
+    players: Res<'w, Players>,
+    bots: Res<'w, Bots>,
+    scores: Res<'w, Scores>,
+    profiles: Res<'w, PlayerProfiles>,
+    stats: Res<'w, StatsStore>,
+    access: ResMut<'w, AccessList>,
+    round: ResMut<'w, RoundState>,
+    cfg: ResMut<'w, ServerConfig>,
+    conn: ConnectInfo<'w>,
+    pending_dc: ResMut<'w, PendingDisconnects>,
+    map: MapControl<'w, 's>,
+}
+
+fn run_admin_commands(
+    queue: Res<AdminQueue>,
+    mut ctx: AdminContext,
+    mut recorder: ResMut<InputRecorder>,
+) {
+    let Ok(rx) = queue.rx.lock() else {
+        return;
+    };
+    while let Ok(req) = rx.try_recv() {
+        recorder.capture(ReplayEvent::Admin(req.line.clone()));
+        let out = match AdminCommand::parse(&req.line) {
+            Ok(cmd) => {
+                info!("admin: {}", req.line.trim());
+                execute(cmd, &mut ctx)
+            }
+            Err(e) => Err(e),
+        };
+        let text = out.unwrap_or_else(|e| format!("error: {}", e));
+        let _ = req.reply.send(text);
+    }
+}
+
+fn execute(cmd: AdminCommand, ctx: &mut AdminContext) -> Result<String, String> {
+    match cmd {
+        AdminCommand::Help => Ok(HELP.to_string()),
+        AdminCommand::Status => Ok(status(ctx)),
+        AdminCommand::DumpScores => Ok(dump_scores(ctx)),
+        AdminCommand::Stats(None) => Ok(stats_table(&ctx.stats.top(STATS_TOP))),
+        AdminCommand::Stats(Some(account_id)) => ctx
+            .stats
+            .get(account_id)
+            .map(|s| stats_table(std::slice::from_ref(s)))
+            .ok_or_else(|| format!("no stats for account {}", account_id)),
+        AdminCommand::Kick(id) => {
+            disconnect_player(ctx, id, "Kicked by admin")?;
+            Ok(format!("kicked {}", id))
+        }
+        AdminCommand::Ban(id, terms) => {
+            let account_id = ctx.profiles.0.get(&id).map_or(0, |p| p.account_id);
+            let target = if account_id != 0 {
+                Target::Account(account_id)
+            } else {
+                let ip = ctx
+                    .conn
+                    .addr(id)
+                    .map(|a| a.ip())
+                    .ok_or_else(|| format!("{} has no account or remote address to ban", id))?;
+                Target::Addr(ip.into())
+            };
+            disconnect_player(ctx, id, &ban_message(&terms))?;
+            let rule = terms.rule(target);
+            ctx.access.ban(rule.clone())?;
+            Ok(format!("banned {} ({})", id, describe_rule(&rule)))
+        }
+        AdminCommand::BanTarget(target, terms) => {
+            // 当てはまる接続中のプレイヤーも切断する
+            let message = ban_message(&terms);
+            let rule = terms.rule(target);
+            let mut ids: Vec<u64> = ctx.players.states.keys().copied().collect();
+            ids.sort_unstable();
+            let mut kicked = Vec::new();
+            for id in ids {
+                let ip = ctx.conn.addr(id).map(|a| a.ip());
+                let account_id = ctx.profiles.0.get(&id).map_or(0, |p| p.account_id);
+                if rule.target.matches(ip, account_id) {
+                    disconnect_player(ctx, id, &message)?;
+                    kicked.push(id.to_string());
+                }
+            }
+            ctx.access.ban(rule.clone())?;
+            let mut out = format!("banned {}", describe_rule(&rule));
+            if !kicked.is_empty() {
+                out.push_str(&format!(", disconnected {}", kicked.join(" ")));
+            }
+            Ok(out)
+        }
+        AdminCommand::Unban(target) => {
+            if ctx.access.unban(target)? {
+                Ok(format!("unbanned {}", target))
+            } else {
+                Err(format!("{} is not banned", target))
+            }
+        }
+        AdminCommand::Bans => Ok(list_access(&ctx.access)),
+        AdminCommand::Allow(target) => {
+            ctx.access.allow(target)?;
+            Ok(format!("allowed {} (only listed players can join)", target))
+        }
+        AdminCommand::Disallow(target) => {
+            if ctx.access.disallow(target)? {
+                Ok(format!("removed {} from the allow list", target))
+            } else {
+                Err(format!("{} is not on the allow list", target))
+            }
+        }
+        AdminCommand::SetBots(n) => {
+            // 増減は ensure_bots / trim_bots が次の Update で反映する
+            ctx.cfg.bots.count = n;
+            Ok(format!("bot count set to {}", n))
+        }
+        AdminCommand::EndRound => {
+            if ctx.round.phase != RoundPhase::Active {
+                return Err("round is already ending".into());
+            }
+            // round_update が次の tick で時間切れとして終了させる
+            ctx.round.time_left = 0.0;
+            Ok("round ending".into())
+        }
+        AdminCommand::ChangeMap(scene) => {
+            let map = &mut ctx.map;
+            // 読めないパスで今のマップを壊すと、移動が止まったままになる
+            if !asset_exists(&map.asset_server, &scene) {
+                return Err(format!("no such map asset: {}", scene));
+            }
+            for e in map.roots.iter() {
+                ctx.commands.entity(e).despawn_recursive();
+            }
+            // 前のマップに置かれた足場も片付ける
+            map.scaffolds.clear(&mut ctx.commands);
+            // 新しいマップのコライダとスポーン地点が揃うまで移動を止める
+            map.spawns.0.clear();
+            map.ready.0 = false;
+            map.scene.0 = Some(scene.clone());
+            spawn_map(&mut ctx.commands, &map.asset_server, scene.clone());
+            ctx.net
+                .broadcast(&ServerMessage::Event(EventMsg::MapChange {
+                    scene: scene.clone(),
+                }));
+            // 全員を新しいスポーン地点に出し直すため、ラウンドを終わらせる
+            if ctx.round.phase == RoundPhase::Active {
+                ctx.round.time_left = 0.0;
+            }
+            Ok(format!("changing map to {}", scene))
+        }
+        AdminCommand::Say(text) => {
+            ctx.net.broadcast(&ServerMessage::Event(EventMsg::Announce {
+                text: text.clone(),
+            }));
+            Ok(format!("announced: {}", text))
+        }
+    }
+}
+
+// シーンパス（"maps/x.glb#Scene0"）のファイルがアセットソースにあるか
+fn asset_exists(asset_server: &AssetServer, scene: &str) -> bool {
+    let path = AssetPath::parse(scene);
+    let Ok(source) = asset_server.get_source(path.source().clone()) else {
+        return false;
+    };
+    let found = bevy::tasks::block_on(source.reader().read(path.path())).is_ok();
+    found
+}
+
+fn disconnect_player(ctx: &mut AdminContext, id: u64, reason: &str) -> Result<(), String> {
+    if is_bot_id(&ctx.bots, id) {
+        return Err(format!("{} is a bot (use setbots)", id));
+    }
+    if !ctx.players.states.contains_key(&id) {
+        return Err(format!("no player with id {}", id));
+    }
+    ctx.net.send_to(
+        id,
+        &ServerMessage::Reject {
+            reason: reason.to_string(),
+        },
+    );
+    ctx.pending_dc.0.insert(id, REJECT_LINGER_SEC);
+    Ok(())
+}
+
+fn status(ctx: &AdminContext) -> String {
+    let round = match ctx.round.phase {
+        RoundPhase::Active => format!("active, {:.0}s left", ctx.round.time_left.max(0.0)),
+        RoundPhase::Ending => format!("ending, next in {:.0}s", ctx.round.end_timer.max(0.0)),
+    };
+    let mut out = format!(
+        "map: {}\nround: {}\nplayers: {}  bots: {}/{}",
+        ctx.map.scene.0.as_deref().unwrap_or("(none)"),
+        round,
+        ctx.players.states.len(),
+        ctx.bots.states.len(),
+        ctx.cfg.bots.count
+    );
+    let mut ids: Vec<u64> = ctx.players.states.keys().copied().collect();
+    ids.sort_unstable();
+    for id in ids {
+        let p = &ctx.players.states[&id];
+        let (k, d) = ctx.scores.0.get(&id).copied().unwrap_or((0, 0));
+        let addr = ctx
+            .conn
+            .addr(id)
+            .map_or_else(|| "local".to_string(), |a| a.to_string());
+        out.push_str(&format!(
+            "\n  {} {} hp={} {} k/d={}/{}",
+            id,
+            addr,
+            p.hp,
+            if p.alive { "alive" } else { "dead" },
+            k,
+            d
+        ));
+        if let Some(profile) = ctx.profiles.0.get(&id) {
+            out.push_str(&format!(" {:?}", profile.name));
+            if profile.account_id != 0 {
+                out.push_str(&format!(" account={}", profile.account_id));
+            }
+        }
+    }
+    out
+}
+
+fn dump_scores(ctx: &AdminContext) -> String {
+    let mut rows: Vec<(u64, u32, u32)> = ctx
+        .scores
+        .0
+        .iter()
+        .map(|(id, (k, d))| (*id, *k, *d))
+        .collect();
+    // キル降順、同数ならデス昇順
+    rows.sort_by(|a, b| b.1.cmp(&a.1).then(a.2.cmp(&b.2)).then(a.0.cmp(&b.0)));
+    let mut out = String::from("id kills deaths");
+    for (id, k, d) in rows {
+        let tag = if is_bot_id(&ctx.bots, id) {
+            " (bot)"
+        } else {
+            ""
+        };
+        out.push_str(&format!("\n{} {} {}{}", id, k, d, tag));
+    }
+    out
+}
+
+fn ban_message(terms: &BanTerms) -> String {
+    if terms.reason.is_empty() {
+        "Banned by admin".to_string()
+    } else {
+        format!("Banned by admin: {}", terms.reason)
+    }
+}
+
+fn describe_rule(rule: &Rule) -> String {
+    let mut out = rule.target.to_string();
+    match rule.until {
+        Some(until) => out.push_str(&format!(
+            ", {} left",
+            format_remaining(until.saturating_sub(unix_now()))
+        )),
+        None => out.push_str(", permanent"),
+    }
+    if !rule.reason.is_empty() {
+        out.push_str(&format!(", {:?}", rule.reason));
+    }
+    out
+}
+
+fn list_access(access: &AccessList) -> String {
+    let now = unix_now();
+    let mut out = String::from("bans:");
+    for rule in access.bans(now) {
+        out.push_str(&format!("\n  {}", describe_rule(rule)));
+    }
+    let allowed: Vec<String> = access.allowed(now).map(|r| r.target.to_string()).collect();
+    if allowed.is_empty() {
+        out.push_str("\nallow list: (off, anyone not banned can join)");
+    } else {
+        out.push_str(&format!("\nallow list: {}", allowed.join(", ")));
+    }
+    out
+}
+
+fn stats_table(rows: &[PlayerStats]) -> String {
+    let mut out = String::from("account name kills deaths acc won scaffolds minutes");
+    for s in rows {
+        out.push_str(&format!(
+            "\n{} {:?} {} {} {:.0}% {} {} {:.0}",
+            s.account_id,
+            s.name,
+            s.kills,
+            s.deaths,
+            s.accuracy() * 100.0,
+            s.rounds_won,
+            s.scaffolds,
+            s.play_secs / 60.0
+        ));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commands_parse_with_arguments() {
+        assert_eq!(AdminCommand::parse("status"), Ok(AdminCommand::Status));
+        assert_eq!(AdminCommand::parse(" KICK 42 "), Ok(AdminCommand::Kick(42)));
+        assert_eq!(
+            AdminCommand::parse("setbots 3"),
+            Ok(AdminCommand::SetBots(3))
+        );
+        assert_eq!(
+            AdminCommand::parse("say  hello   all"),
+            Ok(AdminCommand::Say("hello   all".into()))
+        );
+        assert_eq!(
+            AdminCommand::parse("dump scores"),
+            Ok(AdminCommand::DumpScores)
+        );
+        assert_eq!(
+            AdminCommand::parse("stats 12"),
+            Ok(AdminCommand::Stats(Some(12)))
+        );
+        assert_eq!(
+            AdminCommand::parse("changemap maps/b.glb#Scene0"),
+            Ok(AdminCommand::ChangeMap("maps/b.glb#Scene0".into()))
+        );
+        assert_eq!(
+            AdminCommand::parse("ban 3 30m spam  again"),
+            Ok(AdminCommand::Ban(
+                3,
+                BanTerms {
+                    secs: Some(1800),
+                    reason: "spam  again".into()
+                }
+            ))
+        );
+        assert_eq!(
+            AdminCommand::parse("banip 10.1.2.3/8"),
+            Ok(AdminCommand::BanTarget(
+                Target::parse("10.0.0.0/8").unwrap(),
+                BanTerms {
+                    secs: None,
+                    reason: String::new()
+                }
+            ))
+        );
+        assert_eq!(
+            AdminCommand::parse("unban 5"),
+            Ok(AdminCommand::Unban(Target::Account(5)))
+        );
+    }
+
+    #[test]
+    fn bad_commands_are_rejected() {
+        for line in [
+            "",
+            "kick",
+            "kick bob",
+            "setbots -1",
+            "say",
+            "dump",
+            "changemap",
+            "stats bob",
+            "ban",
+            "banip 5",
+            "banaccount 1.2.3.4",
+            "unban 0",
+            "allow",
+            "fly",
+        ] {
+            assert!(AdminCommand::parse(line).is_err(), "{}", line);
+        }
+    }
+}
@@ -0,0 +1,894 @@
+// ===== Bots（サーバ側 AI プレイヤー） =====
+// 人間プレイヤーと同じ Players/Weapons/Scores を共有し、ID 帯（BOT_ID_START〜）で区別する
+use super::*;
+
+// --- Bot Perception/Movement params ---
+const BOT_DETECT_RANGE: f32 = 30.0; // 発見距離（3D）
+const BOT_DESIRED_NEAR: f32 = 10.0; // 適正距離下限
+const BOT_DESIRED_FAR: f32 = 22.0; // 適正距離上限
+const BOT_STRAFE_SPEED_MUL: f32 = 0.8; // ストラフェ速度倍率
+const BOT_STRAFE_SWITCH_SEC: f32 = 2.0; // ストラフェ左右切替間隔
+const BOT_LOS_GRACE_SEC: f32 = 0.6; // LoS喪失から Lost 遷移まで
+const BOT_LOST_TO_WANDER_SEC: f32 = 2.0; // Lost 状態から Wander 復帰
+const BOT_WANDER_RADIUS: f32 = 16.0; // 徘徊サンプル半径
+const BOT_WANDER_REPLAN_SEC: f32 = 4.0; // 徘徊目的地の再計画間隔
+const BOT_WANDER_RETRY: usize = 8; // 地面投影のリトライ回数
+const BOT_PROBE_AHEAD: f32 = 1.2; // 落下防止: 前方プローブ距離
+const BOT_MAX_DROP: f32 = 0.7; // 落差しきい値
+
+const BOT_SPAWN_COOLDOWN: f32 = 2.0;
+const BOT_ID_START: u64 = 1_000_000_000_000; // 衝突低確率な帯を使用
+const BOT_FOV_COS: f32 = 0.5; // 約60度（厳しめに）
+const BOT_TURN_RATE: f32 = 6.0; // rad/s: 向き直り速度
+const BOT_SPREAD_BASE: f32 = 0.015; // 基本拡散（ラジアン）
+const BOT_SPREAD_DIST_K: f32 = 0.01; // 距離による拡散増加
+const BOT_AIRBORNE_SPREAD_MUL: f32 = 1.5; // 空中ターゲット拡散倍率
+
+pub struct BotPlugin;
+
+impl Plugin for BotPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Bots>()
+            .init_resource::<BotEntities>()
+            .init_resource::<BotRespawnTimers>()
+            .insert_resource(NextBotId(BOT_ID_START))
+            .init_resource::<BotFocus>()
+            .init_resource::<BotWander>()
+            .init_resource::<BotLosMissing>()
+            .init_resource::<BotFSM>()
+            .init_resource::<BotTarget>()
+            .init_resource::<BotStrafe>()
+            .init_resource::<BotSafePos>()
+            .add_systems(Update, (trim_bots, ensure_bots).chain())
+            .add_systems(
+                FixedUpdate,
+                (
+                    bot_ai_perception_and_fsm,
+                    bot_wander_planner,
+                    bot_kcc_move_fsm,
+                )
+                    .before(PhysicsSet::StepSimulation),
+            )
+            .add_systems(
+                FixedUpdate,
+                bot_kcc_post
+                    .after(PhysicsSet::Writeback)
+                    .before(broadcast_snapshots),
+            )
+            .add_systems(FixedUpdate, bot_ai_shoot_and_respawn);
+    }
+}
+
+// --- Bots ---
+#[derive(Default, Clone, Copy)]
+pub(super) struct BotState {
+    pub(super) pos: Vec3,
+    pub(super) yaw: f32,
+    pub(super) hp: u16,
+    pub(super) alive: bool,
+    pub(super) vy: f32,
+    pub(super) grounded: bool,
+}
+
+#[derive(Resource, Default)]
+pub(super) struct Bots {
+    pub(super) states: HashMap<u64, BotState>,
+}
+
+#[derive(Resource, Default)]
+pub(super) struct BotEntities(pub(super) HashMap<u64, Entity>);
+
+#[derive(Resource, Default)]
+pub(super) struct BotRespawnTimers(pub(super) HashMap<u64, f32>);
+
+#[derive(Resource)]
+struct NextBotId(u64);
+
+#[derive(Resource, Default)]
+struct BotFocus(HashMap<u64, (Option<u64>, f32)>); // (target_id, lock_time)
+
+#[derive(Resource, Default)]
+struct BotWander(HashMap<u64, (Vec3, f32)>); // (target, timer)
+
+#[derive(Resource, Default)]
+struct BotLosMissing(HashMap<u64, f32>); // 秒数
+
+// --- Bot FSM/state resources ---
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum BotFsm {
+    Wander,
+    Seek,
+    Combat,
+    Lost,
+}
+
+#[derive(Resource, Default)]
+struct BotFSM(HashMap<u64, (BotFsm, f32)>); // (state, timer)
+
+// 射撃判断に使う状態
+#[derive(SystemParam)]
+struct BotAim<'w> {
+    focus: ResMut<'w, BotFocus>,
+    fsm: Res<'w, BotFSM>,
+}
+
+// リスポーン位置の選択（スポーン地点 + ジッター）
+#[derive(SystemParam)]
+struct BotSpawns<'w> {
+    points: Res<'w, SpawnPoints>,
+    rng: ResMut<'w, GameRng>,
+}
+
+#[derive(Resource, Default)]
+struct BotTarget(HashMap<u64, Option<u64>>);
+
+#[derive(Resource, Default)]
+struct BotStrafe(HashMap<u64, (f32, f32)>); // (dir_sign, timer)
+
+#[derive(Resource, Default)]
+struct BotSafePos(HashMap<u64, Vec3>);
+
+pub(super) fn is_bot_id(bots: &Bots, id: u64) -> bool {
+    bots.states.contains_key(&id)
+}
+
+fn ensure_bots(
+    mut commands: Commands,
+    mut bots: ResMut<Bots>,
+    mut bot_ents: ResMut<BotEntities>,
+    mut ents: ResMut<ServerEntities>,
+    mut next_id: ResMut<NextBotId>,
+    mut weapons: ResMut<Weapons>,
+    mut spawns: BotSpawns,
+    mut profiles: ResMut<PlayerProfiles>,
+    mut net: NetSend,
+    mut protect: ResMut<ProtectTimers>,
+    cfg: Res<ServerConfig>,
+) {
+    // 既に規定数いれば何もしない
+    if bots.states.len() >= cfg.bots.count {
+        return;
+    }
+    // スポーン位置
+    let base_pos = if !spawns.points.0.is_empty() {
+        let n = spawns.points.0.len();
+        spawns.points.0[spawns.rng.stream(RngStream::BotSpawn).index(n)]
+    } else {
+        DEFAULT_SPAWN_POS
+    };
+    while bots.states.len() < cfg.bots.count {
+        let id = {
+            let cur = next_id.0;
+            next_id.0 += 1;
+            cur
+        };
+        let mut pos = base_pos;
+        // 少し散らす
+        pos += spawns
+            .rng
+            .actor(RngStream::BotSpawn, id)
+            .jitter(cfg.spawn.jitter_radius);
+        bots.states.insert(
+            id,
+            BotState {
+                pos,
+                yaw: 0.0,
+                hp: 100,
+                alive: true,
+                vy: 0.0,
+                grounded: true,
+            },
+        );
+        let mut kcc = KinematicCharacterController::default();
+        kcc.autostep = Some(CharacterAutostep {
+            max_height: CharacterLength::Absolute(0.5),
+            min_width: CharacterLength::Absolute(0.3),
+            include_dynamic_bodies: true,
+        });
+        kcc.snap_to_ground = Some(CharacterLength::Absolute(0.25));
+        let ent = commands
+            .spawn((
+                TransformBundle::from_transform(Transform::from_translation(pos)),
+                Collider::capsule_y(0.6, 0.3),
+                kcc,
+            ))
+            .id();
+        bot_ents.0.insert(id, ent);
+        ents.0.insert(id, ent); // レイ判定用に共通Mapにも入れておく
+        weapons.0.insert(
+            id,
+            WeaponStatus {
+                ammo: cfg.weapon.mag_size,
+                cooldown: 0.0,
+                reload: 0.0,
+            },
+        );
+        // 保護
+        protect.0.insert(id, cfg.spawn.protect_sec);
+        // Spawnイベント（Bot）
+        let ev = ServerMessage::Event(EventMsg::Spawn {
+            id,
+            pos: [pos.x, pos.y, pos.z],
+            kind: ActorKind::Bot,
+        });
+        net.send_relevant(&[id], &ev);
+        let profile = PlayerProfile {
+            name: format!("BOT {}", id - BOT_ID_START + 1),
+            account_id: 0,
+        };
+        net.broadcast(&profile.info(id));
+        profiles.0.insert(id, profile);
+        info!(
+            "server: spawned bot id={} at ({:.2},{:.2},{:.2})",
+            id, pos.x, pos.y, pos.z
+        );
+    }
+}
+
+// 設定の再読み込みで bots.count が減ったら、後から出したものから消す
+fn trim_bots(
+    mut commands: Commands,
+    mut bots: ResMut<Bots>,
+    mut bot_ents: ResMut<BotEntities>,
+    mut ents: ResMut<ServerEntities>,
+    mut respawns: ResMut<BotRespawnTimers>,
+    mut weapons: ResMut<Weapons>,
+    mut scores: ResMut<Scores>,
+    mut rng: ResMut<GameRng>,
+    mut profiles: ResMut<PlayerProfiles>,
+    mut net: NetSend,
+    cfg: Res<ServerConfig>,
+) {
+    if bots.states.len() <= cfg.bots.count {
+        return;
+    }
+    let mut ids: Vec<u64> = bots.states.keys().copied().collect();
+    ids.sort_unstable();
+    for id in ids.into_iter().skip(cfg.bots.count) {
+        bots.states.remove(&id);
+        bot_ents.0.remove(&id);
+        if let Some(e) = ents.0.remove(&id) {
+            commands.entity(e).despawn_recursive();
+        }
+        respawns.0.remove(&id);
+        weapons.0.remove(&id);
+        scores.0.remove(&id);
+        rng.forget(id);
+        profiles.0.remove(&id);
+        net.broadcast(&ServerMessage::Event(EventMsg::Despawn { id }));
+        info!("server: removed bot id={} (count={})", id, cfg.bots.count);
+    }
+}
+
+// --- Bot Perception + FSM update ---
+fn bot_ai_perception_and_fsm(
+    time_fixed: Res<Time<Fixed>>,
+    players: Res<Players>,
+    bots: Res<Bots>,
+    ents: Res<ServerEntities>,
+    bot_ents: Res<BotEntities>,
+    rapier: Res<RapierContext>,
+    mut fsm: ResMut<BotFSM>,
+    mut target: ResMut<BotTarget>,
+    mut los_missing: ResMut<BotLosMissing>,
+) {
+    let dt = time_fixed.delta_seconds();
+    for (id, b) in bots.states.iter() {
+        if !b.alive {
+            continue;
+        }
+        let origin = b.pos + Vec3::new(0.0, 0.7, 0.0);
+        // search nearest visible human within range
+        let mut best: Option<(u64, f32)> = None;
+        for (pid, p) in players.states.iter() {
+            if !p.alive {
+                continue;
+            }
+            let to = (p.pos + Vec3::new(0.0, 0.7, 0.0)) - origin;
+            let dist = to.length();
+            if dist > BOT_DETECT_RANGE {
+                continue;
+            }
+            // LoS check: exclude self
+            let mut filter = QueryFilter::default();
+            if let Some(&self_ent) = bot_ents.0.get(id) {
+                filter = filter.exclude_collider(self_ent);
+            }
+            let dir = if dist > 0.0 { to / dist } else { Vec3::ZERO };
+            if dir.length_squared() < 1e-6 {
+                continue;
+            }
+            if let Some((hit_ent, _)) = rapier.cast_ray(origin, dir, dist, true, filter) {
+                if let Some(&target_ent) = ents.0.get(pid) {
+                    if hit_ent != target_ent {
+                        continue;
+                    }
+                } else {
+                    continue;
+                }
+            } else {
+                continue;
+            }
+            if best.map_or(true, |(_, bd)| dist < bd) {
+                best = Some((*pid, dist));
+            }
+        }
+        let entry = fsm.0.entry(*id).or_insert((BotFsm::Wander, 0.0));
+        let tgt = target.0.entry(*id).or_insert(None);
+        if let Some((pid, dist)) = best {
+            *tgt = Some(pid);
+            los_missing.0.insert(*id, 0.0);
+            entry.0 = if dist < BOT_DESIRED_NEAR || dist > BOT_DESIRED_FAR {
+                BotFsm::Seek
+            } else {
+                BotFsm::Combat
+            };
+            // timer used for Lost only; reset here
+            entry.1 = 0.0;
+        } else {
+            // no visible target
+            let miss = los_missing.0.entry(*id).or_insert(0.0);
+            *miss += dt;
+            if tgt.is_some() && *miss >= BOT_LOS_GRACE_SEC {
+                *tgt = None;
+                entry.0 = BotFsm::Lost;
+                entry.1 = BOT_LOST_TO_WANDER_SEC;
+                *miss = 0.0;
+            } else if entry.0 == BotFsm::Lost {
+                if entry.1 > 0.0 {
+                    entry.1 = (entry.1 - dt).max(0.0);
+                }
+                if entry.1 == 0.0 {
+                    entry.0 = BotFsm::Wander;
+                }
+            } else {
+                entry.0 = BotFsm::Wander;
+            }
+        }
+    }
+}
+
+// Plan wander targets by sampling XZ and projecting down onto ground
+fn bot_wander_planner(
+    time_fixed: Res<Time<Fixed>>,
+    bots: Res<Bots>,
+    rapier: Res<RapierContext>,
+    mut wander: ResMut<BotWander>,
+    mut rng: ResMut<GameRng>,
+) {
+    let dt = time_fixed.delta_seconds();
+    for (id, b) in bots.states.iter() {
+        if !b.alive {
+            continue;
+        }
+        // tick timer
+        if let Some((pos, t)) = wander.0.get_mut(id) {
+            *t -= dt;
+        }
+        let need_new = match wander.0.get(id) {
+            Some((p, t)) => (*t <= 0.0) || (b.pos.distance(*p) < 1.0),
+            None => true,
+        };
+        if need_new {
+            let center = b.pos;
+            let mut chosen: Option<Vec3> = None;
+            for _ in 0..BOT_WANDER_RETRY {
+                let rng = rng.actor(RngStream::BotWander, *id);
+                let ang = rng.unit() * std::f32::consts::TAU;
+                let rad = rng.unit() * BOT_WANDER_RADIUS;
+                let dx = ang.cos() * rad;
+                let dz = ang.sin() * rad;
+                let x = center.x + dx;
+                let z = center.z + dz;
+                let start_y = center.y + 30.0;
+                let origin = Vec3::new(x, start_y, z);
+                if let Some((_ent, toi)) =
+                    rapier.cast_ray(origin, Vec3::NEG_Y, 100.0, true, QueryFilter::default())
+                {
+                    let gy = start_y - toi;
+                    chosen = Some(Vec3::new(x, gy + 0.01, z));
+                    break;
+                }
+            }
+            let dest = chosen.unwrap_or(center);
+            wander.0.insert(*id, (dest, BOT_WANDER_REPLAN_SEC));
+        }
+    }
+}
+
+fn bot_kcc_move(
+    time_fixed: Res<Time<Fixed>>,
+    mut bots: ResMut<Bots>,
+    bot_ents: Res<BotEntities>,
+    players: Res<Players>,
+    mut q: Query<&mut KinematicCharacterController>,
+    ready: Res<MapReady>,
+    cfg: Res<ServerConfig>,
+) {
+    if !ready.0 {
+        return;
+    }
+    let dt = time_fixed.delta_seconds();
+    for (id, state) in bots.states.iter_mut() {
+        if !state.alive {
+            continue;
+        }
+        let Some(&entity) = bot_ents.0.get(id) else {
+            continue;
+        };
+        // find nearest player
+        let mut target_dir = Vec3::ZERO;
+        let mut best_d2 = f32::INFINITY;
+        for (_pid, p) in players.states.iter() {
+            if !p.alive {
+                continue;
+            }
+            let d2 = p.pos.distance_squared(state.pos);
+            if d2 < best_d2 {
+                best_d2 = d2;
+                target_dir = (p.pos - state.pos).with_y(0.0);
+            }
+        }
+        if target_dir.length_squared() > 1e-6 {
+            let dir = target_dir.normalize();
+            let desired_yaw = dir.z.atan2(dir.x) + std::f32::consts::FRAC_PI_2;
+            // 正しい角度差でスムーズに向き直る
+            let mut delta = (desired_yaw - state.yaw + std::f32::consts::PI)
+                .rem_euclid(2.0 * std::f32::consts::PI)
+                - std::f32::consts::PI;
+            delta = delta.clamp(-BOT_TURN_RATE * dt, BOT_TURN_RATE * dt);
+            state.yaw += delta;
+            if let Ok(mut kcc) = q.get_mut(entity) {
+                let vy = state.vy - cfg.movement.gravity * dt;
+                kcc.translation = Some(dir * cfg.bots.move_speed * dt + Vec3::Y * vy * dt);
+                state.vy = vy;
+            }
+        } else if let Ok(mut kcc) = q.get_mut(entity) {
+            let vy = state.vy - cfg.movement.gravity * dt;
+            kcc.translation = Some(Vec3::Y * vy * dt);
+            state.vy = vy;
+        }
+    }
+}
+
+// FSM対応版のBot移動
+fn bot_kcc_move_fsm(
+    time_fixed: Res<Time<Fixed>>,
+    mut bots: ResMut<Bots>,
+    bot_ents: Res<BotEntities>,
+    players: Res<Players>,
+    mut q: Query<&mut KinematicCharacterController>,
+    ready: Res<MapReady>,
+    rapier: Res<RapierContext>,
+    fsm: Res<BotFSM>,
+    wander: Res<BotWander>,
+    mut strafe: ResMut<BotStrafe>,
+    target: Res<BotTarget>,
+    cfg: Res<ServerConfig>,
+    mut rng: ResMut<GameRng>,
+) {
+    if !ready.0 {
+        return;
+    }
+    let dt = time_fixed.delta_seconds();
+    for (id, state) in bots.states.iter_mut() {
+        if !state.alive {
+            continue;
+        }
+        let Some(&entity) = bot_ents.0.get(id) else {
+            continue;
+        };
+
+        let (st, _timer) = fsm.0.get(id).copied().unwrap_or((BotFsm::Wander, 0.0));
+        let tgt_id = target.0.get(id).and_then(|o| *o);
+
+        let mut face_dir = Vec3::ZERO;
+        let mut fwd = Vec3::ZERO;
+        let mut strafe_vec = Vec3::ZERO;
+
+        match st {
+            BotFsm::Seek | BotFsm::Combat => {
+                if let Some(pid) = tgt_id {
+                    if let Some(p) = players.states.get(&pid) {
+                        let to = (p.pos - state.pos).with_y(0.0);
+                        if to.length_squared() > 1e-6 {
+                            face_dir = to.normalize();
+                        }
+                        let dist = state.pos.distance(p.pos);
+                        if dist > BOT_DESIRED_FAR {
+                            fwd = face_dir;
+                        } else if dist < BOT_DESIRED_NEAR {
+                            fwd = -face_dir;
+                        } else {
+                            let entry = strafe.0.entry(*id).or_insert((
+                                (if rng.actor(RngStream::BotStrafe, *id).unit() < 0.5 {
+                                    -1.0
+                                } else {
+                                    1.0
+                                }),
+                                BOT_STRAFE_SWITCH_SEC,
+                            ));
+                            entry.1 -= dt;
+                            if entry.1 <= 0.0 {
+                                entry.0 = -entry.0;
+                                entry.1 = BOT_STRAFE_SWITCH_SEC;
+                            }
+                            let side_raw = Vec3::Y.cross(face_dir);
+                            let side = if side_raw.length_squared() > 1e-6 {
+                                side_raw.normalize()
+                            } else {
+                                Vec3::ZERO
+                            };
+                            strafe_vec = side * entry.0;
+                        }
+                    }
+                }
+            }
+            BotFsm::Wander | BotFsm::Lost => {
+                if let Some((dest, _t)) = wander.0.get(id) {
+                    let to = (*dest - state.pos).with_y(0.0);
+                    if to.length_squared() > 1e-6 {
+                        face_dir = to.normalize();
+                        fwd = face_dir;
+                    }
+                }
+            }
+        }
+
+        // 向き回転
+        if face_dir.length_squared() > 1e-6 {
+            let desired_yaw = face_dir.z.atan2(face_dir.x) + std::f32::consts::FRAC_PI_2;
+            let mut delta = (desired_yaw - state.yaw + std::f32::consts::PI)
+                .rem_euclid(2.0 * std::f32::consts::PI)
+                - std::f32::consts::PI;
+            delta = delta.clamp(-BOT_TURN_RATE * dt, BOT_TURN_RATE * dt);
+            state.yaw += delta;
+        }
+
+        // 落下防止: 前進成分のみ抑制
+        if fwd.length_squared() > 1e-6 {
+            let cur_up = state.pos + Vec3::Y * 1.0;
+            let ahead = state.pos + fwd.normalize() * BOT_PROBE_AHEAD + Vec3::Y * 1.0;
+            let g_cur = rapier
+                .cast_ray(cur_up, Vec3::NEG_Y, 3.0, true, QueryFilter::default())
+                .map(|(_e, t)| 1.0 - t + state.pos.y);
+            let g_ahead = rapier
+                .cast_ray(ahead, Vec3::NEG_Y, 3.0, true, QueryFilter::default())
+                .map(|(_e, t)| 1.0 - t + state.pos.y);
+            if let (Some(yc), Some(ya)) = (g_cur, g_ahead) {
+                if yc - ya > BOT_MAX_DROP {
+                    fwd = Vec3::ZERO;
+                }
+            }
+        }
+
+        let mut horiz = Vec3::ZERO;
+        if fwd.length_squared() > 1e-6 {
+            horiz += fwd.normalize() * cfg.bots.move_speed;
+        }
+        if strafe_vec.length_squared() > 1e-6 {
+            horiz += strafe_vec.normalize() * (cfg.bots.move_speed * BOT_STRAFE_SPEED_MUL);
+        }
+
+        if let Ok(mut kcc) = q.get_mut(entity) {
+            let vy = state.vy - cfg.movement.gravity * dt;
+            kcc.translation = Some(horiz * dt + Vec3::Y * vy * dt);
+            state.vy = vy;
+        }
+    }
+}
+
+fn bot_kcc_post(
+    mut bots: ResMut<Bots>,
+    bot_ents: Res<BotEntities>,
+    q: Query<(
+        &GlobalTransform,
+        Option<&KinematicCharacterControllerOutput>,
+    )>,
+    mut qk: Query<&mut KinematicCharacterController>,
+    mut safe: ResMut<BotSafePos>,
+) {
+    for (id, state) in bots.states.iter_mut() {
+        let Some(&entity) = bot_ents.0.get(id) else {
+            continue;
+        };
+        if let Ok((gt, out)) = q.get(entity) {
+            state.pos = gt.translation();
+            let mut shallow_debug: Option<(f32, Vec<(Entity, Vec3)>)> = None;
+            let mut grounded_by_floor = false;
+            if let Some(o) = out {
+                state.grounded = o.grounded;
+                if o.grounded && state.vy <= 0.0 {
+                    let prev_vy = state.vy;
+                    let mut shallow_contacts = Vec::new();
+                    for col in &o.collisions {
+                        if let Some(details) = col.hit.details {
+                            let normal: Vec3 = details.normal1.into();
+                            if normal.y >= shared_consts::GROUND_NORMAL_MIN_Y {
+                                grounded_by_floor = true;
+                            } else {
+                                shallow_contacts.push((col.entity, normal));
+                            }
+                        }
+                    }
+                    if grounded_by_floor {
+                        state.vy = 0.0;
+                        // 最終位置の更新
+                        safe.0.insert(*id, state.pos);
+                    } else if !shallow_contacts.is_empty() {
+                        shallow_debug = Some((prev_vy, shallow_contacts));
+                    }
+                }
+                if !grounded_by_floor {
+                    state.grounded = false;
+                }
+            } else {
+                state.grounded = false;
+            }
+            if let Ok(mut kcc) = qk.get_mut(entity) {
+                if let Some((prev_vy, shallow_contacts)) = shallow_debug.take() {
+                    let normals: Vec<String> = shallow_contacts
+                        .iter()
+                        .map(|(entity, normal)| {
+                            format!(
+                                "{:?}:({:.2},{:.2},{:.2})",
+                                entity, normal.x, normal.y, normal.z
+                            )
+                        })
+                        .collect();
+                    info!(
+                        target: "kcc::grounding",
+                        "server bot shallow normal prev_vy={:.3} snap_to_ground={} normals={:?}",
+                        prev_vy,
+                        kcc.snap_to_ground.is_some(),
+                        normals,
+                    );
+                    kcc.snap_to_ground = None;
+                } else if state.grounded {
+                    if kcc.snap_to_ground.is_none() {
+                        kcc.snap_to_ground = Some(CharacterLength::Absolute(0.25));
+                    }
+                } else {
+                    kcc.snap_to_ground = None;
+                }
+            }
+        }
+    }
+}
+
+fn bot_ai_shoot_and_respawn(
+    mut commands: Commands,
+    time_fixed: Res<Time<Fixed>>,
+    mut players: ResMut<Players>,
+    mut bots: ResMut<Bots>,
+    mut wpnprot: WpnProt,
+    mut net: NetSend,
+    rapier: Res<RapierContext>,
+    ents: Res<ServerEntities>,
+    mut scores: ResMut<Scores>,
+    mut respawns_players: ResMut<RespawnTimers>,
+    mut respawns_bots: ResMut<BotRespawnTimers>,
+    mut spawns: BotSpawns,
+    bot_ents: Res<BotEntities>,
+    mut aim: BotAim,
+    cfg: Res<ServerConfig>,
+    mut log: ResMut<MatchLog>,
+) {
+    let dt = time_fixed.delta_seconds();
+    // 射撃（Bot→人間のみ、FFなし）
+    for (id, b) in bots.states.iter() {
+        if !b.alive {
+            continue;
+        }
+        // 発砲はCombat状態のみ
+        if !matches!(aim.fsm.0.get(id).map(|v| v.0), Some(BotFsm::Combat)) {
+            continue;
+        }
+        let w = wpnprot.weapons.0.entry(*id).or_insert(WeaponStatus {
+            ammo: cfg.weapon.mag_size,
+            cooldown: 0.0,
+            reload: 0.0,
+        });
+        if w.reload > 0.0 || w.cooldown > 0.0 {
+            continue;
+        }
+        // ボット自身が保護中は発砲不可
+        if wpnprot.protect.0.get(id).copied().unwrap_or(0.0) > 0.0 {
+            continue;
+        }
+        if w.ammo == 0 {
+            w.reload = cfg.weapon.reload_sec;
+            continue;
+        }
+        // 索敵
+        let origin = b.pos + Vec3::new(0.0, 0.7, 0.0);
+        let forward = Quat::from_rotation_y(b.yaw) * Vec3::NEG_Z;
+        let range = cfg.bots.fire_range;
+        let mut best: Option<(u64, f32)> = None;
+        for (pid, p) in players.states.iter() {
+            if !p.alive {
+                continue;
+            }
+            let to = (p.pos + Vec3::new(0.0, 0.7, 0.0)) - origin;
+            let dist = to.length();
+            if dist > range {
+                continue;
+            }
+            // (removed FOV gating)
+            //
+            // 視野内の前方で最も近いターゲットを選択（横ずれ判定は行わず、遮蔽は後段のレイ判定で）
+            let t = dist;
+            if best.map_or(true, |(_, bt)| t < bt) {
+                best = Some((*pid, t));
+            }
+        }
+        if let Some((hit_id, t_hit)) = best {
+            // 目標方向へ直接狙う（yawに依存しない）
+            let target_eye = if let Some(p) = players.states.get(&hit_id) {
+                p.pos + Vec3::new(0.0, 0.7, 0.0)
+            } else {
+                continue;
+            };
+            let to = target_eye - origin;
+            let dist = to.length().max(0.001);
+            let aim_dir = (to / dist).normalize();
+            // 反応時間: 同じターゲットに一定時間フォーカスしてから射撃
+            let entry = aim.focus.0.entry(*id).or_insert((None, 0.0));
+            if entry.0 == Some(hit_id) {
+                entry.1 += dt;
+            } else {
+                *entry = (Some(hit_id), 0.0);
+            }
+            if entry.1 < cfg.bots.react_sec {
+                continue;
+            }
+            // 保護中の対象は無効
+            if wpnprot.protect.0.get(&hit_id).copied().unwrap_or(0.0) > 0.0 {
+                continue;
+            }
+            // Fire event（Bot）: 衝突点をレイで取得
+            let mut filter_fire = QueryFilter::default();
+            if let Some(&self_ent) = ents.0.get(id) {
+                filter_fire = filter_fire.exclude_collider(self_ent);
+            }
+            let hit_opt = if let Some((_hit_ent, toi)) =
+                rapier.cast_ray(origin, aim_dir, dist, true, filter_fire)
+            {
+                Some([
+                    origin.x + aim_dir.x * toi,
+                    origin.y + aim_dir.y * toi,
+                    origin.z + aim_dir.z * toi,
+                ])
+            } else {
+                None
+            };
+            let ev = ServerMessage::Event(EventMsg::Fire {
+                id: *id,
+                origin: [origin.x, origin.y, origin.z],
+                dir: [aim_dir.x, aim_dir.y, aim_dir.z],
+                hit: hit_opt,
+            });
+            net.send_relevant(&[*id], &ev);
+            log.record(MatchEvent::Fire {
+                id: *id,
+                origin: pos3(origin),
+                dir: pos3(aim_dir),
+                hit: hit_opt,
+            });
+            // 遮蔽レイ判定（自身は除外）
+            let mut filter = QueryFilter::default();
+            if let Some(&self_ent) = ents.0.get(id) {
+                filter = filter.exclude_collider(self_ent);
+            }
+            if let Some((hit_ent, _)) = rapier.cast_ray(origin, aim_dir, dist, true, filter) {
+                let target_ent_h = ents.0.get(&hit_id).copied();
+                let target_ent_b = bot_ents.0.get(&hit_id).copied();
+                if Some(hit_ent) != target_ent_h && Some(hit_ent) != target_ent_b {
+                    continue;
+                }
+            }
+            if let Some(hit) = players.states.get(&hit_id) {
+                // ダメージ適用（読み取り→書き込みのためクローンIDで再参照）
+                drop(hit);
+                if let Some(hitm) = players.states.get_mut(&hit_id) {
+                    let dmg = cfg.bots.damage;
+                    if hitm.alive {
+                        hitm.hp = hitm.hp.saturating_sub(dmg);
+                        let ev = ServerMessage::Event(EventMsg::Hit {
+                            target_id: hit_id,
+                            new_hp: hitm.hp,
+                            by: *id,
+                        });
+                        net.send_relevant(&[hit_id, *id], &ev);
+                        log.record(MatchEvent::Hit {
+                            target: hit_id,
+                            by: *id,
+                            hp: hitm.hp,
+                            pos: pos3(hitm.pos),
+                        });
+                        if hitm.hp == 0 {
+                            let dead_pos = hitm.pos;
+                            // プレイヤーが同フレーム中に離脱/除去されている場合に備えて防御
+                            if let Some(mut_dead) = players.states.get_mut(&hit_id) {
+                                mut_dead.alive = false;
+                            } else {
+                                // 既に消えているなら以降の処理はスキップ
+                                continue;
+                            }
+                            let ev = ServerMessage::Event(EventMsg::Death {
+                                target_id: hit_id,
+                                by: *id,
+                            });
+                            net.broadcast(&ev);
+                            log.record(MatchEvent::Death {
+                                target: hit_id,
+                                by: *id,
+                                pos: pos3(dead_pos),
+                            });
+                            respawns_players.0.insert(hit_id, cfg.spawn.respawn_sec);
+                            // スコアは人間のみ集計（Botのキルは加算しないがデスは加算）
+                            let e2 = scores.0.entry(hit_id).or_insert((0, 0));
+                            e2.1 = e2.1.saturating_add(1);
+                        }
+                    }
+                }
+                // 射撃消費
+                w.ammo = w.ammo.saturating_sub(1);
+                w.cooldown = cfg.weapon.fire_cooldown();
+            }
+            // 弾消費とクールダウン（Bot用）
+            w.ammo = w.ammo.saturating_sub(1);
+            w.cooldown = cfg.bots.fire_cooldown_sec;
+        }
+    }
+    // Botリスポーン
+    let mut to_spawn = Vec::new();
+    for (bid, t) in respawns_bots.0.iter_mut() {
+        *t -= dt;
+        if *t <= 0.0 {
+            to_spawn.push(*bid);
+        }
+    }
+    for bid in to_spawn {
+        respawns_bots.0.remove(&bid);
+        if let Some(b) = bots.states.get_mut(&bid) {
+            let rng = spawns.rng.actor(RngStream::BotSpawn, bid);
+            let mut spawn = if !spawns.points.0.is_empty() {
+                spawns.points.0[rng.index(spawns.points.0.len())]
+            } else {
+                DEFAULT_SPAWN_POS
+            };
+            // ジッターで分散
+            spawn += rng.jitter(cfg.spawn.jitter_radius);
+            b.alive = true;
+            b.hp = 100;
+            b.pos = spawn;
+            b.vy = 0.0;
+            b.grounded = true;
+            if let Some(&e) = bot_ents.0.get(&bid) {
+                commands.entity(e).insert(TransformBundle::from_transform(
+                    Transform::from_translation(spawn),
+                ));
+            }
+            let ev = ServerMessage::Event(EventMsg::Spawn {
+                id: bid,
+                pos: [spawn.x, spawn.y, spawn.z],
+                kind: ActorKind::Bot,
+            });
+            net.send_relevant(&[bid], &ev);
+            // 武器リセット
+            let w = wpnprot
+                .weapons
+                .0
+                .entry(bid)
+                .or_insert(WeaponStatus::default());
+            *w = WeaponStatus {
+                ammo: cfg.weapon.mag_size,
+                cooldown: 0.0,
+                reload: 0.0,
+            };
+            // 保護付与
+            wpnprot.protect.0.insert(bid, cfg.spawn.protect_sec);
+        }
+    }
+}
@@ -0,0 +1,353 @@
+// ===== サーバ設定 =====
+// 試合ルール・武器・ボットなどの調整値。TOML ファイル（--config で指定）から読み込み、
+// 省略したキーは既定値になる。既定値はこれまでの定数と同じ。
+use crate::movement::MoveParams;
+use crate::net::{shared, RulesMsg};
+use bevy::prelude::Resource;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+#[derive(Resource, Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    // 乱数のシード（スポーンの散らし・Bot の行動）。None なら起動ごとにランダム
+    pub seed: Option<u64>,
+    pub round: RoundConfig,
+    pub weapon: WeaponConfig,
+    pub movement: MovementConfig,
+    pub spawn: SpawnConfig,
+    pub lag_comp: LagCompConfig,
+    pub anticheat: AntiCheatConfig,
+    pub bots: BotConfig,
+    pub scaffold: ScaffoldConfig,
+    pub log: LogConfig,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RoundConfig {
+    pub win_kills: u32,     // このキル数に達したら勝利
+    pub time_sec: f32,      // ラウンドの制限時間
+    pub end_delay_sec: f32, // 終了から次ラウンド開始まで
+}
+
+impl Default for RoundConfig {
+    fn default() -> Self {
+        Self {
+            win_kills: 10,
+            time_sec: 300.0,
+            end_delay_sec: 5.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WeaponConfig {
+    pub mag_size: u16,
+    pub reload_sec: f32,
+    pub fire_rate_hz: f32, // 連射速度（発/秒）。7.5 で ~450 RPM
+    pub damage: u16,
+}
+
+impl Default for WeaponConfig {
+    fn default() -> Self {
+        Self {
+            mag_size: 30,
+            reload_sec: 1.6,
+            fire_rate_hz: 7.5,
+            damage: 35,
+        }
+    }
+}
+
+impl WeaponConfig {
+    pub fn fire_cooldown(&self) -> f32 {
+        1.0 / self.fire_rate_hz
+    }
+}
+
+// プレイヤー移動。クライアントの予測にも Rules で配られる
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MovementConfig {
+    pub move_speed: f32,
+    pub jump_speed: f32,
+    pub gravity: f32,
+}
+
+impl Default for MovementConfig {
+    fn default() -> Self {
+        Self {
+            move_speed: shared::MOVE_SPEED,
+            jump_speed: shared::JUMP_SPEED,
+            gravity: shared::GRAVITY,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SpawnConfig {
+    pub use_spawn_points: bool, // false ならマップの spawn* を無視して既定位置に出す
+    pub jitter_radius: f32,     // スポーン分散半径
+    pub protect_sec: f32,       // リスポーン保護（無敵・発砲不可）
+    pub respawn_sec: f32,       // 死亡からリスポーンまで
+    pub reconnect_grace_sec: f32, // 切断したアカウントの状態を預ける時間（0 で無効）
+}
+
+impl Default for SpawnConfig {
+    fn default() -> Self {
+        Self {
+            use_spawn_points: true,
+            jitter_radius: 6.0,
+            protect_sec: 2.0,
+            respawn_sec: 2.0,
+            reconnect_grace_sec: 60.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LagCompConfig {
+    pub rewind_sec: f32,            // 命中判定で巻き戻す時間
+    pub history_sec: f32,           // 位置履歴の保持時間
+    pub origin_tol: f32,            // 射撃 origin の基本許容（m）
+    pub origin_tol_max: f32,        // RTT 分を加えた許容の上限
+    pub origin_rewind_max_sec: f32, // origin 照合で遡る最大時間
+}
+
+impl Default for LagCompConfig {
+    fn default() -> Self {
+        Self {
+            rewind_sec: 0.10,
+            history_sec: 1.5,
+            origin_tol: 1.0,
+            origin_tol_max: 2.5,
+            origin_rewind_max_sec: 0.5,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AntiCheatConfig {
+    pub kick_score: f32,      // 違反スコアがこれ以上でキック（0 で無効）
+    pub ban_after_kicks: u32, // 同一アドレスのキック回数がこれに達したら BAN（0 で無効）
+    pub ban_sec: f32,         // その BAN の期間（0 で無期限）
+}
+
+impl Default for AntiCheatConfig {
+    fn default() -> Self {
+        Self {
+            kick_score: 5.0,
+            ban_after_kicks: 3,
+            ban_sec: 86_400.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BotConfig {
+    pub count: usize,
+    pub damage: u16,
+    pub move_speed: f32,
+    pub fire_cooldown_sec: f32,
+    pub react_sec: f32, // 目標を捉えてから撃つまで
+    pub fire_range: f32,
+}
+
+impl Default for BotConfig {
+    fn default() -> Self {
+        Self {
+            count: 1,
+            damage: 1,
+            move_speed: 5.5,
+            fire_cooldown_sec: 0.18,
+            react_sec: 0.25,
+            fire_range: 60.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ScaffoldConfig {
+    pub per_player_limit: usize, // 超えたら古いものから消す
+}
+
+impl Default for ScaffoldConfig {
+    fn default() -> Self {
+        Self {
+            per_player_limit: 3,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    pub snapshot_actors: bool, // スナップショットごとにアクター数を出す
+    pub occlusion: bool,       // 遮蔽で外れた射撃の詳細を出す
+}
+
+impl ServerConfig {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+        Self::from_toml(&text).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    pub fn from_toml(text: &str) -> Result<Self, String> {
+        let cfg: Self = toml::from_str(text).map_err(|e| e.to_string())?;
+        cfg.validate()?;
+        Ok(cfg)
+    }
+
+    // 値の範囲チェック。Err は最初に見つかった問題
+    pub fn validate(&self) -> Result<(), String> {
+        fn non_neg(name: &str, v: f32) -> Result<(), String> {
+            if v.is_finite() && v >= 0.0 {
+                Ok(())
+            } else {
+                Err(format!("{} must be a finite value >= 0 (got {})", name, v))
+            }
+        }
+        fn positive(name: &str, v: f32) -> Result<(), String> {
+            if v.is_finite() && v > 0.0 {
+                Ok(())
+            } else {
+                Err(format!("{} must be a finite value > 0 (got {})", name, v))
+            }
+        }
+
+        let r = &self.round;
+        if r.win_kills == 0 {
+            return Err("round.win_kills must be at least 1".into());
+        }
+        positive("round.time_sec", r.time_sec)?;
+        non_neg("round.end_delay_sec", r.end_delay_sec)?;
+
+        let w = &self.weapon;
+        if w.mag_size == 0 {
+            return Err("weapon.mag_size must be at least 1".into());
+        }
+        non_neg("weapon.reload_sec", w.reload_sec)?;
+        positive("weapon.fire_rate_hz", w.fire_rate_hz)?;
+        if w.damage == 0 {
+            return Err("weapon.damage must be at least 1".into());
+        }
+
+        let m = &self.movement;
+        positive("movement.move_speed", m.move_speed)?;
+        positive("movement.jump_speed", m.jump_speed)?;
+        positive("movement.gravity", m.gravity)?;
+
+        let s = &self.spawn;
+        non_neg("spawn.jitter_radius", s.jitter_radius)?;
+        non_neg("spawn.protect_sec", s.protect_sec)?;
+        non_neg("spawn.respawn_sec", s.respawn_sec)?;
+        non_neg("spawn.reconnect_grace_sec", s.reconnect_grace_sec)?;
+
+        let l = &self.lag_comp;
+        non_neg("lag_comp.rewind_sec", l.rewind_sec)?;
+        positive("lag_comp.history_sec", l.history_sec)?;
+        positive("lag_comp.origin_tol", l.origin_tol)?;
+        positive("lag_comp.origin_tol_max", l.origin_tol_max)?;
+        non_neg("lag_comp.origin_rewind_max_sec", l.origin_rewind_max_sec)?;
+        if l.rewind_sec > l.history_sec || l.origin_rewind_max_sec > l.history_sec {
+            return Err("lag_comp rewind windows must not exceed lag_comp.history_sec".into());
+        }
+        if l.origin_tol_max < l.origin_tol {
+            return Err("lag_comp.origin_tol_max must be >= lag_comp.origin_tol".into());
+        }
+
+        non_neg("anticheat.kick_score", self.anticheat.kick_score)?;
+        non_neg("anticheat.ban_sec", self.anticheat.ban_sec)?;
+
+        let b = &self.bots;
+        non_neg("bots.move_speed", b.move_speed)?;
+        positive("bots.fire_cooldown_sec", b.fire_cooldown_sec)?;
+        non_neg("bots.react_sec", b.react_sec)?;
+        non_neg("bots.fire_range", b.fire_range)?;
+
+        if self.scaffold.per_player_limit == 0 {
+            return Err("scaffold.per_player_limit must be at least 1".into());
+        }
+        Ok(())
+    }
+
+    // クライアントに配るルール値
+    pub fn rules(&self) -> RulesMsg {
+        RulesMsg {
+            move_speed: self.movement.move_speed,
+            jump_speed: self.movement.jump_speed,
+            gravity: self.movement.gravity,
+            fire_cooldown: self.weapon.fire_cooldown(),
+            mag_size: self.weapon.mag_size,
+            reload_sec: self.weapon.reload_sec,
+            win_kills: self.round.win_kills,
+            round_time_sec: self.round.time_sec,
+            scaffold_limit: u32::try_from(self.scaffold.per_player_limit).unwrap_or(u32::MAX),
+        }
+    }
+
+    pub fn move_params(&self) -> MoveParams {
+        MoveParams::from_rules(&self.rules())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn example_file_matches_defaults() {
+        let cfg = ServerConfig::from_toml(include_str!("../../server.example.toml")).unwrap();
+        assert_eq!(cfg, ServerConfig::default());
+    }
+
+    #[test]
+    fn default_rules_match_shared_defaults() {
+        assert_eq!(ServerConfig::default().rules(), RulesMsg::default());
+    }
+
+    #[test]
+    fn missing_keys_fall_back_to_defaults() {
+        let cfg = ServerConfig::from_toml("[round]\nwin_kills = 3\n").unwrap();
+        assert_eq!(cfg.round.win_kills, 3);
+        assert_eq!(cfg.round.time_sec, RoundConfig::default().time_sec);
+        assert_eq!(cfg.weapon, WeaponConfig::default());
+    }
+
+    #[test]
+    fn seed_is_a_top_level_key() {
+        let cfg = ServerConfig::from_toml("seed = 42\n[round]\nwin_kills = 3\n").unwrap();
+        assert_eq!(cfg.seed, Some(42));
+        let text = toml::to_string(&cfg).unwrap();
+        assert_eq!(ServerConfig::from_toml(&text).unwrap(), cfg);
+    }
+
+    #[test]
+    fn unknown_keys_are_rejected() {
+        assert!(ServerConfig::from_toml("[round]\nwin_kill = 3\n").is_err());
+        assert!(ServerConfig::from_toml("[rounds]\n").is_err());
+    }
+
+    #[test]
+    fn out_of_range_values_are_rejected() {
+        for text in [
+            "[round]\nwin_kills = 0\n",
+            "[weapon]\nfire_rate_hz = 0.0\n",
+            "[movement]\ngravity = 0.0\n",
+            "[spawn]\nprotect_sec = -1.0\n",
+            "[lag_comp]\nrewind_sec = 2.0\n",
+            "[scaffold]\nper_player_limit = 0\n",
+        ] {
+            assert!(ServerConfig::from_toml(text).is_err(), "{}", text);
+        }
+    }
+}
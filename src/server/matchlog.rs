@@ -0,0 +1,282 @@
+// ===== 試合イベントログ =====
+// 接続・射撃・命中・死亡・足場・ラウンド進行を 1 行 1 JSON でファイルへ書き出す（試合後の分析・当たり判定の検証用）。
+// ファイルはサイズで世代交代する（path → path.1 → … → path.<keep>）。MatchLog::open しなければ何も書かない。
+// ファイルの有無にかかわらず、そのフレームのイベントは recent() で読める（通算成績の集計用。Last で空にする）。
+use super::*;
+use serde::Serialize;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// ログに載るイベント。位置は [x, y, z]、id は ClientId / ボット id
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub(super) enum MatchEvent {
+    Connect {
+        id: u64,
+        name: String,
+        addr: Option<String>,
+        pos: [f32; 3],
+    },
+    Disconnect {
+        id: u64,
+        reason: String,
+    },
+    // hit はクライアントへ通知した着弾点（命中したかは続く Hit で分かる）
+    Fire {
+        id: u64,
+        origin: [f32; 3],
+        dir: [f32; 3],
+        hit: Option<[f32; 3]>,
+    },
+    // 命中候補だったが遮蔽判定で外れた射撃（at は遮った物体の位置、レイが外れたら None）
+    Occluded {
+        id: u64,
+        target: u64,
+        origin: [f32; 3],
+        dir: [f32; 3],
+        at: Option<[f32; 3]>,
+    },
+    // pos は被弾・死亡時の対象の位置
+    Hit {
+        target: u64,
+        by: u64,
+        hp: u16,
+        pos: [f32; 3],
+    },
+    Death {
+        target: u64,
+        by: u64,
+        pos: [f32; 3],
+    },
+    ScaffoldSpawn {
+        sid: u64,
+        owner: u64,
+        pos: [f32; 3],
+    },
+    ScaffoldDespawn {
+        sid: u64,
+        owner: u64,
+    },
+    RoundStart {
+        time_sec: f32,
+    },
+    // scores は (id, kills, deaths)
+    RoundEnd {
+        winner: Option<u64>,
+        scores: Vec<(u64, u32, u32)>,
+    },
+}
+
+#[derive(Serialize)]
+struct Line<'a> {
+    ts_ms: u64,
+    round: u32,
+    #[serde(flatten)]
+    event: &'a MatchEvent,
+}
+
+struct RotatingFile {
+    path: PathBuf,
+    max_bytes: u64,
+    keep: usize,
+    out: BufWriter<File>,
+    written: u64,
+}
+
+impl RotatingFile {
+    fn open(path: PathBuf, max_bytes: u64, keep: usize) -> std::io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let written = file.metadata()?.len();
+        Ok(Self {
+            path,
+            max_bytes,
+            keep,
+            out: BufWriter::new(file),
+            written,
+        })
+    }
+
+    fn write_line(&mut self, line: &str) -> std::io::Result<()> {
+        if self.written > 0 && self.written + line.len() as u64 + 1 > self.max_bytes {
+            self.rotate()?;
+        }
+        writeln!(self.out, "{}", line)?;
+        self.written += line.len() as u64 + 1;
+        Ok(())
+    }
+
+    // path.<keep> を捨てて 1 つずつ繰り下げ、新しい path を開く
+    fn rotate(&mut self) -> std::io::Result<()> {
+        self.out.flush()?;
+        let numbered = |n: usize| {
+            let mut p = self.path.clone().into_os_string();
+            p.push(format!(".{}", n));
+            PathBuf::from(p)
+        };
+        if self.keep == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            for n in (1..self.keep).rev() {
+                let from = numbered(n);
+                if from.exists() {
+                    fs::rename(&from, numbered(n + 1))?;
+                }
+            }
+            fs::rename(&self.path, numbered(1))?;
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.out = BufWriter::new(file);
+        self.written = 0;
+        Ok(())
+    }
+}
+
+#[derive(Resource)]
+pub struct MatchLog {
+    file: Option<RotatingFile>,
+    round: u32, // 起動時のラウンドが 1、RoundStart ごとに +1
+    recent: Vec<MatchEvent>,
+}
+
+impl Default for MatchLog {
+    fn default() -> Self {
+        Self {
+            file: None,
+            round: 1,
+            recent: Vec::new(),
+        }
+    }
+}
+
+impl MatchLog {
+    // max_bytes を超えたら世代交代し、古いものは keep 個まで残す
+    pub fn open(path: impl AsRef<Path>, max_bytes: u64, keep: usize) -> Result<Self, String> {
+        let path = path.as_ref();
+        let file = RotatingFile::open(path.to_path_buf(), max_bytes, keep)
+            .map_err(|e| format!("cannot open {}: {}", path.display(), e))?;
+        Ok(Self {
+            file: Some(file),
+            ..default()
+        })
+    }
+
+    pub(super) fn record(&mut self, event: MatchEvent) {
+        self.recent.push(event.clone());
+        let Some(file) = self.file.as_mut() else {
+            return;
+        };
+        if matches!(event, MatchEvent::RoundStart { .. }) {
+            self.round += 1;
+        }
+        let ts_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis() as u64);
+        let line = Line {
+            ts_ms,
+            round: self.round,
+            event: &event,
+        };
+        let result = serde_json::to_string(&line)
+            .map_err(std::io::Error::from)
+            .and_then(|s| file.write_line(&s));
+        if let Err(e) = result {
+            // 書けなくなったら試合は続けてログだけ止める
+            error!("match log disabled: {}", e);
+            self.file = None;
+        }
+    }
+
+    // このフレームに記録したイベント
+    pub(super) fn recent(&self) -> &[MatchEvent] {
+        &self.recent
+    }
+
+    fn flush(&mut self) {
+        self.recent.clear();
+        if let Some(file) = self.file.as_mut() {
+            let _ = file.out.flush();
+        }
+    }
+}
+
+pub(super) fn pos3(v: Vec3) -> [f32; 3] {
+    [v.x, v.y, v.z]
+}
+
+pub struct MatchLogPlugin;
+
+impl Plugin for MatchLogPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<MatchLog>()
+            .add_systems(Last, flush_match_log.in_set(MatchLogFlush));
+    }
+}
+
+// recent() を読むシステムはこれより前に置く
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub(super) struct MatchLogFlush;
+
+// tail -f で追えるよう毎フレーム書き出す
+fn flush_match_log(mut log: ResMut<MatchLog>) {
+    log.flush();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn events_are_tagged_json_lines() {
+        let dir = TempDir::new("matchlog-lines");
+        let path = dir.join("match.jsonl");
+        let mut log = MatchLog::open(&path, 1 << 20, 2).unwrap();
+        log.record(MatchEvent::RoundStart { time_sec: 300.0 });
+        log.record(MatchEvent::Death {
+            target: 7,
+            by: 3,
+            pos: [1.0, 2.0, 3.0],
+        });
+        log.flush();
+        let text = fs::read_to_string(&path).unwrap();
+        let lines: Vec<serde_json::Value> = text
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        assert_eq!(lines[0]["event"], "round_start");
+        assert_eq!(lines[1]["event"], "death");
+        assert_eq!(lines[1]["round"], 2);
+        assert_eq!(lines[1]["by"], 3);
+        assert_eq!(lines[1]["pos"], serde_json::json!([1.0, 2.0, 3.0]));
+        assert!(lines[1]["ts_ms"].as_u64().unwrap() > 0);
+    }
+
+    #[test]
+    fn files_rotate_by_size() {
+        let dir = TempDir::new("matchlog-rotate");
+        let path = dir.join("match.jsonl");
+        let mut log = MatchLog::open(&path, 200, 2).unwrap();
+        for id in 0..20 {
+            log.record(MatchEvent::Disconnect {
+                id,
+                reason: "test".into(),
+            });
+        }
+        log.flush();
+        let rotated = |n: usize| PathBuf::from(format!("{}.{}", path.display(), n));
+        assert!(rotated(1).exists());
+        assert!(rotated(2).exists());
+        assert!(!rotated(3).exists());
+        for p in [path.clone(), rotated(1), rotated(2)] {
+            assert!(fs::metadata(&p).unwrap().len() <= 200, "{}", p.display());
+        }
+        // 最新のイベントは現在のファイルに残る
+        let text = fs::read_to_string(&path).unwrap();
+        assert!(text.lines().last().unwrap().contains("\"id\":19"));
+    }
+}
@@ -0,0 +1,397 @@
+// ===== メトリクス =====
+// tick 処理時間・送信量・接続品質などを集計し、Prometheus のテキスト形式で公開する。
+// 集計は ECS 内で行い、1 秒ごとに描画したテキストを HTTP スレッド（spawn_metrics_http）と共有する。
+use super::*;
+use std::fmt::Write as _;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const METRICS_PUBLISH_SEC: f32 = 1.0;
+const METRICS_READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+// tick 処理時間（秒）。60Hz の予算は ~16.7ms
+const TICK_BUCKETS: &[f64] = &[
+    0.0005, 0.001, 0.002, 0.004, 0.008, 0.0167, 0.033, 0.066, 0.1, 0.25,
+];
+// スナップショット 1 通のサイズ（バイト）
+const SNAPSHOT_BUCKETS: &[f64] = &[32.0, 64.0, 128.0, 256.0, 512.0, 1024.0, 2048.0, 4096.0];
+
+const CHANNEL_NAMES: [&str; 3] = ["input", "snapshot", "reliable"];
+
+// 累積しない生のバケット数を持ち、描画時に累積する
+pub(super) struct Histogram {
+    bounds: &'static [f64],
+    counts: Vec<u64>, // bounds.len() + 1（最後は +Inf）
+    sum: f64,
+}
+
+impl Histogram {
+    fn new(bounds: &'static [f64]) -> Self {
+        Self {
+            bounds,
+            counts: vec![0; bounds.len() + 1],
+            sum: 0.0,
+        }
+    }
+
+    fn observe(&mut self, v: f64) {
+        let i = self
+            .bounds
+            .iter()
+            .position(|b| v <= *b)
+            .unwrap_or(self.bounds.len());
+        self.counts[i] += 1;
+        self.sum += v;
+    }
+
+    fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    fn render(&self, out: &mut String, name: &str, help: &str) {
+        let _ = writeln!(out, "# HELP {} {}", name, help);
+        let _ = writeln!(out, "# TYPE {} histogram", name);
+        let mut acc = 0;
+        for (bound, n) in self.bounds.iter().zip(&self.counts) {
+            acc += n;
+            let _ = writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, bound, acc);
+        }
+        let _ = writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, self.count());
+        let _ = writeln!(out, "{}_sum {}", name, self.sum);
+        let _ = writeln!(out, "{}_count {}", name, self.count());
+    }
+}
+
+// 送信量（NetSend と update_relevancy の全送信を数える）
+#[derive(Resource)]
+pub(super) struct NetStats {
+    bytes: [u64; 3],
+    messages: [u64; 3],
+    snapshot_size: Histogram,
+}
+
+impl Default for NetStats {
+    fn default() -> Self {
+        Self {
+            bytes: [0; 3],
+            messages: [0; 3],
+            snapshot_size: Histogram::new(SNAPSHOT_BUCKETS),
+        }
+    }
+}
+
+impl NetStats {
+    pub(super) fn record(&mut self, channel: u8, len: usize) {
+        let ch = channel as usize;
+        if ch >= CHANNEL_NAMES.len() {
+            return;
+        }
+        self.bytes[ch] += len as u64;
+        self.messages[ch] += 1;
+        if channel == CH_SNAPSHOT {
+            self.snapshot_size.observe(len as f64);
+        }
+    }
+}
+
+// 固定 tick 1 回（FixedFirst〜FixedLast）の処理時間。tick の間隔を超えたら overrun
+#[derive(Resource)]
+struct TickStats {
+    started: Option<Instant>,
+    duration: Histogram,
+    overruns: u64,
+}
+
+impl Default for TickStats {
+    fn default() -> Self {
+        Self {
+            started: None,
+            duration: Histogram::new(TICK_BUCKETS),
+            overruns: 0,
+        }
+    }
+}
+
+#[derive(Resource, Default)]
+struct RoundsPlayed {
+    count: u64,
+    last_phase: Option<RoundPhase>,
+}
+
+// 描画済みのテキスト（HTTP スレッドと共有）
+#[derive(Resource, Clone, Default)]
+pub struct MetricsExport(Arc<Mutex<String>>);
+
+impl MetricsExport {
+    // 最後に公開したメトリクス（Prometheus テキスト形式）
+    pub fn text(&self) -> String {
+        self.0.lock().map(|t| t.clone()).unwrap_or_default()
+    }
+}
+
+#[derive(Resource)]
+struct MetricsTimer(Timer);
+
+pub struct MetricsPlugin;
+
+impl Plugin for MetricsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<NetStats>()
+            .init_resource::<TickStats>()
+            .init_resource::<RoundsPlayed>()
+            .init_resource::<MetricsExport>()
+            .insert_resource(MetricsTimer(Timer::from_seconds(
+                METRICS_PUBLISH_SEC,
+                TimerMode::Repeating,
+            )))
+            .add_systems(FixedFirst, tick_started)
+            .add_systems(FixedLast, tick_finished)
+            .add_systems(FixedUpdate, count_rounds.after(round_update))
+            .add_systems(Update, publish_metrics);
+    }
+}
+
+fn tick_started(mut ticks: ResMut<TickStats>) {
+    ticks.started = Some(Instant::now());
+}
+
+fn tick_finished(mut ticks: ResMut<TickStats>, time_fixed: Res<Time<Fixed>>) {
+    let Some(started) = ticks.started.take() else {
+        return;
+    };
+    let took = started.elapsed();
+    ticks.duration.observe(took.as_secs_f64());
+    if took > time_fixed.timestep() {
+        ticks.overruns += 1;
+    }
+}
+
+// Active → Ending の遷移を 1 ラウンドとして数える
+fn count_rounds(round: Res<RoundState>, mut rounds: ResMut<RoundsPlayed>) {
+    if rounds.last_phase == Some(RoundPhase::Active) && round.phase == RoundPhase::Ending {
+        rounds.count += 1;
+    }
+    rounds.last_phase = Some(round.phase);
+}
+
+#[derive(SystemParam)]
+struct MetricsSources<'w> {
+    server: Res<'w, RenetServer>,
+    net: Res<'w, NetStats>,
+    ticks: Res<'w, TickStats>,
+    rounds: Res<'w, RoundsPlayed>,
+    bots: Res<'w, Bots>,
+    scaffolds: Res<'w, Scaffolds>,
+}
+
+fn publish_metrics(
+    time: Res<Time>,
+    mut timer: ResMut<MetricsTimer>,
+    export: Res<MetricsExport>,
+    src: MetricsSources,
+) {
+    timer.0.tick(time.delta());
+    if !timer.0.finished() {
+        return;
+    }
+    let text = render(&src);
+    if let Ok(mut t) = export.0.lock() {
+        *t = text;
+    }
+}
+
+fn render(src: &MetricsSources) -> String {
+    let mut out = String::new();
+    src.ticks.duration.render(
+        &mut out,
+        "server_tick_duration_seconds",
+        "Time spent processing one fixed server tick.",
+    );
+    counter(
+        &mut out,
+        "server_tick_overruns_total",
+        "Fixed ticks that took longer than the fixed timestep.",
+        src.ticks.overruns,
+    );
+
+    let mut ids: Vec<ClientId> = src.server.clients_id();
+    ids.sort_unstable_by_key(|c| c.raw());
+    gauge(
+        &mut out,
+        "server_clients",
+        "Connected clients.",
+        ids.len() as f64,
+    );
+    let infos: Vec<(u64, bevy_renet::renet::NetworkInfo)> = ids
+        .iter()
+        .filter_map(|c| src.server.network_info(*c).ok().map(|i| (c.raw(), i)))
+        .collect();
+    let _ = writeln!(
+        out,
+        "# HELP server_client_rtt_seconds Round-trip time per client."
+    );
+    let _ = writeln!(out, "# TYPE server_client_rtt_seconds gauge");
+    for (id, info) in &infos {
+        let _ = writeln!(
+            out,
+            "server_client_rtt_seconds{{client=\"{}\"}} {}",
+            id, info.rtt
+        );
+    }
+    let _ = writeln!(
+        out,
+        "# HELP server_client_packet_loss_ratio Packet loss per client (0-1)."
+    );
+    let _ = writeln!(out, "# TYPE server_client_packet_loss_ratio gauge");
+    for (id, info) in &infos {
+        let _ = writeln!(
+            out,
+            "server_client_packet_loss_ratio{{client=\"{}\"}} {}",
+            id, info.packet_loss
+        );
+    }
+
+    let _ = writeln!(
+        out,
+        "# HELP server_sent_bytes_total Message payload bytes sent per channel."
+    );
+    let _ = writeln!(out, "# TYPE server_sent_bytes_total counter");
+    for (name, n) in CHANNEL_NAMES.iter().zip(src.net.bytes) {
+        let _ = writeln!(out, "server_sent_bytes_total{{channel=\"{}\"}} {}", name, n);
+    }
+    let _ = writeln!(
+        out,
+        "# HELP server_sent_messages_total Messages sent per channel."
+    );
+    let _ = writeln!(out, "# TYPE server_sent_messages_total counter");
+    for (name, n) in CHANNEL_NAMES.iter().zip(src.net.messages) {
+        let _ = writeln!(
+            out,
+            "server_sent_messages_total{{channel=\"{}\"}} {}",
+            name, n
+        );
+    }
+    src.net.snapshot_size.render(
+        &mut out,
+        "server_snapshot_size_bytes",
+        "Encoded size of each snapshot sent to a client.",
+    );
+
+    gauge(
+        &mut out,
+        "server_bots_active",
+        "Bots currently in the match.",
+        src.bots.states.len() as f64,
+    );
+    gauge(
+        &mut out,
+        "server_scaffolds",
+        "Scaffolds currently placed.",
+        src.scaffolds.by_id.len() as f64,
+    );
+    counter(
+        &mut out,
+        "server_rounds_played_total",
+        "Rounds finished since startup.",
+        src.rounds.count,
+    );
+    out
+}
+
+fn gauge(out: &mut String, name: &str, help: &str, v: f64) {
+    let _ = writeln!(
+        out,
+        "# HELP {} {}\n# TYPE {} gauge\n{} {}",
+        name, help, name, name, v
+    );
+}
+
+fn counter(out: &mut String, name: &str, help: &str, v: u64) {
+    let _ = writeln!(
+        out,
+        "# HELP {} {}\n# TYPE {} counter\n{} {}",
+        name, help, name, name, v
+    );
+}
+
+// GET /metrics に最後に公開したテキストを返す。スクレイパ 1 つを想定し、接続は順に処理する
+pub fn spawn_metrics_http(listener: TcpListener, export: &MetricsExport) {
+    let export = export.clone();
+    std::thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let peer = stream.peer_addr().ok();
+            if let Err(e) = serve_metrics(stream, &export) {
+                warn!("metrics {:?}: {}", peer, e);
+            }
+        }
+    });
+}
+
+fn serve_metrics(stream: TcpStream, export: &MetricsExport) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(METRICS_READ_TIMEOUT))?;
+    let mut out = stream.try_clone()?;
+    let mut reader = BufReader::new(stream);
+    let mut request = String::new();
+    reader.read_line(&mut request)?;
+    // ヘッダは読み捨てる
+    let mut line = String::new();
+    while reader.read_line(&mut line)? > 0 && !line.trim().is_empty() {
+        line.clear();
+    }
+    let mut parts = request.split_whitespace();
+    let (status, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => ("200 OK", export.text()),
+        (Some("GET"), _) => ("404 Not Found", "not found\n".to_string()),
+        _ => ("405 Method Not Allowed", "method not allowed\n".to_string()),
+    };
+    write!(
+        out,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )?;
+    out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn histogram_buckets_are_cumulative() {
+        let mut h = Histogram::new(&[1.0, 2.0]);
+        for v in [0.5, 1.0, 1.5, 3.0] {
+            h.observe(v);
+        }
+        let mut out = String::new();
+        h.render(&mut out, "x", "help");
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(
+            lines,
+            [
+                "# HELP x help",
+                "# TYPE x histogram",
+                "x_bucket{le=\"1\"} 2",
+                "x_bucket{le=\"2\"} 3",
+                "x_bucket{le=\"+Inf\"} 4",
+                "x_sum 6",
+                "x_count 4",
+            ]
+        );
+    }
+
+    #[test]
+    fn net_stats_count_per_channel() {
+        let mut stats = NetStats::default();
+        stats.record(CH_RELIABLE, 10);
+        stats.record(CH_RELIABLE, 5);
+        stats.record(CH_SNAPSHOT, 100);
+        stats.record(9, 1);
+        assert_eq!(stats.bytes, [0, 100, 15]);
+        assert_eq!(stats.messages, [0, 1, 2]);
+        assert_eq!(stats.snapshot_size.count(), 1);
+    }
+}
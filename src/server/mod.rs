@@ -0,0 +1,2541 @@
+use bevy::app::PluginGroupBuilder;
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::Name;
+use bevy::prelude::*;
+use bevy::time::Fixed;
+use bevy_rapier3d::prelude::*;
+use bevy_renet::renet::transport::{NetcodeServerTransport, NETCODE_USER_DATA_BYTES};
+use bevy_renet::renet::{ClientId, RenetServer, ServerEvent};
+use bevy_renet::transport::NetcodeServerPlugin;
+use bevy_renet::RenetServerPlugin;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::collections::VecDeque;
+use std::net::{IpAddr, SocketAddr};
+
+use crate::movement::{self, MoveState};
+use crate::net::shared as shared_consts;
+use crate::net::*;
+
+mod access;
+mod admin;
+mod bots;
+mod config;
+mod matchlog;
+mod metrics;
+mod recorder;
+mod reload;
+mod replay;
+mod rng;
+mod scaffold;
+mod session;
+mod stats;
+
+use access::*;
+use bots::*;
+use matchlog::*;
+use metrics::*;
+use reload::*;
+use replay::*;
+use scaffold::*;
+use session::*;
+use stats::*;
+
+pub use access::{AccessList, ServerPassword};
+pub use admin::{spawn_rcon, spawn_stdin_console, AdminPlugin, AdminQueue};
+pub use bots::BotPlugin;
+pub use config::ServerConfig;
+pub use matchlog::{MatchLog, MatchLogPlugin};
+pub use metrics::{spawn_metrics_http, MetricsExport, MetricsPlugin};
+pub use recorder::{DemoRecordPlugin, DemoRecorder};
+pub use reload::{ConfigFile, ConfigReloadPlugin};
+pub use replay::{match_state, InputRecorder, InputReplay, MatchState, ReplayPlugin};
+pub use rng::GameRng;
+use rng::{log_rng_seed, RngStream};
+pub use scaffold::ScaffoldPlugin;
+pub use stats::{StatsPlugin, StatsStore};
+
+// ハンドシェイクを通過したクライアント（caps はサーバ対応分で AND 済み）
+#[derive(Resource, Default)]
+struct ClientHandshakes(HashMap<u64, ConnectUserData>);
+
+// 表示名とアカウント。人間は接続時のユーザデータから、ボットはサーバが名付ける
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlayerProfile {
+    pub name: String,
+    pub account_id: u64, // 0 はゲスト/ボット
+}
+
+impl PlayerProfile {
+    fn info(&self, id: u64) -> ServerMessage {
+        ServerMessage::Event(EventMsg::PlayerInfo {
+            id,
+            name: self.name.clone(),
+            account_id: self.account_id,
+        })
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct PlayerProfiles(pub HashMap<u64, PlayerProfile>);
+
+impl PlayerProfiles {
+    // 参加者に既存の全員を、全員に参加者を知らせる
+    fn join(&mut self, net: &mut NetSend, id: u64, profile: PlayerProfile) {
+        for (&other, p) in self.0.iter() {
+            net.send_to(id, &p.info(other));
+        }
+        net.broadcast(&profile.info(id));
+        self.0.insert(id, profile);
+    }
+}
+
+// netcode を通さず RenetServer へ直接つなぐクライアント（テスト・リッスンサーバ用）
+// id -> 接続時のユーザデータ（ConnectUserData::to_bytes）
+#[derive(Resource, Default)]
+pub struct LocalClients(pub HashMap<u64, [u8; NETCODE_USER_DATA_BYTES]>);
+
+// netcode を Secure 認証で動かしているか（接続トークンのユーザデータは発行サービスが書いたもの）
+// false ならクライアントが自分で書いたユーザデータなので、アカウント ID は信用しない
+#[derive(Resource, Default)]
+pub struct SecureTransport(pub bool);
+
+// 接続元の情報（netcode 経由ならトランスポート、プロセス内クライアントなら LocalClients、再生中は記録）
+#[derive(SystemParam)]
+struct ConnectInfo<'w> {
+    transport: Option<Res<'w, NetcodeServerTransport>>,
+    secure: Res<'w, SecureTransport>,
+    local: Res<'w, LocalClients>,
+    replay: Option<Res<'w, InputReplay>>,
+}
+
+impl ConnectInfo<'_> {
+    fn addr(&self, id: u64) -> Option<SocketAddr> {
+        self.transport
+            .as_ref()
+            .and_then(|t| t.client_addr(ClientId::from_raw(id)))
+            .or_else(|| self.replay.as_ref().and_then(|r| r.addr(id)))
+    }
+
+    fn user_data(&self, id: u64) -> Option<[u8; NETCODE_USER_DATA_BYTES]> {
+        self.transport
+            .as_ref()
+            .and_then(|t| t.user_data(ClientId::from_raw(id)))
+            .or_else(|| self.local.0.get(&id).copied())
+    }
+
+    // ハンドシェイクを検証したユーザデータ。Unsecure の netcode 接続はアカウント ID を名乗れてしまうのでゲストにする
+    fn hello(&self, id: u64) -> Result<ConnectUserData, String> {
+        let mut hello = check_handshake(self.user_data(id).as_ref())?;
+        let via_netcode = self
+            .transport
+            .as_ref()
+            .is_some_and(|t| t.user_data(ClientId::from_raw(id)).is_some());
+        if via_netcode && !self.secure.0 {
+            hello.account_id = 0;
+        }
+        Ok(hello)
+    }
+
+    // ハンドシェイクと BAN・許可リスト・パスワード（accept_clients と入力記録が同じ判定を使う）
+    fn admit(&self, id: u64, gate: &Gate) -> Result<ConnectUserData, String> {
+        let hello = self.hello(id)?;
+        gate.admit(self.addr(id).map(|a| a.ip()), &hello)?;
+        Ok(hello)
+    }
+}
+
+// Reject/キック後、メッセージが届くまで待ってから切断する（id -> 残り秒）
+#[derive(Resource, Default)]
+struct PendingDisconnects(HashMap<u64, f32>);
+
+// 射撃検証の違反スコア（時間で減衰）
+#[derive(Resource, Default)]
+struct Violations(HashMap<u64, f32>);
+
+// アドレス単位の射撃検証キック回数（メモリ上のみ、再起動で消える。BAN は AccessList へ）
+#[derive(Resource, Default)]
+struct AddrKicks(HashMap<IpAddr, u32>);
+
+#[derive(Resource, Default)]
+struct Players {
+    states: HashMap<u64, PlayerState>,
+}
+
+#[derive(Default, Clone, Copy)]
+struct PlayerState {
+    pos: Vec3,
+    yaw: f32,
+    hp: u16,
+    alive: bool,
+    motion: MoveState, // 縦速度・接地・ジャンプタイマ（movement::step で更新）
+}
+
+#[derive(Resource, Default)]
+struct ProtectTimers(HashMap<u64, f32>);
+
+#[derive(SystemParam)]
+struct WpnProt<'w> {
+    protect: ResMut<'w, ProtectTimers>,
+    weapons: ResMut<'w, Weapons>,
+}
+
+// サーバ→クライアントのイベント送信窓口（宛先の絞り込みはここで行う）
+#[derive(SystemParam)]
+struct NetSend<'w> {
+    server: ResMut<'w, RenetServer>,
+    relevancy: Res<'w, Relevancy>,
+    stats: ResMut<'w, NetStats>,
+    demo: ResMut<'w, DemoRecorder>,
+}
+
+impl NetSend<'_> {
+    fn send_on(&mut self, id: u64, channel: u8, msg: &ServerMessage) {
+        if let Ok(bytes) = bincode::serialize(msg) {
+            self.stats.record(channel, bytes.len());
+            let _ = self
+                .server
+                .send_message(ClientId::from_raw(id), channel, bytes);
+        }
+    }
+
+    fn send_to(&mut self, id: u64, msg: &ServerMessage) {
+        self.send_on(id, CH_RELIABLE, msg);
+    }
+
+    // 全クライアントへ（キルログ・スコア・ラウンド進行など位置を含まない情報）
+    fn broadcast(&mut self, msg: &ServerMessage) {
+        self.demo.capture(msg);
+        if let Ok(bytes) = bincode::serialize(msg) {
+            for cid in self.server.clients_id() {
+                self.stats.record(CH_RELIABLE, bytes.len());
+                let _ = self.server.send_message(cid, CH_RELIABLE, bytes.clone());
+            }
+        }
+    }
+
+    // subjects のいずれかが本人、または関心範囲内にいるクライアントへ
+    fn send_relevant(&mut self, subjects: &[u64], msg: &ServerMessage) {
+        self.demo.capture(msg);
+        if let Ok(bytes) = bincode::serialize(msg) {
+            for cid in self.server.clients_id() {
+                let id = cid.raw();
+                if subjects.iter().any(|s| self.relevancy.sees(id, *s)) {
+                    self.stats.record(CH_RELIABLE, bytes.len());
+                    let _ = self.server.send_message(cid, CH_RELIABLE, bytes.clone());
+                }
+            }
+        }
+    }
+}
+
+#[derive(SystemParam)]
+struct ShootRes<'w> {
+    last: Res<'w, LastInputs>,
+    last_fire: ResMut<'w, LastFireSeq>,
+    respawns: ResMut<'w, RespawnTimers>,
+    bot_respawns: ResMut<'w, BotRespawnTimers>,
+    net: NetSend<'w>,
+    rapier: Res<'w, RapierContext>,
+    ents: Res<'w, ServerEntities>,
+    bot_ents: Res<'w, BotEntities>,
+    scores: ResMut<'w, Scores>,
+    round: Res<'w, RoundState>,
+    spawns: Res<'w, SpawnPoints>,
+    fires: ResMut<'w, PendingFires>,
+    wpnprot: WpnProt<'w>,
+    sim: Res<'w, SimTime>,
+    hist: Res<'w, PosHistory>,
+    cfg: Res<'w, ServerConfig>,
+    log: ResMut<'w, MatchLog>,
+}
+
+#[derive(Resource, Default)]
+struct LastInputs(HashMap<u64, InputFrame>); // 最後に処理したフレーム（ACK/射撃判定用）
+
+// --- Input sanitization / throttling ---
+const INPUT_DT_MIN: f32 = 1.0 / 240.0;
+const INPUT_DT_MAX: f32 = 1.0 / 30.0;
+const INPUT_QUEUE_MAX: usize = 32; // 未処理フレームの上限（超過分は捨てる）
+const INPUT_BUDGET_MAX_SEC: f32 = 0.25; // 溜められる時間予算（パケット詰まり後のバースト許容量）
+const INPUT_SEQ_MAX_GAP: u32 = 600; // これ以上 seq が飛んだら警告（~10s分）
+const INPUT_JITTER_FRAMES: usize = 2; // この数だけ溜まってから消費を始める（ジッタ吸収）
+const INPUT_JITTER_MAX_FRAMES: usize = 6; // これを超えて溜まったら古い分を捨てて INPUT_JITTER_FRAMES まで戻す
+
+// クライアントごとの未処理入力（seq 昇順）と時間予算
+#[derive(Default)]
+struct InputQueue {
+    frames: VecDeque<InputFrame>,
+    primed: bool,          // ジッタバッファが溜まり消費中か
+    last_seq: Option<u32>, // 受理済みの最大 seq
+    budget: f32,           // 処理してよいシミュレーション時間（実時間で加算、フレームの dt で消費）
+}
+
+impl InputQueue {
+    // 重複・巻き戻り（seq が進んでいない）フレームは捨てる
+    fn push(&mut self, id: u64, frame: InputFrame) {
+        if let Some(last) = self.last_seq {
+            let ahead = frame.seq.wrapping_sub(last);
+            if ahead == 0 || ahead > u32::MAX / 2 {
+                return;
+            }
+            if ahead > INPUT_SEQ_MAX_GAP {
+                warn!("input: seq jumped by {} for {}", ahead, id);
+            }
+        }
+        self.last_seq = Some(frame.seq);
+        if self.frames.len() >= INPUT_QUEUE_MAX {
+            return;
+        }
+        self.frames.push_back(frame);
+    }
+}
+
+#[derive(Resource, Default)]
+struct InputQueues(HashMap<u64, InputQueue>);
+
+// 受信フレームの正規化。PackedInputFrame から復元した値は常に有限で、mv は軸ごとの -1/0/+1。
+// mv はここで正規化しない（step が方向を正規化する。ここで変えるとクライアントの予測とずれる）
+fn sanitize_frame(mut f: InputFrame) -> InputFrame {
+    use std::f32::consts::{FRAC_PI_2, PI, TAU};
+    f.yaw = (f.yaw + PI).rem_euclid(TAU) - PI;
+    f.pitch = f.pitch.clamp(-FRAC_PI_2, FRAC_PI_2);
+    f.dt = f.dt.clamp(INPUT_DT_MIN, INPUT_DT_MAX);
+    f
+}
+
+#[derive(Resource, Default)]
+struct LastFireSeq(HashMap<u64, u32>);
+
+#[derive(Resource, Default)]
+struct RespawnTimers(HashMap<u64, f32>);
+
+#[derive(Resource, Default)]
+struct ServerEntities(HashMap<u64, Entity>);
+
+#[derive(Resource, Default)]
+struct MapReady(pub bool);
+
+#[derive(Resource, Default)]
+struct Scores(HashMap<u64, (u32, u32)>); // id -> (kills, deaths)
+
+#[derive(Resource, Default)]
+struct SpawnPoints(pub Vec<Vec3>);
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum RoundPhase {
+    Active,
+    Ending,
+}
+
+#[derive(Resource)]
+struct RoundState {
+    phase: RoundPhase,
+    time_left: f32,
+    end_timer: f32,
+}
+
+const REJECT_LINGER_SEC: f32 = 0.5; // Reject 送信から切断までの猶予
+
+#[derive(Default, Clone, Copy)]
+struct WeaponStatus {
+    ammo: u16,
+    cooldown: f32,
+    reload: f32,
+}
+
+#[derive(Resource, Default)]
+struct Weapons(HashMap<u64, WeaponStatus>);
+
+const DEFAULT_SPAWN_POS: Vec3 = Vec3::from_array(shared_consts::PLAYER_START);
+
+// --- Lag compensation params ---
+const HIT_HEIGHT_HALF: f32 = 0.6; // カプセル半高さ（Collider::capsule_y と一致）
+const HIT_RADIUS: f32 = 0.3; // カプセル半径
+const HIT_OCCLUSION_EPS: f32 = 0.15; // ラグ補償位置と物理ワールド位置のズレ吸収用
+
+// --- Fire validation (anti-cheat) ---
+const FIRE_EYE_OFFSET: Vec3 = Vec3::new(0.0, 0.7, 0.0); // クライアントのカメラ高さと一致
+const VIOLATION_DECAY_PER_SEC: f32 = 0.1;
+const VIOLATION_BAD_ORIGIN: f32 = 1.0;
+const VIOLATION_MALFORMED: f32 = 3.0; // NaN/ゼロ方向など正規クライアントでは起きない
+
+// 遮蔽で外れた射撃は試合ログに必ず残し、テキストログは [log] occlusion のときだけ
+fn log_occlusion_block(
+    log: &mut MatchLog,
+    enabled: bool,
+    msg: &str,
+    shooter: u64,
+    target: u64,
+    origin: Vec3,
+    dir: Vec3,
+    intended_t: f32,
+    blocked: Option<(Entity, f32)>,
+) {
+    log.record(MatchEvent::Occluded {
+        id: shooter,
+        target,
+        origin: pos3(origin),
+        dir: pos3(dir),
+        at: blocked.map(|(_, toi)| pos3(origin + dir * toi)),
+    });
+    if !enabled {
+        return;
+    }
+    match blocked {
+        Some((ent, toi)) => {
+            let block_pos = origin + dir * toi;
+            info!(
+                target: "shoot::occlusion",
+                "{} shooter={} target={} blocked_by={:?} intended_t={:.3} block_t={:.3} pos=({:.2},{:.2},{:.2})",
+                msg,
+                shooter,
+                target,
+                ent,
+                intended_t,
+                toi,
+                block_pos.x,
+                block_pos.y,
+                block_pos.z
+            );
+        }
+        None => {
+            info!(
+                target: "shoot::occlusion",
+                "{} shooter={} target={} blocked_by=None intended_t={:.3}",
+                msg,
+                shooter,
+                target,
+                intended_t
+            );
+        }
+    }
+}
+
+// ===== 関心管理（リレバンシー） =====
+const RELEVANCY_NEAR: f32 = 40.0; // これ以内は遮蔽に関係なく関連
+const RELEVANCY_FAR: f32 = 120.0; // 視線が通ればこの距離まで関連
+const RELEVANCY_HYSTERESIS: f32 = 5.0; // 関連中は半径をこれだけ広げる（境界でのばたつき防止）
+const RELEVANCY_LINGER_SEC: f32 = 1.0; // 条件を外れてから Leave を送るまでの猶予
+const RELEVANCY_INTERVAL_SEC: f32 = 0.1; // アクターの再評価間隔（遮蔽レイのコスト抑制）
+const SCAFFOLD_RELEVANCY_RADIUS: f32 = 80.0; // 足場は距離のみで判定（予測の衝突にも使うため）
+
+#[derive(Default)]
+struct ClientRelevancy {
+    actors: HashMap<u64, f32>, // actor id -> 残り猶予秒
+    scaffolds: HashSet<u64>,
+}
+
+#[derive(Resource, Default)]
+struct Relevancy {
+    clients: HashMap<u64, ClientRelevancy>,
+    timer: f32,
+}
+
+impl Relevancy {
+    // client から actor が見えている（本人は常に真）
+    fn sees(&self, client: u64, actor: u64) -> bool {
+        client == actor
+            || self
+                .clients
+                .get(&client)
+                .is_some_and(|c| c.actors.contains_key(&actor))
+    }
+}
+
+#[derive(Resource, Default)]
+struct PendingFires(Vec<(u64, Vec3, Vec3)>); // (shooter_id, origin, dir)
+
+#[derive(Resource, Default)]
+struct PosHistory(HashMap<u64, VecDeque<(f32, Vec3)>>);
+
+#[derive(Resource, Default)]
+struct SimTime(f32);
+
+// サーバ一式。物理（RapierPhysicsPlugin）とスケジュールの駆動は呼び出し側で追加する
+pub struct ServerPlugins;
+
+impl PluginGroup for ServerPlugins {
+    fn build(self) -> PluginGroupBuilder {
+        PluginGroupBuilder::start::<Self>()
+            .add(NetProtocolPlugin::default())
+            .add(ServerGameplayPlugin::default())
+            .add(BotPlugin)
+            .add(ScaffoldPlugin)
+            .add(ConfigReloadPlugin)
+            .add(AdminPlugin)
+            .add(MetricsPlugin)
+            .add(MatchLogPlugin)
+            .add(StatsPlugin)
+            .add(DemoRecordPlugin)
+            .add(ReplayPlugin)
+    }
+}
+
+// renet サーバ、ハンドシェイク、入力受信、関心管理、スナップショット送信
+pub struct NetProtocolPlugin {
+    pub snapshot_hz: f64, // スナップショット送信レート（FixedUpdate のレート以下）
+}
+
+impl Default for NetProtocolPlugin {
+    fn default() -> Self {
+        Self { snapshot_hz: 30.0 }
+    }
+}
+
+impl Plugin for NetProtocolPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins((RenetServerPlugin, NetcodeServerPlugin))
+            .init_resource::<ClientHandshakes>()
+            .init_resource::<PlayerProfiles>()
+            .init_resource::<LocalClients>()
+            .init_resource::<SecureTransport>()
+            .init_resource::<PendingDisconnects>()
+            .init_resource::<Violations>()
+            .init_resource::<AddrKicks>()
+            .init_resource::<AccessList>()
+            .init_resource::<ServerPassword>()
+            .init_resource::<ServerConfig>()
+            .init_resource::<InputQueues>()
+            .init_resource::<SnapshotSeq>()
+            .init_resource::<SnapshotBaselines>()
+            .init_resource::<Relevancy>()
+            .init_resource::<NetStats>()
+            .init_resource::<MatchLog>()
+            .init_resource::<DemoRecorder>()
+            .insert_resource(SnapshotTimer(Timer::from_seconds(
+                (1.0 / self.snapshot_hz) as f32,
+                TimerMode::Repeating,
+            )))
+            .insert_resource(ServerLogTimer(Timer::from_seconds(
+                1.0,
+                TimerMode::Repeating,
+            )))
+            .add_systems(Startup, setup_server)
+            .add_systems(
+                Update,
+                (
+                    accept_clients,
+                    log_clients_count,
+                    process_pending_disconnects,
+                ),
+            )
+            .add_systems(Update, sync_players_with_connections)
+            .add_systems(Update, broadcast_rules.after(accept_clients))
+            .add_systems(FixedUpdate, recv_inputs)
+            .add_systems(FixedUpdate, update_relevancy.before(broadcast_snapshots))
+            // スナップショットは物理反映後の状態（= ACK した seq の結果）を送る
+            .add_systems(FixedUpdate, broadcast_snapshots.after(srv_kcc_post));
+    }
+}
+
+// プレイヤーの移動・射撃・リスポーン・ラウンド進行とマップ読み込み
+pub struct ServerGameplayPlugin {
+    // None ならマップを読み込まない（コライダとスポーン地点は呼び出し側で用意する）
+    pub map_scene: Option<String>,
+    // シミュレーション（FixedUpdate）のレート。クライアントの予測は 60Hz 前提
+    pub tick_hz: f64,
+}
+
+impl Default for ServerGameplayPlugin {
+    fn default() -> Self {
+        Self {
+            map_scene: Some(MAP_SCENE_PATH.to_string()),
+            tick_hz: 60.0,
+        }
+    }
+}
+
+impl Plugin for ServerGameplayPlugin {
+    fn build(&self, app: &mut App) {
+        // 設定は呼び出し側が先に insert していればそれを使う（無ければ既定値）
+        app.init_resource::<ServerConfig>();
+        let round_time = app.world().resource::<ServerConfig>().round.time_sec;
+        // 乱数も同様。無ければ設定のシード（未指定なら起動ごとのランダム）で作る
+        if !app.world().contains_resource::<GameRng>() {
+            let rng = GameRng::from_config(app.world().resource::<ServerConfig>());
+            app.insert_resource(rng);
+        }
+        app.insert_resource(Time::<Fixed>::from_hz(self.tick_hz))
+            .insert_resource(MapReady(false))
+            .insert_resource(MapScene(self.map_scene.clone()))
+            .init_resource::<Players>()
+            .init_resource::<LastInputs>()
+            .init_resource::<LastFireSeq>()
+            .init_resource::<RespawnTimers>()
+            .init_resource::<Scores>()
+            .insert_resource(RoundState {
+                phase: RoundPhase::Active,
+                time_left: round_time,
+                end_timer: 0.0,
+            })
+            .init_resource::<SpawnPoints>()
+            .init_resource::<Weapons>()
+            .init_resource::<ServerEntities>()
+            .init_resource::<ProtectTimers>()
+            .init_resource::<PendingFires>()
+            .init_resource::<PosHistory>()
+            .init_resource::<SimTime>()
+            // 射撃・関心管理・入力受信が参照するため、BotPlugin/ScaffoldPlugin/StatsPlugin を外しても空で用意する
+            .init_resource::<Bots>()
+            .init_resource::<BotEntities>()
+            .init_resource::<BotRespawnTimers>()
+            .init_resource::<Scaffolds>()
+            .init_resource::<PendingScaffold>()
+            .init_resource::<PendingRound>()
+            .init_resource::<MatchLog>()
+            .init_resource::<ParkedSessions>()
+            .init_resource::<StatsStore>()
+            .init_resource::<PendingStatsQueries>()
+            .add_systems(Startup, (setup_map, log_rng_seed))
+            .add_systems(
+                Update,
+                (add_mesh_colliders_for_map, collect_spawn_points_from_map),
+            )
+            .add_systems(FixedUpdate, srv_kcc_move.before(PhysicsSet::StepSimulation))
+            .add_systems(FixedUpdate, srv_kcc_post.after(PhysicsSet::Writeback))
+            .add_systems(FixedUpdate, update_position_history)
+            .add_systems(
+                FixedUpdate,
+                validate_fires
+                    .after(recv_inputs)
+                    .before(srv_shoot_and_respawn),
+            )
+            .add_systems(FixedUpdate, srv_shoot_and_respawn)
+            .add_systems(FixedUpdate, round_update)
+            .add_systems(Update, expire_parked_sessions);
+    }
+}
+
+fn setup_server(
+    mut commands: Commands,
+    existing: Option<Res<RenetServer>>,
+    mut exit: EventWriter<AppExit>,
+) {
+    // 埋め込み利用（テスト等）やサーババイナリが既に用意していればそれを使う
+    if existing.is_some() {
+        return;
+    }
+    let started = ServerNetSettings::from_env()
+        .and_then(|settings| new_server(&settings).map(|st| (settings, st)));
+    match started {
+        Ok((settings, (server, transport))) => {
+            commands.insert_resource(server);
+            commands.insert_resource(transport);
+            commands.insert_resource(SecureTransport(settings.private_key.is_some()));
+            info!("Server listening on {}", settings.bind_addr);
+        }
+        Err(e) => {
+            error!("server start failed: {}", e);
+            exit.send(AppExit::error());
+        }
+    }
+}
+
+// 接続ごとのセッション状態（切断時にまとめて破棄する）
+#[derive(SystemParam)]
+struct Sessions<'w> {
+    handshakes: ResMut<'w, ClientHandshakes>,
+    pending_dc: ResMut<'w, PendingDisconnects>,
+    baselines: ResMut<'w, SnapshotBaselines>,
+    violations: ResMut<'w, Violations>,
+    profiles: ResMut<'w, PlayerProfiles>,
+}
+
+fn accept_clients(
+    mut commands: Commands,
+    mut net: NetSend,
+    mut players: ResMut<Players>,
+    mut ents: ResMut<ServerEntities>,
+    mut scores: ResMut<Scores>,
+    round: Res<RoundState>,
+    map: MapInfo,
+    mut wpnprot: WpnProt,
+    conn: ConnectInfo,
+    mut sessions: Sessions,
+    gate: Gate,
+    mut events: EventReader<ServerEvent>,
+    cfg: Res<ServerConfig>,
+    mut log: ResMut<MatchLog>,
+    mut rng: ResMut<GameRng>,
+    mut reconnect: Reconnect,
+) {
+    // RenetServerPlugin が PreUpdate でイベントを Events<ServerEvent> へ移すので、そちらから読む
+    for event in events.read() {
+        match *event {
+            ServerEvent::ClientConnected { client_id } => {
+                let id = client_id.raw();
+                let addr = conn.addr(id);
+                // だめなら理由を返して切断予約
+                let hello = match conn.admit(id, &gate) {
+                    Ok(hello) => hello,
+                    Err(reason) => {
+                        info!("client rejected: {} ({})", id, reason);
+                        net.send_to(id, &ServerMessage::Reject { reason });
+                        sessions.pending_dc.0.insert(id, REJECT_LINGER_SEC);
+                        continue;
+                    }
+                };
+                let welcome = ServerMessage::Welcome {
+                    version: PROTOCOL_VERSION,
+                    caps: hello.caps,
+                };
+                net.send_to(id, &welcome);
+                net.send_to(id, &ServerMessage::Rules(cfg.rules()));
+                let profile = PlayerProfile {
+                    name: if hello.name.is_empty() {
+                        format!("Player {}", id % 10_000)
+                    } else {
+                        hello.name.clone()
+                    },
+                    account_id: hello.account_id,
+                };
+                info!(
+                    "client {} is {:?} (account {})",
+                    id, profile.name, profile.account_id
+                );
+                let name = profile.name.clone();
+                // 同じアカウントの古い接続がまだ残っていれば追い出して、その状態を引き継ぐ
+                let account_id = profile.account_id;
+                let previous = sessions
+                    .profiles
+                    .0
+                    .iter()
+                    .find(|&(&other, p)| other != id && p.account_id == account_id)
+                    .map(|(&other, _)| other)
+                    .filter(|_| account_id != 0);
+                let mut taken_over = None;
+                if let Some(old) = previous {
+                    info!("client {} replaces {} (account {})", id, old, account_id);
+                    let reason = "Signed in from another client".to_string();
+                    net.send_to(old, &ServerMessage::Reject { reason });
+                    sessions.pending_dc.0.insert(old, REJECT_LINGER_SEC);
+                    // 切断までのあいだに sync_players_with_connections が作り直さないよう、ハンドシェイクも消す
+                    sessions.handshakes.0.remove(&old);
+                    sessions.profiles.0.remove(&old);
+                    wpnprot.protect.0.remove(&old);
+                    let score = scores.0.remove(&old).unwrap_or((0, 0));
+                    let weapon = wpnprot.weapons.0.remove(&old);
+                    if let Some(state) = players.states.remove(&old) {
+                        net.broadcast(&ServerMessage::Event(EventMsg::Despawn { id: old }));
+                        log.record(MatchEvent::Disconnect {
+                            id: old,
+                            reason: "replaced by a new connection".into(),
+                        });
+                        taken_over = Some(reconnect.hand_over(state, score, weapon, old, id));
+                    }
+                    if let Some(e) = ents.0.remove(&old) {
+                        commands.entity(e).despawn_recursive();
+                    }
+                }
+                // 猶予中に同じアカウントで戻ってきたら、預けた状態を新しい id で引き継ぐ
+                let resumed = taken_over.or_else(|| reconnect.resume(account_id, id));
+                sessions.profiles.join(&mut net, id, profile);
+                sessions.handshakes.0.insert(id, hello);
+                if let Some(scene) = map.scene.0.clone() {
+                    net.send_to(id, &ServerMessage::Event(EventMsg::MapChange { scene }));
+                }
+                let state = match &resumed {
+                    Some(parked) => parked.state,
+                    None => {
+                        let mut spawn = choose_spawn_point(&map.spawns, &players);
+                        // スポーン分散ジッター
+                        spawn += rng
+                            .stream(RngStream::PlayerSpawn)
+                            .jitter(cfg.spawn.jitter_radius);
+                        PlayerState {
+                            pos: spawn,
+                            yaw: 0.0,
+                            hp: 100,
+                            alive: true,
+                            motion: MoveState::standing(),
+                        }
+                    }
+                };
+                let spawn = state.pos;
+                players.states.insert(id, state);
+                let mut kcc = KinematicCharacterController::default();
+                kcc.autostep = Some(CharacterAutostep {
+                    max_height: CharacterLength::Absolute(0.5),
+                    min_width: CharacterLength::Absolute(0.3),
+                    include_dynamic_bodies: true,
+                });
+                kcc.snap_to_ground = Some(CharacterLength::Absolute(0.25));
+                let ent = commands
+                    .spawn((
+                        TransformBundle::from_transform(Transform::from_translation(spawn)),
+                        Collider::capsule_y(0.6, 0.3),
+                        kcc,
+                    ))
+                    .id();
+                ents.0.insert(id, ent);
+                info!(
+                    "server: inserted player state for {} (total={})",
+                    id,
+                    players.states.len()
+                );
+                // broadcast spawn（死亡中に抜けた場合はリスポーン時に送る）
+                if state.alive {
+                    let ev = ServerMessage::Event(EventMsg::Spawn {
+                        id,
+                        pos: [spawn.x, spawn.y, spawn.z],
+                        kind: ActorKind::Human,
+                    });
+                    net.send_relevant(&[id], &ev);
+                }
+                let weapon = resumed
+                    .as_ref()
+                    .and_then(|p| p.weapon)
+                    .unwrap_or(WeaponStatus {
+                        ammo: cfg.weapon.mag_size,
+                        cooldown: 0.0,
+                        reload: 0.0,
+                    });
+                wpnprot.weapons.0.insert(id, weapon);
+                let ev = ServerMessage::Event(EventMsg::Ammo {
+                    id,
+                    ammo: weapon.ammo,
+                    reloading: weapon.reload > 0.0,
+                });
+                net.send_to(id, &ev);
+                if let Some(parked) = &resumed {
+                    scores.0.insert(id, parked.score);
+                    net.broadcast(&ServerMessage::Score(score_table(&scores)));
+                    // 保護は付けない（切断→再接続で無敵を得られないように）
+                    info!(
+                        "client connected: {} (resumed at {:.1},{:.1},{:.1} hp={})",
+                        id, spawn.x, spawn.y, spawn.z, state.hp
+                    );
+                } else {
+                    scores.0.entry(id).or_insert((0, 0));
+                    // スポーン保護
+                    wpnprot.protect.0.insert(id, cfg.spawn.protect_sec);
+                    info!(
+                        "client connected: {} (protect {:.1}s)",
+                        id, cfg.spawn.protect_sec
+                    );
+                }
+                log.record(MatchEvent::Connect {
+                    id,
+                    name,
+                    addr: addr.map(|a| a.to_string()),
+                    pos: pos3(spawn),
+                });
+                // 現在のラウンド残り時間を通知
+                let ev = ServerMessage::Event(EventMsg::RoundStart {
+                    time_left_sec: round.time_left.max(0.0) as u32,
+                });
+                net.send_to(id, &ev);
+                // 既存の足場・他アクターは update_relevancy が範囲内の分だけ通知する
+            }
+            ServerEvent::ClientDisconnected { client_id, reason } => {
+                let id = client_id.raw();
+                sessions.handshakes.0.remove(&id);
+                sessions.pending_dc.0.remove(&id);
+                sessions.baselines.0.remove(&id);
+                sessions.violations.0.remove(&id);
+                let account_id = sessions.profiles.0.remove(&id).map_or(0, |p| p.account_id);
+                let score = scores.0.remove(&id).unwrap_or((0, 0));
+                let weapon = wpnprot.weapons.0.remove(&id);
+                // Reject されたクライアントは Spawn していないので Despawn も送らない
+                if let Some(state) = players.states.remove(&id) {
+                    let ev = ServerMessage::Event(EventMsg::Despawn { id });
+                    net.broadcast(&ev);
+                    log.record(MatchEvent::Disconnect {
+                        id,
+                        reason: reason.to_string(),
+                    });
+                    let grace = cfg.spawn.reconnect_grace_sec;
+                    if reconnect.park(account_id, state, score, weapon, id, grace) {
+                        info!(
+                            "session for account {} parked for {:.0}s",
+                            account_id, grace
+                        );
+                    }
+                }
+                if let Some(e) = ents.0.remove(&id) {
+                    commands.entity(e).despawn_recursive();
+                }
+                info!("client disconnected: {} ({:?})", id, reason);
+            }
+        }
+    }
+}
+
+// Reject/キックしたクライアントを猶予後に切断
+fn process_pending_disconnects(
+    time: Res<Time>,
+    mut server: ResMut<RenetServer>,
+    mut pending: ResMut<PendingDisconnects>,
+) {
+    let dt = time.delta_seconds();
+    let mut expired = Vec::new();
+    for (id, t) in pending.0.iter_mut() {
+        *t -= dt;
+        if *t <= 0.0 {
+            expired.push(*id);
+        }
+    }
+    for id in expired {
+        pending.0.remove(&id);
+        server.disconnect(ClientId::from_raw(id));
+    }
+}
+
+const MAP_SCENE_PATH: &str = "maps/map.glb#Scene0";
+
+#[derive(Resource)]
+struct MapScene(Option<String>);
+
+// 読み込んだマップシーンのルート（マップ変更時にまとめて消す）
+#[derive(Component)]
+struct MapRoot;
+
+// 接続処理が参照するマップ情報
+#[derive(SystemParam)]
+struct MapInfo<'w> {
+    scene: Res<'w, MapScene>,
+    spawns: Res<'w, SpawnPoints>,
+}
+
+fn setup_map(mut commands: Commands, asset_server: Res<AssetServer>, scene: Res<MapScene>) {
+    let Some(path) = scene.0.clone() else {
+        return;
+    };
+    spawn_map(&mut commands, &asset_server, path);
+}
+
+fn spawn_map(commands: &mut Commands, asset_server: &AssetServer, path: String) {
+    commands.spawn((
+        SceneBundle {
+            scene: asset_server.load(path),
+            ..default()
+        },
+        MapRoot,
+    ));
+}
+
+fn is_human_id(players: &Players, id: u64) -> bool {
+    players.states.contains_key(&id)
+}
+
+fn recv_inputs(
+    mut server: ResMut<RenetServer>,
+    mut queues: ResMut<InputQueues>,
+    mut pending: ResMut<PendingScaffold>,
+    mut fires: ResMut<PendingFires>,
+    mut baselines: ResMut<SnapshotBaselines>,
+    mut recorder: ResMut<InputRecorder>,
+    replay: Option<ResMut<InputReplay>>,
+    mut stats_queries: ResMut<PendingStatsQueries>,
+) {
+    // 再生中は記録した受信列をそのまま使う（ループバックのクライアントは何も送ってこない）
+    let received = match replay {
+        Some(mut replay) => replay.next_tick_messages(),
+        None => drain_client_messages(&mut server),
+    };
+    recorder.capture_tick(&received);
+    for (id, msg) in received {
+        match msg {
+            ClientMessage::Input(frame) => {
+                let frame = sanitize_frame(frame);
+                queues.0.entry(id).or_default().push(id, frame);
+            }
+            ClientMessage::PlaceScaffold { pos } => {
+                let p = Vec3::new(pos[0], pos[1], pos[2]);
+                pending.0.push((id, p));
+            }
+            ClientMessage::Fire { origin, dir } => {
+                let o = Vec3::new(origin[0], origin[1], origin[2]);
+                let d = Vec3::new(dir[0], dir[1], dir[2]);
+                fires.0.push((id, o, d));
+            }
+            ClientMessage::SnapshotAck { tick } => {
+                // 新しい tick へのみ進める（wrap を考慮）
+                let bl = baselines.0.entry(id).or_default();
+                if bl.acked.is_none_or(|a| (tick.wrapping_sub(a) as i32) > 0) {
+                    bl.acked = Some(tick);
+                }
+            }
+            ClientMessage::StatsQuery { account_id } => stats_queries.0.push((id, account_id)),
+        }
+    }
+}
+
+// この tick に届いたメッセージを受信順に（クライアントごとに入力チャネル → 信頼チャネル）
+fn drain_client_messages(server: &mut RenetServer) -> Vec<(u64, ClientMessage)> {
+    let mut out = Vec::new();
+    for client_id in server.clients_id() {
+        let id = client_id.raw();
+        while let Some(raw) = server.receive_message(client_id, CH_INPUT) {
+            if let Ok(msg) = bincode::deserialize::<ClientMessage>(&raw) {
+                out.push((id, msg));
+            }
+        }
+        // 念のため、信頼チャネルにも PlaceScaffold などが来た場合を拾う（ACK は入力チャネルのみ）
+        while let Some(raw) = server.receive_message(client_id, CH_RELIABLE) {
+            match bincode::deserialize::<ClientMessage>(&raw) {
+                Ok(ClientMessage::SnapshotAck { .. }) | Err(_) => {}
+                Ok(msg) => out.push((id, msg)),
+            }
+        }
+    }
+    out
+}
+
+// クライアント申告の射撃（origin/dir）を検証し、不正なものは捨てて違反として記録
+fn validate_fires(
+    time_fixed: Res<Time<Fixed>>,
+    mut fires: ResMut<PendingFires>,
+    players: Res<Players>,
+    protect: Res<ProtectTimers>,
+    hist: Res<PosHistory>,
+    sim: Res<SimTime>,
+    mut net: NetSend,
+    mut violations: ResMut<Violations>,
+    mut pending_dc: ResMut<PendingDisconnects>,
+    mut kicks: ResMut<AddrKicks>,
+    mut access: ResMut<AccessList>,
+    cfg: Res<ServerConfig>,
+    conn: ConnectInfo,
+) {
+    let dt = time_fixed.delta_seconds();
+    for v in violations.0.values_mut() {
+        *v = (*v - VIOLATION_DECAY_PER_SEC * dt).max(0.0);
+    }
+    let mut accepted = Vec::new();
+    for (id, origin, dir) in fires.0.drain(..) {
+        if !origin.is_finite() || !dir.is_finite() || dir.length_squared() < 1e-6 {
+            warn!("fire rejected: malformed shot from {}", id);
+            *violations.0.entry(id).or_insert(0.0) += VIOLATION_MALFORMED;
+            continue;
+        }
+        let Some(st) = players.states.get(&id) else {
+            continue;
+        };
+        // 死亡中/保護中の射撃は遅延で起こりうるので違反にはせず捨てるだけ
+        if !st.alive || protect.0.get(&id).copied().unwrap_or(0.0) > 0.0 {
+            continue;
+        }
+        // RTT 分だけ遡った位置履歴 + 目線オフセットと照合
+        let rtt = net
+            .server
+            .network_info(ClientId::from_raw(id))
+            .map(|info| info.rtt as f32)
+            .unwrap_or(0.0);
+        let lc = &cfg.lag_comp;
+        let window = (rtt + lc.rewind_sec).min(lc.origin_rewind_max_sec);
+        let tol = (lc.origin_tol + cfg.movement.move_speed * rtt).min(lc.origin_tol_max);
+        let mut best = origin.distance(st.pos + FIRE_EYE_OFFSET);
+        if let Some(dq) = hist.0.get(&id) {
+            for (t, p) in dq.iter().rev() {
+                if sim.0 - *t > window {
+                    break;
+                }
+                best = best.min(origin.distance(*p + FIRE_EYE_OFFSET));
+            }
+        }
+        if best > tol {
+            warn!(
+                "fire rejected: origin off by {:.2}m (tol {:.2}m) from {}",
+                best, tol, id
+            );
+            *violations.0.entry(id).or_insert(0.0) += VIOLATION_BAD_ORIGIN;
+            continue;
+        }
+        accepted.push((id, origin, dir));
+    }
+    fires.0 = accepted;
+
+    // しきい値超過でキック、同一アドレスのキックが重なれば BAN
+    let policy = &cfg.anticheat;
+    if policy.kick_score <= 0.0 {
+        return;
+    }
+    let offenders: Vec<u64> = violations
+        .0
+        .iter()
+        .filter(|(id, v)| **v >= policy.kick_score && !pending_dc.0.contains_key(id))
+        .map(|(id, _)| *id)
+        .collect();
+    for id in offenders {
+        violations.0.remove(&id);
+        let addr = conn.addr(id);
+        let mut reason = "Kicked: invalid fire data".to_string();
+        if let Some(ip) = addr.map(|a| a.ip()) {
+            let count = kicks.0.entry(ip).or_insert(0);
+            *count += 1;
+            if policy.ban_after_kicks > 0 && *count >= policy.ban_after_kicks {
+                kicks.0.remove(&ip);
+                // 誤検知は bans で見つけて unban で外せるよう、理由と期限つきで BAN リストに載せる
+                let rule = Rule {
+                    target: Target::Addr(ip.into()),
+                    reason: "repeated invalid fire data (anti-cheat)".into(),
+                    until: (policy.ban_sec > 0.0).then(|| unix_now() + policy.ban_sec as u64),
+                };
+                if let Err(e) = access.ban(rule) {
+                    error!("anti-cheat ban of {}: {}", ip, e);
+                }
+                reason = "Banned: repeated invalid fire data".to_string();
+            }
+        }
+        warn!("anti-cheat: {} ({:?}) {}", id, addr, reason);
+        net.send_to(id, &ServerMessage::Reject { reason });
+        pending_dc.0.insert(id, REJECT_LINGER_SEC);
+    }
+}
+
+// 設定が変わってクライアント向けのルール値も変わったら、ハンドシェイク済みの全員へ送り直す
+// （接続時の分は accept_clients が Welcome の直後に送る）
+fn broadcast_rules(
+    cfg: Res<ServerConfig>,
+    handshakes: Res<ClientHandshakes>,
+    mut net: NetSend,
+    mut sent: Local<Option<RulesMsg>>,
+) {
+    if !cfg.is_changed() {
+        return;
+    }
+    let rules = cfg.rules();
+    let prev = sent.replace(rules);
+    if prev.is_none() || prev == Some(rules) {
+        return;
+    }
+    info!("rules changed, notifying {} clients", handshakes.0.len());
+    let msg = ServerMessage::Rules(rules);
+    for id in handshakes.0.keys() {
+        net.send_to(*id, &msg);
+    }
+}
+
+// Pre-physics movement using KCC
+// 1tickに1フレームずつ seq 順に処理し、処理した seq を LastInputs に残す（スナップショットで ACK）
+fn srv_kcc_move(
+    time_fixed: Res<Time<Fixed>>,
+    mut players: ResMut<Players>,
+    ents: Res<ServerEntities>,
+    mut last: ResMut<LastInputs>,
+    mut queues: ResMut<InputQueues>,
+    mut q: Query<&mut KinematicCharacterController>,
+    ready: Res<MapReady>,
+    cfg: Res<ServerConfig>,
+) {
+    if !ready.0 {
+        return;
+    }
+    let params = cfg.move_params();
+    queues.0.retain(|id, _| players.states.contains_key(id));
+    let tick_dt = time_fixed.delta_seconds();
+    for (id, state) in players.states.iter_mut() {
+        let Some(queue) = queues.0.get_mut(id) else {
+            continue;
+        };
+        queue.budget = (queue.budget + tick_dt).min(INPUT_BUDGET_MAX_SEC);
+        let Some(&entity) = ents.0.get(id) else {
+            continue;
+        };
+        let Ok(mut kcc) = q.get_mut(entity) else {
+            continue;
+        };
+        // ジッタバッファ: 溜まるまで開始せず、枯渇したら溜め直す（その間はシミュレーションしない）
+        if queue.frames.is_empty() {
+            queue.primed = false;
+        } else if !queue.primed && queue.frames.len() >= INPUT_JITTER_FRAMES {
+            queue.primed = true;
+        }
+        if !queue.primed {
+            continue;
+        }
+        // 遅延が溜まり過ぎたら古いフレームを捨てて追いつく（1tickに2フレーム進めるとクライアントの予測とずれる）
+        // 捨てたフレームの jump/fire は次に処理するフレームへ持ち越す（押下を失わない）
+        if queue.frames.len() > INPUT_JITTER_MAX_FRAMES {
+            let surplus = queue.frames.len() - INPUT_JITTER_FRAMES;
+            let (jump, fire) = queue
+                .frames
+                .drain(..surplus)
+                .fold((false, false), |(j, f), d| (j || d.jump, f || d.fire));
+            if let Some(next) = queue.frames.front_mut() {
+                next.jump |= jump;
+                next.fire |= fire;
+            }
+        }
+        // 予算を超えるフレームは次tickへ持ち越し（実時間より速く送るスピードハック対策）
+        if !queue
+            .frames
+            .front()
+            .is_some_and(|f| f.dt <= queue.budget + 1e-4)
+        {
+            continue;
+        }
+        let Some(inp) = queue.frames.pop_front() else {
+            continue;
+        };
+        queue.budget = (queue.budget - inp.dt).max(0.0);
+        if !state.alive {
+            // 死亡中も seq は進める（ACK のため）が移動はしない
+            last.0.insert(*id, inp);
+            continue;
+        }
+        let out = movement::step(&mut state.motion, &inp, &params);
+        state.yaw = inp.yaw;
+        last.0.insert(*id, inp);
+        if out.jumped {
+            // disable snap this frame to avoid glue-to-ground
+            kcc.snap_to_ground = None;
+        }
+        kcc.translation = Some(out.motion);
+    }
+}
+
+// Post-physics: update states from transforms/outputs
+fn srv_kcc_post(
+    mut players: ResMut<Players>,
+    ents: Res<ServerEntities>,
+    q: Query<(
+        &GlobalTransform,
+        Option<&KinematicCharacterControllerOutput>,
+    )>,
+    mut qk: Query<&mut KinematicCharacterController>,
+) {
+    for (id, state) in players.states.iter_mut() {
+        let Some(&entity) = ents.0.get(id) else {
+            continue;
+        };
+        if let Ok((gt, out)) = q.get(entity) {
+            state.pos = gt.translation();
+            let mut shallow_debug: Option<(f32, Vec<(Entity, Vec3)>)> = None;
+            let mut grounded_by_floor = false;
+            if let Some(o) = out {
+                if o.grounded && state.motion.vy <= 0.0 {
+                    let prev_vy = state.motion.vy;
+                    let mut shallow_contacts = Vec::new();
+                    for col in &o.collisions {
+                        if let Some(details) = col.hit.details {
+                            let normal: Vec3 = details.normal1.into();
+                            if normal.y >= shared_consts::GROUND_NORMAL_MIN_Y {
+                                grounded_by_floor = true;
+                            } else {
+                                shallow_contacts.push((col.entity, normal));
+                            }
+                        }
+                    }
+                    if !grounded_by_floor && !shallow_contacts.is_empty() {
+                        shallow_debug = Some((prev_vy, shallow_contacts));
+                    }
+                }
+            }
+            state.motion.land(grounded_by_floor);
+            if let Ok(mut kcc) = qk.get_mut(entity) {
+                if let Some((prev_vy, shallow_contacts)) = shallow_debug.take() {
+                    let normals: Vec<String> = shallow_contacts
+                        .iter()
+                        .map(|(entity, normal)| {
+                            format!(
+                                "{:?}:({:.2},{:.2},{:.2})",
+                                entity, normal.x, normal.y, normal.z
+                            )
+                        })
+                        .collect();
+                    info!(
+                        target: "kcc::grounding",
+                        "server shallow normal prev_vy={:.3} snap_to_ground={} normals={:?}",
+                        prev_vy,
+                        kcc.snap_to_ground.is_some(),
+                        normals,
+                    );
+                    kcc.snap_to_ground = None;
+                } else if state.motion.grounded {
+                    if kcc.snap_to_ground.is_none() {
+                        kcc.snap_to_ground = Some(CharacterLength::Absolute(0.25));
+                    }
+                } else {
+                    kcc.snap_to_ground = None;
+                }
+            }
+        }
+    }
+}
+
+// --- Position history sampling for lag compensation ---
+fn update_position_history(
+    time_fixed: Res<Time<Fixed>>,
+    mut sim: ResMut<SimTime>,
+    players: Res<Players>,
+    bots: Res<Bots>,
+    mut hist: ResMut<PosHistory>,
+    cfg: Res<ServerConfig>,
+) {
+    let dt = time_fixed.delta_seconds();
+    sim.0 += dt;
+    let now = sim.0;
+    // humans
+    for (id, s) in players.states.iter() {
+        let dq = hist.0.entry(*id).or_default();
+        dq.push_back((now, s.pos));
+        while let Some((t, _)) = dq.front().copied() {
+            if now - t > cfg.lag_comp.history_sec {
+                dq.pop_front();
+            } else {
+                break;
+            }
+        }
+    }
+    // bots
+    for (id, s) in bots.states.iter() {
+        let dq = hist.0.entry(*id).or_default();
+        dq.push_back((now, s.pos));
+        while let Some((t, _)) = dq.front().copied() {
+            if now - t > cfg.lag_comp.history_sec {
+                dq.pop_front();
+            } else {
+                break;
+            }
+        }
+    }
+}
+
+fn rewind_pos(hist: &PosHistory, id: u64, t_target: f32) -> Option<Vec3> {
+    let dq = hist.0.get(&id)?;
+    if dq.is_empty() {
+        return None;
+    }
+    // if outside range, clamp to ends
+    if t_target <= dq.front()?.0 {
+        return Some(dq.front()?.1);
+    }
+    if t_target >= dq.back()?.0 {
+        return Some(dq.back()?.1);
+    }
+    // find segment bracketing t_target (linear search; deques are short)
+    let mut prev = dq.front().copied()?;
+    for &(t, p) in dq.iter() {
+        if t >= t_target {
+            let (t0, p0) = prev;
+            let (t1, p1) = (t, p);
+            let alpha = ((t_target - t0) / (t1 - t0)).clamp(0.0, 1.0);
+            return Some(p0.lerp(p1, alpha));
+        }
+        prev = (t, p);
+    }
+    Some(dq.back()?.1)
+}
+
+fn ray_cylinder_hit(
+    origin: Vec3,
+    dir: Vec3,
+    range: f32,
+    center: Vec3,
+    half_h: f32,
+    radius: f32,
+) -> Option<f32> {
+    let mut best: Option<f32> = None;
+    let mut best_t = range;
+    let margin = 0.05;
+
+    // finite cylinder part (aligned to Y)
+    let dx = dir.x;
+    let dz = dir.z;
+    let a = dx * dx + dz * dz;
+    if a >= 1e-6 {
+        let ox = origin.x - center.x;
+        let oz = origin.z - center.z;
+        let b = 2.0 * (dx * ox + dz * oz);
+        let c = ox * ox + oz * oz - radius * radius;
+        let disc = b * b - 4.0 * a * c;
+        if disc >= 0.0 {
+            let sqrt_disc = disc.sqrt();
+            let mut roots = [(-b - sqrt_disc) / (2.0 * a), (-b + sqrt_disc) / (2.0 * a)];
+            roots.sort_by(|l, r| l.partial_cmp(r).unwrap_or(std::cmp::Ordering::Equal));
+            for t in roots {
+                if t < 0.0 || t > best_t {
+                    continue;
+                }
+                let y = origin.y + dir.y * t;
+                if y >= center.y - half_h - margin && y <= center.y + half_h + margin {
+                    best_t = t;
+                    best = Some(t);
+                    break;
+                }
+            }
+        }
+    }
+
+    // spherical caps
+    let a_s = dir.length_squared();
+    if a_s >= 1e-6 {
+        for cap_sign in [-1.0f32, 1.0f32] {
+            let cap_center = center + Vec3::Y * (cap_sign * half_h);
+            let oc = origin - cap_center;
+            let b = 2.0 * oc.dot(dir);
+            let c = oc.length_squared() - radius * radius;
+            let disc = b * b - 4.0 * a_s * c;
+            if disc < 0.0 {
+                continue;
+            }
+            let sqrt_disc = disc.sqrt();
+            let mut roots = [(-b - sqrt_disc) / (2.0 * a_s), (-b + sqrt_disc) / (2.0 * a_s)];
+            roots.sort_by(|l, r| l.partial_cmp(r).unwrap_or(std::cmp::Ordering::Equal));
+            for t in roots {
+                if t < 0.0 || t > best_t {
+                    continue;
+                }
+                if t <= range {
+                    best_t = t;
+                    best = Some(t);
+                    break;
+                }
+            }
+        }
+    }
+
+    best
+}
+
+fn srv_shoot_and_respawn(
+    mut commands: Commands,
+    time_fixed: Res<Time<Fixed>>,
+    mut players: ResMut<Players>,
+    mut bots: ResMut<Bots>,
+    mut s: ShootRes,
+) {
+    if s.round.phase != RoundPhase::Active {
+        return;
+    }
+    let dt = time_fixed.delta_seconds();
+    // tick weapon timers
+    for (id, w) in s.wpnprot.weapons.0.iter_mut() {
+        if w.cooldown > 0.0 {
+            w.cooldown = (w.cooldown - dt).max(0.0);
+        }
+        if w.reload > 0.0 {
+            w.reload = (w.reload - dt).max(0.0);
+            if w.reload == 0.0 {
+                w.ammo = s.cfg.weapon.mag_size;
+                // notify reload complete
+                let ev = ServerMessage::Event(EventMsg::Ammo {
+                    id: *id,
+                    ammo: w.ammo,
+                    reloading: false,
+                });
+                s.net.send_to(*id, &ev);
+            }
+        }
+    }
+    // immutable snapshot of states for safe iteration (humans + bots)
+    // ローカルエイリアス（既存コードの参照名を維持）
+    let last_fire = &mut s.last_fire;
+    let rapier = &s.rapier;
+    let respawns = &mut s.respawns;
+    let bot_respawns = &mut s.bot_respawns;
+    let wpnprot = &mut s.wpnprot;
+    let ents = &s.ents;
+    let bot_ents = &s.bot_ents;
+    let scores = &mut s.scores;
+    let round = &s.round;
+    let spawns = &s.spawns;
+    let cfg = &s.cfg;
+    let log = &mut s.log;
+
+    let mut snap: Vec<(u64, Vec3, bool)> = players
+        .states
+        .iter()
+        .map(|(id, s)| (*id, s.pos, s.alive))
+        .collect();
+    snap.extend(bots.states.iter().map(|(id, s)| (*id, s.pos, s.alive)));
+    // 当Tickに受領したクライアント由来の射撃をマップへ
+    let mut firemap: std::collections::HashMap<u64, (Vec3, Vec3)> =
+        std::collections::HashMap::new();
+    for (sid, o, d) in s.fires.0.drain(..) {
+        if d.length_squared() > 1e-6 {
+            firemap.insert(sid, (o, d.normalize()));
+        }
+    }
+
+    for (id, pos, alive) in snap.iter().copied() {
+        let Some(inp) = s.last.0.get(&id) else {
+            continue;
+        };
+        // クライアント由来の射撃（origin/dir）があれば優先処理し、以降の通常フローはスキップ
+        if let Some((origin, forward)) = firemap.remove(&id) {
+            let shot_dir = forward.normalize_or_zero();
+            if shot_dir.length_squared() < 1e-6 {
+                continue;
+            }
+            let w = wpnprot.weapons.0.entry(id).or_insert(WeaponStatus {
+                ammo: cfg.weapon.mag_size,
+                cooldown: 0.0,
+                reload: 0.0,
+            });
+            let last_seq = last_fire.0.entry(id).or_insert(0);
+            *last_seq = inp.seq;
+            if w.reload <= 0.0
+                && w.cooldown <= 0.0
+                && wpnprot.protect.0.get(&id).copied().unwrap_or(0.0) <= 0.0
+            {
+                if w.ammo == 0 {
+                    if w.reload <= 0.0 {
+                        w.reload = cfg.weapon.reload_sec;
+                    }
+                    let ev = ServerMessage::Event(EventMsg::Ammo {
+                        id,
+                        ammo: w.ammo,
+                        reloading: true,
+                    });
+                    s.net.send_to(id, &ev);
+                    continue;
+                }
+                w.ammo = w.ammo.saturating_sub(1);
+                w.cooldown = cfg.weapon.fire_cooldown();
+                let ev = ServerMessage::Event(EventMsg::Ammo {
+                    id,
+                    ammo: w.ammo,
+                    reloading: false,
+                });
+                s.net.send_to(id, &ev);
+                // Lag-compensated hit decision (rewind 100ms), with current-world occlusion check
+                let t_query = s.sim.0 - cfg.lag_comp.rewind_sec;
+                let range = 100.0f32;
+                let mut best: Option<(u64, f32)> = None;
+                for (tid, st) in players.states.iter() {
+                    if *tid != id && st.alive {
+                        if let Some(cpos) = rewind_pos(&s.hist, *tid, t_query) {
+                            if let Some(t) = ray_cylinder_hit(
+                                origin,
+                                shot_dir,
+                                range,
+                                cpos,
+                                HIT_HEIGHT_HALF,
+                                HIT_RADIUS,
+                            ) {
+                                if best.map_or(true, |(_, bt)| t < bt) {
+                                    best = Some((*tid, t));
+                                }
+                            }
+                        }
+                    }
+                }
+                for (tid, st) in bots.states.iter() {
+                    if *tid != id && st.alive {
+                        if let Some(cpos) = rewind_pos(&s.hist, *tid, t_query) {
+                            if let Some(t) = ray_cylinder_hit(
+                                origin,
+                                shot_dir,
+                                range,
+                                cpos,
+                                HIT_HEIGHT_HALF,
+                                HIT_RADIUS,
+                            ) {
+                                if best.map_or(true, |(_, bt)| t < bt) {
+                                    best = Some((*tid, t));
+                                }
+                            }
+                        }
+                    }
+                }
+                let mut hit_point: Option<[f32; 3]> = None;
+                let mut hit_id_opt: Option<u64> = None;
+                if let Some((hid, t_hit)) = best {
+                    let mut filter = QueryFilter::default();
+                    if let Some(&self_ent) = ents.0.get(&id) {
+                        filter = filter.exclude_collider(self_ent);
+                    }
+                    let max_toi = (t_hit + HIT_OCCLUSION_EPS).min(range);
+                    if let Some((hit_ent, toi_phys)) =
+                        rapier.cast_ray(origin, shot_dir, max_toi, true, filter)
+                    {
+                        let target_ent_h = ents.0.get(&hid).copied();
+                        let target_ent_b = bot_ents.0.get(&hid).copied();
+                        if Some(hit_ent) == target_ent_h || Some(hit_ent) == target_ent_b {
+                            let impact_t = toi_phys.min(t_hit);
+                            hit_id_opt = Some(hid);
+                            hit_point = Some([
+                                origin.x + shot_dir.x * impact_t,
+                                origin.y + shot_dir.y * impact_t,
+                                origin.z + shot_dir.z * impact_t,
+                            ]);
+                        } else {
+                            log_occlusion_block(
+                                log,
+                                cfg.log.occlusion,
+                                "client-fire blocked by other collider",
+                                id,
+                                hid,
+                                origin,
+                                shot_dir,
+                                t_hit,
+                                Some((hit_ent, toi_phys)),
+                            );
+                        }
+                    } else {
+                        log_occlusion_block(
+                            log,
+                            cfg.log.occlusion,
+                            "client-fire occlusion ray missed",
+                            id,
+                            hid,
+                            origin,
+                            shot_dir,
+                            t_hit,
+                            None,
+                        );
+                    }
+                }
+                let ev = ServerMessage::Event(EventMsg::Fire {
+                    id,
+                    origin: [origin.x, origin.y, origin.z],
+                    dir: [shot_dir.x, shot_dir.y, shot_dir.z],
+                    hit: hit_point,
+                });
+                s.net.send_relevant(&[id], &ev);
+                log.record(MatchEvent::Fire {
+                    id,
+                    origin: pos3(origin),
+                    dir: pos3(shot_dir),
+                    hit: hit_point,
+                });
+                if let Some(hit_id) = hit_id_opt {
+                    if wpnprot.protect.0.get(&hit_id).copied().unwrap_or(0.0) <= 0.0 {
+                        if let Some(hit) = players.states.get_mut(&hit_id) {
+                            if hit.alive {
+                                let dmg = cfg.weapon.damage;
+                                hit.hp = hit.hp.saturating_sub(dmg);
+                                let ev = ServerMessage::Event(EventMsg::Hit {
+                                    target_id: hit_id,
+                                    new_hp: hit.hp,
+                                    by: id,
+                                });
+                                s.net.send_relevant(&[hit_id, id], &ev);
+                                log.record(MatchEvent::Hit {
+                                    target: hit_id,
+                                    by: id,
+                                    hp: hit.hp,
+                                    pos: pos3(hit.pos),
+                                });
+                                if hit.hp == 0 {
+                                    hit.alive = false;
+                                    let ev = ServerMessage::Event(EventMsg::Death {
+                                        target_id: hit_id,
+                                        by: id,
+                                    });
+                                    s.net.broadcast(&ev);
+                                    log.record(MatchEvent::Death {
+                                        target: hit_id,
+                                        by: id,
+                                        pos: pos3(hit.pos),
+                                    });
+                                    respawns.0.insert(hit_id, cfg.spawn.respawn_sec);
+                                    if players.states.contains_key(&id) {
+                                        let e = scores.0.entry(id).or_insert((0, 0));
+                                        e.0 = e.0.saturating_add(1);
+                                    }
+                                    let e2 = scores.0.entry(hit_id).or_insert((0, 0));
+                                    e2.1 = e2.1.saturating_add(1);
+                                    s.net.broadcast(&ServerMessage::Score(score_table(&scores)));
+                                }
+                            }
+                        } else if let Some(hit) = bots.states.get_mut(&hit_id) {
+                            if hit.alive {
+                                let dmg = cfg.weapon.damage;
+                                hit.hp = hit.hp.saturating_sub(dmg);
+                                let ev = ServerMessage::Event(EventMsg::Hit {
+                                    target_id: hit_id,
+                                    new_hp: hit.hp,
+                                    by: id,
+                                });
+                                s.net.send_relevant(&[hit_id, id], &ev);
+                                log.record(MatchEvent::Hit {
+                                    target: hit_id,
+                                    by: id,
+                                    hp: hit.hp,
+                                    pos: pos3(hit.pos),
+                                });
+                                if hit.hp == 0 {
+                                    hit.alive = false;
+                                    let ev = ServerMessage::Event(EventMsg::Death {
+                                        target_id: hit_id,
+                                        by: id,
+                                    });
+                                    s.net.broadcast(&ev);
+                                    log.record(MatchEvent::Death {
+                                        target: hit_id,
+                                        by: id,
+                                        pos: pos3(hit.pos),
+                                    });
+                                    bot_respawns.0.insert(hit_id, cfg.spawn.respawn_sec);
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+            continue;
+        }
+        let w = wpnprot.weapons.0.entry(id).or_insert(WeaponStatus {
+            ammo: cfg.weapon.mag_size,
+            cooldown: 0.0,
+            reload: 0.0,
+        });
+        let last_seq = last_fire.0.entry(id).or_insert(0);
+        if inp.fire && inp.seq != *last_seq && alive {
+            *last_seq = inp.seq;
+            // Can fire?
+            if w.reload > 0.0 || w.cooldown > 0.0 {
+                continue;
+            }
+            // 保護中は発砲不可
+            if wpnprot.protect.0.get(&id).copied().unwrap_or(0.0) > 0.0 {
+                continue;
+            }
+            if w.ammo == 0 {
+                // start reload
+                if w.reload <= 0.0 {
+                    w.reload = cfg.weapon.reload_sec;
+                }
+                let ev = ServerMessage::Event(EventMsg::Ammo {
+                    id,
+                    ammo: w.ammo,
+                    reloading: true,
+                });
+                s.net.send_to(id, &ev);
+                continue;
+            }
+            // consume ammo and set cooldown
+            w.ammo = w.ammo.saturating_sub(1);
+            w.cooldown = cfg.weapon.fire_cooldown();
+            let ev = ServerMessage::Event(EventMsg::Ammo {
+                id,
+                ammo: w.ammo,
+                reloading: false,
+            });
+            s.net.send_to(id, &ev);
+            let yaw_rot = Quat::from_rotation_y(inp.yaw);
+            let pitch_rot = Quat::from_rotation_x(inp.pitch);
+            let forward = yaw_rot * pitch_rot * Vec3::NEG_Z;
+            let origin = pos + Vec3::new(0.0, 0.7, 0.0);
+            let range = 100.0f32;
+            // Lag-compensated Fire event point (rewind 100ms)
+            let t_query = s.sim.0 - cfg.lag_comp.rewind_sec;
+            let mut best_t: Option<f32> = None;
+            for (tid, st) in players.states.iter() {
+                if *tid != id && st.alive {
+                    if let Some(cpos) = rewind_pos(&s.hist, *tid, t_query) {
+                        if let Some(t) = ray_cylinder_hit(
+                            origin,
+                            forward,
+                            range,
+                            cpos,
+                            HIT_HEIGHT_HALF,
+                            HIT_RADIUS,
+                        ) {
+                            if best_t.map_or(true, |bt| t < bt) {
+                                best_t = Some(t);
+                            }
+                        }
+                    }
+                }
+            }
+            for (tid, st) in bots.states.iter() {
+                if *tid != id && st.alive {
+                    if let Some(cpos) = rewind_pos(&s.hist, *tid, t_query) {
+                        if let Some(t) = ray_cylinder_hit(
+                            origin,
+                            forward,
+                            range,
+                            cpos,
+                            HIT_HEIGHT_HALF,
+                            HIT_RADIUS,
+                        ) {
+                            if best_t.map_or(true, |bt| t < bt) {
+                                best_t = Some(t);
+                            }
+                        }
+                    }
+                }
+            }
+            let hit_opt = best_t.map(|t| {
+                [
+                    origin.x + forward.x * t,
+                    origin.y + forward.y * t,
+                    origin.z + forward.z * t,
+                ]
+            });
+            let ev = ServerMessage::Event(EventMsg::Fire {
+                id,
+                origin: [origin.x, origin.y, origin.z],
+                dir: [forward.x, forward.y, forward.z],
+                hit: hit_opt,
+            });
+            s.net.send_relevant(&[id], &ev);
+            log.record(MatchEvent::Fire {
+                id,
+                origin: pos3(origin),
+                dir: pos3(forward),
+                hit: hit_opt,
+            });
+            let mut best: Option<(u64, f32)> = None;
+            for (oid, opos, oalive) in snap.iter().copied() {
+                if oid == id || !oalive {
+                    continue;
+                }
+                let target = opos + Vec3::new(0.0, 0.7, 0.0);
+                let t = (target - origin).dot(forward).clamp(0.0, range);
+                let closest = origin + forward * t;
+                let dist2 = (target - closest).length_squared();
+                if dist2 <= 0.35f32 * 0.35f32 {
+                    if best.map_or(true, |(_, bt)| t < bt) {
+                        best = Some((oid, t));
+                    }
+                }
+            }
+            if let Some((hit_id, t_hit)) = best {
+                // 保護中の対象は無効
+                if wpnprot.protect.0.get(&hit_id).copied().unwrap_or(0.0) > 0.0 {
+                    continue;
+                }
+                // 射線上の障害物チェック（自分自身のコライダーは除外）
+                let mut filter = QueryFilter::default();
+                if let Some(&self_ent) = ents.0.get(&id) {
+                    filter = filter.exclude_collider(self_ent);
+                }
+                if players.states.contains_key(&hit_id) {
+                    let max_toi = (t_hit + HIT_OCCLUSION_EPS).min(range);
+                    if let Some((hit_ent, toi_phys)) =
+                        rapier.cast_ray(origin, forward, max_toi, true, filter)
+                    {
+                        // 射線上の障害物チェック（自分自身のコライダーは除外）
+                        let target_ent = ents.0.get(&hit_id).copied();
+                        let target_ent_bot = bot_ents.0.get(&hit_id).copied();
+                        if Some(hit_ent) != target_ent && Some(hit_ent) != target_ent_bot {
+                            log_occlusion_block(
+                                log,
+                                cfg.log.occlusion,
+                                "server-check blocked by other collider",
+                                id,
+                                hit_id,
+                                origin,
+                                forward,
+                                t_hit,
+                                Some((hit_ent, toi_phys)),
+                            );
+                            continue;
+                        }
+                    } else {
+                        log_occlusion_block(
+                            log,
+                            cfg.log.occlusion,
+                            "server-check occlusion ray missed",
+                            id,
+                            hit_id,
+                            origin,
+                            forward,
+                            t_hit,
+                            None,
+                        );
+                        continue;
+                    }
+                }
+                if let Some(hit) = players.states.get_mut(&hit_id) {
+                    if hit.alive {
+                        let dmg = cfg.weapon.damage;
+                        hit.hp = hit.hp.saturating_sub(dmg);
+                        let ev = ServerMessage::Event(EventMsg::Hit {
+                            target_id: hit_id,
+                            new_hp: hit.hp,
+                            by: id,
+                        });
+                        s.net.send_relevant(&[hit_id, id], &ev);
+                        log.record(MatchEvent::Hit {
+                            target: hit_id,
+                            by: id,
+                            hp: hit.hp,
+                            pos: pos3(hit.pos),
+                        });
+                        if hit.hp == 0 {
+                            hit.alive = false;
+                            let ev = ServerMessage::Event(EventMsg::Death {
+                                target_id: hit_id,
+                                by: id,
+                            });
+                            s.net.broadcast(&ev);
+                            log.record(MatchEvent::Death {
+                                target: hit_id,
+                                by: id,
+                                pos: pos3(hit.pos),
+                            });
+                            respawns.0.insert(hit_id, cfg.spawn.respawn_sec);
+                            // update scores and broadcast（人間のみスコア集計）
+                            if players.states.contains_key(&id) {
+                                let e = scores.0.entry(id).or_insert((0, 0));
+                                e.0 = e.0.saturating_add(1);
+                            }
+                            if players.states.contains_key(&hit_id) {
+                                let e2 = scores.0.entry(hit_id).or_insert((0, 0));
+                                e2.1 = e2.1.saturating_add(1);
+                            }
+                            s.net.broadcast(&ServerMessage::Score(score_table(&scores)));
+                            // auto reload on kill if empty and not already reloading
+                            let ww = wpnprot.weapons.0.entry(id).or_insert(WeaponStatus {
+                                ammo: cfg.weapon.mag_size,
+                                cooldown: 0.0,
+                                reload: 0.0,
+                            });
+                            if ww.ammo == 0 && ww.reload <= 0.0 {
+                                ww.reload = cfg.weapon.reload_sec;
+                                let ev = ServerMessage::Event(EventMsg::Ammo {
+                                    id,
+                                    ammo: ww.ammo,
+                                    reloading: true,
+                                });
+                                s.net.send_to(id, &ev);
+                            }
+                        }
+                    }
+                } else if let Some(hit) = bots.states.get_mut(&hit_id) {
+                    if hit.alive {
+                        let dmg = cfg.weapon.damage;
+                        hit.hp = hit.hp.saturating_sub(dmg);
+                        let ev = ServerMessage::Event(EventMsg::Hit {
+                            target_id: hit_id,
+                            new_hp: hit.hp,
+                            by: id,
+                        });
+                        s.net.send_relevant(&[hit_id, id], &ev);
+                        log.record(MatchEvent::Hit {
+                            target: hit_id,
+                            by: id,
+                            hp: hit.hp,
+                            pos: pos3(hit.pos),
+                        });
+                        if hit.hp == 0 {
+                            hit.alive = false;
+                            let ev = ServerMessage::Event(EventMsg::Death {
+                                target_id: hit_id,
+                                by: id,
+                            });
+                            s.net.broadcast(&ev);
+                            log.record(MatchEvent::Death {
+                                target: hit_id,
+                                by: id,
+                                pos: pos3(hit.pos),
+                            });
+                            bot_respawns.0.insert(hit_id, cfg.spawn.respawn_sec);
+                        }
+                    }
+                }
+            }
+        }
+    }
+    // 保護タイマー更新
+    for t in wpnprot.protect.0.values_mut() {
+        if *t > 0.0 {
+            *t = (*t - dt).max(0.0);
+        }
+    }
+    // respawn countdown
+    let mut to_spawn = Vec::new();
+    for (pid, t) in respawns.0.iter_mut() {
+        *t -= dt;
+        if *t <= 0.0 {
+            to_spawn.push(*pid);
+        }
+    }
+    for pid in to_spawn {
+        respawns.0.remove(&pid);
+        let spawn = choose_spawn_point(&spawns, &players);
+        if let Some(p) = players.states.get_mut(&pid) {
+            p.alive = true;
+            p.hp = 100;
+            p.pos = spawn;
+            p.motion = MoveState::standing();
+            if let Some(&e) = ents.0.get(&pid) {
+                commands.entity(e).insert(TransformBundle::from_transform(
+                    Transform::from_translation(spawn),
+                ));
+            }
+            // リスポーン保護
+            wpnprot.protect.0.insert(pid, cfg.spawn.protect_sec);
+            let ev = ServerMessage::Event(EventMsg::Spawn {
+                id: pid,
+                pos: [p.pos.x, p.pos.y, p.pos.z],
+                kind: ActorKind::Human,
+            });
+            s.net.send_relevant(&[pid], &ev);
+        }
+        // reset weapon
+        let w = wpnprot
+            .weapons
+            .0
+            .entry(pid)
+            .or_insert(WeaponStatus::default());
+        *w = WeaponStatus {
+            ammo: cfg.weapon.mag_size,
+            cooldown: 0.0,
+            reload: 0.0,
+        };
+        let ev = ServerMessage::Event(EventMsg::Ammo {
+            id: pid,
+            ammo: cfg.weapon.mag_size,
+            reloading: false,
+        });
+        s.net.send_to(pid, &ev);
+    }
+}
+
+// Build static colliders for any meshes loaded from the map scene
+fn add_mesh_colliders_for_map(
+    mut commands: Commands,
+    meshes: Res<Assets<Mesh>>,
+    mut ready: ResMut<MapReady>,
+    q: Query<(Entity, &Handle<Mesh>), (Added<Handle<Mesh>>, Without<Collider>)>,
+) {
+    let mut any_inserted = false;
+    for (e, h) in &q {
+        if let Some(mesh) = meshes.get(h) {
+            if let Some(collider) = Collider::from_bevy_mesh(mesh, &ComputedColliderShape::TriMesh)
+            {
+                commands.entity(e).insert((collider, RigidBody::Fixed));
+                any_inserted = true;
+            }
+        }
+    }
+    if any_inserted && !ready.0 {
+        ready.0 = true;
+        info!("Map colliders ready (server)");
+    }
+}
+
+// Fallback: ensure Players map stays in sync with current connections.
+// This covers cases where ServerEvent is not observed in this schedule ordering.
+fn sync_players_with_connections(
+    mut commands: Commands,
+    mut net: NetSend,
+    mut players: ResMut<Players>,
+    mut ents: ResMut<ServerEntities>,
+    mut scores: ResMut<Scores>,
+    spawns: Res<SpawnPoints>,
+    mut weapons: ResMut<Weapons>,
+    handshakes: Res<ClientHandshakes>,
+    mut profiles: ResMut<PlayerProfiles>,
+    cfg: Res<ServerConfig>,
+    mut log: ResMut<MatchLog>,
+    mut reconnect: Reconnect,
+) {
+    use std::collections::HashSet;
+    let current: HashSet<u64> = net.server.clients_id().iter().map(|c| c.raw()).collect();
+
+    // Add missing players for newly connected clients (handshake passed only)
+    for id in current.iter().copied() {
+        if !players.states.contains_key(&id) && handshakes.0.contains_key(&id) {
+            let spawn = choose_spawn_point(&spawns, &players);
+            players.states.insert(
+                id,
+                PlayerState {
+                    pos: spawn,
+                    yaw: 0.0,
+                    hp: 100,
+                    alive: true,
+                    motion: MoveState::standing(),
+                },
+            );
+            let ent = commands
+                .spawn((
+                    TransformBundle::from_transform(Transform::from_translation(spawn)),
+                    Collider::capsule_y(0.6, 0.3),
+                    KinematicCharacterController::default(),
+                ))
+                .id();
+            ents.0.insert(id, ent);
+            info!(
+                "server: sync add player {} (total={})",
+                id,
+                players.states.len()
+            );
+            let ev = ServerMessage::Event(EventMsg::Spawn {
+                id,
+                pos: [spawn.x, spawn.y, spawn.z],
+                kind: ActorKind::Human,
+            });
+            net.send_relevant(&[id], &ev);
+            scores.0.entry(id).or_insert((0, 0));
+            // init weapon and notify
+            let w = WeaponStatus {
+                ammo: cfg.weapon.mag_size,
+                cooldown: 0.0,
+                reload: 0.0,
+            };
+            weapons.0.insert(id, w);
+            let ev = ServerMessage::Event(EventMsg::Ammo {
+                id,
+                ammo: cfg.weapon.mag_size,
+                reloading: false,
+            });
+            net.send_to(id, &ev);
+            net.broadcast(&ServerMessage::Score(score_table(&scores)));
+        }
+    }
+
+    // Remove players for disconnected clients
+    let known: Vec<u64> = players.states.keys().copied().collect();
+    for id in known {
+        if !current.contains(&id) {
+            let Some(state) = players.states.remove(&id) else {
+                continue;
+            };
+            if let Some(e) = ents.0.remove(&id) {
+                commands.entity(e).despawn_recursive();
+            }
+            info!(
+                "server: sync remove player {} (total={})",
+                id,
+                players.states.len()
+            );
+            let ev = ServerMessage::Event(EventMsg::Despawn { id });
+            net.broadcast(&ev);
+            log.record(MatchEvent::Disconnect {
+                id,
+                reason: "connection closed".into(),
+            });
+            let score = scores.0.remove(&id).unwrap_or((0, 0));
+            let weapon = weapons.0.remove(&id);
+            let account_id = profiles.0.remove(&id).map_or(0, |p| p.account_id);
+            let grace = cfg.spawn.reconnect_grace_sec;
+            if reconnect.park(account_id, state, score, weapon, id, grace) {
+                info!(
+                    "session for account {} parked for {:.0}s",
+                    account_id, grace
+                );
+            }
+        }
+    }
+}
+
+fn collect_spawn_points_from_map(
+    mut spawns: ResMut<SpawnPoints>,
+    q: Query<(&GlobalTransform, Option<&Name>), Added<GlobalTransform>>,
+    cfg: Res<ServerConfig>,
+) {
+    if !cfg.spawn.use_spawn_points {
+        return;
+    }
+    let mut added = 0;
+    for (gt, name) in &q {
+        if let Some(n) = name {
+            let s = n.as_str();
+            if s.starts_with("spawn")
+                || s.starts_with("Spawn")
+                || s.starts_with("SPAWN")
+                || s.starts_with("spawn_")
+            {
+                let p = gt.translation();
+                spawns.0.push(p);
+                added += 1;
+            }
+        }
+    }
+    if added > 0 {
+        info!(
+            "Map spawn points collected: +{} (total={})",
+            added,
+            spawns.0.len()
+        );
+    }
+}
+
+fn choose_spawn_point(spawns: &SpawnPoints, players: &Players) -> Vec3 {
+    // spawn.use_spawn_points = false なら収集されず空のまま
+    if spawns.0.is_empty() {
+        return DEFAULT_SPAWN_POS;
+    }
+    let mut best_pos = spawns.0[0];
+    let mut best_score = f32::MIN;
+    for &p in &spawns.0 {
+        let mut mind = f32::INFINITY;
+        for (_id, s) in players.states.iter() {
+            if s.alive {
+                let d = s.pos.distance(p);
+                if d < mind {
+                    mind = d;
+                }
+            }
+        }
+        if mind > best_score {
+            best_score = mind;
+            best_pos = p;
+        }
+    }
+    best_pos
+}
+
+fn round_update(
+    time_fixed: Res<Time<Fixed>>,
+    mut round: ResMut<RoundState>,
+    mut scores: ResMut<Scores>,
+    mut players: ResMut<Players>,
+    mut net: NetSend,
+    mut respawns: ResMut<RespawnTimers>,
+    spawns: Res<SpawnPoints>,
+    mut cfg: ResMut<ServerConfig>,
+    mut pending: ResMut<PendingRound>,
+    mut log: ResMut<MatchLog>,
+    mut parked: ResMut<ParkedSessions>,
+) {
+    let dt = time_fixed.delta_seconds();
+    match round.phase {
+        RoundPhase::Active => {
+            round.time_left -= dt;
+            // 勝利条件チェック
+            let mut winner: Option<u64> = None;
+            for (id, (k, _d)) in scores.0.iter() {
+                if *k >= cfg.round.win_kills {
+                    winner = Some(*id);
+                    break;
+                }
+            }
+            if winner.is_some() || round.time_left <= 0.0 {
+                // 終了を通知
+                let ev = ServerMessage::Event(EventMsg::RoundEnd {
+                    winner_id: winner,
+                    next_in_sec: cfg.round.end_delay_sec as u32,
+                });
+                net.broadcast(&ev);
+                let mut table: Vec<(u64, u32, u32)> =
+                    scores.0.iter().map(|(id, (k, d))| (*id, *k, *d)).collect();
+                table.sort_unstable();
+                log.record(MatchEvent::RoundEnd {
+                    winner,
+                    scores: table,
+                });
+                round.phase = RoundPhase::Ending;
+                round.end_timer = cfg.round.end_delay_sec;
+            }
+        }
+        RoundPhase::Ending => {
+            round.end_timer -= dt;
+            if round.end_timer <= 0.0 {
+                // リセット: スコア、プレイヤー状態、リスポーン
+                // scores は accept/sync で再周知されるため、ここでクリア
+                // ただし現状の実装ではスコア配布のために空配列を通知
+                // プレイヤーを全員リスポーン
+                let ids: Vec<u64> = players.states.keys().copied().collect();
+                for id in ids {
+                    let spawn = choose_spawn_point(&spawns, &players);
+                    if let Some(state) = players.states.get_mut(&id) {
+                        state.alive = true;
+                        state.hp = 100;
+                        state.pos = spawn;
+                        state.motion = MoveState::standing();
+                        // 送信
+                        let ev = ServerMessage::Event(EventMsg::Spawn {
+                            id,
+                            pos: [state.pos.x, state.pos.y, state.pos.z],
+                            kind: ActorKind::Human,
+                        });
+                        net.send_relevant(&[id], &ev);
+                    }
+                }
+                respawns.0.clear();
+                parked.clear();
+                // スコアをゼロクリア
+                // 既存のキーを維持して0にする
+                for (_id, kd) in scores.0.iter_mut() {
+                    *kd = (0, 0);
+                }
+                net.broadcast(&ServerMessage::Score(score_table(&scores)));
+                // 再読み込みで保留していたラウンド設定はここから有効
+                if let Some(next) = pending.0.take() {
+                    cfg.round = next;
+                }
+                // ラウンド開始通知
+                round.phase = RoundPhase::Active;
+                round.time_left = cfg.round.time_sec;
+                let ev = ServerMessage::Event(EventMsg::RoundStart {
+                    time_left_sec: round.time_left as u32,
+                });
+                net.broadcast(&ev);
+                log.record(MatchEvent::RoundStart {
+                    time_sec: round.time_left,
+                });
+            }
+        }
+    }
+}
+
+// 粗い遮蔽判定: 視点から対象の頭/胴へのレイのどちらかが通れば可視
+fn has_line_of_sight(
+    rapier: &RapierContext,
+    ents: &ServerEntities,
+    viewer: u64,
+    eye: Vec3,
+    target: u64,
+    target_pos: Vec3,
+) -> bool {
+    let mut filter = QueryFilter::default();
+    if let Some(&self_ent) = ents.0.get(&viewer) {
+        filter = filter.exclude_collider(self_ent);
+    }
+    let target_ent = ents.0.get(&target).copied();
+    for p in [target_pos + Vec3::new(0.0, 0.7, 0.0), target_pos] {
+        let to = p - eye;
+        let dist = to.length();
+        if dist < 1e-3 {
+            return true;
+        }
+        match rapier.cast_ray(eye, to / dist, dist, true, filter) {
+            None => return true,
+            Some((hit_ent, _)) if Some(hit_ent) == target_ent => return true,
+            _ => {}
+        }
+    }
+    false
+}
+
+// クライアントごとの関心範囲を更新し、出入りを ActorEnter/Leave・ScaffoldSpawn/Despawn で通知
+fn update_relevancy(
+    time_fixed: Res<Time<Fixed>>,
+    mut rel: ResMut<Relevancy>,
+    mut server: ResMut<RenetServer>,
+    players: Res<Players>,
+    bots: Res<Bots>,
+    scaffolds: Res<Scaffolds>,
+    ents: Res<ServerEntities>,
+    rapier: Res<RapierContext>,
+    mut stats: ResMut<NetStats>,
+) {
+    let dt = time_fixed.delta_seconds();
+    // 切断済みクライアントの状態を破棄
+    rel.clients.retain(|id, _| players.states.contains_key(id));
+    let mut actors: Vec<(u64, Vec3, ActorKind)> = players
+        .states
+        .iter()
+        .map(|(id, s)| (*id, s.pos, ActorKind::Human))
+        .collect();
+    actors.extend(
+        bots.states
+            .iter()
+            .map(|(id, s)| (*id, s.pos, ActorKind::Bot)),
+    );
+    rel.timer -= dt;
+    let reevaluate = rel.timer <= 0.0;
+    if reevaluate {
+        rel.timer = RELEVANCY_INTERVAL_SEC;
+    }
+    let mut out: Vec<(u64, EventMsg)> = Vec::new();
+    for (cid, viewer) in players.states.iter() {
+        let cr = rel.clients.entry(*cid).or_default();
+        // 消えたアクターは Despawn 済みなので通知せず外す
+        cr.actors
+            .retain(|id, _| actors.iter().any(|(aid, _, _)| aid == id));
+        if reevaluate {
+            let eye = viewer.pos + Vec3::new(0.0, 0.7, 0.0);
+            for (aid, apos, kind) in actors.iter() {
+                if aid == cid {
+                    continue;
+                }
+                let slack = if cr.actors.contains_key(aid) {
+                    RELEVANCY_HYSTERESIS
+                } else {
+                    0.0
+                };
+                let dist = eye.distance(*apos);
+                let relevant = dist <= RELEVANCY_NEAR + slack
+                    || (dist <= RELEVANCY_FAR + slack
+                        && has_line_of_sight(&rapier, &ents, *cid, eye, *aid, *apos));
+                if relevant {
+                    if cr.actors.insert(*aid, RELEVANCY_LINGER_SEC).is_none() {
+                        out.push((
+                            *cid,
+                            EventMsg::ActorEnter {
+                                id: *aid,
+                                pos: [apos.x, apos.y, apos.z],
+                                kind: *kind,
+                            },
+                        ));
+                    }
+                } else if let Some(t) = cr.actors.get_mut(aid) {
+                    *t -= RELEVANCY_INTERVAL_SEC;
+                    if *t <= 0.0 {
+                        cr.actors.remove(aid);
+                        out.push((*cid, EventMsg::ActorLeave { id: *aid }));
+                    }
+                }
+            }
+        }
+        // 足場は毎tick（設置直後に所有者へ届くように）
+        for (sid, (owner, spos)) in scaffolds.by_id.iter() {
+            let slack = if cr.scaffolds.contains(sid) {
+                RELEVANCY_HYSTERESIS
+            } else {
+                0.0
+            };
+            if viewer.pos.distance(*spos) <= SCAFFOLD_RELEVANCY_RADIUS + slack {
+                if cr.scaffolds.insert(*sid) {
+                    out.push((
+                        *cid,
+                        EventMsg::ScaffoldSpawn {
+                            sid: *sid,
+                            owner: *owner,
+                            pos: [spos.x, spos.y, spos.z],
+                        },
+                    ));
+                }
+            } else if cr.scaffolds.remove(sid) {
+                out.push((*cid, EventMsg::ScaffoldDespawn { sid: *sid }));
+            }
+        }
+        let gone: Vec<u64> = cr
+            .scaffolds
+            .iter()
+            .filter(|sid| !scaffolds.by_id.contains_key(sid))
+            .copied()
+            .collect();
+        for sid in gone {
+            cr.scaffolds.remove(&sid);
+            out.push((*cid, EventMsg::ScaffoldDespawn { sid }));
+        }
+    }
+    for (cid, ev) in out {
+        if let Ok(bytes) = bincode::serialize(&ServerMessage::Event(ev)) {
+            stats.record(CH_RELIABLE, bytes.len());
+            let _ = server.send_message(ClientId::from_raw(cid), CH_RELIABLE, bytes);
+        }
+    }
+}
+
+#[derive(Resource)]
+struct SnapshotTimer(Timer);
+
+#[derive(Resource)]
+struct ServerLogTimer(Timer);
+
+#[derive(Resource, Default)]
+struct SnapshotSeq(u32);
+
+// 差分スナップショット用: クライアントごとの送信履歴と、ACK 済み tick
+#[derive(Default)]
+struct ClientBaseline {
+    sent: VecDeque<(u32, Vec<PlayerStateMsg>)>,
+    acked: Option<u32>,
+}
+
+#[derive(Resource, Default)]
+struct SnapshotBaselines(HashMap<u64, ClientBaseline>);
+
+const SNAPSHOT_HISTORY: usize = 32; // 送信履歴の保持数（~1s @30Hz）。これより古い ACK は全量送信に戻る
+
+fn broadcast_snapshots(
+    time_fixed: Res<Time<Fixed>>,
+    mut timer: ResMut<SnapshotTimer>,
+    mut net: NetSend,
+    players: Res<Players>,
+    bots: Res<Bots>,
+    last: Res<LastInputs>,
+    mut seq: ResMut<SnapshotSeq>,
+    handshakes: Res<ClientHandshakes>,
+    mut baselines: ResMut<SnapshotBaselines>,
+    cfg: Res<ServerConfig>,
+) {
+    timer.0.tick(time_fixed.delta());
+    if !timer.0.finished() {
+        return;
+    }
+    let players_vec = actor_states(&players, &bots);
+    if cfg.log.snapshot_actors {
+        info!("server: snapshot actors={}", players_vec.len());
+    }
+    let tick = {
+        seq.0 = seq.0.wrapping_add(1);
+        seq.0
+    };
+    for client_id in net.server.clients_id() {
+        let cid = client_id.raw();
+        // 関心範囲内のアクターのみ載せる
+        let visible: Vec<PlayerStateMsg> = players_vec
+            .iter()
+            .filter(|p| net.relevancy.sees(cid, p.id))
+            .cloned()
+            .collect();
+        // ACK は受信者自身の分だけ載せる
+        let acks: Vec<(u64, u32)> = last
+            .0
+            .get(&cid)
+            .map(|inp| vec![(cid, inp.seq)])
+            .unwrap_or_default();
+        let snap = SnapshotMsg {
+            tick,
+            players: visible,
+            acks,
+        };
+        let use_delta = handshakes
+            .0
+            .get(&cid)
+            .is_some_and(|h| h.caps & caps::DELTA_SNAPSHOTS != 0);
+        if !use_delta {
+            net.send_on(cid, CH_SNAPSHOT, &ServerMessage::Snapshot(snap));
+            continue;
+        }
+        let bl = baselines.0.entry(cid).or_default();
+        // ACK 済みの基準が履歴に残っていれば差分、無ければ全量
+        let base = bl
+            .acked
+            .and_then(|t| bl.sent.iter().find(|(st, _)| *st == t));
+        let msg = match base {
+            Some((base_tick, base_players)) => {
+                ServerMessage::DeltaSnapshot(diff_snapshot(*base_tick, base_players, &snap))
+            }
+            None => ServerMessage::Snapshot(snap.clone()),
+        };
+        net.send_on(cid, CH_SNAPSHOT, &msg);
+        bl.sent.push_back((tick, snap.players));
+        while bl.sent.len() > SNAPSHOT_HISTORY {
+            bl.sent.pop_front();
+        }
+    }
+}
+
+// 全アクター（人間 + ボット）の現在状態。関心範囲での絞り込みは呼び出し側で行う
+fn actor_states(players: &Players, bots: &Bots) -> Vec<PlayerStateMsg> {
+    let mut out: Vec<PlayerStateMsg> = players
+        .states
+        .iter()
+        .map(|(id, s)| PlayerStateMsg {
+            id: *id,
+            pos: [s.pos.x, s.pos.y, s.pos.z],
+            yaw: s.yaw,
+            alive: s.alive,
+            hp: s.hp,
+            vy: s.motion.vy,
+            grounded: s.motion.grounded,
+            kind: ActorKind::Human,
+        })
+        .collect();
+    out.extend(bots.states.iter().map(|(id, s)| PlayerStateMsg {
+        id: *id,
+        pos: [s.pos.x, s.pos.y, s.pos.z],
+        yaw: s.yaw,
+        alive: s.alive,
+        hp: s.hp,
+        vy: s.vy,
+        grounded: s.grounded,
+        kind: ActorKind::Bot,
+    }));
+    out
+}
+
+fn score_table(scores: &Scores) -> Vec<ScoreEntry> {
+    scores
+        .0
+        .iter()
+        .map(|(id, (k, d))| ScoreEntry {
+            id: *id,
+            kills: *k,
+            deaths: *d,
+        })
+        .collect()
+}
+
+fn log_clients_count(time: Res<Time>, mut timer: ResMut<ServerLogTimer>, server: Res<RenetServer>) {
+    timer.0.tick(time.delta());
+    if timer.0.finished() {
+        let n = server.clients_id().len();
+        info!("server: clients={} ", n);
+    }
+}
@@ -0,0 +1,184 @@
+// ===== デモ録画 =====
+// tick ごとの全アクター状態（関心範囲で絞らない）と、クライアントへ配ったイベントを crate::demo 形式で書き出す。
+// イベントは NetSend の broadcast / send_relevant を 1 回ずつ記録する（send_to の個人宛ては残さない）。
+use super::*;
+use crate::demo::{DemoFrame, DemoHeader, DemoWriter, DEMO_KEYFRAME_TICKS};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+struct Recording {
+    writer: DemoWriter<BufWriter<File>>,
+    tick: u32,
+    last: Option<(u32, Vec<PlayerStateMsg>)>, // 直前フレームの状態（差分の基準）
+    scaffolds: HashSet<u64>,
+    rules: Option<RulesMsg>,
+}
+
+#[derive(Resource, Default)]
+pub struct DemoRecorder {
+    rec: Option<Recording>,
+    pending: Vec<ServerMessage>, // 次のフレームに載せるイベント
+}
+
+impl DemoRecorder {
+    // 録画を開始する。ヘッダには開始時のマップと設定を残す
+    pub fn create(
+        path: impl AsRef<Path>,
+        map: Option<String>,
+        config: &ServerConfig,
+        tick_hz: f64,
+    ) -> Result<Self, String> {
+        let path = path.as_ref();
+        let header = DemoHeader {
+            protocol_version: PROTOCOL_VERSION,
+            map,
+            config: toml::to_string(config).map_err(|e| e.to_string())?,
+            tick_hz,
+            started_unix_ms: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_millis() as u64),
+        };
+        let file =
+            File::create(path).map_err(|e| format!("cannot create {}: {}", path.display(), e))?;
+        let writer = DemoWriter::new(BufWriter::new(file), &header)
+            .map_err(|e| format!("cannot write {}: {}", path.display(), e))?;
+        Ok(Self {
+            rec: Some(Recording {
+                writer,
+                tick: 0,
+                last: None,
+                scaffolds: HashSet::new(),
+                rules: None,
+            }),
+            pending: Vec::new(),
+        })
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.rec.is_some()
+    }
+
+    // 書き残しを出してファイルを閉じる（以降は何も記録しない）
+    pub fn finish(&mut self) {
+        if let Some(mut rec) = self.rec.take() {
+            if let Err(e) = rec.writer.flush() {
+                error!("demo: {}", e);
+            }
+        }
+        self.pending.clear();
+    }
+
+    pub(super) fn capture(&mut self, msg: &ServerMessage) {
+        if self.rec.is_some() {
+            self.pending.push(msg.clone());
+        }
+    }
+}
+
+pub struct DemoRecordPlugin;
+
+impl Plugin for DemoRecordPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<DemoRecorder>()
+            .add_systems(
+                FixedUpdate,
+                record_demo_frame
+                    .after(broadcast_snapshots)
+                    .after(round_update),
+            )
+            .add_systems(Last, finish_demo_on_exit);
+    }
+}
+
+#[derive(SystemParam)]
+struct DemoSources<'w> {
+    players: Res<'w, Players>,
+    bots: Res<'w, Bots>,
+    scaffolds: Res<'w, Scaffolds>,
+    scores: Res<'w, Scores>,
+    round: Res<'w, RoundState>,
+    scene: Res<'w, MapScene>,
+    cfg: Res<'w, ServerConfig>,
+}
+
+fn record_demo_frame(mut recorder: ResMut<DemoRecorder>, src: DemoSources) {
+    let DemoRecorder { rec, pending } = &mut *recorder;
+    let Some(r) = rec.as_mut() else {
+        return;
+    };
+    r.tick = r.tick.wrapping_add(1);
+    let snap = SnapshotMsg {
+        tick: r.tick,
+        players: actor_states(&src.players, &src.bots),
+        acks: Vec::new(),
+    };
+    let keyframe = r.last.is_none() || r.tick % DEMO_KEYFRAME_TICKS == 0;
+    let mut messages = vec![match (&r.last, keyframe) {
+        (Some((base_tick, base)), false) => {
+            ServerMessage::DeltaSnapshot(diff_snapshot(*base_tick, base, &snap))
+        }
+        _ => ServerMessage::Snapshot(snap.clone()),
+    }];
+    if r.last.is_none() {
+        // 再生側が途中参加のクライアントと同じ状態から始められるように
+        if let Some(scene) = src.scene.0.clone() {
+            messages.push(ServerMessage::Event(EventMsg::MapChange { scene }));
+        }
+        messages.push(ServerMessage::Event(EventMsg::RoundStart {
+            time_left_sec: src.round.time_left.max(0.0) as u32,
+        }));
+        messages.push(ServerMessage::Score(score_table(&src.scores)));
+    }
+    let rules = src.cfg.rules();
+    if r.rules != Some(rules) {
+        r.rules = Some(rules);
+        messages.push(ServerMessage::Rules(rules));
+    }
+    // 足場は関心管理経由で送っているので、ここで全体の増減を拾う
+    r.scaffolds.retain(|sid| {
+        let alive = src.scaffolds.by_id.contains_key(sid);
+        if !alive {
+            messages.push(ServerMessage::Event(EventMsg::ScaffoldDespawn {
+                sid: *sid,
+            }));
+        }
+        alive
+    });
+    let mut added: Vec<(&u64, &(u64, Vec3))> = src
+        .scaffolds
+        .by_id
+        .iter()
+        .filter(|(sid, _)| !r.scaffolds.contains(sid))
+        .collect();
+    added.sort_unstable_by_key(|(sid, _)| **sid);
+    for (sid, (owner, pos)) in added {
+        r.scaffolds.insert(*sid);
+        messages.push(ServerMessage::Event(EventMsg::ScaffoldSpawn {
+            sid: *sid,
+            owner: *owner,
+            pos: pos.to_array(),
+        }));
+    }
+    messages.append(pending);
+    let frame = DemoFrame {
+        tick: r.tick,
+        messages,
+    };
+    let mut result = r.writer.write_frame(&frame);
+    if keyframe && result.is_ok() {
+        result = r.writer.flush();
+    }
+    r.last = Some((r.tick, snap.players));
+    if let Err(e) = result {
+        error!("demo recording stopped: {}", e);
+        *rec = None;
+    }
+}
+
+fn finish_demo_on_exit(mut exit: EventReader<AppExit>, mut recorder: ResMut<DemoRecorder>) {
+    if exit.read().next().is_some() {
+        recorder.finish();
+    }
+}
@@ -0,0 +1,113 @@
+// ===== 設定ファイルの再読み込み =====
+// ConfigFile があればそのファイルを定期的に見て、変わっていれば読み直して ServerConfig に反映する。
+// ほとんどの値は次の tick から効く。ラウンド設定は進行中のラウンドを壊さないよう次のラウンド開始で反映。
+use super::config::RoundConfig;
+use super::*;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+const CONFIG_POLL_SEC: f32 = 1.0;
+
+// 監視する設定ファイル（--config で起動したときに挿入する）
+#[derive(Resource)]
+pub struct ConfigFile {
+    path: PathBuf,
+    stamp: Option<(SystemTime, u64)>, // (mtime, size)。同一秒内の書き換えもサイズで拾う
+    poll: Timer,
+}
+
+impl ConfigFile {
+    // 読み込み済みの内容を基準にする（起動直後に同じ内容を読み直さない）
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let stamp = file_stamp(&path);
+        Self {
+            path,
+            stamp,
+            poll: Timer::from_seconds(CONFIG_POLL_SEC, TimerMode::Repeating),
+        }
+    }
+}
+
+fn file_stamp(path: &std::path::Path) -> Option<(SystemTime, u64)> {
+    let meta = std::fs::metadata(path).ok()?;
+    Some((meta.modified().ok()?, meta.len()))
+}
+
+// 次のラウンド開始で反映するラウンド設定
+#[derive(Resource, Default)]
+pub(super) struct PendingRound(pub(super) Option<RoundConfig>);
+
+pub struct ConfigReloadPlugin;
+
+impl Plugin for ConfigReloadPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<PendingRound>()
+            .add_systems(Update, watch_config_file);
+    }
+}
+
+fn watch_config_file(
+    time: Res<Time>,
+    file: Option<ResMut<ConfigFile>>,
+    mut cfg: ResMut<ServerConfig>,
+    mut pending: ResMut<PendingRound>,
+    mut recorder: ResMut<InputRecorder>,
+) {
+    let Some(mut file) = file else {
+        return;
+    };
+    if !file.poll.tick(time.delta()).just_finished() {
+        return;
+    }
+    let stamp = file_stamp(&file.path);
+    if stamp.is_none() || stamp == file.stamp {
+        return;
+    }
+    file.stamp = stamp;
+    let next = match ServerConfig::load(&file.path) {
+        Ok(next) => next,
+        Err(e) => {
+            warn!("config reload failed, keeping current settings: {}", e);
+            return;
+        }
+    };
+    if recorder.is_recording() {
+        if let Ok(text) = toml::to_string(&next) {
+            recorder.capture(ReplayEvent::Config(text));
+        }
+    }
+    if apply_reloaded(next, &mut cfg, &mut pending) {
+        info!("config reloaded from {}", file.path.display());
+    }
+}
+
+// 読み直した設定を反映する（入力記録の再生からも使う）。値が変わったら true
+pub(super) fn apply_reloaded(
+    mut next: ServerConfig,
+    cfg: &mut ServerConfig,
+    pending: &mut PendingRound,
+) -> bool {
+    // スポーン地点はマップ読み込み時に一度だけ集めるので途中では切り替えない
+    if next.spawn.use_spawn_points != cfg.spawn.use_spawn_points {
+        warn!("config reload: spawn.use_spawn_points requires a restart (ignored)");
+        next.spawn.use_spawn_points = cfg.spawn.use_spawn_points;
+    }
+    // 乱数は起動時のシードで作ったストリームを使い続ける
+    if next.seed != cfg.seed {
+        warn!("config reload: seed requires a restart (ignored)");
+        next.seed = cfg.seed;
+    }
+    if next.round != cfg.round {
+        info!("config reload: round settings apply from the next round");
+        pending.0 = Some(next.round.clone());
+        next.round = cfg.round.clone();
+    } else {
+        pending.0 = None;
+    }
+    if next == *cfg {
+        return false;
+    }
+    *cfg = next;
+    true
+}
@@ -0,0 +1,550 @@
+// ===== 入力の記録と再生（回帰テスト用） =====
+// サーバが受け取ったもの（接続・切断、tick ごとの ClientMessage、管理コマンド、設定の再読み込み）を
+// フレームごとに書き出し、ヘッドレスで同じ順に流し直して同じ Scores/位置を再現する。
+// 乱数は GameRng のシードをヘッダに残し、再生開始時に同じシードへ戻す。
+//
+// 形式: "BOCR" + 形式版数(u16 LE) + [長さ + bincode(ReplayHeader)] + [長さ + bincode(ReplayFrame)]*
+// （レコードの枠は crate::demo と同じ）
+use super::*;
+use crate::demo::{read_record, write_record};
+use bevy::time::{TimeSystem, TimeUpdateStrategy};
+use bevy_renet::renet::{DisconnectReason, RenetClient};
+use bevy_renet::RenetReceive;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::time::Duration;
+
+pub const REPLAY_MAGIC: [u8; 4] = *b"BOCR";
+pub const REPLAY_FORMAT_VERSION: u16 = 1;
+const REPLAY_FLUSH_FRAMES: u32 = 60;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReplayHeader {
+    pub protocol_version: u32,
+    // 記録開始時のマップと設定（再生側はこれで App を組む）
+    pub map: Option<String>,
+    pub config: String,
+    pub tick_hz: f64,
+    pub seed: u64,
+}
+
+// サーバの 1 フレーム（Update 1 回）分。FixedUpdate は delta に応じて 0 回以上走る
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ReplayFrame {
+    pub delta: Duration,
+    // フレーム開始時点の MapReady（マップの非同期読み込みの進み具合を揃える）
+    pub map_ready: bool,
+    pub events: Vec<ReplayEvent>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ReplayEvent {
+    Connect {
+        id: u64,
+        addr: Option<SocketAddr>,
+        user_data: Vec<u8>,
+    },
+    Disconnect {
+        id: u64,
+    },
+    // 切断イベントなしで接続が消えた（トランスポートを通さないローカル接続など）
+    Dropped {
+        id: u64,
+    },
+    // FixedUpdate 1 回分の受信（受信順）
+    Tick(Vec<(u64, ClientMessage)>),
+    Admin(String),
+    // 再読み込みで読んだ設定ファイルの内容（反映時の調整前）
+    Config(String),
+}
+
+// ---- 記録 ----
+
+struct Recording {
+    out: BufWriter<File>,
+    frame: ReplayFrame,
+    frames: u32,
+    connected: HashSet<u64>,
+}
+
+#[derive(Resource, Default)]
+pub struct InputRecorder {
+    rec: Option<Recording>,
+}
+
+impl InputRecorder {
+    // seed は GameRng のシード（同じ値で GameRng を入れておくこと）
+    pub fn create(
+        path: impl AsRef<Path>,
+        map: Option<String>,
+        config: &ServerConfig,
+        tick_hz: f64,
+        seed: u64,
+    ) -> Result<Self, String> {
+        let path = path.as_ref();
+        let header = ReplayHeader {
+            protocol_version: PROTOCOL_VERSION,
+            map,
+            config: toml::to_string(config).map_err(|e| e.to_string())?,
+            tick_hz,
+            seed,
+        };
+        let file =
+            File::create(path).map_err(|e| format!("cannot create {}: {}", path.display(), e))?;
+        let mut out = BufWriter::new(file);
+        out.write_all(&REPLAY_MAGIC)
+            .and_then(|_| out.write_all(&REPLAY_FORMAT_VERSION.to_le_bytes()))
+            .and_then(|_| write_record(&mut out, &header))
+            .map_err(|e| format!("cannot write {}: {}", path.display(), e))?;
+        Ok(Self {
+            rec: Some(Recording {
+                out,
+                frame: ReplayFrame::default(),
+                frames: 0,
+                connected: HashSet::new(),
+            }),
+        })
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.rec.is_some()
+    }
+
+    // 書き残しを出してファイルを閉じる（記録中のフレームは捨てる）
+    pub fn finish(&mut self) {
+        if let Some(mut rec) = self.rec.take() {
+            if let Err(e) = rec.out.flush() {
+                error!("input recording: {}", e);
+            }
+        }
+    }
+
+    pub(super) fn capture(&mut self, ev: ReplayEvent) {
+        if let Some(rec) = self.rec.as_mut() {
+            rec.frame.events.push(ev);
+        }
+    }
+
+    pub(super) fn capture_tick(&mut self, received: &[(u64, ClientMessage)]) {
+        if self.rec.is_some() {
+            self.capture(ReplayEvent::Tick(received.to_vec()));
+        }
+    }
+}
+
+// ---- 再生 ----
+
+#[derive(Resource)]
+pub struct InputReplay {
+    header: ReplayHeader,
+    frames: Vec<ReplayFrame>,
+    next: usize,      // 再生中のフレーム
+    ticks_run: usize, // そのフレームで消費した Tick の数
+    holding: bool,    // マップ読み込み待ちで進めていないフレーム
+    seeded: bool,
+    diverged: bool,
+    addrs: HashMap<u64, SocketAddr>,
+    // 送信を ACK するだけのループバック接続（溜まった再送で接続が切られないように）
+    clients: HashMap<u64, RenetClient>,
+}
+
+impl InputReplay {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref();
+        let file =
+            File::open(path).map_err(|e| format!("cannot open {}: {}", path.display(), e))?;
+        let mut input = BufReader::new(file);
+        let mut magic = [0u8; 6];
+        input
+            .read_exact(&mut magic)
+            .map_err(|e| format!("not an input recording: {}", e))?;
+        if magic[..4] != REPLAY_MAGIC {
+            return Err("not an input recording (bad magic)".into());
+        }
+        let format = u16::from_le_bytes([magic[4], magic[5]]);
+        if format != REPLAY_FORMAT_VERSION {
+            return Err(format!(
+                "unsupported recording format {} (this build reads {})",
+                format, REPLAY_FORMAT_VERSION
+            ));
+        }
+        let header: ReplayHeader =
+            read_record(&mut input)?.ok_or_else(|| "recording header is missing".to_string())?;
+        if header.protocol_version != PROTOCOL_VERSION {
+            return Err(format!(
+                "recorded with protocol {} (this build speaks {})",
+                header.protocol_version, PROTOCOL_VERSION
+            ));
+        }
+        // 落ちて途中で切れた記録も、そこまでは再生できる
+        let mut frames = Vec::new();
+        while let Some(frame) = read_record(&mut input)? {
+            frames.push(frame);
+        }
+        Ok(Self {
+            header,
+            frames,
+            next: 0,
+            ticks_run: 0,
+            holding: false,
+            seeded: false,
+            diverged: false,
+            addrs: HashMap::new(),
+            clients: HashMap::new(),
+        })
+    }
+
+    pub fn header(&self) -> &ReplayHeader {
+        &self.header
+    }
+
+    // 記録時の設定（再生する App にはこれを入れておく）
+    pub fn config(&self) -> Result<ServerConfig, String> {
+        toml::from_str(&self.header.config).map_err(|e| format!("recorded config: {}", e))
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.next >= self.frames.len()
+    }
+
+    // tick 数が記録と合わなかった（設定やマップが記録時と違う、など）
+    pub fn has_diverged(&self) -> bool {
+        self.diverged
+    }
+
+    pub(super) fn addr(&self, id: u64) -> Option<SocketAddr> {
+        self.addrs.get(&id).copied()
+    }
+
+    fn frame(&self) -> Option<&ReplayFrame> {
+        self.frames.get(self.next).filter(|_| !self.holding)
+    }
+
+    // 今のフレームの次の Tick に記録された受信列
+    pub(super) fn next_tick_messages(&mut self) -> Vec<(u64, ClientMessage)> {
+        let n = self.ticks_run;
+        self.ticks_run += 1;
+        let recorded = self.frame().and_then(|f| {
+            f.events
+                .iter()
+                .filter_map(|ev| match ev {
+                    ReplayEvent::Tick(msgs) => Some(msgs),
+                    _ => None,
+                })
+                .nth(n)
+                .cloned()
+        });
+        recorded.unwrap_or_else(|| {
+            self.warn_diverged("more ticks than recorded");
+            Vec::new()
+        })
+    }
+
+    fn warn_diverged(&mut self, what: &str) {
+        if !self.diverged {
+            self.diverged = true;
+            warn!("replay diverged at frame {}: {}", self.next, what);
+        }
+    }
+}
+
+pub struct ReplayPlugin;
+
+impl Plugin for ReplayPlugin {
+    fn build(&self, app: &mut App) {
+        let replaying = resource_exists::<InputReplay>;
+        app.init_resource::<InputRecorder>()
+            .add_systems(First, record_frame_start.after(TimeSystem))
+            .add_systems(
+                First,
+                replay_frame_start.before(TimeSystem).run_if(replaying),
+            )
+            .add_systems(
+                PreUpdate,
+                (replay_connections.run_if(replaying), record_connections)
+                    .chain()
+                    .after(RenetReceive),
+            )
+            .add_systems(Update, replay_config.run_if(replaying))
+            .add_systems(Last, replay_frame_end.run_if(replaying))
+            .add_systems(Last, (record_frame_end, finish_recording_on_exit).chain());
+    }
+}
+
+fn record_frame_start(mut recorder: ResMut<InputRecorder>, ready: Res<MapReady>) {
+    if let Some(rec) = recorder.rec.as_mut() {
+        rec.frame.map_ready = ready.0;
+    }
+}
+
+fn record_connections(
+    mut recorder: ResMut<InputRecorder>,
+    mut events: EventReader<ServerEvent>,
+    conn: ConnectInfo,
+    gate: Gate,
+    server: Res<RenetServer>,
+) {
+    let Some(rec) = recorder.rec.as_mut() else {
+        events.clear();
+        return;
+    };
+    for event in events.read() {
+        let ev = match *event {
+            ServerEvent::ClientConnected { client_id } => {
+                let id = client_id.raw();
+                // 検証後の内容を残す（再生はプロセス内接続なので、Unsecure で名乗ったアカウント ID を落としておく）
+                // サーバパスワードは残さない。受け入れなかった接続（BAN・許可リスト・パスワード）は中身ごと捨て、
+                // 再生時のリストやパスワードに関係なく弾かれるようにする
+                let user_data = match conn.admit(id, &gate) {
+                    Ok(hello) => ConnectUserData {
+                        password: String::new(),
+                        ..hello
+                    }
+                    .to_bytes()
+                    .to_vec(),
+                    _ => Vec::new(),
+                };
+                ReplayEvent::Connect {
+                    id,
+                    addr: conn.addr(id),
+                    user_data,
+                }
+            }
+            ServerEvent::ClientDisconnected { client_id, .. } => {
+                rec.connected.remove(&client_id.raw());
+                ReplayEvent::Disconnect {
+                    id: client_id.raw(),
+                }
+            }
+        };
+        rec.frame.events.push(ev);
+    }
+    let now: HashSet<u64> = server.clients_id_iter().map(|c| c.raw()).collect();
+    let mut dropped: Vec<u64> = rec.connected.difference(&now).copied().collect();
+    dropped.sort_unstable();
+    rec.frame
+        .events
+        .extend(dropped.into_iter().map(|id| ReplayEvent::Dropped { id }));
+    rec.connected = now;
+}
+
+fn record_frame_end(time: Res<Time>, mut recorder: ResMut<InputRecorder>) {
+    let Some(rec) = recorder.rec.as_mut() else {
+        return;
+    };
+    let mut frame = std::mem::take(&mut rec.frame);
+    frame.delta = time.delta();
+    rec.frames += 1;
+    let mut result = write_record(&mut rec.out, &frame);
+    if rec.frames % REPLAY_FLUSH_FRAMES == 0 && result.is_ok() {
+        result = rec.out.flush();
+    }
+    if let Err(e) = result {
+        error!("input recording stopped: {}", e);
+        recorder.rec = None;
+    }
+}
+
+fn finish_recording_on_exit(mut exit: EventReader<AppExit>, mut recorder: ResMut<InputRecorder>) {
+    if exit.read().next().is_some() {
+        recorder.finish();
+    }
+}
+
+// 記録どおりの経過時間でフレームを進める。記録ではマップが読めているのにまだなら、時間を止めて待つ
+fn replay_frame_start(
+    mut replay: ResMut<InputReplay>,
+    mut strategy: ResMut<TimeUpdateStrategy>,
+    mut ready: ResMut<MapReady>,
+    mut rng: ResMut<GameRng>,
+    map_colliders: Query<(), (With<Handle<Mesh>>, With<Collider>)>,
+) {
+    if !replay.seeded {
+        replay.seeded = true;
+        *rng = GameRng::new(replay.header.seed);
+    }
+    replay.ticks_run = 0;
+    let Some(frame) = replay.frames.get(replay.next) else {
+        *strategy = TimeUpdateStrategy::ManualDuration(Duration::ZERO);
+        return;
+    };
+    let (delta, map_ready) = (frame.delta, frame.map_ready);
+    replay.holding = map_ready && map_colliders.is_empty();
+    if replay.holding {
+        *strategy = TimeUpdateStrategy::ManualDuration(Duration::ZERO);
+        return;
+    }
+    ready.0 = map_ready;
+    *strategy = TimeUpdateStrategy::ManualDuration(delta);
+}
+
+// 接続・切断は記録から起こす（ループバック接続が出す ServerEvent は捨てる）。管理コマンドもここで積む
+fn replay_connections(
+    mut replay: ResMut<InputReplay>,
+    mut server: ResMut<RenetServer>,
+    mut events: ResMut<Events<ServerEvent>>,
+    mut local: ResMut<LocalClients>,
+    admin: Option<Res<AdminQueue>>,
+) {
+    events.clear();
+    let replay = &mut *replay;
+    let Some(frame) = replay.frames.get(replay.next).filter(|_| !replay.holding) else {
+        return;
+    };
+    for ev in &frame.events {
+        match ev {
+            ReplayEvent::Connect {
+                id,
+                addr,
+                user_data,
+            } => {
+                let client_id = ClientId::from_raw(*id);
+                if let Ok(data) = user_data.as_slice().try_into() {
+                    local.0.insert(*id, data);
+                }
+                if let Some(addr) = addr {
+                    replay.addrs.insert(*id, *addr);
+                }
+                server.add_connection(client_id);
+                let mut client = RenetClient::new(connection_config());
+                client.set_connected();
+                replay.clients.insert(*id, client);
+                events.send(ServerEvent::ClientConnected { client_id });
+            }
+            ReplayEvent::Disconnect { id } => {
+                let client_id = ClientId::from_raw(*id);
+                local.0.remove(id);
+                replay.addrs.remove(id);
+                replay.clients.remove(id);
+                if server.is_connected(client_id) {
+                    server.remove_connection(client_id);
+                }
+                events.send(ServerEvent::ClientDisconnected {
+                    client_id,
+                    reason: DisconnectReason::DisconnectedByClient,
+                });
+            }
+            ReplayEvent::Dropped { id } => {
+                local.0.remove(id);
+                replay.addrs.remove(id);
+                replay.clients.remove(id);
+                server.disconnect(ClientId::from_raw(*id));
+            }
+            ReplayEvent::Admin(line) => {
+                if let Some(admin) = &admin {
+                    // 応答は誰も待っていない
+                    let _ = admin.submit(line.clone());
+                }
+            }
+            ReplayEvent::Tick(_) | ReplayEvent::Config(_) => {}
+        }
+    }
+}
+
+fn replay_config(
+    replay: Res<InputReplay>,
+    mut cfg: ResMut<ServerConfig>,
+    mut pending: ResMut<PendingRound>,
+) {
+    let Some(frame) = replay.frame() else {
+        return;
+    };
+    for ev in &frame.events {
+        if let ReplayEvent::Config(text) = ev {
+            match toml::from_str(text) {
+                Ok(next) => {
+                    apply_reloaded(next, &mut cfg, &mut pending);
+                }
+                Err(e) => warn!("replay: recorded config is unreadable: {}", e),
+            }
+        }
+    }
+}
+
+fn replay_frame_end(
+    time: Res<Time>,
+    mut replay: ResMut<InputReplay>,
+    mut server: ResMut<RenetServer>,
+    players: Res<Players>,
+    bots: Res<Bots>,
+    scores: Res<Scores>,
+    mut exit: EventWriter<AppExit>,
+) {
+    let replay = &mut *replay;
+    for (id, client) in replay.clients.iter_mut() {
+        let client_id = ClientId::from_raw(*id);
+        if let Ok(packets) = server.get_packets_to_send(client_id) {
+            for p in packets {
+                client.process_packet(&p);
+            }
+        }
+        client.update(time.delta());
+        for p in client.get_packets_to_send() {
+            let _ = server.process_packet_from(&p, client_id);
+        }
+        for channel in [CH_INPUT, CH_SNAPSHOT, CH_RELIABLE] {
+            while client.receive_message(channel).is_some() {}
+        }
+    }
+    if replay.holding || replay.is_finished() {
+        return;
+    }
+    let recorded = replay.frames[replay.next]
+        .events
+        .iter()
+        .filter(|ev| matches!(ev, ReplayEvent::Tick(_)))
+        .count();
+    if replay.ticks_run != recorded {
+        let what = format!("ran {} ticks, recorded {}", replay.ticks_run, recorded);
+        replay.warn_diverged(&what);
+    }
+    replay.next += 1;
+    if replay.is_finished() {
+        let state = MatchState::new(&players, &bots, &scores);
+        info!(
+            "replay finished: {} frames, scores {:?}, positions {:?}",
+            replay.frames.len(),
+            state.scores,
+            state.positions
+        );
+        exit.send(AppExit::Success);
+    }
+}
+
+// ---- 結果の比較 ----
+
+// 再現できたかを比べるための試合状態（id 順）
+#[derive(Debug, Clone, PartialEq)]
+pub struct MatchState {
+    pub scores: Vec<(u64, u32, u32)>, // (id, kills, deaths)
+    pub positions: Vec<(u64, Vec3)>,
+}
+
+impl MatchState {
+    fn new(players: &Players, bots: &Bots, scores: &Scores) -> Self {
+        let mut table: Vec<(u64, u32, u32)> = score_table(scores)
+            .into_iter()
+            .map(|e| (e.id, e.kills, e.deaths))
+            .collect();
+        table.sort_unstable_by_key(|e| e.0);
+        let mut positions: Vec<(u64, Vec3)> = actor_states(players, bots)
+            .into_iter()
+            .map(|a| (a.id, Vec3::from_array(a.pos)))
+            .collect();
+        positions.sort_unstable_by_key(|p| p.0);
+        Self {
+            scores: table,
+            positions,
+        }
+    }
+}
+
+pub fn match_state(world: &World) -> MatchState {
+    MatchState::new(
+        world.resource::<Players>(),
+        world.resource::<Bots>(),
+        world.resource::<Scores>(),
+    )
+}
@@ -0,0 +1,130 @@
+// ===== サーバの乱数 =====
+// スポーンのジッターや Bot の徘徊などはすべて GameRng から引く。
+// シードが同じで入力も同じなら同じ試合になる（入力記録の再生が前提にしている）。
+//
+// 用途ごと（Bot は個体ごと）に独立したストリームを持つので、ある系統で引く回数が
+// 変わっても他の系統の結果はずれない。HashMap の走査順にも左右されない。
+use super::*;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(super) enum RngStream {
+    PlayerSpawn,
+    BotSpawn,
+    BotWander,
+    BotStrafe,
+}
+
+pub(super) struct SubRng(StdRng);
+
+impl SubRng {
+    // [0, 1)
+    pub(super) fn unit(&mut self) -> f32 {
+        self.0.gen()
+    }
+
+    // [0, len)。len は 1 以上
+    pub(super) fn index(&mut self, len: usize) -> usize {
+        self.0.gen_range(0..len)
+    }
+
+    // 水平方向に各軸 ±radius の散らし
+    pub(super) fn jitter(&mut self, radius: f32) -> Vec3 {
+        Vec3::new(
+            (self.unit() - 0.5) * 2.0 * radius,
+            0.0,
+            (self.unit() - 0.5) * 2.0 * radius,
+        )
+    }
+}
+
+#[derive(Resource)]
+pub struct GameRng {
+    seed: u64,
+    streams: HashMap<(RngStream, u64), SubRng>,
+}
+
+impl GameRng {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            streams: HashMap::new(),
+        }
+    }
+
+    // config の seed を使う。未指定ならランダム
+    pub fn from_config(cfg: &ServerConfig) -> Self {
+        cfg.seed.map_or_else(Self::default, Self::new)
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    // 系統全体で 1 本のストリーム
+    pub(super) fn stream(&mut self, stream: RngStream) -> &mut SubRng {
+        self.actor(stream, 0)
+    }
+
+    // 系統 × アクター ID ごとのストリーム。初回に (シード, 系統, ID) から作る
+    pub(super) fn actor(&mut self, stream: RngStream, id: u64) -> &mut SubRng {
+        let seed = self.seed;
+        self.streams.entry((stream, id)).or_insert_with(|| {
+            let mut key = [0u8; 32];
+            key[..8].copy_from_slice(&seed.to_le_bytes());
+            key[8..16].copy_from_slice(&(stream as u64 + 1).to_le_bytes());
+            key[16..24].copy_from_slice(&id.to_le_bytes());
+            SubRng(StdRng::from_seed(key))
+        })
+    }
+
+    // 退場したアクターのストリームを捨てる（同じ ID が戻れば最初からやり直し）
+    pub(super) fn forget(&mut self, id: u64) {
+        self.streams.retain(|(_, actor), _| *actor != id);
+    }
+}
+
+// シード未指定なら起動ごとに変わる。
+// ログに出た値を設定ファイルへ貼り戻せるよう、TOML の整数（i64）に収まる範囲にする
+impl Default for GameRng {
+    fn default() -> Self {
+        Self::new(rand::random::<u64>() >> 1)
+    }
+}
+
+// 同じ試合を再現するときはこの値を seed に設定する
+pub(super) fn log_rng_seed(rng: Res<GameRng>) {
+    info!("server: rng seed = {}", rng.seed());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn streams_do_not_disturb_each_other() {
+        let mut a = GameRng::new(7);
+        let mut b = GameRng::new(7);
+        // a だけ別系統・別個体から多めに引いておく
+        for _ in 0..10 {
+            a.stream(RngStream::BotWander).unit();
+            a.actor(RngStream::BotStrafe, 3).unit();
+        }
+        let xa: Vec<f32> = (0..4)
+            .map(|_| a.actor(RngStream::BotStrafe, 5).unit())
+            .collect();
+        let xb: Vec<f32> = (0..4)
+            .map(|_| b.actor(RngStream::BotStrafe, 5).unit())
+            .collect();
+        assert_eq!(xa, xb);
+        assert_ne!(
+            a.stream(RngStream::PlayerSpawn).unit(),
+            a.stream(RngStream::BotSpawn).unit()
+        );
+        assert_ne!(
+            GameRng::new(1).stream(RngStream::PlayerSpawn).unit(),
+            GameRng::new(2).stream(RngStream::PlayerSpawn).unit()
+        );
+    }
+}
@@ -0,0 +1,176 @@
+// ===== 足場（サーバ権威で生成・寿命管理） =====
+use super::*;
+
+// サーバ権威のパラメータ（クライアントと合わせる）
+const SCAFFOLD_SIZE: Vec3 = Vec3::new(2.0, 0.5, 2.0);
+const SCAFFOLD_RANGE: f32 = 5.0;
+
+#[derive(Resource, Default)]
+pub(super) struct Scaffolds {
+    // sid -> (owner, pos)
+    pub(super) by_id: HashMap<u64, (u64, Vec3)>,
+    // owner -> [sid FIFO]
+    pub(super) per_owner: HashMap<u64, Vec<u64>>,
+}
+
+#[derive(Resource, Default)]
+struct ScaffoldEntities(HashMap<u64, Entity>); // sid -> entity
+
+#[derive(Resource)]
+struct NextScaffoldId(u64);
+
+#[derive(Resource, Default)]
+pub(super) struct PendingScaffold(pub(super) Vec<(u64, Vec3)>); // (owner, final_pos)
+
+// 全足場の撤去（マップ変更時）。クライアントへは関心判定が ScaffoldDespawn を送る
+#[derive(SystemParam)]
+pub(super) struct ScaffoldReset<'w> {
+    scaffolds: ResMut<'w, Scaffolds>,
+    entities: ResMut<'w, ScaffoldEntities>,
+    pending: ResMut<'w, PendingScaffold>,
+    log: ResMut<'w, MatchLog>,
+}
+
+impl ScaffoldReset<'_> {
+    pub(super) fn clear(&mut self, commands: &mut Commands) {
+        let mut removed: Vec<(u64, u64)> = self
+            .scaffolds
+            .by_id
+            .drain()
+            .map(|(sid, (owner, _))| (sid, owner))
+            .collect();
+        removed.sort_unstable();
+        for (sid, owner) in removed {
+            if let Some(e) = self.entities.0.remove(&sid) {
+                commands.entity(e).despawn_recursive();
+            }
+            self.log.record(MatchEvent::ScaffoldDespawn { sid, owner });
+        }
+        self.scaffolds.per_owner.clear();
+        self.pending.0.clear();
+    }
+}
+
+pub struct ScaffoldPlugin;
+
+impl Plugin for ScaffoldPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Scaffolds>()
+            .init_resource::<ScaffoldEntities>()
+            .insert_resource(NextScaffoldId(2_000_000_000_000))
+            .init_resource::<PendingScaffold>()
+            // 生成した足場はその tick の関心判定で ScaffoldSpawn される
+            .add_systems(
+                FixedUpdate,
+                process_scaffold_requests.before(update_relevancy),
+            );
+    }
+}
+
+fn process_scaffold_requests(
+    mut commands: Commands,
+    mut pending: ResMut<PendingScaffold>,
+    mut scaffolds: ResMut<Scaffolds>,
+    mut sc_ents: ResMut<ScaffoldEntities>,
+    players: Res<Players>,
+    ents: Res<ServerEntities>,
+    rapier: Res<RapierContext>,
+    mut next_sid: ResMut<NextScaffoldId>,
+    ready: Res<MapReady>,
+    cfg: Res<ServerConfig>,
+    mut log: ResMut<MatchLog>,
+) {
+    if pending.0.is_empty() {
+        return;
+    }
+    // マップが未準備なら後で再試行
+    if !ready.0 {
+        return;
+    }
+    let requests: Vec<(u64, Vec3)> = pending.0.drain(..).collect();
+    for (owner, place_in) in requests {
+        // プレイヤー状態やエンティティがまだ未登録なら再試行キューへ戻す
+        let Some(_pstate) = players.states.get(&owner) else {
+            pending.0.push((owner, place_in));
+            continue;
+        };
+        let Some(&_p_ent) = ents.0.get(&owner) else {
+            pending.0.push((owner, place_in));
+            continue;
+        };
+        let mut place = place_in;
+
+        // --- 交差/近接チェック（所有者と重ならないように最小押し出し）
+        if let Some(pst) = players.states.get(&owner) {
+            let ply = pst.pos;
+            let player_radius = 0.3f32; // Collider::capsule_y(0.6, 0.3) に合わせる
+            let half_extent = (SCAFFOLD_SIZE.x.max(SCAFFOLD_SIZE.z)) * 0.5;
+            let margin = 0.06f32;
+            let min_dist = player_radius + half_extent + margin;
+            let dx = place.x - ply.x;
+            let dz = place.z - ply.z;
+            let d2 = dx * dx + dz * dz;
+            let min_d2 = min_dist * min_dist;
+            if d2 < min_d2 {
+                let mut nx = dx;
+                let mut nz = dz;
+                let len = (nx * nx + nz * nz).sqrt();
+                if len < 1e-5 {
+                    // 向きが不定なら +X 方向に押し出す
+                    nx = 1.0;
+                    nz = 0.0;
+                } else {
+                    nx /= len;
+                    nz /= len;
+                }
+                let push = min_dist - len.min(min_dist);
+                place.x = ply.x + nx * (len + push);
+                place.z = ply.z + nz * (len + push);
+            }
+        }
+
+        // per-owner limit (FIFO)
+        let mut to_remove: Option<u64> = None;
+        {
+            let vec = scaffolds.per_owner.entry(owner).or_default();
+            if vec.len() >= cfg.scaffold.per_player_limit {
+                to_remove = Some(vec.remove(0));
+            }
+        }
+        if let Some(old) = to_remove {
+            if let Some(e) = sc_ents.0.remove(&old) {
+                commands.entity(e).despawn_recursive();
+            }
+            scaffolds.by_id.remove(&old);
+            log.record(MatchEvent::ScaffoldDespawn { sid: old, owner });
+        }
+
+        let sid = {
+            let cur = next_sid.0;
+            next_sid.0 += 1;
+            cur
+        };
+        let ent = commands
+            .spawn((
+                TransformBundle::from_transform(Transform::from_translation(place)),
+                Collider::cuboid(
+                    SCAFFOLD_SIZE.x * 0.5,
+                    SCAFFOLD_SIZE.y * 0.5,
+                    SCAFFOLD_SIZE.z * 0.5,
+                ),
+                RigidBody::Fixed,
+            ))
+            .id();
+        sc_ents.0.insert(sid, ent);
+        scaffolds.per_owner.entry(owner).or_default().push(sid);
+        scaffolds
+            .by_id
+            .insert(sid, (owner, place));
+        log.record(MatchEvent::ScaffoldSpawn {
+            sid,
+            owner,
+            pos: pos3(place),
+        });
+        // ScaffoldSpawn/Despawn の通知は update_relevancy が距離に応じて行う
+    }
+}
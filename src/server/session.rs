@@ -0,0 +1,104 @@
+// ===== 再接続の猶予 =====
+// アカウントのある（account_id != 0）プレイヤーが切断したら、状態・スコア・武器を
+// spawn.reconnect_grace_sec のあいだアカウント ID で預かる。同じアカウントで接続し直すと
+// （クライアント ID は変わる）accept_clients がそれを引き継ぐ。ゲストは本人確認できないので預からない。
+// 古い接続がまだ切れていない（netcode のタイムアウト前に入り直した）ときは、古い方を追い出してその場で引き継ぐ。
+use super::*;
+
+pub(super) struct ParkedPlayer {
+    pub(super) state: PlayerState,
+    pub(super) score: (u32, u32),
+    pub(super) weapon: Option<WeaponStatus>,
+    pub(super) respawn: Option<f32>, // 死亡中ならリスポーンまでの残り
+    left: f32,                       // 猶予の残り
+}
+
+#[derive(Resource, Default)]
+pub(super) struct ParkedSessions(HashMap<u64, ParkedPlayer>);
+
+#[derive(SystemParam)]
+pub(super) struct Reconnect<'w> {
+    parked: ResMut<'w, ParkedSessions>,
+    respawns: ResMut<'w, RespawnTimers>,
+}
+
+impl Reconnect<'_> {
+    // 預けたら true（呼び出し側は通常どおり id の状態を片付ける）
+    pub(super) fn park(
+        &mut self,
+        account_id: u64,
+        state: PlayerState,
+        score: (u32, u32),
+        weapon: Option<WeaponStatus>,
+        id: u64,
+        grace: f32,
+    ) -> bool {
+        let respawn = self.respawns.0.remove(&id);
+        if account_id == 0 || grace <= 0.0 {
+            return false;
+        }
+        self.parked.0.insert(
+            account_id,
+            ParkedPlayer {
+                state,
+                score,
+                weapon,
+                respawn,
+                left: grace,
+            },
+        );
+        true
+    }
+
+    // まだつながっている from の状態を to へ渡す（猶予の設定に関係なく、死亡中ならリスポーン待ちも移す）
+    pub(super) fn hand_over(
+        &mut self,
+        state: PlayerState,
+        score: (u32, u32),
+        weapon: Option<WeaponStatus>,
+        from: u64,
+        to: u64,
+    ) -> ParkedPlayer {
+        let respawn = self.respawns.0.remove(&from);
+        if !state.alive {
+            self.respawns.0.insert(to, respawn.unwrap_or(0.0));
+        }
+        ParkedPlayer {
+            state,
+            score,
+            weapon,
+            respawn,
+            left: 0.0,
+        }
+    }
+
+    // 預かっていれば取り出し、死亡中ならリスポーン待ちも新しい id で戻す
+    pub(super) fn resume(&mut self, account_id: u64, id: u64) -> Option<ParkedPlayer> {
+        if account_id == 0 {
+            return None;
+        }
+        let parked = self.parked.0.remove(&account_id)?;
+        if !parked.state.alive {
+            self.respawns.0.insert(id, parked.respawn.unwrap_or(0.0));
+        }
+        Some(parked)
+    }
+}
+
+impl ParkedSessions {
+    // 新しいラウンドでは全員やり直しなので、預かった状態も意味がなくなる
+    pub(super) fn clear(&mut self) {
+        self.0.clear();
+    }
+}
+
+pub(super) fn expire_parked_sessions(time: Res<Time>, mut parked: ResMut<ParkedSessions>) {
+    let dt = time.delta_seconds();
+    parked.0.retain(|account_id, p| {
+        p.left -= dt;
+        if p.left <= 0.0 {
+            info!("session for account {} expired", account_id);
+        }
+        p.left > 0.0
+    });
+}
@@ -0,0 +1,268 @@
+// ===== 通算成績 =====
+// アカウント（account_id != 0）ごとにキル・デス・射撃・命中・ラウンド勝利・足場・プレイ時間を積算する。
+// 集計元は MatchLog::recent()（そのフレームの試合イベント）なので、ゲストとボットは数えない。
+// StatsStore::open したファイルへ保存し、open しなければメモリ上だけで集計する。
+// 成績が変わったら 30 秒ごと、ラウンド終了時と AppExit では（プレイ時間だけの変化も含めて）必ず書き出す。
+//
+// ファイルは TOML。項目は net::PlayerStats と同じ
+// [[account]]
+// account_id = 1
+// name = "alice"
+// kills = 12
+// ...
+use super::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const STATS_SAVE_SEC: f32 = 30.0; // 成績が変わっていればこの間隔で保存する
+pub(super) const STATS_TOP: usize = 10; // 問い合わせ・管理コマンドで返す上位の件数
+
+#[derive(Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct StatsFile {
+    #[serde(default, rename = "account")]
+    accounts: Vec<PlayerStats>,
+}
+
+#[derive(Resource, Default)]
+pub struct StatsStore {
+    path: Option<PathBuf>,
+    accounts: BTreeMap<u64, PlayerStats>,
+    dirty: bool,      // 保存していない成績の変化
+    time_dirty: bool, // 保存していないプレイ時間（毎フレーム増えるので、これだけでは定期保存しない）
+    save_in: f32,
+}
+
+impl StatsStore {
+    // 無ければ空で始め、最初の保存で作る
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref();
+        let file: StatsFile = if path.exists() {
+            let text = fs::read_to_string(path)
+                .map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+            toml::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))?
+        } else {
+            StatsFile::default()
+        };
+        Ok(Self {
+            path: Some(path.to_path_buf()),
+            accounts: file
+                .accounts
+                .into_iter()
+                .map(|s| (s.account_id, s))
+                .collect(),
+            ..default()
+        })
+    }
+
+    pub(super) fn get(&self, account_id: u64) -> Option<&PlayerStats> {
+        self.accounts.get(&account_id)
+    }
+
+    // キル降順、同数ならデス昇順
+    pub(super) fn top(&self, n: usize) -> Vec<PlayerStats> {
+        let mut rows: Vec<&PlayerStats> = self.accounts.values().collect();
+        rows.sort_by(|a, b| {
+            b.kills
+                .cmp(&a.kills)
+                .then(a.deaths.cmp(&b.deaths))
+                .then(a.account_id.cmp(&b.account_id))
+        });
+        rows.into_iter().take(n).cloned().collect()
+    }
+
+    fn entry(&mut self, profile: &PlayerProfile) -> &mut PlayerStats {
+        self.dirty = true;
+        self.row(profile)
+    }
+
+    fn add_play_time(&mut self, profile: &PlayerProfile, secs: f32) {
+        self.time_dirty = true;
+        self.row(profile).play_secs += secs as f64;
+    }
+
+    fn row(&mut self, profile: &PlayerProfile) -> &mut PlayerStats {
+        let s = self
+            .accounts
+            .entry(profile.account_id)
+            .or_insert_with(|| PlayerStats {
+                account_id: profile.account_id,
+                ..default()
+            });
+        if s.name != profile.name {
+            s.name = profile.name.clone();
+        }
+        s
+    }
+
+    // 一時ファイルに書いてから置き換える（書き込み中に落ちても前回分は残る）
+    // flush ならプレイ時間だけの変化でも書く
+    fn save(&mut self, flush: bool) {
+        self.save_in = STATS_SAVE_SEC;
+        let Some(path) = &self.path else {
+            return;
+        };
+        let pending = self.dirty || (flush && self.time_dirty);
+        if !pending {
+            return;
+        }
+        let file = StatsFile {
+            accounts: self.accounts.values().cloned().collect(),
+        };
+        let mut tmp = path.clone().into_os_string();
+        tmp.push(".tmp");
+        let result = toml::to_string(&file)
+            .map_err(|e| e.to_string())
+            .and_then(|text| fs::write(&tmp, text).map_err(|e| e.to_string()))
+            .and_then(|_| fs::rename(&tmp, path).map_err(|e| e.to_string()));
+        match result {
+            Ok(()) => {
+                self.dirty = false;
+                self.time_dirty = false;
+            }
+            // 次の機会に再試行する
+            Err(e) => error!("cannot save stats to {}: {}", path.display(), e),
+        }
+    }
+}
+
+// recv_inputs が積む（クライアント id, 問い合わせ対象）
+#[derive(Resource, Default)]
+pub(super) struct PendingStatsQueries(pub Vec<(u64, Option<u64>)>);
+
+pub struct StatsPlugin;
+
+impl Plugin for StatsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Last,
+            (accumulate_stats, save_stats_on_exit)
+                .chain()
+                .before(MatchLogFlush),
+        )
+        .add_systems(Update, answer_stats_queries);
+    }
+}
+
+fn accumulate_stats(
+    time: Res<Time>,
+    log: Res<MatchLog>,
+    profiles: Res<PlayerProfiles>,
+    mut stats: ResMut<StatsStore>,
+) {
+    let dt = time.delta_seconds();
+    let account = |id: u64| profiles.0.get(&id).filter(|p| p.account_id != 0);
+    for profile in profiles.0.values().filter(|p| p.account_id != 0) {
+        stats.add_play_time(profile, dt);
+    }
+    let mut round_ended = false;
+    for event in log.recent() {
+        match event {
+            MatchEvent::Fire { id, .. } => {
+                if let Some(p) = account(*id) {
+                    stats.entry(p).shots += 1;
+                }
+            }
+            MatchEvent::Hit { by, .. } => {
+                if let Some(p) = account(*by) {
+                    stats.entry(p).hits += 1;
+                }
+            }
+            MatchEvent::Death { target, by, .. } => {
+                if let Some(p) = account(*by) {
+                    stats.entry(p).kills += 1;
+                }
+                if let Some(p) = account(*target) {
+                    stats.entry(p).deaths += 1;
+                }
+            }
+            MatchEvent::ScaffoldSpawn { owner, .. } => {
+                if let Some(p) = account(*owner) {
+                    stats.entry(p).scaffolds += 1;
+                }
+            }
+            MatchEvent::RoundEnd { winner, .. } => {
+                if let Some(p) = winner.and_then(account) {
+                    stats.entry(p).rounds_won += 1;
+                }
+                round_ended = true;
+            }
+            _ => {}
+        }
+    }
+    stats.save_in -= dt;
+    if round_ended || stats.save_in <= 0.0 {
+        stats.save(round_ended);
+    }
+}
+
+fn save_stats_on_exit(mut exit: EventReader<AppExit>, mut stats: ResMut<StatsStore>) {
+    if exit.read().next().is_some() {
+        stats.save(true);
+    }
+}
+
+fn answer_stats_queries(
+    mut queries: ResMut<PendingStatsQueries>,
+    stats: Res<StatsStore>,
+    mut net: NetSend,
+) {
+    for (id, query) in queries.0.drain(..) {
+        let rows = match query {
+            Some(account_id) => stats.get(account_id).cloned().into_iter().collect(),
+            None => stats.top(STATS_TOP),
+        };
+        net.send_to(id, &ServerMessage::Stats(rows));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn stats_survive_save_and_reopen() {
+        let dir = TempDir::new("stats");
+        let path = dir.join("stats.toml");
+        let mut store = StatsStore::open(&path).unwrap();
+        for (account_id, kills) in [(1, 3), (2, 5)] {
+            let profile = PlayerProfile {
+                name: format!("p{}", account_id),
+                account_id,
+            };
+            let s = store.entry(&profile);
+            s.kills = kills;
+            s.shots = 10;
+            s.hits = 4;
+        }
+        store.save(false);
+        let store = StatsStore::open(&path).unwrap();
+        let top = store.top(STATS_TOP);
+        assert_eq!(
+            top.iter().map(|s| s.account_id).collect::<Vec<_>>(),
+            vec![2, 1]
+        );
+        assert_eq!(top[0].name, "p2");
+        assert!((top[0].accuracy() - 0.4).abs() < 1e-6);
+    }
+
+    #[test]
+    fn play_time_alone_is_saved_only_on_flush() {
+        let dir = TempDir::new("stats-time");
+        let path = dir.join("stats.toml");
+        let mut store = StatsStore::open(&path).unwrap();
+        let profile = PlayerProfile {
+            name: "idle".into(),
+            account_id: 4,
+        };
+        store.add_play_time(&profile, 1.5);
+        store.save(false);
+        assert!(!path.exists());
+        store.save(true);
+        let store = StatsStore::open(&path).unwrap();
+        assert_eq!(store.get(4).map(|s| s.play_secs), Some(1.5));
+    }
+}
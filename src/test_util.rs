@@ -0,0 +1,29 @@
+// ===== 単体テスト用の一時ディレクトリ =====
+// 名前とプロセス ID で分けて並列実行でも衝突させず、drop で中身ごと消す。
+// 結合テスト（tests/）は tests/common/mod.rs の同じものを使う
+use std::path::{Path, PathBuf};
+
+pub(crate) struct TempDir(PathBuf);
+
+impl TempDir {
+    pub(crate) fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("campus-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).expect("create temp dir");
+        Self(dir)
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.0
+    }
+
+    pub(crate) fn join(&self, file: &str) -> PathBuf {
+        self.0.join(file)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
@@ -0,0 +1,85 @@
+mod common;
+
+use bevy_online_campus::net::*;
+use bevy_online_campus::server::ServerPassword;
+use common::*;
+
+fn connect_as(server: &mut TestServer, account_id: u64, password: &str) -> u64 {
+    server.connect_with(ConnectUserData {
+        account_id,
+        password: password.into(),
+        ..ConnectUserData::local()
+    })
+}
+
+fn rejection(server: &mut TestServer, id: u64) -> Option<String> {
+    server.run(5);
+    server.client(id).inbox.iter().find_map(|m| match m {
+        ServerMessage::Reject { reason } => Some(reason.clone()),
+        _ => None,
+    })
+}
+
+#[test]
+fn account_bans_reject_until_unbanned() {
+    let mut server = TestServer::new();
+    let a = connect_as(&mut server, 5, "");
+    server.run(10);
+    let out = server.admin(&format!("ban {} 1h griefing", a));
+    assert!(
+        out.starts_with(&format!("banned {} (account 5, 1h left", a)),
+        "{}",
+        out
+    );
+    assert_eq!(
+        rejection(&mut server, a).as_deref(),
+        Some("Banned by admin: griefing")
+    );
+    server.disconnect(a);
+
+    let again = connect_as(&mut server, 5, "");
+    let reason = rejection(&mut server, again).expect("banned account was admitted");
+    assert!(
+        reason.contains("griefing") && reason.contains("1h left"),
+        "{}",
+        reason
+    );
+    assert!(!has_event(server.client(again), |ev| matches!(
+        ev,
+        EventMsg::Spawn { .. }
+    )));
+    assert!(server.admin("bans").contains("account 5"));
+    server.disconnect(again);
+
+    assert_eq!(server.admin("unban 5"), "unbanned account 5");
+    let back = connect_as(&mut server, 5, "");
+    assert_eq!(rejection(&mut server, back), None);
+    assert!(server.admin("unban 5").starts_with("error:"));
+}
+
+#[test]
+fn password_and_allow_list_gate_joins() {
+    let mut server = TestServer::new();
+    server
+        .app
+        .insert_resource(ServerPassword(Some("campus".into())));
+    let none = connect_as(&mut server, 0, "");
+    let wrong = connect_as(&mut server, 0, "guess");
+    let right = connect_as(&mut server, 0, "campus");
+    assert_eq!(
+        rejection(&mut server, none).as_deref(),
+        Some("This server requires a password")
+    );
+    assert_eq!(
+        rejection(&mut server, wrong).as_deref(),
+        Some("Wrong server password")
+    );
+    assert_eq!(rejection(&mut server, right), None);
+
+    // 許可リストを使い始めると、載っていない人は入れない
+    assert!(server.admin("allow 3").starts_with("allowed account 3"));
+    let guest = connect_as(&mut server, 0, "campus");
+    let listed = connect_as(&mut server, 3, "campus");
+    assert!(rejection(&mut server, guest).is_some());
+    assert_eq!(rejection(&mut server, listed), None);
+}
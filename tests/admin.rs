@@ -0,0 +1,152 @@
+mod common;
+
+use bevy::prelude::Vec3;
+use bevy_online_campus::net::*;
+use bevy_online_campus::server::ServerConfig;
+use common::*;
+
+#[test]
+fn status_lists_connected_players() {
+    let mut server = TestServer::new();
+    let (a, _) = server.join();
+    let (b, _) = server.join();
+    let out = server.admin("status");
+    assert!(out.contains("players: 2"), "{}", out);
+    assert!(
+        out.contains(&format!("\n  {} local hp=100 alive", a)),
+        "{}",
+        out
+    );
+    assert!(out.contains(&format!("\n  {} local", b)), "{}", out);
+}
+
+#[test]
+fn kick_rejects_and_disconnects_the_player() {
+    let mut server = TestServer::new();
+    let (a, _) = server.join();
+    let (b, _) = server.join();
+    assert_eq!(
+        server.admin(&format!("kick {}", b)),
+        format!("kicked {}", b)
+    );
+    assert!(server.client(b).inbox.iter().any(|m| matches!(
+        m,
+        ServerMessage::Reject { reason } if reason == "Kicked by admin"
+    )));
+    let despawned = server.run_until(TICK_HZ, |s| {
+        s.clients.iter().any(|c| {
+            c.id == a && has_event(c, |ev| matches!(ev, EventMsg::Despawn { id } if *id == b))
+        })
+    });
+    assert!(despawned.is_some());
+    assert!(server.admin("kick 999").starts_with("error: no player"));
+}
+
+#[test]
+fn local_players_cannot_be_banned_by_address() {
+    let mut server = TestServer::new();
+    let (a, _) = server.join();
+    let out = server.admin(&format!("ban {}", a));
+    assert!(out.starts_with("error:"), "{}", out);
+    assert!(!server
+        .client(a)
+        .inbox
+        .iter()
+        .any(|m| matches!(m, ServerMessage::Reject { .. })));
+}
+
+#[test]
+fn say_and_endround_reach_every_client() {
+    let mut server = TestServer::new();
+    let (a, _) = server.join();
+    let (b, _) = server.join();
+    server.admin("say  server restarts in 5 minutes");
+    server.admin("endround");
+    server.run(2);
+    for id in [a, b] {
+        let c = server.client(id);
+        assert!(has_event(c, |ev| matches!(
+            ev,
+            EventMsg::Announce { text } if text == "server restarts in 5 minutes"
+        )));
+        assert!(has_event(c, |ev| matches!(
+            ev,
+            EventMsg::RoundEnd {
+                winner_id: None,
+                ..
+            }
+        )));
+    }
+    assert_eq!(server.admin("endround"), "error: round is already ending");
+}
+
+#[test]
+fn setbots_changes_the_configured_count() {
+    let mut server = TestServer::new();
+    assert_eq!(server.admin("setbots 4"), "bot count set to 4");
+    assert_eq!(server.app.world().resource::<ServerConfig>().bots.count, 4);
+}
+
+#[test]
+fn changemap_is_announced_and_restarts_the_round() {
+    let mut server = TestServer::new();
+    let (a, spawn) = server.join();
+    server.run(10);
+    server
+        .client(a)
+        .place_scaffold(spawn + Vec3::new(3.0, 0.0, 0.0));
+    server.run(15);
+    server.admin("changemap maps/other.glb#Scene0");
+    server.run(2);
+    let c = server.client(a);
+    assert!(has_event(c, |ev| matches!(
+        ev,
+        EventMsg::MapChange { scene } if scene == "maps/other.glb#Scene0"
+    )));
+    assert!(has_event(c, |ev| matches!(ev, EventMsg::RoundEnd { .. })));
+    // 前のマップの足場は残さない
+    assert!(has_event(c, |ev| matches!(
+        ev,
+        EventMsg::ScaffoldDespawn { .. }
+    )));
+    assert!(server
+        .admin("status")
+        .starts_with("map: maps/other.glb#Scene0"));
+}
+
+#[test]
+fn changemap_to_a_missing_scene_keeps_the_current_map() {
+    let mut server = TestServer::new();
+    let (a, _) = server.join();
+    let out = server.admin("changemap maps/typo.glb#Scene0");
+    assert_eq!(out, "error: no such map asset: maps/typo.glb#Scene0");
+    server.run(2);
+    assert!(!has_event(server.client(a), |ev| matches!(
+        ev,
+        EventMsg::MapChange { .. }
+    )));
+    assert!(!server.admin("status").starts_with("map: maps/typo.glb"));
+}
+
+#[test]
+fn dump_scores_orders_by_kills() {
+    let mut server = TestServer::new();
+    let (a, _) = server.join();
+    let (b, _) = server.join();
+    server.run(PROTECT_TICKS);
+    server
+        .shoot_until_dead(a, b, 10)
+        .expect("target never died");
+    let out = server.admin("dump scores");
+    let rows: Vec<&str> = out.lines().collect();
+    assert_eq!(rows[0], "id kills deaths");
+    assert_eq!(rows[1], format!("{} 1 0", a));
+    assert_eq!(rows[2], format!("{} 0 1", b));
+}
+
+#[test]
+fn unknown_commands_report_usage() {
+    let mut server = TestServer::new();
+    let out = server.admin("fly 10");
+    assert!(out.starts_with("error: unknown command: fly"), "{}", out);
+}
@@ -0,0 +1,109 @@
+mod common;
+
+use bevy::prelude::*;
+use bevy_online_campus::net::*;
+use common::*;
+
+fn hits_on(c: &FakeClient, target: u64) -> Vec<u16> {
+    c.events()
+        .filter_map(|ev| match ev {
+            EventMsg::Hit {
+                target_id, new_hp, ..
+            } if *target_id == target => Some(*new_hp),
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn spawn_protection_blocks_damage() {
+    let mut server = TestServer::new();
+    let (a, _) = server.join();
+    let (b, _) = server.join();
+    server.run(10);
+    server.shoot(a, b);
+    server.run(FIRE_INTERVAL_TICKS);
+    assert!(hits_on(server.client(a), b).is_empty());
+}
+
+#[test]
+fn three_hits_kill_and_score() {
+    let mut server = TestServer::new();
+    let (a, _) = server.join();
+    let (b, _) = server.join();
+    server.run(PROTECT_TICKS);
+    let shots = server
+        .shoot_until_dead(a, b, 10)
+        .expect("target never died");
+    assert_eq!(shots, 3);
+    assert_eq!(hits_on(server.client(a), b), vec![65, 30, 0]);
+    // 死亡はキルログとして全員へ
+    assert!(has_event(server.client(b), |ev| matches!(
+        ev,
+        EventMsg::Death { target_id, by } if *target_id == b && *by == a
+    )));
+    let table = server.client(b).latest_scores().expect("score broadcast");
+    let kd = |id: u64| {
+        table
+            .iter()
+            .find(|e| e.id == id)
+            .map(|e| (e.kills, e.deaths))
+    };
+    assert_eq!(kd(a), Some((1, 0)));
+    assert_eq!(kd(b), Some((0, 1)));
+    // 2 秒後にリスポーン
+    server.client(b).clear_inbox();
+    let respawned = server.run_until(3 * TICK_HZ, |s| {
+        s.clients.iter().any(|c| {
+            c.id == b && has_event(c, |ev| matches!(ev, EventMsg::Spawn { id, .. } if *id == b))
+        })
+    });
+    assert!(respawned.is_some());
+}
+
+#[test]
+fn shot_is_judged_against_rewound_position() {
+    let mut server = TestServer::new();
+    let (a, _) = server.join();
+    let (b, _) = server.join();
+    server.run(PROTECT_TICKS);
+    let hold = server.client(a).pos_of(b).unwrap();
+    // b が横へ走り出し、100ms 以上経ってから「今いる位置」を狙う
+    server.client(b).input = Some(InputFrame {
+        mv: [1.0, 0.0],
+        ..idle_input()
+    });
+    server.run(30);
+    let now = server.client(a).pos_of(b).unwrap();
+    assert!(now.distance(hold) > 1.0, "target should have moved");
+    server.client(a).clear_inbox();
+    server.shoot(a, b);
+    server.run(FIRE_INTERVAL_TICKS);
+    // サーバは LAG_COMP_SEC 前の位置で判定するので、走っている相手の現在位置には当たらない
+    assert!(hits_on(server.client(a), b).is_empty());
+    // 止まってから 100ms 以上待てば巻き戻し位置と現在位置が一致して当たる
+    server.client(b).input = Some(idle_input());
+    server.run(20);
+    server.shoot(a, b);
+    server.run(FIRE_INTERVAL_TICKS);
+    assert_eq!(hits_on(server.client(a), b), vec![65]);
+}
+
+#[test]
+fn fire_from_far_origin_is_dropped() {
+    let mut server = TestServer::new();
+    let (a, _) = server.join();
+    let (b, _) = server.join();
+    server.run(PROTECT_TICKS);
+    let target = server.client(a).pos_of(b).unwrap();
+    // 相手の目の前から撃ったと申告する
+    let origin = target + Vec3::new(0.0, 0.0, 2.0);
+    server.client(a).fire(origin, (target - origin).normalize());
+    server.run(FIRE_INTERVAL_TICKS);
+    let c = server.client(a);
+    assert!(hits_on(c, b).is_empty());
+    assert!(!has_event(
+        c,
+        |ev| matches!(ev, EventMsg::Fire { id, .. } if *id == a)
+    ));
+}
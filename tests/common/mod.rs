@@ -0,0 +1,432 @@
+// ===== 結合テスト用ハーネス =====
+// サーバ App を MinimalPlugins で起動し、netcode を通さずプロセス内の RenetClient を直結する。
+// tick() ごとに「クライアント送信 → サーバ 1 フレーム（FixedUpdate 1 回）→ クライアント受信」を進める。
+#![allow(dead_code)]
+
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use bevy::prelude::*;
+use bevy::time::TimeUpdateStrategy;
+use bevy_online_campus::net::*;
+use bevy_online_campus::server::{
+    AdminQueue, BotPlugin, LocalClients, ServerConfig, ServerGameplayPlugin, ServerPlugins,
+};
+use bevy_rapier3d::prelude::*;
+use bevy_renet::renet::{ClientId, RenetClient, RenetServer};
+
+pub const DT: f32 = 1.0 / 60.0;
+pub const TICK_HZ: u32 = 60;
+pub const PROTECT_TICKS: u32 = 2 * TICK_HZ + 5; // リスポーン保護（2s）を抜けるまで
+pub const FIRE_INTERVAL_TICKS: u32 = 9; // FIRE_COOLDOWN（1/7.5s）より長く
+pub const EYE: Vec3 = Vec3::new(0.0, 0.7, 0.0); // サーバの FIRE_EYE_OFFSET と一致
+
+// y=0 が床面。スポーン地点はカプセル中心（半高 0.9）の少し上
+pub const SPAWN_A: Vec3 = Vec3::new(0.0, 1.0, 0.0);
+pub const SPAWN_B: Vec3 = Vec3::new(0.0, 1.0, -10.0);
+
+const SNAPSHOT_KEEP: usize = 64;
+
+pub struct FakeClient {
+    pub id: u64,
+    conn: RenetClient,
+    // 毎 tick 送る入力（seq と dt はハーネスが埋める）。None なら入力を送らない
+    pub input: Option<InputFrame>,
+    seq: u32,
+    // 受信した ServerMessage（Snapshot/DeltaSnapshot を含む、受信順）
+    pub inbox: Vec<ServerMessage>,
+    // 復元済みスナップショット（差分の基準として保持）
+    snapshots: VecDeque<(u32, Vec<PlayerStateMsg>)>,
+}
+
+impl FakeClient {
+    fn new(id: u64) -> Self {
+        let mut conn = RenetClient::new(connection_config());
+        conn.set_connected();
+        Self {
+            id,
+            conn,
+            input: Some(idle_input()),
+            seq: 0,
+            inbox: Vec::new(),
+            snapshots: VecDeque::new(),
+        }
+    }
+
+    pub fn send(&mut self, msg: &ClientMessage) {
+        let channel = match msg {
+            ClientMessage::Input(_) | ClientMessage::SnapshotAck { .. } => CH_INPUT,
+            ClientMessage::PlaceScaffold { .. }
+            | ClientMessage::Fire { .. }
+            | ClientMessage::StatsQuery { .. } => CH_RELIABLE,
+        };
+        let bytes = bincode::serialize(msg).expect("serialize client message");
+        self.conn.send_message(channel, bytes);
+    }
+
+    pub fn fire(&mut self, origin: Vec3, dir: Vec3) {
+        self.send(&ClientMessage::Fire {
+            origin: origin.to_array(),
+            dir: dir.to_array(),
+        });
+    }
+
+    pub fn place_scaffold(&mut self, pos: Vec3) {
+        self.send(&ClientMessage::PlaceScaffold {
+            pos: pos.to_array(),
+        });
+    }
+
+    pub fn events(&self) -> impl Iterator<Item = &EventMsg> {
+        self.inbox.iter().filter_map(|m| match m {
+            ServerMessage::Event(ev) => Some(ev),
+            _ => None,
+        })
+    }
+
+    pub fn clear_inbox(&mut self) {
+        self.inbox.clear();
+    }
+
+    // 最新スナップショットでのアクター状態
+    pub fn actor(&self, id: u64) -> Option<&PlayerStateMsg> {
+        self.snapshots
+            .back()
+            .and_then(|(_, players)| players.iter().find(|p| p.id == id))
+    }
+
+    pub fn pos_of(&self, id: u64) -> Option<Vec3> {
+        self.actor(id).map(|p| Vec3::from_array(p.pos))
+    }
+
+    // 最新スナップショットでサーバが id について処理済みとした入力 seq
+    pub fn ack_of(&self, id: u64) -> Option<u32> {
+        let acks = self.inbox.iter().rev().find_map(|m| match m {
+            ServerMessage::Snapshot(s) => Some(&s.acks),
+            ServerMessage::DeltaSnapshot(d) => Some(&d.acks),
+            _ => None,
+        })?;
+        acks.iter().find(|(c, _)| *c == id).map(|(_, seq)| *seq)
+    }
+
+    pub fn latest_scores(&self) -> Option<&Vec<ScoreEntry>> {
+        self.inbox.iter().rev().find_map(|m| match m {
+            ServerMessage::Score(table) => Some(table),
+            _ => None,
+        })
+    }
+
+    fn send_tick_input(&mut self) {
+        let Some(template) = self.input.clone() else {
+            return;
+        };
+        self.seq = self.seq.wrapping_add(1);
+        let frame = InputFrame {
+            seq: self.seq,
+            dt: DT,
+            ..template
+        }
+        .quantized();
+        self.send(&ClientMessage::Input(frame));
+    }
+
+    fn receive(&mut self) {
+        while let Some(raw) = self.conn.receive_message(CH_RELIABLE) {
+            if let Ok(msg) = bincode::deserialize::<ServerMessage>(&raw) {
+                self.inbox.push(msg);
+            }
+        }
+        let mut ack = None;
+        while let Some(raw) = self.conn.receive_message(CH_SNAPSHOT) {
+            let Ok(msg) = bincode::deserialize::<ServerMessage>(&raw) else {
+                continue;
+            };
+            let restored = match &msg {
+                ServerMessage::Snapshot(s) => Some((s.tick, s.players.clone())),
+                ServerMessage::DeltaSnapshot(d) => self
+                    .snapshots
+                    .iter()
+                    .find(|(t, _)| *t == d.base_tick)
+                    .map(|(_, base)| (d.tick, apply_delta(base, d).players)),
+                _ => None,
+            };
+            if let Some((tick, players)) = restored {
+                self.snapshots.push_back((tick, players));
+                if self.snapshots.len() > SNAPSHOT_KEEP {
+                    self.snapshots.pop_front();
+                }
+                ack = Some(tick);
+            }
+            self.inbox.push(msg);
+        }
+        // 実クライアントと同じく復元できた最新 tick を ACK する
+        if let Some(tick) = ack {
+            self.send(&ClientMessage::SnapshotAck { tick });
+        }
+    }
+}
+
+pub fn idle_input() -> InputFrame {
+    InputFrame {
+        seq: 0,
+        mv: [0.0, 0.0],
+        jump: false,
+        fire: false,
+        ads: false,
+        yaw: 0.0,
+        pitch: 0.0,
+        dt: DT,
+    }
+}
+
+pub struct TestServer {
+    pub app: App,
+    pub clients: Vec<FakeClient>,
+    next_id: u64,
+}
+
+impl TestServer {
+    // 平らな床とスポーン地点 2 つ、bot なし
+    pub fn new() -> Self {
+        Self::with_config(ServerConfig::default())
+    }
+
+    pub fn with_config(config: ServerConfig) -> Self {
+        Self::build(config, &[SPAWN_A, SPAWN_B])
+    }
+
+    pub fn with_spawns(spawns: &[Vec3]) -> Self {
+        Self::build(ServerConfig::default(), spawns)
+    }
+
+    fn build(config: ServerConfig, spawns: &[Vec3]) -> Self {
+        let mut app = App::new();
+        app.add_plugins((
+            MinimalPlugins,
+            TransformPlugin,
+            HierarchyPlugin,
+            // changemap が存在を確かめるマップ（中身は読まない）は tests/assets に置く
+            AssetPlugin {
+                file_path: "tests/assets".into(),
+                ..default()
+            },
+            bevy::scene::ScenePlugin,
+        ))
+        .init_asset::<Mesh>()
+        .insert_resource(TimeUpdateStrategy::ManualDuration(Duration::from_secs_f32(
+            DT,
+        )))
+        .add_plugins(RapierPhysicsPlugin::<NoUserData>::default())
+        // setup_server は既存の RenetServer があればトランスポートを作らない
+        .insert_resource(RenetServer::new(connection_config()))
+        .insert_resource(config)
+        .add_plugins(
+            ServerPlugins
+                .build()
+                .set(ServerGameplayPlugin {
+                    map_scene: None,
+                    ..default()
+                })
+                .disable::<BotPlugin>(),
+        );
+
+        // マップの代わり: 床メッシュ（add_mesh_colliders_for_map が拾う）と spawn* ノード
+        let floor = app
+            .world_mut()
+            .resource_mut::<Assets<Mesh>>()
+            .add(Mesh::from(Cuboid::new(200.0, 1.0, 200.0)));
+        app.world_mut().spawn((
+            floor,
+            TransformBundle::from_transform(Transform::from_xyz(0.0, -0.5, 0.0)),
+        ));
+        for (i, p) in spawns.iter().enumerate() {
+            app.world_mut().spawn((
+                Name::new(format!("spawn_{}", i)),
+                TransformBundle::from_transform(Transform::from_translation(*p)),
+            ));
+        }
+        app.finish();
+        app.cleanup();
+
+        let mut server = Self {
+            app,
+            clients: Vec::new(),
+            next_id: 1,
+        };
+        // 床コライダとスポーン地点の収集を済ませておく
+        server.run(2);
+        server
+    }
+
+    pub fn connect(&mut self) -> u64 {
+        self.connect_with(ConnectUserData::local())
+    }
+
+    // ハンドシェイク内容を変えて接続（バージョン不一致など）
+    pub fn connect_with(&mut self, hello: ConnectUserData) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.app
+            .world_mut()
+            .resource_mut::<LocalClients>()
+            .0
+            .insert(id, hello.to_bytes());
+        self.app
+            .world_mut()
+            .resource_mut::<RenetServer>()
+            .add_connection(ClientId::from_raw(id));
+        self.clients.push(FakeClient::new(id));
+        id
+    }
+
+    pub fn disconnect(&mut self, id: u64) {
+        self.clients.retain(|c| c.id != id);
+        self.app
+            .world_mut()
+            .resource_mut::<RenetServer>()
+            .disconnect(ClientId::from_raw(id));
+        self.app
+            .world_mut()
+            .resource_mut::<LocalClients>()
+            .0
+            .remove(&id);
+    }
+
+    pub fn client(&mut self, id: u64) -> &mut FakeClient {
+        self.clients
+            .iter_mut()
+            .find(|c| c.id == id)
+            .expect("unknown test client")
+    }
+
+    pub fn tick(&mut self) {
+        let dt = Duration::from_secs_f32(DT);
+        for c in self.clients.iter_mut() {
+            c.send_tick_input();
+            c.conn.update(dt);
+            let packets = c.conn.get_packets_to_send();
+            let mut server = self.app.world_mut().resource_mut::<RenetServer>();
+            for p in packets {
+                let _ = server.process_packet_from(&p, ClientId::from_raw(c.id));
+            }
+        }
+        self.app.update();
+        for c in self.clients.iter_mut() {
+            let mut server = self.app.world_mut().resource_mut::<RenetServer>();
+            if let Ok(packets) = server.get_packets_to_send(ClientId::from_raw(c.id)) {
+                for p in packets {
+                    c.conn.process_packet(&p);
+                }
+            }
+            c.receive();
+        }
+    }
+
+    pub fn run(&mut self, ticks: u32) {
+        for _ in 0..ticks {
+            self.tick();
+        }
+    }
+
+    pub fn run_secs(&mut self, secs: f32) {
+        self.run((secs * TICK_HZ as f32).ceil() as u32);
+    }
+
+    // 条件が満たされるまで進める（満たされた tick 数、タイムアウトなら None）
+    pub fn run_until(
+        &mut self,
+        max_ticks: u32,
+        mut done: impl FnMut(&TestServer) -> bool,
+    ) -> Option<u32> {
+        for n in 0..max_ticks {
+            if done(self) {
+                return Some(n);
+            }
+            self.tick();
+        }
+        done(self).then_some(max_ticks)
+    }
+
+    // 接続して Spawn を受け取るまで進め、スポーン位置を返す
+    pub fn join(&mut self) -> (u64, Vec3) {
+        let id = self.connect();
+        let mut spawned = None;
+        self.run_until(60, |s| {
+            spawned = s.clients.iter().find(|c| c.id == id).and_then(|c| {
+                c.events().find_map(|ev| match ev {
+                    EventMsg::Spawn { id: sid, pos, .. } if *sid == id => {
+                        Some(Vec3::from_array(*pos))
+                    }
+                    _ => None,
+                })
+            });
+            spawned.is_some()
+        })
+        .expect("client never spawned");
+        (id, spawned.unwrap())
+    }
+}
+
+impl TestServer {
+    // 管理コマンドを 1 つ実行して応答を返す（1 tick 進む）
+    pub fn admin(&mut self, line: &str) -> String {
+        let reply = self.app.world().resource::<AdminQueue>().submit(line);
+        self.tick();
+        reply.try_recv().expect("admin command was not processed")
+    }
+
+    // shooter の最新スナップショット位置（目線）から target の胴体中心へ撃つ
+    pub fn shoot(&mut self, shooter: u64, target: u64) {
+        let c = self.client(shooter);
+        let from = c.pos_of(shooter).expect("shooter not in snapshot") + EYE;
+        let to = c.pos_of(target).expect("target not in snapshot");
+        c.fire(from, (to - from).normalize());
+    }
+
+    // target が死ぬまで撃ち続ける（連射間隔を空ける）。かかった発数を返す
+    pub fn shoot_until_dead(&mut self, shooter: u64, target: u64, max_shots: u32) -> Option<u32> {
+        for n in 1..=max_shots {
+            self.shoot(shooter, target);
+            self.run(FIRE_INTERVAL_TICKS);
+            if has_event(
+                self.client(shooter),
+                |ev| matches!(ev, EventMsg::Death { target_id, by } if *target_id == target && *by == shooter),
+            ) {
+                return Some(n);
+            }
+        }
+        None
+    }
+}
+
+pub fn has_event(c: &FakeClient, pred: impl Fn(&EventMsg) -> bool) -> bool {
+    c.events().any(pred)
+}
+
+// ===== 一時ディレクトリ =====
+// 名前とプロセス ID で分けて並列実行でも衝突させず、drop で中身ごと消す
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("campus-it-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).expect("create temp dir");
+        Self(dir)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+
+    pub fn join(&self, file: &str) -> PathBuf {
+        self.0.join(file)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
@@ -0,0 +1,184 @@
+mod common;
+
+use bevy::prelude::*;
+use bevy_online_campus::net::*;
+use common::*;
+
+#[test]
+fn handshake_welcomes_and_spawns_client() {
+    let mut server = TestServer::new();
+    let (id, spawn) = server.join();
+    let c = server.client(id);
+    assert!(c.inbox.iter().any(|m| matches!(
+        m,
+        ServerMessage::Welcome { version, .. } if *version == PROTOCOL_VERSION
+    )));
+    // 最初のスポーン地点 + ジッター範囲内
+    assert!(spawn.distance(SPAWN_A) <= 6.0 * 2f32.sqrt() + 0.01);
+    assert!(has_event(c, |ev| matches!(
+        ev,
+        EventMsg::Ammo { id: a, ammo: 30, reloading: false } if *a == id
+    )));
+}
+
+#[test]
+fn version_mismatch_is_rejected_without_spawn() {
+    let mut server = TestServer::new();
+    let id = server.connect_with(ConnectUserData {
+        version: PROTOCOL_VERSION + 1,
+        ..ConnectUserData::local()
+    });
+    server.run(10);
+    let c = server.client(id);
+    assert!(c
+        .inbox
+        .iter()
+        .any(|m| matches!(m, ServerMessage::Reject { .. })));
+    assert!(!has_event(c, |ev| matches!(ev, EventMsg::Spawn { .. })));
+}
+
+#[test]
+fn snapshots_carry_both_players_and_ack_inputs() {
+    let mut server = TestServer::new();
+    let (a, _) = server.join();
+    let (b, _) = server.join();
+    server.run(30);
+    let c = server.client(a);
+    assert!(c.actor(a).is_some());
+    assert!(c.actor(b).is_some(), "nearby player should be relevant");
+    let acked = c.inbox.iter().rev().find_map(|m| match m {
+        ServerMessage::Snapshot(s) => s.acks.iter().find(|(id, _)| *id == a).map(|x| x.1),
+        ServerMessage::DeltaSnapshot(d) => d.acks.iter().find(|(id, _)| *id == a).map(|x| x.1),
+        _ => None,
+    });
+    assert!(acked.is_some_and(|seq| seq > 0), "inputs should be acked");
+}
+
+#[test]
+fn disconnect_despawns_for_remaining_players() {
+    let mut server = TestServer::new();
+    let (a, _) = server.join();
+    let (b, _) = server.join();
+    server.run(10);
+    server.client(a).clear_inbox();
+    server.disconnect(b);
+    let despawned = server.run_until(30, |s| {
+        s.clients
+            .iter()
+            .any(|c| has_event(c, |ev| matches!(ev, EventMsg::Despawn { id } if *id == b)))
+    });
+    assert!(despawned.is_some());
+}
+
+#[test]
+fn player_names_reach_existing_and_new_players() {
+    let mut server = TestServer::new();
+    let a = server.connect_with(ConnectUserData {
+        name: " alice\n".into(),
+        account_id: 7,
+        ..ConnectUserData::local()
+    });
+    server.run(10);
+    let b = server.connect_with(ConnectUserData {
+        name: "bob".into(),
+        ..ConnectUserData::local()
+    });
+    server.run(10);
+    let info = |c: &FakeClient, who: u64| {
+        c.events().find_map(|ev| match ev {
+            EventMsg::PlayerInfo {
+                id,
+                name,
+                account_id,
+            } if *id == who => Some((name.clone(), *account_id)),
+            _ => None,
+        })
+    };
+    // 後から来た b は既存の a を、a は b の参加を受け取る
+    assert_eq!(info(server.client(b), a), Some(("alice".into(), 7)));
+    assert_eq!(info(server.client(a), b), Some(("bob".into(), 0)));
+    let status = server.admin("status");
+    assert!(status.contains("\"alice\" account=7"), "{}", status);
+}
+
+#[test]
+fn reconnect_with_same_account_resumes_score_hp_and_position() {
+    let mut server = TestServer::new();
+    let (a, _) = server.join();
+    let b = server.connect_with(ConnectUserData {
+        name: "bob".into(),
+        account_id: 9,
+        ..ConnectUserData::local()
+    });
+    server.run(PROTECT_TICKS);
+    server.shoot_until_dead(b, a, 10).expect("a never died");
+    // リスポーン待ちと保護を抜けてから撃ち返す
+    server.run(2 * PROTECT_TICKS);
+    server.shoot(a, b);
+    server.run(FIRE_INTERVAL_TICKS);
+    let before = server.client(a).actor(b).expect("b in snapshot").clone();
+    assert_eq!(before.hp, 65);
+    server.disconnect(b);
+    server.run(10);
+    // クライアント ID は変わるが、アカウントが同じなら続きから
+    let b2 = server.connect_with(ConnectUserData {
+        name: "bob".into(),
+        account_id: 9,
+        ..ConnectUserData::local()
+    });
+    server.run(10);
+    let c = server.client(b2);
+    let me = c.actor(b2).expect("resumed player in snapshot");
+    assert_eq!(me.hp, 65);
+    assert!(Vec3::from_array(me.pos).distance(Vec3::from_array(before.pos)) < 0.5);
+    let kills = c
+        .latest_scores()
+        .and_then(|t| t.iter().find(|e| e.id == b2))
+        .map(|e| e.kills);
+    assert_eq!(kills, Some(1));
+    // ゲストは預からないので新規扱い
+    server.disconnect(a);
+    server.run(10);
+    let (a2, _) = server.join();
+    server.run(10);
+    let c = server.client(a2);
+    assert_eq!(c.actor(a2).map(|p| p.hp), Some(100));
+}
+
+#[test]
+fn rejoining_before_the_old_connection_drops_takes_it_over() {
+    let mut server = TestServer::new();
+    let (a, _) = server.join();
+    let bob = ConnectUserData {
+        name: "bob".into(),
+        account_id: 9,
+        ..ConnectUserData::local()
+    };
+    let b = server.connect_with(bob.clone());
+    server.run(PROTECT_TICKS);
+    server.shoot(a, b);
+    server.run(FIRE_INTERVAL_TICKS);
+    let before = server.client(a).actor(b).expect("b in snapshot").clone();
+    assert_eq!(before.hp, 65);
+    // 古い接続が切れる前に同じアカウントで入り直す
+    let b2 = server.connect_with(bob);
+    server.run(10);
+    let rejected = server.client(b).inbox.iter().any(
+        |m| matches!(m, ServerMessage::Reject { reason } if reason.contains("another client")),
+    );
+    assert!(rejected);
+    assert!(has_event(
+        server.client(a),
+        |ev| matches!(ev, EventMsg::Despawn { id } if *id == b)
+    ));
+    let me = server
+        .client(b2)
+        .actor(b2)
+        .expect("new connection in snapshot");
+    assert_eq!(me.hp, 65);
+    assert!(Vec3::from_array(me.pos).distance(Vec3::from_array(before.pos)) < 0.5);
+    // 古い接続が実際に切れても、預け直したり作り直したりしない
+    server.run(PROTECT_TICKS);
+    assert!(server.client(a).actor(b).is_none());
+    assert_eq!(server.client(a).actor(b2).map(|p| p.hp), Some(65));
+}
@@ -0,0 +1,129 @@
+mod common;
+
+use bevy_online_campus::demo::{read_demo, DemoFrame, DEMO_KEYFRAME_TICKS};
+use bevy_online_campus::net::*;
+use bevy_online_campus::server::{DemoRecorder, ServerConfig};
+use common::*;
+
+fn events(frames: &[DemoFrame]) -> impl Iterator<Item = &EventMsg> {
+    frames
+        .iter()
+        .flat_map(|f| f.messages.iter())
+        .filter_map(|m| match m {
+            ServerMessage::Event(ev) => Some(ev),
+            _ => None,
+        })
+}
+
+// キーフレームと差分をたどって各フレームの全アクター状態を復元する
+fn replay_states(frames: &[DemoFrame]) -> Vec<(u32, Vec<PlayerStateMsg>)> {
+    let mut out: Vec<(u32, Vec<PlayerStateMsg>)> = Vec::new();
+    for f in frames {
+        let players = match f.messages.first() {
+            Some(ServerMessage::Snapshot(s)) => s.players.clone(),
+            Some(ServerMessage::DeltaSnapshot(d)) => {
+                let (base_tick, base) = out.last().expect("delta before first keyframe");
+                assert_eq!(d.base_tick, *base_tick);
+                apply_delta(base, d).players
+            }
+            other => panic!(
+                "frame {} does not start with a snapshot: {:?}",
+                f.tick, other
+            ),
+        };
+        out.push((f.tick, players));
+    }
+    out
+}
+
+#[test]
+fn recorded_match_replays_state_and_events() {
+    let dir = TempDir::new("demo-match");
+    let path = dir.join("match.demo");
+    let mut server = TestServer::new();
+    let recorder =
+        DemoRecorder::create(&path, None, &ServerConfig::default(), TICK_HZ as f64).unwrap();
+    server.app.insert_resource(recorder);
+    let (a, _) = server.join();
+    let (b, _) = server.join();
+    server.run(PROTECT_TICKS);
+    server
+        .shoot_until_dead(a, b, 10)
+        .expect("target never died");
+    server.run(2);
+    let live = server.client(a).pos_of(a).unwrap();
+    server
+        .app
+        .world_mut()
+        .resource_mut::<DemoRecorder>()
+        .finish();
+
+    let (header, frames) = read_demo(&path).unwrap();
+    assert_eq!(header.protocol_version, PROTOCOL_VERSION);
+    assert_eq!(header.map, None);
+    assert_eq!(
+        toml::from_str::<ServerConfig>(&header.config).unwrap(),
+        ServerConfig::default()
+    );
+    assert!(frames[0].is_keyframe());
+    assert!(frames
+        .iter()
+        .filter(|f| f.tick % DEMO_KEYFRAME_TICKS == 0)
+        .all(DemoFrame::is_keyframe));
+    assert!(frames.windows(2).all(|w| w[1].tick == w[0].tick + 1));
+
+    let spawned: Vec<u64> = events(&frames)
+        .filter_map(|ev| match ev {
+            EventMsg::Spawn { id, .. } => Some(*id),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(spawned, [a, b]);
+    assert!(events(&frames).any(|ev| matches!(ev, EventMsg::Fire { id, .. } if *id == a)));
+    assert!(events(&frames)
+        .any(|ev| matches!(ev, EventMsg::Death { target_id, by } if *target_id == b && *by == a)));
+    // 個人宛ての弾数通知は録画しない
+    assert!(!events(&frames).any(|ev| matches!(ev, EventMsg::Ammo { .. })));
+
+    let states = replay_states(&frames);
+    let (_, last) = states.last().unwrap();
+    let dead = last.iter().find(|p| p.id == b).unwrap();
+    assert!(!dead.alive);
+    let shooter = last.iter().find(|p| p.id == a).unwrap();
+    assert!(live.distance(shooter.pos.into()) < 0.1);
+}
+
+#[test]
+fn rules_changes_are_recorded() {
+    let dir = TempDir::new("demo-rules");
+    let path = dir.join("match.demo");
+    let mut server = TestServer::new();
+    let recorder =
+        DemoRecorder::create(&path, None, &ServerConfig::default(), TICK_HZ as f64).unwrap();
+    server.app.insert_resource(recorder);
+    server.join();
+    server.run(2);
+    server
+        .app
+        .world_mut()
+        .resource_mut::<ServerConfig>()
+        .weapon
+        .mag_size = 12;
+    server.run(2);
+    server
+        .app
+        .world_mut()
+        .resource_mut::<DemoRecorder>()
+        .finish();
+
+    let (_, frames) = read_demo(&path).unwrap();
+    let mags: Vec<u16> = frames
+        .iter()
+        .flat_map(|f| f.messages.iter())
+        .filter_map(|m| match m {
+            ServerMessage::Rules(r) => Some(r.mag_size),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(mags, [30, 12]);
+}
@@ -0,0 +1,103 @@
+mod common;
+
+use std::path::PathBuf;
+
+use bevy::prelude::Vec3;
+use bevy_online_campus::net::EventMsg;
+use bevy_online_campus::server::MatchLog;
+use common::*;
+use serde_json::Value;
+
+fn read_events(path: &PathBuf) -> Vec<Value> {
+    std::fs::read_to_string(path)
+        .unwrap()
+        .lines()
+        .map(|l| serde_json::from_str(l).expect("each line is JSON"))
+        .collect()
+}
+
+fn find<'a>(events: &'a [Value], kind: &str) -> Vec<&'a Value> {
+    events.iter().filter(|e| e["event"] == kind).collect()
+}
+
+fn server_with_log(path: &PathBuf) -> TestServer {
+    let mut server = TestServer::new();
+    server
+        .app
+        .insert_resource(MatchLog::open(path, 1 << 20, 1).unwrap());
+    server
+}
+
+#[test]
+fn combat_is_logged_with_ids_and_positions() {
+    let dir = TempDir::new("matchlog-combat");
+    let path = dir.join("match.jsonl");
+    let mut server = server_with_log(&path);
+    let (a, _) = server.join();
+    let (b, _) = server.join();
+    server.run(PROTECT_TICKS);
+    server
+        .shoot_until_dead(a, b, 10)
+        .expect("target never died");
+    server.run(1);
+
+    let events = read_events(&path);
+    let connects: Vec<u64> = find(&events, "connect")
+        .iter()
+        .map(|e| e["id"].as_u64().unwrap())
+        .collect();
+    assert_eq!(connects, [a, b]);
+    let fires = find(&events, "fire");
+    assert!(!fires.is_empty());
+    assert!(fires.iter().all(|e| e["id"] == a && e["origin"].is_array()));
+    let hits = find(&events, "hit");
+    assert_eq!(hits.last().unwrap()["hp"], 0);
+    assert!(hits.iter().all(|e| e["target"] == b && e["by"] == a));
+    let deaths = find(&events, "death");
+    assert_eq!(deaths.len(), 1);
+    assert_eq!(deaths[0]["target"], b);
+    assert_eq!(deaths[0]["pos"].as_array().unwrap().len(), 3);
+    assert!(events
+        .iter()
+        .all(|e| e["ts_ms"].as_u64().is_some() && e["round"] == 1));
+}
+
+#[test]
+fn rounds_scaffolds_and_disconnects_are_logged() {
+    let dir = TempDir::new("matchlog-round");
+    let path = dir.join("match.jsonl");
+    let mut server = server_with_log(&path);
+    let (a, spawn) = server.join();
+    server.run(10);
+    server
+        .client(a)
+        .place_scaffold(spawn + Vec3::new(3.0, 0.0, 0.0));
+    server.run(2);
+    server.client(a).clear_inbox();
+    server.admin("endround");
+    server
+        .run_until(TICK_HZ * 10, |s| {
+            s.clients
+                .iter()
+                .all(|c| has_event(c, |ev| matches!(ev, EventMsg::RoundStart { .. })))
+        })
+        .expect("next round never started");
+    server.run(2);
+    server.disconnect(a);
+    server.run(2);
+
+    let events = read_events(&path);
+    let spawned = find(&events, "scaffold_spawn");
+    assert_eq!(spawned.len(), 1);
+    assert_eq!(spawned[0]["owner"], a);
+    let end = find(&events, "round_end");
+    assert_eq!(end.len(), 1);
+    assert!(end[0]["winner"].is_null());
+    assert_eq!(end[0]["scores"][0][0], a);
+    let start = find(&events, "round_start");
+    assert_eq!(start.len(), 1);
+    assert_eq!(start[0]["round"], 2);
+    let gone = find(&events, "disconnect");
+    assert_eq!(gone.len(), 1);
+    assert_eq!(gone[0]["id"], a);
+}
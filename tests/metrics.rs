@@ -0,0 +1,79 @@
+mod common;
+
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+use bevy::prelude::Vec3;
+use bevy_online_campus::server::{spawn_metrics_http, MetricsExport};
+use common::*;
+
+fn metrics(server: &TestServer) -> String {
+    server.app.world().resource::<MetricsExport>().text()
+}
+
+// `name{labels} value` の値
+fn sample(text: &str, series: &str) -> Option<f64> {
+    text.lines()
+        .find_map(|l| l.strip_prefix(series)?.strip_prefix(' ')?.parse().ok())
+}
+
+#[test]
+fn metrics_cover_traffic_and_clients() {
+    let mut server = TestServer::new();
+    let (a, _) = server.join();
+    let (b, _) = server.join();
+    server.run_secs(1.0);
+    let text = metrics(&server);
+    assert_eq!(sample(&text, "server_clients"), Some(2.0), "{}", text);
+    for id in [a, b] {
+        let rtt = format!("server_client_rtt_seconds{{client=\"{}\"}}", id);
+        assert!(sample(&text, &rtt).is_some(), "{}", text);
+    }
+    assert!(sample(&text, "server_sent_bytes_total{channel=\"snapshot\"}").unwrap() > 0.0);
+    assert!(sample(&text, "server_sent_messages_total{channel=\"reliable\"}").unwrap() > 0.0);
+    assert!(sample(&text, "server_snapshot_size_bytes_count").unwrap() > 0.0);
+    assert!(sample(&text, "server_tick_duration_seconds_count").unwrap() >= TICK_HZ as f64);
+    assert_eq!(sample(&text, "server_bots_active"), Some(0.0));
+    assert_eq!(sample(&text, "server_rounds_played_total"), Some(0.0));
+}
+
+#[test]
+fn finished_rounds_and_scaffolds_are_counted() {
+    let mut server = TestServer::new();
+    let (a, spawn) = server.join();
+    server.run(10);
+    server
+        .client(a)
+        .place_scaffold(spawn + Vec3::new(3.0, 0.0, 0.0));
+    server.admin("endround");
+    server.run_secs(1.0);
+    let text = metrics(&server);
+    assert_eq!(
+        sample(&text, "server_rounds_played_total"),
+        Some(1.0),
+        "{}",
+        text
+    );
+    assert_eq!(sample(&text, "server_scaffolds"), Some(1.0), "{}", text);
+}
+
+#[test]
+fn http_endpoint_serves_the_latest_text() {
+    let mut server = TestServer::new();
+    server.run_secs(1.0);
+    let listener = TcpListener::bind("127.0.0.1:0").expect("bind");
+    let addr = listener.local_addr().unwrap();
+    spawn_metrics_http(listener, server.app.world().resource::<MetricsExport>());
+
+    let get = |path: &str| {
+        let mut s = TcpStream::connect(addr).expect("connect");
+        write!(s, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).unwrap();
+        let mut resp = String::new();
+        s.read_to_string(&mut resp).unwrap();
+        resp
+    };
+    let resp = get("/metrics");
+    assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"), "{}", resp);
+    assert!(resp.contains("\r\n\r\n# HELP server_tick_duration_seconds"));
+    assert!(get("/").starts_with("HTTP/1.1 404"));
+}
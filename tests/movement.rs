@@ -0,0 +1,120 @@
+mod common;
+
+use bevy::math::Vec3Swizzles;
+use bevy_online_campus::movement::{predict, MoveParams, MoveState};
+use bevy_online_campus::net::*;
+use common::*;
+
+fn forward(seq: u32) -> InputFrame {
+    InputFrame {
+        seq,
+        mv: [0.0, 1.0],
+        ..idle_input()
+    }
+}
+
+// 溜まり過ぎた入力は古い分を捨てて追いつく（1tick に複数フレーム進めない。移動量は捨てた分だけ減る）
+#[test]
+fn input_backlog_is_dropped_instead_of_fast_forwarded() {
+    let mut server = TestServer::new();
+    let (a, _) = server.join();
+    let (b, _) = server.join();
+    // a は自前の毎 tick 入力を止め、送るフレームの seq をこちらで決める
+    server.client(a).input = None;
+    server.run(10);
+    let start_a = server.client(a).pos_of(a).expect("a in snapshot");
+    let start_b = server.client(b).pos_of(b).expect("b in snapshot");
+    // a は詰まっていた 20 フレームがまとめて届く。b は毎 tick 1 フレームずつ
+    for seq in 1..=20 {
+        server.client(a).send(&ClientMessage::Input(forward(seq)));
+    }
+    server.client(b).input = Some(forward(0));
+    server.run(20);
+    server.client(b).input = None;
+    server.run(20);
+    let moved_a = server.client(a).pos_of(a).unwrap().distance(start_a);
+    let moved_b = server.client(b).pos_of(b).unwrap().distance(start_b);
+    assert!(moved_b > 0.5, "b barely moved ({})", moved_b);
+    assert!(
+        moved_a < moved_b / 3.0,
+        "backlog was replayed: a {} vs b {}",
+        moved_a,
+        moved_b
+    );
+}
+
+// 捨てたフレームのジャンプは次に処理するフレームへ持ち越される
+#[test]
+fn buffered_jump_survives_a_burst() {
+    let mut server = TestServer::new();
+    let (a, _) = server.join();
+    server.client(a).input = None;
+    server.run(10);
+    let ground = server.client(a).pos_of(a).expect("a in snapshot").y;
+    for seq in 1..=10 {
+        server.client(a).send(&ClientMessage::Input(InputFrame {
+            seq,
+            jump: seq == 3,
+            ..idle_input()
+        }));
+    }
+    // 以降は 1 tick 1 フレーム（ジャンプの続きをシミュレーションさせる）
+    let mut peak = ground;
+    for seq in 11..=40 {
+        server.client(a).send(&ClientMessage::Input(InputFrame {
+            seq,
+            ..idle_input()
+        }));
+        server.tick();
+        peak = peak.max(server.client(a).pos_of(a).unwrap().y);
+    }
+    assert!(
+        peak > ground + 0.3,
+        "jump was lost: peak {} ground {}",
+        peak,
+        ground
+    );
+}
+
+// サーバの受信経路（量子化・正規化・ジッタバッファ）を通した水平位置が、クライアントの予測と一致する。
+// 縦は KCC の接地判定が入るので見ない（ジャンプが通ったことだけ確かめる）
+#[test]
+fn server_follows_the_client_prediction() {
+    let mut server = TestServer::new();
+    let (a, _) = server.join();
+    server.client(a).input = None;
+    server.run(10);
+    let base = server.client(a).pos_of(a).expect("a in snapshot");
+    // 斜め移動・旋回・ジャンプを混ぜた入力列（クライアントと同じく量子化済みで送る）
+    let frames: Vec<InputFrame> = (1..=120)
+        .map(|seq| {
+            InputFrame {
+                seq,
+                mv: [1.0, -1.0],
+                jump: seq % 40 == 5,
+                yaw: seq as f32 * 0.013,
+                ..idle_input()
+            }
+            .quantized()
+        })
+        .collect();
+    let params = MoveParams::default();
+    let (mut checked, mut max_err, mut peak) = (0, 0.0f32, base.y);
+    for f in &frames {
+        server.client(a).send(&ClientMessage::Input(f.clone()));
+        server.tick();
+        let Some(ack) = server.client(a).ack_of(a) else {
+            continue;
+        };
+        // ACK したフレームの移動は次のフレームの物理で反映されるので、スナップショットの位置は ack-1 まで
+        let applied = frames.iter().take_while(|f| f.seq < ack);
+        let predicted = predict(base, MoveState::standing(), applied, &params);
+        let actual = server.client(a).pos_of(a).unwrap();
+        max_err = max_err.max(predicted.xz().distance(actual.xz()));
+        peak = peak.max(actual.y);
+        checked += 1;
+    }
+    assert!(checked > 100, "server acked too few frames ({})", checked);
+    assert!(max_err < 0.005, "prediction drifted by {}", max_err);
+    assert!(peak > base.y + 0.5, "jump was not simulated");
+}
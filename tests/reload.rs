@@ -0,0 +1,93 @@
+mod common;
+
+use std::path::PathBuf;
+
+use bevy_online_campus::net::*;
+use bevy_online_campus::server::{ConfigFile, ServerConfig};
+use common::*;
+
+fn temp_config(dir: &TempDir, text: &str) -> PathBuf {
+    let path = dir.join("server.toml");
+    std::fs::write(&path, text).expect("write temp config");
+    path
+}
+
+fn server_watching(path: &PathBuf) -> TestServer {
+    let cfg = ServerConfig::load(path).expect("initial config");
+    let mut server = TestServer::with_config(cfg);
+    server.app.insert_resource(ConfigFile::new(path));
+    server
+}
+
+fn latest_rules(c: &FakeClient) -> Option<RulesMsg> {
+    c.inbox.iter().rev().find_map(|m| match m {
+        ServerMessage::Rules(r) => Some(*r),
+        _ => None,
+    })
+}
+
+#[test]
+fn rules_are_sent_after_welcome() {
+    let mut server = TestServer::new();
+    let (a, _) = server.join();
+    assert_eq!(latest_rules(server.client(a)), Some(RulesMsg::default()));
+}
+
+#[test]
+fn edited_file_applies_live_and_defers_round_settings() {
+    let dir = TempDir::new("reload-live");
+    let path = temp_config(&dir, "[weapon]\ndamage = 35\n");
+    let mut server = server_watching(&path);
+    let (a, _) = server.join();
+    let (b, _) = server.join();
+    std::fs::write(
+        &path,
+        "[weapon]\ndamage = 50\n\n[movement]\nmove_speed = 7.0\n\n[round]\nwin_kills = 3\n\n[scaffold]\nper_player_limit = 5\n",
+    )
+    .unwrap();
+
+    // 1 秒ごとのポーリングで拾われ、接続中の全員へ Rules が届く
+    let got = server.run_until(2 * TICK_HZ, |s| {
+        s.clients
+            .iter()
+            .all(|c| latest_rules(c).is_some_and(|r| r.move_speed == 7.0))
+    });
+    assert!(got.is_some(), "rules were not rebroadcast");
+    // ラウンド設定は次のラウンドまで保留
+    let rules = latest_rules(server.client(b)).unwrap();
+    assert_eq!(rules.win_kills, 10);
+    assert_eq!(rules.scaffold_limit, 5);
+
+    // ダメージは次の射撃から
+    server.run(PROTECT_TICKS);
+    server.shoot(a, b);
+    server.run(FIRE_INTERVAL_TICKS);
+    assert!(has_event(server.client(a), |ev| matches!(
+        ev,
+        EventMsg::Hit { target_id, new_hp: 50, .. } if *target_id == b
+    )));
+}
+
+#[test]
+fn invalid_edit_keeps_current_settings() {
+    let dir = TempDir::new("reload-invalid");
+    let path = temp_config(&dir, "[weapon]\ndamage = 35\n");
+    let mut server = server_watching(&path);
+    let (a, _) = server.join();
+    let (b, _) = server.join();
+    std::fs::write(&path, "[weapon]\ndamage = 0\nfire_rate_hz = 20.0\n").unwrap();
+    server.run(2 * TICK_HZ);
+    assert_eq!(
+        server.app.world().resource::<ServerConfig>(),
+        &ServerConfig::default()
+    );
+    server.client(a).clear_inbox();
+    server.run(PROTECT_TICKS);
+    server.shoot(a, b);
+    server.run(FIRE_INTERVAL_TICKS);
+    assert!(latest_rules(server.client(a)).is_none());
+    assert!(has_event(server.client(a), |ev| matches!(
+        ev,
+        EventMsg::Hit { target_id, new_hp: 65, .. } if *target_id == b
+    )));
+}
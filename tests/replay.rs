@@ -0,0 +1,150 @@
+mod common;
+
+use std::path::PathBuf;
+
+use bevy_online_campus::net::*;
+use bevy_online_campus::server::{
+    match_state, GameRng, InputRecorder, InputReplay, MatchState, ServerConfig, ServerPassword,
+};
+use common::*;
+
+fn start_recording(server: &mut TestServer, path: &PathBuf) {
+    let seed = server.app.world().resource::<GameRng>().seed();
+    let recorder =
+        InputRecorder::create(path, None, &ServerConfig::default(), TICK_HZ as f64, seed).unwrap();
+    server.app.insert_resource(recorder);
+}
+
+fn stop_recording(server: &mut TestServer) -> MatchState {
+    server
+        .app
+        .world_mut()
+        .resource_mut::<InputRecorder>()
+        .finish();
+    match_state(server.app.world())
+}
+
+// 同じ手順で組んだ別のサーバ（シードは違う）で最後まで再生する
+fn replay(path: &PathBuf) -> (MatchState, bool) {
+    let replay = InputReplay::open(path).unwrap();
+    let mut server = TestServer::new();
+    server.app.insert_resource(replay);
+    let mut frames = 0;
+    while !server.app.world().resource::<InputReplay>().is_finished() {
+        server.app.update();
+        frames += 1;
+        assert!(frames < 100_000, "replay never finished");
+    }
+    let diverged = server.app.world().resource::<InputReplay>().has_diverged();
+    (match_state(server.app.world()), diverged)
+}
+
+#[test]
+fn recorded_match_replays_to_the_same_scores_and_positions() {
+    let dir = TempDir::new("replay-match");
+    let path = dir.join("inputs.rec");
+    let mut server = TestServer::new();
+    start_recording(&mut server, &path);
+    let (a, _) = server.join();
+    let (b, _) = server.join();
+    server.client(a).input = Some(InputFrame {
+        mv: [0.3, 1.0],
+        yaw: 0.4,
+        ..idle_input()
+    });
+    server.client(b).input = Some(InputFrame {
+        jump: true,
+        ..idle_input()
+    });
+    server.run(40);
+    server.client(a).input = Some(idle_input());
+    server.client(b).input = Some(idle_input());
+    server.run(PROTECT_TICKS);
+    server
+        .shoot_until_dead(a, b, 10)
+        .expect("target never died");
+    let (c, _) = server.join();
+    server.disconnect(c);
+    server.run(10);
+    let live = stop_recording(&mut server);
+    assert!(live.scores.contains(&(a, 1, 0)));
+    assert!(live.scores.contains(&(b, 0, 1)));
+
+    let (replayed, diverged) = replay(&path);
+    assert!(!diverged);
+    assert_eq!(replayed, live);
+}
+
+#[test]
+fn admin_commands_are_replayed() {
+    let dir = TempDir::new("replay-admin");
+    let path = dir.join("inputs.rec");
+    let mut server = TestServer::new();
+    start_recording(&mut server, &path);
+    let (a, _) = server.join();
+    let (b, _) = server.join();
+    server.run(PROTECT_TICKS);
+    server
+        .shoot_until_dead(a, b, 10)
+        .expect("target never died");
+    server.admin(&format!("kick {}", a));
+    server.run(30);
+    let live = stop_recording(&mut server);
+    assert!(live.positions.iter().all(|(id, _)| *id != a));
+
+    let (replayed, diverged) = replay(&path);
+    assert!(!diverged);
+    assert_eq!(replayed, live);
+}
+
+#[test]
+fn server_password_is_not_recorded() {
+    let dir = TempDir::new("replay-password");
+    let path = dir.join("inputs.rec");
+    let mut server = TestServer::new();
+    server
+        .app
+        .insert_resource(ServerPassword(Some("hunter2-campus".into())));
+    start_recording(&mut server, &path);
+    let with = |password: &str| ConnectUserData {
+        password: password.into(),
+        ..ConnectUserData::local()
+    };
+    let a = server.connect_with(with("hunter2-campus"));
+    let b = server.connect_with(with("hunter2-guess"));
+    server.run(40);
+    let live = stop_recording(&mut server);
+    assert!(live.positions.iter().any(|(id, _)| *id == a));
+    assert!(live.positions.iter().all(|(id, _)| *id != b));
+
+    let bytes = std::fs::read(&path).unwrap();
+    assert!(!bytes.windows(7).any(|w| w == b"hunter2"));
+    // パスワードなしの再生でも、合わなかった接続は弾かれたまま
+    let (replayed, diverged) = replay(&path);
+    assert!(!diverged);
+    assert_eq!(replayed, live);
+}
+
+// 記録前からの BAN で弾いた接続は、BAN リストなしの再生でも弾かれたまま
+#[test]
+fn rejected_connections_stay_rejected_in_replay() {
+    let dir = TempDir::new("replay-banned");
+    let path = dir.join("inputs.rec");
+    let mut server = TestServer::new();
+    server.admin("banaccount 42");
+    start_recording(&mut server, &path);
+    let a = server.connect();
+    let b = server.connect_with(ConnectUserData {
+        account_id: 42,
+        ..ConnectUserData::local()
+    });
+    // Reject から切断までの猶予中に止める（再生で受け入れてしまうと b が出てくる）
+    server.run(10);
+    let live = stop_recording(&mut server);
+    assert!(live.positions.iter().any(|(id, _)| *id == a));
+    assert!(live.positions.iter().all(|(id, _)| *id != b));
+
+    let (replayed, diverged) = replay(&path);
+    assert!(!diverged);
+    assert_eq!(replayed, live);
+}
@@ -0,0 +1,71 @@
+mod common;
+
+use bevy_online_campus::net::*;
+use bevy_online_campus::server::ServerConfig;
+use common::*;
+
+#[test]
+fn ten_kills_end_the_round_and_reset_scores() {
+    let mut server = TestServer::new();
+    let (a, _) = server.join();
+    let (b, _) = server.join();
+    for kill in 1..=10 {
+        server.run(PROTECT_TICKS);
+        server.client(a).clear_inbox();
+        assert!(
+            server.shoot_until_dead(a, b, 10).is_some(),
+            "kill {} failed",
+            kill
+        );
+        if kill < 10 {
+            // リスポーン待ち（死亡から 2 秒）
+            server.run_secs(2.0);
+        }
+    }
+    let ended = server.run_until(5, |s| {
+        s.clients.iter().all(|c| {
+            has_event(
+                c,
+                |ev| matches!(ev, EventMsg::RoundEnd { winner_id: Some(w), .. } if *w == a),
+            )
+        })
+    });
+    assert!(ended.is_some(), "RoundEnd should be broadcast to everyone");
+
+    // ROUND_END_DELAY_SEC 後に次のラウンドが始まりスコアが 0 に戻る
+    server.client(a).clear_inbox();
+    let restarted = server.run_until(6 * TICK_HZ, |s| {
+        s.clients
+            .iter()
+            .any(|c| c.id == a && has_event(c, |ev| matches!(ev, EventMsg::RoundStart { .. })))
+    });
+    assert!(restarted.is_some());
+    let table = server.client(a).latest_scores().unwrap();
+    assert!(table.iter().all(|e| e.kills == 0 && e.deaths == 0));
+}
+
+#[test]
+fn configured_win_kills_and_end_delay_are_used() {
+    let mut config = ServerConfig::default();
+    config.round.win_kills = 1;
+    config.round.end_delay_sec = 1.0;
+    let mut server = TestServer::with_config(config);
+    let (a, _) = server.join();
+    let (b, _) = server.join();
+    server.run(PROTECT_TICKS);
+    server
+        .shoot_until_dead(a, b, 10)
+        .expect("target never died");
+    server.run(2);
+    assert!(has_event(server.client(b), |ev| matches!(
+        ev,
+        EventMsg::RoundEnd { winner_id: Some(w), next_in_sec: 1 } if *w == a
+    )));
+    server.client(b).clear_inbox();
+    let restarted = server.run_until(TICK_HZ + 10, |s| {
+        s.clients
+            .iter()
+            .any(|c| c.id == b && has_event(c, |ev| matches!(ev, EventMsg::RoundStart { .. })))
+    });
+    assert!(restarted.is_some());
+}
@@ -0,0 +1,53 @@
+mod common;
+
+use bevy::prelude::*;
+use bevy_online_campus::net::*;
+use common::*;
+
+fn scaffold_spawns(c: &FakeClient) -> Vec<(u64, u64, Vec3)> {
+    c.events()
+        .filter_map(|ev| match ev {
+            EventMsg::ScaffoldSpawn { sid, owner, pos } => {
+                Some((*sid, *owner, Vec3::from_array(*pos)))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn placed_scaffold_is_announced_to_nearby_players() {
+    let mut server = TestServer::new();
+    let (a, spawn) = server.join();
+    let (b, _) = server.join();
+    server.run(10);
+    let pos = spawn + Vec3::new(3.0, 0.0, 0.0);
+    server.client(a).place_scaffold(pos);
+    server.run(15);
+    for id in [a, b] {
+        let spawned = scaffold_spawns(server.client(id));
+        assert_eq!(spawned.len(), 1, "client {} should see the scaffold", id);
+        assert_eq!(spawned[0].1, a);
+        assert!(spawned[0].2.distance(pos) < 0.05);
+    }
+}
+
+#[test]
+fn per_player_limit_removes_oldest() {
+    let mut server = TestServer::new();
+    let (a, spawn) = server.join();
+    server.run(10);
+    for i in 0..4 {
+        let pos = spawn + Vec3::new(3.0, 0.0, -3.0 + 2.5 * i as f32);
+        server.client(a).place_scaffold(pos);
+        server.run(15);
+    }
+    let c = server.client(a);
+    let spawned = scaffold_spawns(c);
+    assert_eq!(spawned.len(), 4);
+    let first = spawned[0].0;
+    assert!(has_event(c, |ev| matches!(
+        ev,
+        EventMsg::ScaffoldDespawn { sid } if *sid == first
+    )));
+}
@@ -0,0 +1,59 @@
+mod common;
+
+use bevy_online_campus::net::*;
+use common::*;
+
+fn join_as(server: &mut TestServer, name: &str, account_id: u64) -> u64 {
+    server.connect_with(ConnectUserData {
+        name: name.into(),
+        account_id,
+        ..ConnectUserData::local()
+    })
+}
+
+fn latest_stats(c: &FakeClient) -> Option<&Vec<PlayerStats>> {
+    c.inbox.iter().rev().find_map(|m| match m {
+        ServerMessage::Stats(rows) => Some(rows),
+        _ => None,
+    })
+}
+
+#[test]
+fn account_stats_accumulate_and_answer_queries() {
+    let mut server = TestServer::new();
+    let a = join_as(&mut server, "alice", 3);
+    let b = join_as(&mut server, "bob", 4);
+    let guest = server.connect();
+    server.run(PROTECT_TICKS);
+    let shots = server.shoot_until_dead(a, b, 10).expect("b never died");
+    server.run(2);
+
+    server
+        .client(guest)
+        .send(&ClientMessage::StatsQuery { account_id: None });
+    server.run(10);
+    let rows = latest_stats(server.client(guest)).expect("stats reply");
+    // ゲストは載らない。キル順
+    assert_eq!(
+        rows.iter().map(|s| s.account_id).collect::<Vec<_>>(),
+        vec![3, 4]
+    );
+    let alice = &rows[0];
+    assert_eq!(alice.name, "alice");
+    assert_eq!((alice.kills, alice.deaths), (1, 0));
+    assert_eq!((alice.shots, alice.hits), (shots, shots));
+    assert!(alice.play_secs > 1.0);
+    assert_eq!((rows[1].kills, rows[1].deaths), (0, 1));
+
+    server
+        .client(guest)
+        .send(&ClientMessage::StatsQuery {
+            account_id: Some(99),
+        });
+    server.run(10);
+    assert_eq!(latest_stats(server.client(guest)).map(Vec::len), Some(0));
+
+    let table = server.admin("stats 3");
+    assert!(table.contains("3 \"alice\" 1 0 100%"), "{}", table);
+    assert!(server.admin("stats 99").starts_with("error:"));
+}